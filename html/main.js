@@ -5,6 +5,7 @@ var default_params = {
 	'show_redirects':'both',
 	'show_soft_redirects':'both',
 	'show_disambiguation_pages':'both',
+	'show_uncategorized':'both',
 	'edits[bots]':'both',
 	'edits[anons]':'both',
 	'edits[flagged]':'both',
@@ -13,6 +14,7 @@ var default_params = {
 	'ores_prediction':'any',
 	'combination':'subset',
 	'wpiu':'any',
+	'statement_rank':'any',
 	'format':'html',
 	'sortby':'none',
 	'depth':'0',
@@ -569,12 +571,27 @@ function initializeInterface () {
 		}
 	}
 	$('[name="manual_list"]').keyup ( function () {highlightMissingWiki('manual_list','manual_list_wiki')} ) ;
+	// Show what the query is doing while the result page loads, via server-sent events
+	$('#main_form').submit ( function () {
+		if ( typeof EventSource == 'undefined' ) return ;
+		var progress_id = Math.random().toString(36).substr(2) + Date.now().toString(36) ;
+		$('#main_form input[name="progress_id"]').remove() ;
+		$('#main_form').append ( $("<input type='hidden' name='progress_id' />").val(progress_id) ) ;
+		var source = new EventSource ( '/progress/' + progress_id ) ;
+		source.onmessage = function ( e ) { $('#query_progress').text ( e.data ) ; } ;
+		source.addEventListener ( 'done' , function () { source.close() ; } ) ;
+	} ) ;
+	// Uploaded files need a multipart form
+	$('[name="manual_list_file"]').change ( function () { $('#main_form').attr('enctype','multipart/form-data') ; } ) ;
 	$('[name="search_query"]').keyup ( function () {highlightMissingWiki('search_query','search_wiki')} ) ;
 	$('[name="manual_list_wiki"]').keyup ( function () {highlightMissingWiki('manual_list','manual_list_wiki')} ) ;
 	$('[name="search_wiki"]').keyup ( function () {highlightMissingWiki('search_query','search_wiki')} ) ;
+	$('[name="source_url"]').keyup ( function () {highlightMissingWiki('source_url','source_url_wiki')} ) ;
+	$('[name="source_url_wiki"]').keyup ( function () {highlightMissingWiki('source_url','source_url_wiki')} ) ;
 	$('[name="common_wiki_other"]').keyup ( function () {highlightMissingWiki('common_wiki_other','common_wiki_other')} ) ;
 	highlightMissingWiki('manual_list','manual_list_wiki') ;
 	highlightMissingWiki('search_query','search_wiki');
+	highlightMissingWiki('source_url','source_url_wiki');
 	
 	$('#tab-list').click ( function () {
 		if ( $('#main_form div.tab-pane').length > 0 ) {
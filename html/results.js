@@ -0,0 +1,113 @@
+// Client-side sorting, filtering, and selection for the HTML result table (#main_table)
+
+var results_sort = { col:-1 , ascending:true } ;
+
+function resultsCellValue ( tr , col , sort_type ) {
+	var text = $.trim ( $(tr.cells[col]).text() ) ;
+	if ( sort_type != 'number' ) return text.toLowerCase() ;
+	var n = parseFloat ( text.replace(/[^0-9.\-]/g,'') ) ;
+	return isNaN(n) ? -Infinity : n ;
+}
+
+function resultsSortBy ( th ) {
+	var sort_type = th.attr('data-sort-type') ;
+	if ( sort_type == 'none' ) return ;
+	var col = th.index() ;
+	results_sort.ascending = ( results_sort.col == col ) ? !results_sort.ascending : true ;
+	results_sort.col = col ;
+	var tbody = $('#main_table tbody') ;
+	var rows = tbody.find('tr').get() ;
+	rows.sort ( function ( a , b ) {
+		var va = resultsCellValue ( a , col , sort_type ) ;
+		var vb = resultsCellValue ( b , col , sort_type ) ;
+		var ret = va < vb ? -1 : ( va > vb ? 1 : 0 ) ;
+		return results_sort.ascending ? ret : -ret ;
+	} ) ;
+	$.each ( rows , function ( i , tr ) { tbody.append ( tr ) ; } ) ;
+	$('#main_table thead th .results_sort_marker').remove() ;
+	th.append ( "<span class='results_sort_marker'>" + ( results_sort.ascending ? ' &#9650;' : ' &#9660;' ) + "</span>" ) ;
+}
+
+function resultsFilter ( text ) {
+	text = $.trim(text).toLowerCase() ;
+	$('#main_table tbody tr').each ( function () {
+		var tr = $(this) ;
+		tr.toggle ( text == '' || tr.text().toLowerCase().indexOf(text) != -1 ) ;
+	} ) ;
+	resultsUpdateSelection() ;
+}
+
+function resultsSelectedTitles () {
+	var ret = [] ;
+	$('#main_table tbody tr:visible input.results_select:checked').each ( function () {
+		ret.push ( $(this).closest('tr').attr('data-title') ) ;
+	} ) ;
+	return ret ;
+}
+
+function resultsUpdateSelection () {
+	var titles = resultsSelectedTitles() ;
+	$('#results_selected_count').text ( titles.length + ' / ' + $('#main_table tbody tr:visible').length ) ;
+	$('#results_selected_titles').val ( titles.join("\n") ) ;
+}
+
+function resultsCopySelected () {
+	var ta = $('#results_selected_titles') ;
+	ta.show().select() ;
+	if ( navigator.clipboard ) navigator.clipboard.writeText ( ta.val() ) ;
+	else document.execCommand ( 'copy' ) ;
+}
+
+// Download, or create a PagePile from, the selected rows only; needs the stored result token
+function resultsInitSelectionActions () {
+	var token = $('#result_token').attr('data-token') ;
+	if ( typeof token == 'undefined' || token == '' ) return ;
+	var h = "<form id='results_selection_form' method='post' target='_blank' style='display:inline'>" ;
+	h += "<input type='hidden' name='titles' />" ;
+	h += "<input type='hidden' name='action' value='download' />" ;
+	h += " <select name='format' class='form-control form-control-sm'>" ;
+	$.each ( ['csv','tsv','json','wiki','plain','kml'] , function ( i , format ) {
+		h += "<option value='" + format + "'>" + format + "</option>" ;
+	} ) ;
+	h += "</select>" ;
+	h += " <button class='btn btn-sm btn-light' name='download' tt='download_selected'></button>" ;
+	h += " <button class='btn btn-sm btn-light' name='pagepile' tt='pagepile_from_selected'></button>" ;
+	h += "</form>" ;
+	var form = $(h) ;
+	form.attr ( 'action' , '/result/' + encodeURIComponent(token) + '/selection' ) ;
+	form.find('[tt]').each ( function () { $(this).text ( _t($(this).attr('tt')) ) ; } ) ;
+	form.find('button').click ( function () {
+		form.find('input[name="action"]').val ( $(this).attr('name') ) ;
+		form.find('input[name="titles"]').val ( resultsSelectedTitles().join("\n") ) ;
+	} ) ;
+	$('#results_tools').append ( form ) ;
+}
+
+function initResultsTable () {
+	var table = $('#main_table') ;
+	if ( table.length == 0 ) return ;
+
+	// Selection checkboxes, in an extra first column
+	table.find('thead tr').prepend ( "<th data-sort-type='none'><input type='checkbox' id='results_select_all' checked /></th>" ) ;
+	table.find('tbody tr').prepend ( "<td><input type='checkbox' class='results_select' checked /></td>" ) ;
+
+	table.on ( 'click' , 'thead th[data-sort-type]' , function ( e ) {
+		if ( $(e.target).is('input') ) return ;
+		resultsSortBy ( $(this) ) ;
+	} ) ;
+	table.find('thead th[data-sort-type!="none"]').css ( { cursor:'pointer' } ) ;
+	table.on ( 'change' , 'input.results_select' , resultsUpdateSelection ) ;
+	$('#results_select_all').change ( function () {
+		$('#main_table tbody tr:visible input.results_select').prop ( 'checked' , $(this).prop('checked') ) ;
+		resultsUpdateSelection() ;
+	} ) ;
+	$('#results_filter').on ( 'input' , function () { resultsFilter ( $(this).val() ) ; } ) ;
+	$('#results_copy_selected').click ( function ( e ) {
+		e.preventDefault() ;
+		resultsCopySelected() ;
+	} ) ;
+	resultsInitSelectionActions() ;
+	resultsUpdateSelection() ;
+}
+
+$(document).ready ( initResultsTable ) ;
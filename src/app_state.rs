@@ -1,8 +1,19 @@
 use rand::seq::SliceRandom;
 use rand::prelude::thread_rng;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
+use crate::category_tree::DEFAULT_CATEGORY_TREE_MAX_NODES;
+use crate::endpoints::Endpoints;
 use crate::form_parameters::FormParameters;
-use crate::platform::{ContentType, MyResponse};
+use crate::http_client::HttpClient;
+use crate::oauth::{OAuth, OAuthSession};
+use crate::platform::{ContentType, MyResponse, StoredResult};
+use crate::presets::{Preset, Presets, DEFAULT_PRESETS_PATH};
+use crate::progress::Progress;
+use crate::rate_limit::RateLimiter;
+use crate::request_context::RequestContext;
+use crate::scheduler::{ScheduleInterval, ScheduledQuery};
+use crate::translations::{Translations, DEFAULT_TRANSLATIONS_PATH};
+use crate::wiki_db::{MockDatabase, ReplicaConnection, WikiDbConnection};
 use chrono::prelude::*;
 use mysql_async::prelude::Queryable;
 use mysql_async::from_row;
@@ -12,11 +23,69 @@ use rayon::prelude::*;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
+use futures::future::AbortHandle;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use wikibase::mediawiki::api::Api;
 
 pub type DbUserPass = (String, String);
 
+/// (wiki, root category, depth, hidden categories skipped) => (time of creation, categories in tree)
+type CategoryTreeCache = HashMap<(String, String, u16, bool), (Instant, Vec<String>)>;
+
+static DEFAULT_CATEGORY_TREE_CACHE_TTL_SECONDS: u64 = 3600;
+static MAX_CATEGORY_TREE_CACHE_ENTRIES: usize = 1000;
+static DEFAULT_RESULT_TOKEN_TTL_SECONDS: u64 = 600;
+static DEFAULT_DB_QUERY_TIMEOUT_SECONDS: u64 = 600;
+static DEFAULT_QUERY_TIME_BUDGET_SECONDS: u64 = 240;
+/// Bot exclusion and "no item" templates, for the unconnected pages mode
+static DEFAULT_UNCONNECTED_EXCLUDE_TEMPLATES: &[&str] = &["Bots", "Nobots", "Noitem"];
+static DB_CONNECT_TIMEOUT_SECONDS: u64 = 30;
+static DB_CONNECT_ATTEMPTS: u32 = 3;
+/// The site matrix is loaded from here, before the servers of the other wikis are known
+static SITE_MATRIX_SERVER: &str = "https://www.wikidata.org";
+static DEFAULT_MAINTENANCE_MESSAGE: &str =
+    "PetScan is down for maintenance of the Wikimedia database replicas. Please try again in a little while.";
+/// Host (suffixes) the URL source may fetch from, unless "source_url_allowed_hosts" is set in the config
+static DEFAULT_SOURCE_URL_ALLOWED_HOSTS: &[&str] = &[
+    "wikipedia.org",
+    "wikimedia.org",
+    "wikidata.org",
+    "toolforge.org",
+    "wmcloud.org",
+    "wmflabs.org",
+];
+static MAX_RESULT_TOKENS: usize = 200;
+pub static MAX_RESULT_TOKEN_ENTRIES: usize = 500000;
+
+/// token => (time of creation, result)
+type ResultTokens = HashMap<String, (Instant, Arc<StoredResult>)>;
+/// Queries whose ETag is remembered, for Last-Modified
+static MAX_RESPONSE_ETAGS: usize = 10000;
+
+/// Shared response of a running query; None until it has finished
+pub type QueryFlightResponse = Option<Arc<MyResponse>>;
+
+/// A query that is currently being run, and can be aborted
+#[derive(Debug, Clone)]
+pub struct RunningQuery {
+    pub query: String,
+    /// Who started the query, see `RequestContext::description`
+    pub client: String,
+    pub started: Instant,
+    pub stage: Progress,
+    /// Set by the HTML form, to follow the progress via /progress/ID
+    pub progress_id: Option<String>,
+    abort_handle: AbortHandle,
+}
+
+/// A query is either run by this request (leader), or by an earlier, identical one (follower)
+pub enum QueryFlight {
+    Leader(watch::Sender<QueryFlightResponse>),
+    Follower(watch::Receiver<QueryFlightResponse>),
+}
+
 #[derive(Debug, Clone)]
 pub struct AppState {
     db_pool:Arc<Mutex<Vec<DbUserPass>>>,
@@ -26,11 +95,37 @@ pub struct AppState {
     shutting_down: Arc<RwLock<bool>>,
     site_matrix: Value,
     main_page: String,
-    local_testing: bool
+    local_testing: bool,
+    category_tree_cache: Arc<RwLock<CategoryTreeCache>>,
+    translations: Arc<Translations>,
+    presets: Arc<Presets>,
+    result_tokens: Arc<RwLock<ResultTokens>>,
+    /// query => (ETag of the last response, when that response was first sent)
+    response_etags: Arc<RwLock<HashMap<String, (String, DateTime<Utc>)>>>,
+    /// query string => response receiver, for queries that are currently running
+    queries_in_flight: Arc<RwLock<HashMap<String, watch::Receiver<QueryFlightResponse>>>>,
+    rate_limiter: Arc<RateLimiter>,
+    running_queries: Arc<RwLock<HashMap<u64, RunningQuery>>>,
+    next_running_query_id: Arc<AtomicU64>,
+    /// wiki => API, with the site info (namespaces etc.) already loaded
+    apis: Arc<RwLock<HashMap<String, Api>>>,
+    http_client: Arc<HttpClient>,
+    endpoints: Endpoints,
+    /// Message shown instead of running new queries; None if not in maintenance mode
+    maintenance: Arc<RwLock<Option<String>>>,
+    /// Answers the wiki database queries instead of the replicas, in tests
+    mock_db: Option<Arc<MockDatabase>>,
+    /// None if OAuth login is not configured
+    oauth: Option<Arc<OAuth>>,
 }
 
 impl AppState {
     pub async fn new_from_config(config: &Value) -> Self {
+        Self::new_from_config_and_site_matrix(config, AppState::load_site_matrix(config).await).await
+    }
+
+    /// As `new_from_config`, with a given site matrix (the result of action=sitematrix) instead of loading it, eg for tests
+    pub async fn new_from_config_and_site_matrix(config: &Value, site_matrix: Value) -> Self {
         let main_page_path = "./html/index.html";
         let tool_db_access_tuple = (
             config["user"]
@@ -42,20 +137,43 @@ impl AppState {
                 .expect("No password key in config file")
                 .to_string(),
         );
+        let http_client = Arc::new(HttpClient::new_from_config(config).expect("Could not create HTTP client"));
         let ret = Self {
             db_pool : Arc::new(Mutex::new(vec![])),
             config: config.to_owned(),
             local_testing: config["host"] == "127.0.0.1",
             threads_running: Arc::new(RwLock::new(0)),
             shutting_down: Arc::new(RwLock::new(false)),
-            site_matrix: AppState::load_site_matrix().await,
+            site_matrix,
             tool_db_mutex: Arc::new(Mutex::new(tool_db_access_tuple)),
             main_page: String::from_utf8_lossy(
                 &fs::read(main_page_path).expect("Could not read index.html file form disk"),
             )
             .parse()
             .expect("Parsing index.html failed"),
-
+            category_tree_cache: Arc::new(RwLock::new(HashMap::new())),
+            result_tokens: Arc::new(RwLock::new(HashMap::new())),
+            response_etags: Arc::new(RwLock::new(HashMap::new())),
+            queries_in_flight: Arc::new(RwLock::new(HashMap::new())),
+            rate_limiter: Arc::new(RateLimiter::new_from_config(config)),
+            running_queries: Arc::new(RwLock::new(HashMap::new())),
+            next_running_query_id: Arc::new(AtomicU64::new(1)),
+            apis: Arc::new(RwLock::new(HashMap::new())),
+            oauth: OAuth::new_from_config(config, http_client.clone()).map(Arc::new),
+            http_client,
+            endpoints: Endpoints::new_from_config(config),
+            maintenance: Arc::new(RwLock::new(Self::maintenance_message_from_config(config))),
+            mock_db: None,
+            translations: Arc::new(Translations::new_from_directory(
+                config["translations_path"]
+                    .as_str()
+                    .unwrap_or(DEFAULT_TRANSLATIONS_PATH),
+            )),
+            presets: Arc::new(Presets::new_from_file(
+                config["presets_path"]
+                    .as_str()
+                    .unwrap_or(DEFAULT_PRESETS_PATH),
+            )),
         };
 
         if let Some(up_list) = config["mysql"].as_array() {
@@ -84,6 +202,38 @@ impl AppState {
         ret
     }
 
+    /// Returns the time to wait, if `client` (an IP address) has run out of requests
+    pub fn check_rate_limit(&self, client: &str) -> Result<(), Duration> {
+        self.rate_limiter.check(client)
+    }
+
+    /// Request header with the user name, set by an authenticating proxy in front of PetScan; None if there is none
+    pub fn get_user_header(&self) -> Option<&str> {
+        self.config["user_header"].as_str().filter(|h| !h.is_empty())
+    }
+
+    /// Addresses of the proxies in front of PetScan, whose X-Forwarded-For hops are trusted; see RequestContext
+    pub fn get_trusted_proxies(&self) -> Vec<String> {
+        match self.config["trusted_proxies"].as_array() {
+            Some(proxies) => proxies
+                .iter()
+                .filter_map(|proxy| proxy.as_str())
+                .map(|proxy| proxy.trim().to_string())
+                .filter(|proxy| !proxy.is_empty())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// The PagePile API action that appends data to an existing pile, if the PagePile installation has one.
+    /// Without it, results are uploaded to PagePile in a single request.
+    pub fn get_pagepile_append_action(&self) -> Option<String> {
+        self.config["pagepile_append_action"]
+            .as_str()
+            .map(|action| action.trim().to_string())
+            .filter(|action| !action.is_empty())
+    }
+
     pub fn get_restart_code(&self) -> Option<&str> {
         self.config["restart-code"].as_str()
     }
@@ -105,6 +255,7 @@ impl AppState {
     }
 
     pub fn get_main_page(&self, interface_language: String) -> String {
+        let interface_language = Translations::sanitize_language(&interface_language);
         let direction = if self.is_language_rtl(&interface_language) {
             "rtl"
         } else {
@@ -113,9 +264,10 @@ impl AppState {
         let h = format!(
             "<html dir='{}' lang='{}'>",
             direction,
-            interface_language.replace("'", "")
+            interface_language
         );
         let ret = self.main_page.replace("<html>", &h) ;
+        let ret = self.localize_html(&interface_language, &ret);
         if self.is_language_rtl(&interface_language) {
             ret.replace("bootstrap.min.css","bootstrap-rtl.min.css")
         } else {
@@ -123,6 +275,162 @@ impl AppState {
         }
     }
 
+    /// Fills in interface messages in `interface_language`, with English fallback
+    pub fn localize_html(&self, interface_language: &str, html: &str) -> String {
+        self.translations.localize_html(interface_language, html)
+    }
+
+    /// A named parameter bundle from the presets file
+    pub fn get_preset(&self, name: &str) -> Option<&Preset> {
+        self.presets.get(name)
+    }
+
+    /// All presets, name => label
+    pub fn get_presets_json(&self) -> Value {
+        self.presets.as_json()
+    }
+
+    /// Maximum number of categories in a single expanded category tree
+    pub fn get_category_tree_max_nodes(&self) -> usize {
+        match self.config["category_tree_max_nodes"].as_u64() {
+            Some(max_nodes) => max_nodes as usize,
+            None => DEFAULT_CATEGORY_TREE_MAX_NODES,
+        }
+    }
+
+    /// How long expanded category trees are kept; 0 turns the cache off
+    fn get_category_tree_cache_ttl(&self) -> Duration {
+        let seconds = self.config["category_tree_cache_ttl"]
+            .as_u64()
+            .unwrap_or(DEFAULT_CATEGORY_TREE_CACHE_TTL_SECONDS);
+        Duration::from_secs(seconds)
+    }
+
+    pub fn get_cached_category_tree(&self, wiki: &str, root: &str, depth: u16, skip_hidden: bool) -> Option<Vec<String>> {
+        let cache = self.category_tree_cache.read().ok()?;
+        let (created, categories) = cache.get(&(wiki.to_string(), root.to_string(), depth, skip_hidden))?;
+        if created.elapsed() >= self.get_category_tree_cache_ttl() {
+            return None;
+        }
+        Some(categories.clone())
+    }
+
+    pub fn cache_category_tree(&self, wiki: &str, root: &str, depth: u16, skip_hidden: bool, categories: &[String]) {
+        let ttl = self.get_category_tree_cache_ttl();
+        if ttl.as_secs() == 0 {
+            return;
+        }
+        if let Ok(mut cache) = self.category_tree_cache.write() {
+            cache.retain(|_, (created, _)| created.elapsed() < ttl);
+            if cache.len() >= MAX_CATEGORY_TREE_CACHE_ENTRIES {
+                return;
+            }
+            cache.insert(
+                (wiki.to_string(), root.to_string(), depth, skip_hidden),
+                (Instant::now(), categories.to_vec()),
+            );
+        }
+    }
+
+    /// Templates that mark a page as not needing a Wikidata item, for the unconnected pages mode; from the config
+    /// ("unconnected_exclude_templates":{"enwiki":["Bots","Noitem"],"default":[...]}), or the defaults
+    pub fn get_unconnected_exclude_templates(&self, wiki: &str) -> Vec<String> {
+        let templates = &self.config["unconnected_exclude_templates"];
+        let templates = if templates[wiki].is_array() { &templates[wiki] } else { &templates["default"] };
+        match templates.as_array() {
+            Some(templates) => templates.iter().filter_map(|t| t.as_str()).map(|t| t.to_string()).collect(),
+            None => DEFAULT_UNCONNECTED_EXCLUDE_TEMPLATES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    /// Overall time limit for running a query, across sources and post-processing ("query_time_budget" in seconds;
+    /// 0 for no limit)
+    pub fn get_query_time_budget(&self) -> Option<Duration> {
+        match self.config["query_time_budget"]
+            .as_u64()
+            .unwrap_or(DEFAULT_QUERY_TIME_BUDGET_SECONDS)
+        {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+
+    /// How long a finished result can be downloaded again via its token; 0 disables result tokens
+    pub fn get_result_token_ttl(&self) -> Duration {
+        Duration::from_secs(
+            self.config["result_token_ttl"]
+                .as_u64()
+                .unwrap_or(DEFAULT_RESULT_TOKEN_TTL_SECONDS),
+        )
+    }
+
+    /// Keeps a finished result, and returns a token to retrieve it
+    pub fn store_result(&self, result: StoredResult) -> Option<String> {
+        let ttl = self.get_result_token_ttl();
+        if ttl.as_secs() == 0 {
+            return None;
+        }
+        let mut tokens = self.result_tokens.write().ok()?;
+        tokens.retain(|_, (created, _)| created.elapsed() < ttl);
+        if tokens.len() >= MAX_RESULT_TOKENS {
+            return None;
+        }
+        let token: String = (0..4).map(|_| format!("{:08x}", rand::random::<u32>())).collect();
+        tokens.insert(token.to_owned(), (Instant::now(), Arc::new(result)));
+        Some(token)
+    }
+
+    pub fn get_stored_result(&self, token: &str) -> Option<Arc<StoredResult>> {
+        let tokens = self.result_tokens.read().ok()?;
+        let (created, result) = tokens.get(token)?;
+        if created.elapsed() >= self.get_result_token_ttl() {
+            return None;
+        }
+        Some(result.clone())
+    }
+
+    /// When the response with `etag` was first sent for `query`; now, if the response has changed since
+    pub fn get_last_modified(&self, query: &str, etag: &str) -> DateTime<Utc> {
+        let now = Utc::now();
+        let mut etags = match self.response_etags.write() {
+            Ok(etags) => etags,
+            Err(_) => return now,
+        };
+        if let Some((known_etag, modified)) = etags.get(query) {
+            if known_etag == etag {
+                return *modified;
+            }
+        }
+        if etags.len() >= MAX_RESPONSE_ETAGS {
+            etags.clear();
+        }
+        etags.insert(query.to_string(), (etag.to_string(), now));
+        now
+    }
+
+    /// Joins an identical, running query if there is one, otherwise registers this one as running
+    pub fn join_query_flight(&self, query: &str) -> Option<QueryFlight> {
+        let mut in_flight = self.queries_in_flight.write().ok()?;
+        if let Some(receiver) = in_flight.get(query) {
+            // A closed sender means the leader went away without finishing, so take over
+            if receiver.has_changed().is_ok() {
+                return Some(QueryFlight::Follower(receiver.clone()));
+            }
+        }
+        let (sender, receiver) = watch::channel(None);
+        in_flight.insert(query.to_string(), receiver);
+        Some(QueryFlight::Leader(sender))
+    }
+
+    /// Hands the response of a finished query to all waiting followers
+    pub fn finish_query_flight(&self, query: &str, sender: watch::Sender<QueryFlightResponse>, response: &MyResponse) {
+        if let Ok(mut in_flight) = self.queries_in_flight.write() {
+            in_flight.remove(query);
+        }
+        // No receivers is fine
+        let _ = sender.send(Some(Arc::new(response.clone())));
+    }
+
     fn get_db_server_group(&self) -> &str {
         self.config["dbservergroup"].as_str().unwrap_or(".web.db.svc.eqiad.wmflabs")
     }
@@ -164,32 +472,85 @@ impl AppState {
         (host, schema)
     }
 
-    async fn set_group_concat_max_len(&self, wiki: &str, conn: &mut my::Conn) -> Result<(), String> {
+    /// Run on every new replica connection; they are part of the connection options, so a connection that is
+    /// re-established after it was lost (see `ReplicaConnection`) has them too
+    fn get_session_init_statements(&self, wiki: &str) -> Vec<String> {
+        let mut ret = vec![];
         if wiki == "commonswiki" {
-            conn.exec_drop("SET SESSION group_concat_max_len = 1000000000",()).await.map_err(|e|format!("{:?}",e))?;
+            ret.push("SET SESSION group_concat_max_len = 1000000000".to_string());
+        }
+        // The server stops queries running longer than the timeout (MariaDB max_statement_time), instead of them hanging
+        let timeout = self.get_db_query_timeout();
+        if timeout > 0 {
+            ret.push(format!("SET SESSION max_statement_time = {}", timeout));
+        }
+        ret
+    }
+
+    /// Maximum run time of a single replica query in seconds ("db_query_timeout" in the config; 0 for no limit)
+    fn get_db_query_timeout(&self) -> u64 {
+        self.config["db_query_timeout"]
+            .as_u64()
+            .unwrap_or(DEFAULT_DB_QUERY_TIMEOUT_SECONDS)
+    }
+
+    /// Turns database errors into messages for the user, where they are known
+    pub fn user_visible_db_error(error: String) -> String {
+        if error.contains("max_statement_time exceeded") || error.contains("code: 1969") {
+            "The database query took too long, and was stopped. Please try a smaller query, eg with less category depth.".to_string()
+        } else if error.contains("ConnectionClosed") || error.contains("Lost connection") {
+            "The connection to the database was lost. Please try again later.".to_string()
+        } else {
+            error
         }
-        Ok(())
     }
 
     fn is_local_testing(&self) -> bool {
         self.local_testing
     }
 
-    pub async fn get_wiki_db_connection(
+    /// Uses `db` instead of the replicas for all wiki database queries
+    pub fn with_mock_db(mut self, db: Arc<MockDatabase>) -> Self {
+        self.mock_db = Some(db);
+        self
+    }
+
+    /// A connection to the database of `wiki`; the mock database if one is set
+    pub async fn get_wiki_db(&self, wiki: &str) -> Result<Box<dyn WikiDbConnection>, String> {
+        match &self.mock_db {
+            Some(db) => Ok(Box::new(db.connection(wiki))),
+            None => Ok(Box::new(ReplicaConnection::new(self.get_wiki_db_connection(wiki).await?))),
+        }
+    }
+
+    async fn get_wiki_db_connection(
         &self,
         wiki: &str,
     ) -> Result<my::Conn, String> {
-        let mut pool = self.db_pool.lock().await;
-        if pool.is_empty() {
-            panic!("pool is empty");
+        // Dropped or refused connections are retried, with the next user from the pool
+        let mut last_error = String::new();
+        for attempt in 0..DB_CONNECT_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(Duration::from_millis(500 * 2u64.pow(attempt))).await;
+            }
+            let opts_builder = {
+                let mut pool = self.db_pool.lock().await;
+                if pool.is_empty() {
+                    panic!("pool is empty");
+                }
+                pool.rotate_left(1);
+                let last = pool.len()-1;
+                self.get_mysql_opts_for_wiki(wiki,&pool[last].0,&pool[last].1)?
+                    .init(self.get_session_init_statements(wiki))
+            };
+            let connect = tokio::time::timeout(Duration::from_secs(DB_CONNECT_TIMEOUT_SECONDS), my::Conn::new(opts_builder));
+            match connect.await {
+                Ok(Ok(conn)) => return Ok(conn),
+                Ok(Err(e)) => last_error = format!("{:?}",e),
+                Err(_) => last_error = format!("no connection after {} seconds",DB_CONNECT_TIMEOUT_SECONDS),
+            }
         }
-        pool.rotate_left(1);
-        let last = pool.len()-1;
-        let opts_builder = self.get_mysql_opts_for_wiki(wiki,&pool[last].0,&pool[last].1)?;
-        let conn = my::Conn::new(opts_builder).await;
-        let mut conn = conn.map_err(|e|format!("{:?}",e))? ;
-        self.set_group_concat_max_len(wiki,&mut conn).await?;
-        Ok(conn)
+        Err(format!("Could not connect to the database for {}: {}",wiki,last_error))
     }
 
     pub fn render_error(&self, error: String, form_parameters: &FormParameters) -> MyResponse {
@@ -210,6 +571,8 @@ impl AppState {
                 MyResponse {
                     s: html.to_string(),
                     content_type: ContentType::HTML,
+                    filename: None,
+                    partial_result: false,
                 }
             }
             Some("json") => {
@@ -219,6 +582,8 @@ impl AppState {
             _ => MyResponse {
                 s: error,
                 content_type: ContentType::Plain,
+                filename: None,
+                partial_result: false,
             },
         }
     }
@@ -234,21 +599,56 @@ impl AppState {
                 MyResponse {
                     s: text,
                     content_type: ContentType::JSONP,
+                    filename: None,
+                    partial_result: false,
                 }
             }
             None => MyResponse {
                 s: ::serde_json::to_string(&value)
                     .expect("app_state::output_json can't stringify JSON [2]"),
                 content_type: ContentType::JSON,
+                filename: None,
+                partial_result: false,
             },
         }
     }
 
+    /// The shared client for external HTTP calls
+    pub fn http_client(&self) -> Arc<HttpClient> {
+        self.http_client.clone()
+    }
+
+    /// API, SPARQL, LiftWing, and PagePile URLs, from the "endpoints" config
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    /// The API for a wiki; cached, so the site info is only loaded once per wiki
     pub async fn get_api_for_wiki(&self, wiki: String) -> Result<Api, String> {
-        // TODO cache url and/or api object?
-        let url = self.get_server_url_for_wiki(&wiki)? + "/w/api.php";
-        match Api::new(&url).await {
-            Ok(api) => Ok(api),
+        if let Some(api) = self.apis.read().map_err(|e| format!("{:?}", e))?.get(&wiki) {
+            return Ok(api.clone());
+        }
+        let api = self.get_uncached_api_for_wiki(wiki.to_owned()).await?;
+        self.apis
+            .write()
+            .map_err(|e| format!("{:?}", e))?
+            .insert(wiki, api.clone());
+        Ok(api)
+    }
+
+    /// The API URL of a wiki, eg "https://www.wikidata.org/w/api.php" for wikidatawiki
+    pub fn get_api_url_for_wiki(&self, wiki: &str) -> Result<String, String> {
+        Ok(self.endpoints.api_url(&self.get_server_url_for_wiki(wiki)?))
+    }
+
+    /// A new API object, eg for logging in without affecting the cached one
+    pub async fn get_uncached_api_for_wiki(&self, wiki: String) -> Result<Api, String> {
+        let url = self.get_api_url_for_wiki(&wiki)?;
+        match Api::new_from_builder(&url, self.http_client.client_builder()).await {
+            Ok(mut api) => {
+                api.set_user_agent(self.http_client.user_agent());
+                Ok(api)
+            }
             Err(e) => Err(format!("{:?}", e)),
         }
     }
@@ -303,6 +703,9 @@ impl AppState {
     }
 
     pub fn get_wiki_for_server_url(&self, url: &str) -> Option<String> {
+        if let Some(wiki) = self.endpoints.wiki_for_server_url(url) {
+            return Some(wiki);
+        }
         self.site_matrix["sitematrix"]
             .as_object()
             .expect("AppState::get_wiki_for_server_url: sitematrix not an object")
@@ -327,9 +730,25 @@ impl AppState {
     }
 
     pub fn get_server_url_for_wiki(&self, wiki: &str) -> Result<String, String> {
+        if let Some(server_url) = self.endpoints.server_url(wiki) {
+            return Ok(server_url);
+        }
+        self.get_site_matrix_url_for_wiki(wiki).ok_or(format!(
+            "AppState::get_server_url_for_wiki: Cannot find server for wiki '{}'",
+            &wiki
+        ))
+    }
+
+    /// Wikis in the Wikimedia site matrix have replicas; others (eg from "endpoints") only the API.
+    /// The mock database stands in for all wikis.
+    pub fn has_replica_db(&self, wiki: &str) -> bool {
+        self.mock_db.is_some() || self.get_site_matrix_url_for_wiki(wiki).is_some()
+    }
+
+    fn get_site_matrix_url_for_wiki(&self, wiki: &str) -> Option<String> {
         match wiki.replace("_", "-").as_str() {
             "be-taraskwiki" | "be-x-oldwiki" => {
-                return Ok("https://be-tarask.wikipedia.org".to_string())
+                return Some("https://be-tarask.wikipedia.org".to_string())
             }
             _ => {}
         }
@@ -354,10 +773,6 @@ impl AppState {
                 },
             })
             .next()
-            .ok_or(format!(
-                "AppState::get_server_url_for_wiki: Cannot find server for wiki '{}'",
-                &wiki
-            ))
     }
 
     pub async fn get_tool_db_connection(
@@ -447,6 +862,281 @@ impl AppState {
             .map_err(|e|format!("{:?}",e))
     }
 
+    /// Query audit logging is enabled in the config ("query_log":true), and not opted out of by the query ("nolog")
+    pub fn is_query_audit_log_enabled(&self, form_parameters: &FormParameters) -> bool {
+        self.config["query_log"].as_bool().unwrap_or(false)
+            && !form_parameters.params.contains_key("nolog")
+    }
+
+    /// Logs a finished query to the `query_log` table:
+    /// CREATE TABLE `query_log` (`id` int unsigned NOT NULL AUTO_INCREMENT PRIMARY KEY, `querystring` mediumtext NOT NULL,
+    /// `created` datetime NOT NULL, `duration_ms` int unsigned NOT NULL, `result_count` int unsigned DEFAULT NULL,
+    /// `status` varchar(16) NOT NULL, `client_ip` varchar(64) NOT NULL DEFAULT '', `user_agent` varchar(255) DEFAULT NULL,
+    /// `user` varchar(255) DEFAULT NULL, KEY `created` (`created`))
+    pub async fn log_query_audit(
+        &self,
+        form_parameters: &FormParameters,
+        context: &RequestContext,
+        duration: Duration,
+        result_count: Option<usize>,
+        status: &str,
+    ) -> Result<(), String> {
+        if !self.is_query_audit_log_enabled(form_parameters) {
+            return Ok(());
+        }
+        let utc: DateTime<Utc> = Utc::now();
+        let now = utc.format("%Y-%m-%d %H:%M:%S").to_string();
+        let sql = (
+            "INSERT INTO `query_log` (querystring,created,duration_ms,result_count,status,client_ip,user_agent,user) VALUES (?,?,?,?,?,?,?,?)",
+            vec![
+                MyValue::Bytes(form_parameters.canonical_query().into()),
+                MyValue::Bytes(now.into()),
+                MyValue::UInt(duration.as_millis() as u64),
+                match result_count {
+                    Some(count) => MyValue::UInt(count as u64),
+                    None => MyValue::NULL,
+                },
+                MyValue::Bytes(status.to_owned().into()),
+                MyValue::Bytes(context.client_ip.to_owned().into()),
+                match &context.user_agent {
+                    Some(user_agent) => MyValue::Bytes(user_agent.to_owned().into()),
+                    None => MyValue::NULL,
+                },
+                match &context.user {
+                    Some(user) => MyValue::Bytes(user.to_owned().into()),
+                    None => MyValue::NULL,
+                },
+            ],
+        );
+        let tool_db_user_pass = self.tool_db_mutex.lock().await;
+        self.get_tool_db_connection(tool_db_user_pass.clone())
+            .await?
+            .exec_drop(sql.0, mysql_async::Params::Positional(sql.1))
+            .await
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    /// Summary of logged queries over the last `days`: (querystring, runs, average duration in ms, failed runs), most popular first
+    pub async fn get_query_log_stats(&self, days: i64, limit: u64) -> Result<Vec<(String, u64, u64, u64)>, String> {
+        let since = (Utc::now() - chrono::Duration::days(days)).format("%Y-%m-%d %H:%M:%S").to_string();
+        let sql = (
+            "SELECT querystring,COUNT(*),CAST(ROUND(AVG(duration_ms)) AS UNSIGNED),CAST(SUM(status!='ok') AS UNSIGNED) FROM `query_log` WHERE created>=? GROUP BY querystring ORDER BY COUNT(*) DESC LIMIT ?",
+            vec![MyValue::Bytes(since.into()), MyValue::UInt(limit)],
+        );
+        let tool_db_user_pass = self.tool_db_mutex.lock().await;
+        let mut conn = self.get_tool_db_connection(tool_db_user_pass.clone()).await?;
+        conn.exec_iter(sql.0, mysql_async::Params::Positional(sql.1))
+            .await
+            .map_err(|e| format!("{:?}", e))?
+            .map_and_drop(from_row::<(String, u64, u64, u64)>)
+            .await
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    pub fn get_oauth(&self) -> Option<Arc<OAuth>> {
+        self.oauth.clone()
+    }
+
+    /// The OAuth session of the user who sent the request, if they are logged in
+    pub fn get_oauth_session(&self, context: &RequestContext) -> Option<OAuthSession> {
+        self.oauth.as_ref()?.get_session(context.session_id.as_ref()?)
+    }
+
+    /// The bot account for wiki edits, from the config ("wiki_bot":{"user":"...","password":"..."})
+    pub fn get_wiki_bot_account(&self) -> Option<(String, String)> {
+        let user = self.config["wiki_bot"]["user"].as_str()?;
+        let password = self.config["wiki_bot"]["password"].as_str()?;
+        if user.is_empty() || password.is_empty() {
+            return None;
+        }
+        Some((user.to_string(), password.to_string()))
+    }
+
+    /// Scheduled query runs are enabled in the config ("scheduler":true)
+    pub fn is_scheduler_enabled(&self) -> bool {
+        self.config["scheduler"].as_bool().unwrap_or(false)
+    }
+
+    /// The sendmail binary for scheduled query emails; None if not configured
+    pub fn get_sendmail_path(&self) -> Option<String> {
+        match self.config["sendmail"].as_str() {
+            Some(path) if !path.is_empty() => Some(path.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Registers a scheduled query in the `scheduled_query` table, returns its ID:
+    /// CREATE TABLE `scheduled_query` (`id` int unsigned NOT NULL AUTO_INCREMENT PRIMARY KEY, `psid` int unsigned NOT NULL,
+    /// `interval_days` tinyint unsigned NOT NULL, `last_run` datetime DEFAULT NULL, `snapshot` mediumtext DEFAULT NULL,
+    /// `report_wiki` varchar(64) NOT NULL DEFAULT '', `report_page` varchar(255) NOT NULL DEFAULT '',
+    /// `email` varchar(255) NOT NULL DEFAULT '', `user` varchar(255) NOT NULL, KEY `last_run` (`last_run`), KEY `user` (`user`))
+    pub async fn add_scheduled_query(&self, sq: &ScheduledQuery) -> Result<u64, String> {
+        let sql = (
+            "INSERT INTO `scheduled_query` (psid,interval_days,report_wiki,report_page,email,user) VALUES (?,?,?,?,?,?)",
+            vec![
+                MyValue::UInt(sq.psid),
+                MyValue::UInt(sq.interval.days()),
+                MyValue::Bytes(sq.report_wiki.to_owned().into()),
+                MyValue::Bytes(sq.report_page.to_owned().into()),
+                MyValue::Bytes(sq.email.to_owned().into()),
+                MyValue::Bytes(sq.user.to_owned().into()),
+            ],
+        );
+        let tool_db_user_pass = self.tool_db_mutex.lock().await;
+        let mut conn = self.get_tool_db_connection(tool_db_user_pass.clone()).await?;
+        conn.exec_drop(sql.0, mysql_async::Params::Positional(sql.1))
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        conn.last_insert_id().ok_or_else(|| "No ID for new scheduled query".to_string())
+    }
+
+    /// Scheduled queries that have never run, or not within their interval
+    pub async fn get_due_scheduled_queries(&self) -> Result<Vec<ScheduledQuery>, String> {
+        let now = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let sql = (
+            "SELECT id,psid,interval_days,snapshot,report_wiki,report_page,email,user FROM `scheduled_query` WHERE last_run IS NULL OR last_run<=DATE_SUB(?,INTERVAL interval_days DAY)",
+            vec![MyValue::Bytes(now.into())],
+        );
+        let tool_db_user_pass = self.tool_db_mutex.lock().await;
+        let mut conn = self.get_tool_db_connection(tool_db_user_pass.clone()).await?;
+        let rows = conn
+            .exec_iter(sql.0, mysql_async::Params::Positional(sql.1))
+            .await
+            .map_err(|e| format!("{:?}", e))?
+            .map_and_drop(from_row::<(u64, u64, u64, Option<String>, String, String, String, String)>)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, psid, days, snapshot, report_wiki, report_page, email, user)| ScheduledQuery {
+                id,
+                psid,
+                interval: ScheduleInterval::from_days(days),
+                snapshot: snapshot.map(|s| s.lines().map(|l| l.to_string()).filter(|l| !l.is_empty()).collect()),
+                report_wiki,
+                report_page,
+                email,
+                user,
+            })
+            .collect())
+    }
+
+    /// Stores the latest result titles of a scheduled query, and marks it as run now
+    pub async fn set_scheduled_query_snapshot(&self, id: u64, titles: &[String]) -> Result<(), String> {
+        let now = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let sql = (
+            "UPDATE `scheduled_query` SET last_run=?,snapshot=? WHERE id=?",
+            vec![
+                MyValue::Bytes(now.into()),
+                MyValue::Bytes(titles.join("\n").into()),
+                MyValue::UInt(id),
+            ],
+        );
+        let tool_db_user_pass = self.tool_db_mutex.lock().await;
+        self.get_tool_db_connection(tool_db_user_pass.clone())
+            .await?
+            .exec_drop(sql.0, mysql_async::Params::Positional(sql.1))
+            .await
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    /// Registers a query run; returns the ID to unregister, or kill, it
+    pub fn register_running_query(
+        &self,
+        query: &str,
+        context: &RequestContext,
+        stage: Progress,
+        progress_id: Option<String>,
+        abort_handle: AbortHandle,
+    ) -> u64 {
+        let id = self.next_running_query_id.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut running) = self.running_queries.write() {
+            running.insert(
+                id,
+                RunningQuery {
+                    query: query.to_string(),
+                    client: context.description(),
+                    started: Instant::now(),
+                    stage,
+                    progress_id,
+                    abort_handle,
+                },
+            );
+        }
+        id
+    }
+
+    pub fn unregister_running_query(&self, id: u64) {
+        if let Ok(mut running) = self.running_queries.write() {
+            running.remove(&id);
+        }
+    }
+
+    /// Running queries, oldest first
+    pub fn get_running_queries(&self) -> Vec<(u64, RunningQuery)> {
+        let mut ret: Vec<(u64, RunningQuery)> = match self.running_queries.read() {
+            Ok(running) => running.iter().map(|(id, rq)| (*id, rq.clone())).collect(),
+            Err(_) => vec![],
+        };
+        ret.sort_by_key(|(id, _)| *id);
+        ret
+    }
+
+    /// The current stage of the running query with this `progress_id`; None if there is none (any more)
+    pub fn get_query_progress(&self, progress_id: &str) -> Option<String> {
+        self.running_queries
+            .read()
+            .ok()?
+            .values()
+            .find(|rq| rq.progress_id.as_deref() == Some(progress_id))
+            .map(|rq| rq.stage.get())
+    }
+
+    /// Aborts a running query; returns false if there is no such query
+    pub fn kill_running_query(&self, id: u64) -> bool {
+        match self.running_queries.read() {
+            Ok(running) => match running.get(&id) {
+                Some(rq) => {
+                    rq.abort_handle.abort();
+                    true
+                }
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// The URL source only fetches via https, from allowed hosts or their subdomains
+    pub fn is_source_url_allowed(&self, url: &reqwest::Url) -> bool {
+        if url.scheme() != "https" {
+            return false;
+        }
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        let allowed: Vec<String> = match self.config["source_url_allowed_hosts"].as_array() {
+            Some(hosts) => hosts.iter().filter_map(|h| h.as_str()).map(|h| h.to_lowercase()).collect(),
+            None => DEFAULT_SOURCE_URL_ALLOWED_HOSTS.iter().map(|h| h.to_string()).collect(),
+        };
+        allowed
+            .iter()
+            .any(|a| host == *a || host.ends_with(&format!(".{}", a)))
+    }
+
+    /// Admin pages need the "admin_token" from the config; no token configured means no admin access
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        match self.config["admin_token"].as_str() {
+            Some(admin_token) => !admin_token.is_empty() && Self::constant_time_eq(admin_token.as_bytes(), token.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Takes the same time for all tokens of the same length, so the admin token can not be guessed by timing
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+
     pub async fn get_or_create_psid_for_query(&self, query_string: &str) -> Result<u64, String> {
         let tool_db_user_pass = self.tool_db_mutex.lock().await;
         let mut conn = self.get_tool_db_connection(tool_db_user_pass.clone()).await?;
@@ -480,9 +1170,13 @@ impl AppState {
         }
     }
 
-    async fn load_site_matrix() -> Value {
-        let api =
-            Api::new("https://www.wikidata.org/w/api.php").await.expect("Can't talk to Wikidata API");
+    /// From the Wikidata API, or the one of the "endpoints" server for wikidatawiki
+    async fn load_site_matrix(config: &Value) -> Value {
+        let endpoints = Endpoints::new_from_config(config);
+        let server_url = endpoints
+            .server_url("wikidatawiki")
+            .unwrap_or_else(|| SITE_MATRIX_SERVER.to_string());
+        let api = Api::new(&endpoints.api_url(&server_url)).await.expect("Can't talk to Wikidata API");
         let params: HashMap<String, String> = vec![("action", "sitematrix")]
             .par_iter()
             .map(|(k, v)| (k.to_string(), v.to_string()))
@@ -517,6 +1211,35 @@ impl AppState {
     pub fn shut_down(&self) {
         if let Ok(mut sd) = self.shutting_down.write() { *sd = true ; }
     }
+
+    /// "maintenance" in the config is either true, or a message for the users
+    fn maintenance_message_from_config(config: &Value) -> Option<String> {
+        match &config["maintenance"] {
+            Value::Bool(true) => Some(DEFAULT_MAINTENANCE_MESSAGE.to_string()),
+            Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            _ => None,
+        }
+    }
+
+    /// In maintenance mode, new queries are rejected with this message; running ones can finish
+    pub fn get_maintenance_message(&self) -> Option<String> {
+        self.maintenance.read().ok()?.clone()
+    }
+
+    /// Turns maintenance mode on (with an optional message) or off
+    pub fn set_maintenance(&self, enabled: bool, message: &str) {
+        if let Ok(mut maintenance) = self.maintenance.write() {
+            *maintenance = match (enabled, message.trim()) {
+                (false, _) => None,
+                (true, "") => Some(DEFAULT_MAINTENANCE_MESSAGE.to_string()),
+                (true, message) => Some(message.to_string()),
+            };
+        }
+    }
+
+    pub fn get_threads_running(&self) -> i64 {
+        self.threads_running.read().map(|tr| *tr).unwrap_or(0)
+    }
 }
 
 #[cfg(test)]
@@ -582,6 +1305,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn constant_time_eq() {
+        assert!(AppState::constant_time_eq(b"secret", b"secret"));
+        assert!(!AppState::constant_time_eq(b"secret", b"secreT"));
+        assert!(!AppState::constant_time_eq(b"secret", b"secret2"));
+        assert!(!AppState::constant_time_eq(b"secret", b""));
+    }
+
+    #[test]
+    fn maintenance_message_from_config() {
+        assert_eq!(AppState::maintenance_message_from_config(&json!({})), None);
+        assert_eq!(AppState::maintenance_message_from_config(&json!({"maintenance":false})), None);
+        assert_eq!(
+            AppState::maintenance_message_from_config(&json!({"maintenance":true})),
+            Some(DEFAULT_MAINTENANCE_MESSAGE.to_string())
+        );
+        assert_eq!(
+            AppState::maintenance_message_from_config(&json!({"maintenance":" Back at 12:00 "})),
+            Some("Back at 12:00".to_string())
+        );
+    }
+
+    #[test]
+    fn user_visible_db_error() {
+        let e = "Server(ServerError { code: 1969, message: \"Query execution was interrupted (max_statement_time exceeded)\", state: \"70100\" })";
+        assert!(AppState::user_visible_db_error(e.to_string()).starts_with("The database query took too long"));
+        assert_eq!(AppState::user_visible_db_error("Other".to_string()), "Other");
+    }
+
     #[tokio::test]
     async fn is_language_rtl() {
         let state = get_state().await;
@@ -0,0 +1,147 @@
+//! Runs a single PetScan query without the web server, eg from cron:
+//!
+//! petscan-cli [--config config.json] [--format tsv] [--output FILE] (QUERY_STRING | --json QUERY.json)
+//!
+//! The query is a parameter string as in a PetScan URL, or a JSON file as written by the query export.
+//! The result is written to stdout, or to the output file.
+
+use petscan_rs::{AppState, FormParameters, Platform};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::process::exit;
+use std::sync::Arc;
+
+static DEFAULT_FORMAT: &str = "tsv";
+static USAGE: &str =
+    "Usage: petscan-cli [--config config.json] [--format FORMAT] [--output FILE] (QUERY_STRING | --json QUERY.json)";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Query {
+    Parameters(String),
+    JsonFile(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CliArgs {
+    config: String,
+    format: Option<String>,
+    output: Option<String>,
+    query: Query,
+}
+
+impl CliArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut config = "./config.json".to_string();
+        let mut format = None;
+        let mut output = None;
+        let mut query = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--config" => config = value()?,
+                "--format" => format = Some(value()?),
+                "--output" => output = Some(value()?),
+                "--json" => query = Some(Query::JsonFile(value()?)),
+                other if other.starts_with("--") => return Err(format!("Unknown option {}", other)),
+                other => query = Some(Query::Parameters(other.trim_start_matches('?').to_string())),
+            }
+        }
+        Ok(Self {
+            config,
+            format,
+            output,
+            query: query.ok_or_else(|| "No query given".to_string())?,
+        })
+    }
+
+    fn form_parameters(&self) -> Result<FormParameters, String> {
+        let mut ret = match &self.query {
+            Query::Parameters(query) => FormParameters::new_from_query(query),
+            Query::JsonFile(path) => FormParameters::new_from_query_json(&read_json(path)?)?,
+        };
+        match &self.format {
+            Some(format) => ret.set_param("format", format),
+            None if !ret.params.contains_key("format") => ret.set_param("format", DEFAULT_FORMAT),
+            None => {}
+        }
+        Ok(ret)
+    }
+}
+
+fn read_json(path: &str) -> Result<Value, String> {
+    let file = File::open(path).map_err(|e| format!("Can not open {}: {}", path, e))?;
+    serde_json::from_reader(file).map_err(|e| format!("Can not parse JSON from {}: {}", path, e))
+}
+
+async fn run(args: &CliArgs) -> Result<String, String> {
+    let config = read_json(&args.config)?;
+    let state = Arc::new(AppState::new_from_config(&config).await);
+    let mut platform = Platform::new_from_parameters(&args.form_parameters()?, state);
+    platform.run().await?;
+    for warning in platform.warnings()? {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(platform.get_response().await?.s)
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args = match CliArgs::parse(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+    let output = match run(&args).await {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    let written = match &args.output {
+        Some(path) => File::create(path).and_then(|mut file| file.write_all(output.as_bytes())),
+        None => std::io::stdout().write_all(output.as_bytes()),
+    };
+    if let Err(e) = written {
+        eprintln!("Can not write result: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let cli = CliArgs::parse(&args(&["--format", "json", "?language=en&categories=Physics"])).unwrap();
+        assert_eq!(cli.query, Query::Parameters("language=en&categories=Physics".to_string()));
+        assert_eq!(cli.form_parameters().unwrap().params.get("format"), Some(&"json".to_string()));
+        assert_eq!(cli.config, "./config.json");
+
+        let cli = CliArgs::parse(&args(&["--json", "q.json", "--output", "out.tsv"])).unwrap();
+        assert_eq!(cli.query, Query::JsonFile("q.json".to_string()));
+        assert_eq!(cli.output, Some("out.tsv".to_string()));
+
+        assert!(CliArgs::parse(&args(&[])).is_err());
+        assert!(CliArgs::parse(&args(&["--format"])).is_err());
+        assert!(CliArgs::parse(&args(&["--verbose", "a=b"])).is_err());
+    }
+
+    #[test]
+    fn default_format() {
+        let cli = CliArgs::parse(&args(&["categories=Physics"])).unwrap();
+        assert_eq!(cli.form_parameters().unwrap().params.get("format"), Some(&"tsv".to_string()));
+        let cli = CliArgs::parse(&args(&["categories=Physics&format=wiki"])).unwrap();
+        assert_eq!(cli.form_parameters().unwrap().params.get("format"), Some(&"wiki".to_string()));
+    }
+}
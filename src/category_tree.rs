@@ -0,0 +1,303 @@
+use crate::app_state::AppState;
+use crate::datasource::SQLtuple;
+use crate::graph::Graph;
+use crate::platform::{Platform, PAGE_BATCH_SIZE};
+use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+pub static DEFAULT_CATEGORY_TREE_MAX_NODES: usize = 250000;
+
+/// A subcategory, and the category it was first reached from, `depth` levels below the root
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryTreeEdge {
+    pub parent: String,
+    pub child: String,
+    pub depth: u16,
+}
+
+/// The categories that were actually traversed, to show why pages are in a result
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CategoryTraversal {
+    pub roots: Vec<String>,
+    pub edges: Vec<CategoryTreeEdge>,
+}
+
+impl CategoryTraversal {
+    /// Categories as nodes (with spaces), parent->child edges labelled with the depth
+    pub fn to_graph(&self) -> Graph {
+        let mut graph = Graph::new();
+        self.roots.iter().for_each(|root| {
+            graph.add_node(&root.replace('_', " "));
+        });
+        self.edges.iter().for_each(|edge| {
+            graph.add_labelled_edge(
+                &edge.parent.replace('_', " "),
+                &edge.child.replace('_', " "),
+                &edge.depth.to_string(),
+            )
+        });
+        graph
+    }
+}
+
+/// Expands category trees on a single wiki, breadth-first.
+/// Subcategory lists are kept, so several trees (eg positive and negative categories) can share the database work.
+#[derive(Debug)]
+pub struct CategoryTree {
+    wiki: String,
+    max_nodes: usize,
+    skip_hidden: bool,
+    children: RwLock<HashMap<String, Vec<String>>>,
+    truncated: RwLock<bool>,
+    traversal: Option<RwLock<CategoryTraversal>>,
+}
+
+impl CategoryTree {
+    /// With `skip_hidden`, hidden (maintenance) categories are not traversed
+    pub fn new(wiki: &str, max_nodes: usize, skip_hidden: bool) -> Self {
+        Self {
+            wiki: wiki.to_string(),
+            max_nodes,
+            skip_hidden,
+            children: RwLock::new(HashMap::new()),
+            truncated: RwLock::new(false),
+            traversal: None,
+        }
+    }
+
+    /// Also records which subcategory was reached from which category; cached trees are then not used, as they do
+    /// not have that
+    pub fn record_traversal(mut self) -> Self {
+        self.traversal = Some(RwLock::new(CategoryTraversal::default()));
+        self
+    }
+
+    /// The traversed trees so far, if recorded
+    pub fn traversal(&self) -> Option<CategoryTraversal> {
+        self.traversal.as_ref()?.read().ok().map(|t| t.clone())
+    }
+
+    pub fn wiki(&self) -> &String {
+        &self.wiki
+    }
+
+    /// Returns true if any tree was cut short because it hit the node limit
+    pub fn is_truncated(&self) -> bool {
+        match self.truncated.read() {
+            Ok(t) => *t,
+            _ => false,
+        }
+    }
+
+    /// Returns the root category and all subcategories down to `depth`; `root` needs to be normalized
+    pub async fn expand(&self, state: &AppState, root: &str, depth: u16) -> Result<Vec<String>, String> {
+        if let Some(traversal) = &self.traversal {
+            let mut traversal = traversal.write().map_err(|e| format!("{:?}", e))?;
+            if !traversal.roots.iter().any(|r| r == root) {
+                traversal.roots.push(root.to_string());
+            }
+        }
+        if depth > 0 && self.traversal.is_none() {
+            if let Some(categories) = state.get_cached_category_tree(&self.wiki, root, depth, self.skip_hidden) {
+                return Ok(categories);
+            }
+        }
+        let mut is_complete = true;
+        let mut visited: HashSet<String> = HashSet::new();
+        visited.insert(root.to_string());
+        let mut level = vec![root.to_string()];
+        let mut depth_left = depth;
+        while depth_left > 0 && !level.is_empty() {
+            Platform::profile("CategoryTree::expand level", Some(level.len()));
+            self.load_children(state, &level).await?;
+            let (next_level, truncated) = {
+                let children = self.children.read().map_err(|e| format!("{:?}", e))?;
+                // All trees on this wiki together may not load more than max_nodes categories either
+                if children.len() > self.max_nodes {
+                    (vec![], true)
+                } else {
+                    let (next_level, truncated) = Self::walk_level(&children, &level, &mut visited, self.max_nodes);
+                    self.record_level(&children, &level, &next_level, depth - depth_left + 1)?;
+                    (next_level, truncated)
+                }
+            };
+            if truncated {
+                *self.truncated.write().map_err(|e| format!("{:?}", e))? = true;
+                is_complete = false;
+                break;
+            }
+            level = next_level;
+            depth_left -= 1;
+        }
+        let ret: Vec<String> = visited.drain().collect();
+        if depth > 0 && is_complete {
+            state.cache_category_tree(&self.wiki, root, depth, self.skip_hidden, &ret);
+        }
+        Ok(ret)
+    }
+
+    /// Collects the not-yet-seen subcategories of one level.
+    /// Categories already in `visited` are skipped, which also takes care of cycles.
+    fn walk_level(
+        children: &HashMap<String, Vec<String>>,
+        level: &[String],
+        visited: &mut HashSet<String>,
+        max_nodes: usize,
+    ) -> (Vec<String>, bool) {
+        let mut next_level: Vec<String> = vec![];
+        for category in level {
+            let subcats = match children.get(category) {
+                Some(subcats) => subcats,
+                None => continue,
+            };
+            for subcat in subcats {
+                if visited.len() >= max_nodes {
+                    return (next_level, true);
+                }
+                if visited.insert(subcat.to_owned()) {
+                    next_level.push(subcat.to_owned());
+                }
+            }
+        }
+        (next_level, false)
+    }
+
+    /// Records, for each category of `next_level`, the first category of `level` it was reached from
+    fn record_level(
+        &self,
+        children: &HashMap<String, Vec<String>>,
+        level: &[String],
+        next_level: &[String],
+        depth: u16,
+    ) -> Result<(), String> {
+        let traversal = match &self.traversal {
+            Some(traversal) => traversal,
+            None => return Ok(()),
+        };
+        let mut traversal = traversal.write().map_err(|e| format!("{:?}", e))?;
+        let mut unreached: HashSet<&String> = next_level.iter().collect();
+        for parent in level {
+            for child in children.get(parent).into_iter().flatten() {
+                if unreached.remove(child) {
+                    traversal.edges.push(CategoryTreeEdge {
+                        parent: parent.to_owned(),
+                        child: child.to_owned(),
+                        depth,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the subcategories for all categories in `level` that were not loaded before
+    async fn load_children(&self, state: &AppState, level: &[String]) -> Result<(), String> {
+        let missing: Vec<String> = {
+            let children = self.children.read().map_err(|e| format!("{:?}", e))?;
+            level
+                .iter()
+                .filter(|c| !children.contains_key(*c))
+                .cloned()
+                .collect()
+        };
+        let mut futures = vec![];
+        for batch in missing.chunks(PAGE_BATCH_SIZE) {
+            futures.push(self.load_children_batch(state, batch.to_vec()));
+        }
+        for result in join_all(futures).await {
+            result?;
+        }
+        Ok(())
+    }
+
+    async fn load_children_batch(&self, state: &AppState, batch: Vec<String>) -> Result<(), String> {
+        let mut sql : SQLtuple = ("SELECT DISTINCT cl_to,page_title FROM page,categorylinks WHERE cl_from=page_id AND cl_type='subcat' AND cl_to IN (".to_string(),vec![]);
+        Platform::append_sql(&mut sql, Platform::prep_quote(&batch));
+        sql.0 += ")";
+        if self.skip_hidden {
+            sql.0 += " AND NOT EXISTS (SELECT * FROM page_props WHERE pp_page=page_id AND pp_propname='hiddencat')";
+        }
+
+        let mut conn = state.get_wiki_db(&self.wiki).await?;
+        let rows = conn.query_as::<(Vec<u8>,Vec<u8>)>(sql).await?;
+        conn.close().await?;
+
+        let mut children = self.children.write().map_err(|e| format!("{:?}", e))?;
+        // Categories without subcategories get an empty list, so they are not queried again
+        for category in batch {
+            children.entry(category).or_insert_with(Vec::new);
+        }
+        for (parent, child) in rows {
+            let parent = String::from_utf8_lossy(&parent).into_owned();
+            let child = String::from_utf8_lossy(&child).into_owned();
+            children.entry(parent).or_insert_with(Vec::new).push(child);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn children_from(edges: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut ret: HashMap<String, Vec<String>> = HashMap::new();
+        for (parent, child) in edges {
+            ret.entry(parent.to_string())
+                .or_insert_with(Vec::new)
+                .push(child.to_string());
+        }
+        ret
+    }
+
+    #[test]
+    fn walk_level_skips_cycles() {
+        let children = children_from(&[("A", "B"), ("B", "C"), ("C", "A"), ("B", "A")]);
+        let mut visited: HashSet<String> = vec!["A".to_string()].into_iter().collect();
+        let (level, truncated) = CategoryTree::walk_level(&children, &["A".to_string()], &mut visited, 100);
+        assert_eq!(level, vec!["B".to_string()]);
+        assert!(!truncated);
+        let (level, truncated) = CategoryTree::walk_level(&children, &level, &mut visited, 100);
+        assert_eq!(level, vec!["C".to_string()]);
+        assert!(!truncated);
+        let (level, truncated) = CategoryTree::walk_level(&children, &level, &mut visited, 100);
+        assert!(level.is_empty());
+        assert!(!truncated);
+        assert_eq!(visited.len(), 3);
+    }
+
+    #[test]
+    fn walk_level_node_limit() {
+        let children = children_from(&[("A", "B"), ("A", "C"), ("A", "D")]);
+        let mut visited: HashSet<String> = vec!["A".to_string()].into_iter().collect();
+        let (level, truncated) = CategoryTree::walk_level(&children, &["A".to_string()], &mut visited, 3);
+        assert_eq!(level, vec!["B".to_string(), "C".to_string()]);
+        assert!(truncated);
+        assert_eq!(visited.len(), 3);
+    }
+
+    #[test]
+    fn record_level() {
+        let children = children_from(&[("A", "B"), ("A", "C"), ("C", "B"), ("C", "D_E")]);
+        let tree = CategoryTree::new("enwiki", 100, false);
+        tree.record_level(&children, &["A".to_string()], &["B".to_string()], 1).unwrap();
+        assert_eq!(tree.traversal(), None);
+
+        let tree = CategoryTree::new("enwiki", 100, false).record_traversal();
+        let level = vec!["A".to_string()];
+        let mut visited: HashSet<String> = level.iter().cloned().collect();
+        let (level2, _) = CategoryTree::walk_level(&children, &level, &mut visited, 100);
+        tree.record_level(&children, &level, &level2, 1).unwrap();
+        let (level3, _) = CategoryTree::walk_level(&children, &level2, &mut visited, 100);
+        tree.record_level(&children, &level2, &level3, 2).unwrap();
+        let traversal = tree.traversal().unwrap();
+        let edges: Vec<(&str, &str, u16)> = traversal
+            .edges
+            .iter()
+            .map(|e| (e.parent.as_str(), e.child.as_str(), e.depth))
+            .collect();
+        assert_eq!(edges, vec![("A", "B", 1), ("A", "C", 1), ("C", "D_E", 2)]);
+        assert!(traversal.to_graph().to_dot().contains("[label=\"D E\"]"));
+    }
+}
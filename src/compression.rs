@@ -0,0 +1,124 @@
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::Write;
+
+/// Smaller responses are sent as they are; compression would not save much
+pub static MIN_COMPRESSED_BYTES: usize = 1024;
+static COMPRESSIBLE_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/vnd.google-earth.kml+xml",
+    "application/graphml+xml",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// The encoding the client prefers, from an Accept-Encoding header; gzip if both are equally acceptable
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+        for part in accept_encoding.split(',') {
+            let mut params = part.split(';').map(|p| p.trim());
+            let encoding = match params.next().map(|e| e.to_lowercase()).as_deref() {
+                Some("gzip") | Some("x-gzip") | Some("*") => Self::Gzip,
+                Some("deflate") => Self::Deflate,
+                _ => continue,
+            };
+            let quality = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            let is_better = match best {
+                Some((best_encoding, best_quality)) => {
+                    quality > best_quality || (quality == best_quality && encoding == Self::Gzip && best_encoding != Self::Gzip)
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let ret = match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+        };
+        ret.map_err(|e| format!("Compression: {}", e))
+    }
+}
+
+/// Text outputs (JSON, CSV, wikitext etc.) are compressed; images and other binary data are not, nor are streamed
+/// server-sent events
+pub fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_lowercase();
+    if content_type.starts_with("text/event-stream") {
+        return false;
+    }
+    COMPRESSIBLE_CONTENT_TYPES
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(ContentEncoding::negotiate("gzip, deflate, br"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::negotiate("deflate, gzip"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::negotiate("gzip;q=0.5, deflate"), Some(ContentEncoding::Deflate));
+        assert_eq!(ContentEncoding::negotiate("gzip;q=0, *;q=0"), None);
+        assert_eq!(ContentEncoding::negotiate("*"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::negotiate("br, identity"), None);
+        assert_eq!(ContentEncoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn compress() {
+        let data = "Magnus_Manske\t1234\n".repeat(1000);
+        let compressed = ContentEncoding::Gzip.compress(data.as_bytes()).unwrap();
+        assert!(compressed.len() < data.len() / 10);
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        assert!(!ContentEncoding::Deflate.compress(data.as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn is_compressible() {
+        assert!(super::is_compressible(" application/json"));
+        assert!(super::is_compressible("text/csv; charset=utf-8"));
+        assert!(super::is_compressible("application/vnd.google-earth.kml+xml"));
+        assert!(!super::is_compressible("image/png"));
+        assert!(!super::is_compressible("text/event-stream"));
+        assert!(!super::is_compressible("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"));
+    }
+}
@@ -1,13 +1,20 @@
+use crate::json_path::JsonPath;
+use crate::label_pattern::{LabelEntityType, LabelPattern};
+use crate::wikidata_search::{WikidataSearch, MAX_WIKIDATA_ENTITY_SEARCH_RESULTS};
+use futures::future::join_all;
+use chrono::prelude::*;
+use chrono::Duration;
 use crate::pagelist::*;
 use crate::platform::Platform;
-use mysql_async::from_row;
-use mysql_async::prelude::Queryable;
+use crate::safe_sql;
+use crate::title_normalization;
 use mysql_async::Value as MyValue;
 use rayon::prelude::*;
+use regex::{Captures, Regex};
 use serde_json::value::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time;
-use wikibase::mediawiki::api::Api;
+use wikibase::mediawiki::api::{Api, NamespaceID};
 use wikibase::mediawiki::title::Title;
 use async_trait::async_trait;
 
@@ -16,14 +23,31 @@ pub type SQLtuple = (String, Vec<MyValue>);
 #[async_trait]
 pub trait DataSource {
     fn can_run(&self, platform: &Platform) -> bool;
+    /// Reads and validates the parameters for this source. Called once, before `run`
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String>;
     async fn run(&mut self, platform: &Platform) -> Result<PageList, String>;
     fn name(&self) -> String;
+    /// The wiki the results will be on, if known before running
+    fn wiki(&self) -> Option<String>;
+    /// Rough estimate of the effort to run this source, relative to other sources
+    fn cost(&self) -> usize;
+    /// The pages found so far, after `run` was cancelled by the time budget; `None` if the source can not tell
+    fn partial_result(&mut self) -> Option<PageList> {
+        None
+    }
+    /// A cheaper estimate of how many pages `run` would return, for `count_only`; `None` if there is none
+    async fn estimate_count(&mut self, _platform: &Platform) -> Result<Option<usize>, String> {
+        Ok(None)
+    }
 }
 
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourceLabels {}
+pub struct SourceLabels {
+    sql: SQLtuple,
+    entity_type: LabelEntityType,
+}
 
 #[async_trait]
 impl DataSource for SourceLabels {
@@ -35,32 +59,131 @@ impl DataSource for SourceLabels {
         platform.has_param("labels_yes") || platform.has_param("labels_any")
     }
 
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.entity_type = LabelEntityType::new_from_param(&platform.get_param_blank("entity_type"));
+        if !self.entity_type.in_term_store() {
+            return Ok(());
+        }
+        platform.check_label_source_regexes()?;
+        self.sql = platform.get_label_sql(self.entity_type);
+        if self.sql.0.is_empty() {
+            return Err("SourceLabels: No labels given".to_string());
+        }
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some("wikidatawiki".to_string())
+    }
+
+    fn cost(&self) -> usize {
+        50
+    }
+
     async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let sql = platform.get_label_sql();
+        if !self.entity_type.in_term_store() {
+            return self.search_lexemes(platform).await;
+        }
+        let sql = self.sql.clone();
         let mut conn = platform
             .state()
-            .get_wiki_db_connection( &"wikidatawiki".to_string())
+            .get_wiki_db( &"wikidatawiki".to_string())
             .await? ;
-        let rows = conn
-            .exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<(Vec<u8>,)>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        let rows = conn.query_as::<(Vec<u8>,)>(sql).await?;
+        conn.close().await?;
         let ret = PageList::new_from_wiki_with_capacity("wikidatawiki",rows.len());
         rows
             .iter()
             .map(|row|String::from_utf8_lossy(&row.0))
             .filter_map(|item|Platform::entry_from_entity(&item))
             .for_each(|entry| ret.add_entry(entry).unwrap_or(()) );
+        platform.process_label_regexes(&ret).await?;
         Ok(ret)
     }
 }
 
 impl SourceLabels {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
+    }
+
+    /// Lexemes are not in the term store, so each "yes"/"any" line is a prefix search for lemmas (or form
+    /// representations) via the API, checked against the whole pattern; "no" lines remove lexemes found the same way.
+    async fn search_lexemes(&self, platform: &Platform) -> Result<PageList, String> {
+        let mut yes: Option<HashSet<String>> = None;
+        for line in platform.form_parameters().get_all("labels_yes").iter().flat_map(|v| v.split('\n')) {
+            let found = self.search_lexemes_for_line(platform, line, "yes").await?;
+            yes = Some(match yes {
+                Some(yes) => yes.intersection(&found).cloned().collect(),
+                None => found,
+            });
+        }
+        let mut any: Option<HashSet<String>> = None;
+        for line in platform.form_parameters().get_all("labels_any").iter().flat_map(|v| v.split('\n')) {
+            let found = self.search_lexemes_for_line(platform, line, "any").await?;
+            any.get_or_insert_with(HashSet::new).extend(found);
+        }
+        let mut lexemes = match (yes, any) {
+            (Some(yes), Some(any)) => yes.intersection(&any).cloned().collect(),
+            (Some(found), None) | (None, Some(found)) => found,
+            (None, None) => return Err("SourceLabels: No labels given".to_string()),
+        };
+        for line in platform.form_parameters().get_all("labels_no").iter().flat_map(|v| v.split('\n')) {
+            let found = self.search_lexemes_for_line(platform, line, "no").await?;
+            lexemes.retain(|lexeme| !found.contains(lexeme));
+        }
+        let ret = PageList::new_from_wiki_with_capacity("wikidatawiki", lexemes.len());
+        lexemes
+            .iter()
+            .filter_map(|lexeme| Platform::entry_from_entity(lexeme))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
+        Ok(ret)
+    }
+
+    /// Lexemes with a lemma (or form) matching one line of labels_yes/any/no, in the languages for that part
+    async fn search_lexemes_for_line(&self, platform: &Platform, line: &str, part: &str) -> Result<HashSet<String>, String> {
+        let mut ret = HashSet::new();
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(ret);
+        }
+        let pattern = LabelPattern::parse(line, platform.has_param("label_wildcards"))?;
+        let text = match pattern.search_prefix() {
+            Some(text) if !text.is_empty() => text,
+            _ => return Err(format!("SourceLabels: Lexeme searches need some text before any wildcard: '{}'", line)),
+        };
+        let mut languages = platform.get_param_as_vec(&format!("langs_labels_{}", part), ",");
+        if languages.is_empty() {
+            languages.push(platform.get_param_default("interface_language", "en"));
+        }
+        let api_url = platform.state().get_api_url_for_wiki("wikidatawiki")?;
+        for language in languages {
+            let search = WikidataSearch::new(platform.state().http_client(), &api_url, &language);
+            let (results, truncated) = search
+                .find_entities(&text, self.entity_type.as_str())
+                .await
+                .ok_or_else(|| format!("SourceLabels: Lexeme search for '{}' failed", text))?;
+            if truncated {
+                // Lexemes beyond the limit would be left in, not removed
+                if part == "no" {
+                    return Err(format!(
+                        "SourceLabels: The lexeme search for '{}' to exclude has more than {} results",
+                        text, MAX_WIKIDATA_ENTITY_SEARCH_RESULTS
+                    ));
+                }
+                platform.warn(format!(
+                    "<span tt='warn_lexeme_search_truncated'></span> {} ({})",
+                    MAX_WIKIDATA_ENTITY_SEARCH_RESULTS, text
+                ))?;
+            }
+            results
+                .iter()
+                .filter(|(_id, matched)| pattern.is_match(matched))
+                .for_each(|(id, _matched)| {
+                    ret.insert(LabelEntityType::lexeme_id(id));
+                });
+        }
+        Ok(ret)
     }
 }
 
@@ -68,7 +191,12 @@ impl SourceLabels {
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct SourceSitelinks {
-    main_wiki: String
+    main_wiki: String,
+    sitelinks_yes: Vec<String>,
+    sitelinks_any: Vec<String>,
+    sitelinks_no: Vec<String>,
+    sitelinks_min: String,
+    sitelinks_max: String,
 }
 
 #[async_trait]
@@ -81,22 +209,39 @@ impl DataSource for SourceSitelinks {
         platform.has_param("sitelinks_yes") || platform.has_param("sitelinks_any")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let sitelinks_yes = platform.get_param_as_vec("sitelinks_yes", "\n");
-        let sitelinks_any = platform.get_param_as_vec("sitelinks_any", "\n");
-        let sitelinks_no = platform.get_param_as_vec("sitelinks_no", "\n");
-        let sitelinks_min = platform.get_param_blank("min_sitelink_count");
-        let sitelinks_max = platform.get_param_blank("max_sitelink_count");
-
-        let use_min_max = !sitelinks_min.is_empty() || !sitelinks_max.is_empty();
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.sitelinks_yes = platform.get_param_as_vec("sitelinks_yes", "\n");
+        self.sitelinks_any = platform.get_param_as_vec("sitelinks_any", "\n");
+        self.sitelinks_no = platform.get_param_as_vec("sitelinks_no", "\n");
+        self.sitelinks_min = platform.get_param_blank("min_sitelink_count");
+        self.sitelinks_max = platform.get_param_blank("max_sitelink_count");
 
         let mut yes_any = vec![] ;
-        yes_any.extend(&sitelinks_yes);
-        yes_any.extend(&sitelinks_any);
+        yes_any.extend(&self.sitelinks_yes);
+        yes_any.extend(&self.sitelinks_any);
         self.main_wiki = match yes_any.get(0) {
             Some(wiki) => wiki.to_string(),
-            None => return Err("No yes/any sitelink found in SourceSitelinks::run".to_string())
+            None => return Err("No yes/any sitelink found in SourceSitelinks::parse_params".to_string())
         };
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.main_wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        50
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let sitelinks_yes = self.sitelinks_yes.clone();
+        let sitelinks_any = self.sitelinks_any.clone();
+        let sitelinks_no = self.sitelinks_no.clone();
+        let sitelinks_min = self.sitelinks_min.clone();
+        let sitelinks_max = self.sitelinks_max.clone();
+
+        let use_min_max = !sitelinks_min.is_empty() || !sitelinks_max.is_empty();
 
         let sitelinks_any : Vec<String> = sitelinks_any.iter().filter_map(|site|self.site2lang(site)).collect();
         let sitelinks_no : Vec<String> = sitelinks_no.iter().filter_map(|site|self.site2lang(site)).collect();
@@ -141,15 +286,10 @@ impl DataSource for SourceSitelinks {
 
         let mut conn = platform
             .state()
-            .get_wiki_db_connection( &self.main_wiki)
+            .get_wiki_db( &self.main_wiki)
             .await? ;
-        let rows = conn
-            .exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<(Vec<u8>,u32)>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        let rows = conn.query_as::<(Vec<u8>,u32)>(sql).await?;
+        conn.close().await?;
 
         let ret = PageList::new_from_wiki_with_capacity(&self.main_wiki,rows.len());
         if use_min_max {
@@ -191,7 +331,10 @@ impl SourceSitelinks {
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourceWikidata {}
+pub struct SourceWikidata {
+    sites: Vec<String>,
+    no_statements: bool,
+}
 
 #[async_trait]
 impl DataSource for SourceWikidata {
@@ -203,17 +346,32 @@ impl DataSource for SourceWikidata {
         platform.has_param("wpiu_no_statements") && platform.has_param("wikidata_source_sites")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let no_statements = platform.has_param("wpiu_no_statements");
-        let sites = platform
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.no_statements = platform.has_param("wpiu_no_statements");
+        self.sites = platform
             .get_param("wikidata_source_sites")
-            .ok_or_else(|| "Missing parameter \'wikidata_source_sites\'".to_string())?;
-        let sites: Vec<String> = sites.split(',').map(|s| s.to_string()).collect();
-        if sites.is_empty() {
+            .ok_or_else(|| "Missing parameter \'wikidata_source_sites\'".to_string())?
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if self.sites.is_empty() {
             return Err("SourceWikidata: No wikidata source sites given".to_string());
         }
+        Ok(())
+    }
 
-        let sites = Platform::prep_quote(&sites);
+    fn wiki(&self) -> Option<String> {
+        Some("wikidatawiki".to_string())
+    }
+
+    fn cost(&self) -> usize {
+        100
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let no_statements = self.no_statements;
+        let sites = Platform::prep_quote(&self.sites);
 
         let mut sql = "SELECT ips_item_id FROM wb_items_per_site".to_string();
         if no_statements {
@@ -229,15 +387,10 @@ impl DataSource for SourceWikidata {
         // Perform DB query
         let mut conn = platform
             .state()
-            .get_wiki_db_connection(&"wikidatawiki".to_string())
+            .get_wiki_db(&"wikidatawiki".to_string())
             .await? ;
-        let rows = conn
-            .exec_iter(sql.as_str(),()).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<usize>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        let rows = conn.query_as::<usize>((sql, sites.1)).await?;
+        conn.close().await?;
         let ret = PageList::new_from_wiki(&"wikidatawiki".to_string());
         for ips_item_id in rows {
             let term_full_entity_id = format!("Q{}", ips_item_id);
@@ -249,14 +402,16 @@ impl DataSource for SourceWikidata {
 
 impl SourceWikidata {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
     }
 }
 
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourcePagePile {}
+pub struct SourcePagePile {
+    pagepile: String,
+}
 
 #[async_trait]
 impl DataSource for SourcePagePile {
@@ -268,29 +423,53 @@ impl DataSource for SourcePagePile {
         platform.has_param("pagepile")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let pagepile = platform
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.pagepile = platform
             .get_param("pagepile")
-            .ok_or_else(|| "Missing parameter \'pagepile\'".to_string())?;
-        let timeout = time::Duration::from_secs(240);
-        let builder = reqwest::ClientBuilder::new().timeout(timeout);
-        let api = Api::new_from_builder("https://www.wikidata.org/w/api.php", builder).await
-            .map_err(|e| e.to_string())?;
-        let params = api.params_into(&[
-            ("id", &pagepile.to_string()),
-            ("action", "get_data"),
-            ("format", "json"),
-            ("doit", "1"),
-        ]);
-        let text = api
-            .query_raw("https://tools.wmflabs.org/pagepile/api.php", &params, "GET").await
+            .ok_or_else(|| "Missing parameter \'pagepile\'".to_string())?
+            .trim()
+            .to_string();
+        if self.pagepile.parse::<usize>().is_err() {
+            return Err(format!("Bad PagePile ID '{}'", &self.pagepile));
+        }
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        None // Only known after loading the pile
+    }
+
+    fn cost(&self) -> usize {
+        5
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        platform.set_stage("loading PagePile...");
+        let pagepile = self.pagepile.clone();
+        let http = platform.state().http_client();
+        let params = [
+            ("id", pagepile.to_string()),
+            ("action", "get_data".to_string()),
+            ("format", "json".to_string()),
+            ("doit", "1".to_string()),
+        ];
+        let request = http
+            .get(platform.state().endpoints().pagepile())
+            .query(&params)
+            .timeout(time::Duration::from_secs(240));
+        let text = http
+            .send(request)
+            .await
+            .map_err(|e| format!("PagePile: {}", e))?
+            .text()
+            .await
             .map_err(|e| format!("PagePile: {:?}", e))?;
         let v: Value =
             serde_json::from_str(&text).map_err(|e| format!("PagePile JSON: {:?}", e))?;
         let wiki = v["wiki"]
             .as_str()
             .ok_or(format!("PagePile {} does not specify a wiki", &pagepile))?;
-        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?; // Just because we need query_raw
+        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
         let ret = PageList::new_from_wiki(wiki);
         v["pages"]
             .as_array()
@@ -311,14 +490,19 @@ impl DataSource for SourcePagePile {
 
 impl SourcePagePile {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
     }
 }
 
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourceSearch {}
+pub struct SourceSearch {
+    wiki: String,
+    query: String,
+    max: usize,
+    namespace_ids: Vec<usize>,
+}
 
 #[async_trait]
 impl DataSource for SourceSearch {
@@ -334,14 +518,14 @@ impl DataSource for SourceSearch {
             && !platform.is_param_blank("search_wiki")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let wiki = platform
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform
             .get_param("search_wiki")
             .ok_or_else(|| "Missing parameter \'search_wiki\'".to_string())?;
-        let query = platform
+        self.query = platform
             .get_param("search_query")
             .ok_or_else(|| "Missing parameter \'search_query\'".to_string())?;
-        let max = match platform
+        self.max = match platform
             .get_param("search_max_results")
             .ok_or_else(|| "Missing parameter \'search_max_results\'".to_string())?
             .parse::<usize>()
@@ -349,24 +533,32 @@ impl DataSource for SourceSearch {
             Ok(max) => max,
             Err(e) => return Err(format!("{:?}", e)),
         };
-        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
-        let srlimit = if max > 500 { 500 } else { max };
-        let srlimit = format!("{}", srlimit);
-        let namespace_ids = platform
+        self.namespace_ids = platform
             .form_parameters()
             .ns
             .par_iter()
             .cloned()
             .collect::<Vec<usize>>();
-        let namespace_ids = if namespace_ids.is_empty() {
-            "*".to_string()
-        } else {
-            namespace_ids
-                .iter()
-                .map(|i| i.to_string())
-                .collect::<Vec<String>>()
-                .join(",")
-        };
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        10 + self.max / 500
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        platform.set_stage("searching...");
+        let wiki = self.wiki.clone();
+        let query = self.query.clone();
+        let max = self.max;
+        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
+        let srlimit = if max > 500 { 500 } else { max };
+        let srlimit = format!("{}", srlimit);
+        let namespace_ids = self.namespace_ids_param();
         let params = api.params_into(&[("action", "query"),
             ("list", "search"),
             ("srlimit", srlimit.as_str()),
@@ -387,18 +579,49 @@ impl DataSource for SourceSearch {
         }
         Ok(ret)
     }
+
+    async fn estimate_count(&mut self, platform: &Platform) -> Result<Option<usize>, String> {
+        let api = platform.state().get_api_for_wiki(self.wiki.to_string()).await?;
+        let namespace_ids = self.namespace_ids_param();
+        let params = api.params_into(&[("action", "query"),
+            ("list", "search"),
+            ("srlimit", "1"),
+            ("srinfo", "totalhits"),
+            ("srprop", ""),
+            ("srsearch", self.query.as_str()),
+            ("srnamespace", namespace_ids.as_str())]);
+        let result = api.get_query_api_json(&params).await.map_err(|e| format!("{:?}", e))?;
+        Ok(result["query"]["searchinfo"]["totalhits"]
+            .as_u64()
+            .map(|hits| (hits as usize).min(self.max)))
+    }
 }
 
 impl SourceSearch {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
+    }
+
+    fn namespace_ids_param(&self) -> String {
+        if self.namespace_ids.is_empty() {
+            "*".to_string()
+        } else {
+            self.namespace_ids
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        }
     }
 }
 
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourceManual {}
+pub struct SourceManual {
+    wiki: String,
+    titles: Vec<String>,
+}
 
 #[async_trait]
 impl DataSource for SourceManual {
@@ -410,41 +633,179 @@ impl DataSource for SourceManual {
         platform.has_param("manual_list") && platform.has_param("manual_list_wiki")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let wiki = platform
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform
             .get_param("manual_list_wiki")
             .ok_or_else(|| "Missing parameter \'manual_list_wiki\'".to_string())?;
-        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
-        let ret = PageList::new_from_wiki(&wiki);
-        platform
+        self.titles = platform
             .get_param("manual_list")
             .ok_or_else(|| "Missing parameter \'manual_list\'".to_string())?
             .split('\n')
-            .filter_map(|line| {
-                let line = line.trim().to_string();
-                if !line.is_empty() {
-                    let title = Title::new_from_full(&line, &api);
-                    let entry = PageListEntry::new(title);
-                    Some(entry)
-                } else {
-                    None
-                }
-            })
+            .map(|line| title_normalization::normalize(line))
+            .filter(|line| !line.is_empty())
+            .collect();
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        1
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let api = platform.state().get_api_for_wiki(self.wiki.to_string()).await?;
+        let ret = PageList::new_from_wiki(&self.wiki);
+        self.titles
+            .iter()
+            .map(|line| PageListEntry::new(Title::new_from_full(line, &api)))
             .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
         Ok(ret)
     }
+
+    async fn estimate_count(&mut self, _platform: &Platform) -> Result<Option<usize>, String> {
+        Ok(Some(self.titles.len()))
+    }
 }
 
 impl SourceManual {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
     }
 }
 
 //________________________________________________________________________________________________________________________
 
+static MAX_SOURCE_URL_BYTES: usize = 5 * 1024 * 1024;
+static MAX_SOURCE_URL_TITLES: usize = 100000;
+
+/// Page titles from a JSON or plain text document at a URL. Titles are read with a JSONPath (`source_url_jsonpath`),
+/// or the first group (or whole match) of a regular expression per line (`source_url_regex`), or one title per line.
+#[derive(Debug, Clone, Default)]
+pub struct SourceUrl {
+    url: String,
+    wiki: String,
+    json_path: Option<JsonPath>,
+    line_regex: Option<Regex>,
+}
+
+#[async_trait]
+impl DataSource for SourceUrl {
+    fn name(&self) -> String {
+        "url".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        platform.has_param("source_url") && platform.has_param("source_url_wiki")
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.url = platform
+            .get_param("source_url")
+            .ok_or_else(|| "Missing parameter \'source_url\'".to_string())?;
+        self.wiki = platform
+            .get_param("source_url_wiki")
+            .ok_or_else(|| "Missing parameter \'source_url_wiki\'".to_string())?;
+        let url = reqwest::Url::parse(&self.url).map_err(|e| format!("Invalid source URL '{}': {}", &self.url, e))?;
+        if !platform.state().is_source_url_allowed(&url) {
+            return Err(format!("Source URL '{}' is not allowed; only https, from Wikimedia hosts", &self.url));
+        }
+        self.json_path = match platform.get_param("source_url_jsonpath") {
+            Some(path) => Some(JsonPath::parse(&path)?),
+            None => None,
+        };
+        self.line_regex = match platform.get_param("source_url_regex") {
+            Some(re) => Some(Regex::new(&re).map_err(|e| format!("Invalid source URL regular expression: {}", e))?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        5
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let http = platform.state().http_client();
+        let client = http
+            .client_builder()
+            .timeout(time::Duration::from_secs(60))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+        let mut response = http
+            .send(client.get(&self.url))
+            .await
+            .map_err(|e| format!("SourceUrl: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("SourceUrl: {} returned {}", &self.url, response.status()));
+        }
+        let mut body: Vec<u8> = vec![];
+        while let Some(chunk) = response.chunk().await.map_err(|e| format!("SourceUrl: {:?}", e))? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_SOURCE_URL_BYTES {
+                return Err(format!("SourceUrl: document is larger than {} bytes", MAX_SOURCE_URL_BYTES));
+            }
+        }
+        let text = String::from_utf8_lossy(&body);
+        let titles = self.extract_titles(&text)?;
+        if titles.len() > MAX_SOURCE_URL_TITLES {
+            return Err(format!("SourceUrl: too many titles ({}), maximum is {}", titles.len(), MAX_SOURCE_URL_TITLES));
+        }
+        let api = platform.state().get_api_for_wiki(self.wiki.to_string()).await?;
+        let ret = PageList::new_from_wiki(&self.wiki);
+        titles
+            .iter()
+            .map(|title| PageListEntry::new(Title::new_from_full(title, &api)))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
+        Ok(ret)
+    }
+}
+
+impl SourceUrl {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    fn extract_titles(&self, text: &str) -> Result<Vec<String>, String> {
+        let titles: Vec<String> = match (&self.json_path, &self.line_regex) {
+            (Some(json_path), _) => {
+                let json: Value = serde_json::from_str(text).map_err(|e| format!("SourceUrl: invalid JSON: {}", e))?;
+                json_path.select_strings(&json)
+            }
+            (None, Some(re)) => text
+                .lines()
+                .filter_map(|line| {
+                    let caps = re.captures(line)?;
+                    caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str().to_string())
+                })
+                .collect(),
+            (None, None) => text.lines().map(|line| line.to_string()).collect(),
+        };
+        Ok(titles
+            .iter()
+            .map(|title| title_normalization::normalize(title))
+            .filter(|title| !title.is_empty())
+            .collect())
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// Placeholder in SPARQL for the items from the other sources; `%PETSCAN_ITEMS:var%` binds `?var` instead of `?item`
+pub static SPARQL_ITEMS_PLACEHOLDER: &str = "%PETSCAN_ITEMS";
+pub static MAX_SPARQL_PLACEHOLDER_ITEMS: usize = 20000;
+
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct SourceSparql {}
+pub struct SourceSparql {
+    sparql: String,
+}
 
 #[async_trait]
 impl DataSource for SourceSparql {
@@ -456,30 +817,44 @@ impl DataSource for SourceSparql {
         platform.has_param("sparql")
     }
 
-    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let sparql = platform
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.sparql = platform
             .get_param("sparql")
             .ok_or_else(|| "Missing parameter \'sparql\'".to_string())?;
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some("wikidatawiki".to_string())
+    }
+
+    fn cost(&self) -> usize {
+        20
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        platform.set_stage("running SPARQL...");
+        let sparql = self.sparql.clone();
 
-        let timeout = time::Duration::from_secs(120);
-        let builder = reqwest::ClientBuilder::new().timeout(timeout);
-        let api = Api::new_from_builder("https://www.wikidata.org/w/api.php", builder).await
-            .map_err(|e| format!("SourceSparql::run:1 {:?}", e))?;
+        let api = platform.state().get_api_for_wiki("wikidatawiki".to_string()).await
+            .map_err(|e| format!("SourceSparql::run:1 {}", e))?;
 
-        let sparql_url = api.get_site_info_string("general", "wikibase-sparql")?;
+        let sparql_url = match platform.state().endpoints().configured_wdqs() {
+            Some(url) => url.to_string(),
+            None => api.get_site_info_string("general", "wikibase-sparql")?.to_string(),
+        };
         let mut params: HashMap<String, String> = HashMap::new();
         params.insert("query".to_string(), sparql.to_string());
         params.insert("format".to_string(), "json".to_string());
 
-        let response = match api
-            .client()
-            .post(sparql_url)
-            .header(reqwest::header::USER_AGENT, "PetScan")
-            .form(&params)
-            .send().await
-        {
+        let http = platform.state().http_client();
+        let request = http
+            .post(&sparql_url)
+            .timeout(time::Duration::from_secs(120))
+            .form(&params);
+        let response = match http.send(request).await {
             Ok(resp) => resp,
-            Err(e) => return Err(format!("SPARL: {:?}", e)),
+            Err(e) => return Err(format!("SPARL: {}", e)),
         };
 
         let ret = PageList::new_from_wiki("wikidatawiki");
@@ -526,39 +901,640 @@ impl DataSource for SourceSparql {
 
         Ok(ret)
     }
+}
 
-    /*
-    // using serde, obsolete because of high memory usage
-    fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let sparql = platform
-            .get_param("sparql")
-            .ok_or(format!("Missing parameter 'sparql'"))?;
-
-        let timeout = Some(time::Duration::from_secs(120));
-        let builder = reqwest::blocking::ClientBuilder::new().timeout(timeout);
-        let api = Api::new_from_builder("https://www.wikidata.org/w/api.php", builder)
-            .map_err(|e| format!("SourceSparql::run:1 {:?}", e))?;
-        let result = api
-            .sparql_query(sparql.as_str())
-            .map_err(|e| format!("SourceSparql::run:2 {:?}", e))?;
-        let first_var = result["head"]["vars"][0]
-            .as_str()
-            .ok_or(format!("No variables found in SPARQL result"))?;
-        let ret = PageList::new_from_wiki("wikidatawiki");
-        api.entities_from_sparql_result(&result, first_var)
-            .par_iter()
-            .filter_map(|e| Platform::entry_from_entity(e))
-            .for_each(|entry| ret.add_entry(entry));
-        if ret.is_empty() {
-            platform.warn(format!("<span tt='warn_sparql'></span>"));
+impl SourceSparql {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn uses_items_placeholder(sparql: &str) -> bool {
+        sparql.contains(SPARQL_ITEMS_PLACEHOLDER)
+    }
+
+    /// Replaces `%PETSCAN_ITEMS%` (or `%PETSCAN_ITEMS:var%`) with a VALUES block of the items
+    pub fn expand_items_placeholder(sparql: &str, items: &[String]) -> String {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"%PETSCAN_ITEMS(?::([A-Za-z_][A-Za-z0-9_]*))?%")
+                .expect("SourceSparql::expand_items_placeholder RE");
+        }
+        let values: Vec<String> = items.iter().map(|q| format!("wd:{}", q)).collect();
+        let values = values.join(" ");
+        RE.replace_all(sparql, |caps: &Captures| {
+            let var = caps.get(1).map(|m| m.as_str()).unwrap_or("item");
+            format!("VALUES ?{} {{ {} }}", var, values)
+        })
+        .into_owned()
+    }
+
+    /// Fills in the items from the other sources, for a query with the items placeholder
+    pub fn set_items(&mut self, items: &[String]) -> Result<(), String> {
+        if items.len() > MAX_SPARQL_PLACEHOLDER_ITEMS {
+            return Err(format!(
+                "Too many items ({}) for the SPARQL placeholder, maximum is {}",
+                items.len(),
+                MAX_SPARQL_PLACEHOLDER_ITEMS
+            ));
         }
+        self.sparql = Self::expand_items_placeholder(&self.sparql, items);
+        Ok(())
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+static DEFAULT_EDIT_TAGS_DAYS: i64 = 7;
+
+/// Pages with edits in a time window that have one of the change tags (`edit_tags`), or hit one of the
+/// abuse filters (`abuse_filters`, local filter IDs). The window is `edit_tags_since` to `edit_tags_until`,
+/// by default the last week.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceEditTags {
+    wiki: String,
+    tags: Vec<String>,
+    abuse_filters: Vec<u64>,
+    since: String,
+    until: String,
+}
+
+#[async_trait]
+impl DataSource for SourceEditTags {
+    fn name(&self) -> String {
+        "edit_tags".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        platform.has_param("edit_tags") || platform.has_param("abuse_filters")
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform
+            .get_main_wiki()
+            .ok_or_else(|| "SourceEditTags::parse_params: no wiki".to_string())?;
+        self.tags = platform
+            .get_param_as_vec("edit_tags", "\n")
+            .iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        self.abuse_filters = Self::parse_filter_ids(&platform.get_param_blank("abuse_filters"))?;
+        self.since = platform.get_param_blank("edit_tags_since");
+        if self.since.is_empty() {
+            let utc: DateTime<Utc> = Utc::now() - Duration::days(DEFAULT_EDIT_TAGS_DAYS);
+            self.since = utc.format("%Y%m%d%H%M%S").to_string();
+        }
+        self.until = platform.get_param_blank("edit_tags_until");
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        20
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let mut conn = platform.state().get_wiki_db(&self.wiki).await?;
+        let mut rows = vec![];
+        for sql in [self.sql_tags(), self.sql_abuse_filters()].iter().flatten() {
+            let mut result = conn.query_as::<(Vec<u8>, NamespaceID)>(sql.to_owned()).await?;
+            rows.append(&mut result);
+        }
+        conn.close().await?;
+
+        let ret = PageList::new_from_wiki_with_capacity(&self.wiki, rows.len());
+        rows.iter()
+            .map(|(title, namespace)| PageListEntry::new(Title::new(&String::from_utf8_lossy(title), *namespace)))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
         Ok(ret)
     }
-    */
 }
 
-impl SourceSparql {
+impl SourceEditTags {
     pub fn new() -> Self {
-        Self {}
+        Self { ..Default::default() }
+    }
+
+    /// Filter IDs, separated by commas, spaces, or newlines
+    fn parse_filter_ids(s: &str) -> Result<Vec<u64>, String> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<u64>().map_err(|_| format!("Invalid abuse filter ID '{}'", id)))
+            .collect()
+    }
+
+    fn sql_timestamps(&self, sql: &mut SQLtuple, column: &str) {
+        sql.0 += &format!(" AND {}>=?", column);
+        sql.1.push(MyValue::Bytes(self.since.to_owned().into()));
+        if !self.until.is_empty() {
+            sql.0 += &format!(" AND {}<=?", column);
+            sql.1.push(MyValue::Bytes(self.until.to_owned().into()));
+        }
+    }
+
+    fn sql_tags(&self) -> Option<SQLtuple> {
+        if self.tags.is_empty() {
+            return None;
+        }
+        let mut sql: SQLtuple = ("SELECT DISTINCT page_title,page_namespace FROM page,revision,change_tag,change_tag_def WHERE rev_page=page_id AND ct_rev_id=rev_id AND ct_tag_id=ctd_id AND ctd_name IN (".to_string(), vec![]);
+        Platform::append_sql(&mut sql, Platform::prep_quote(&self.tags));
+        sql.0 += ")";
+        self.sql_timestamps(&mut sql, "rev_timestamp");
+        Some(sql)
+    }
+
+    fn sql_abuse_filters(&self) -> Option<SQLtuple> {
+        if self.abuse_filters.is_empty() {
+            return None;
+        }
+        let ids: Vec<String> = self.abuse_filters.iter().map(|id| id.to_string()).collect();
+        let mut sql: SQLtuple = (
+            format!("SELECT DISTINCT afl_title,afl_namespace FROM abuse_filter_log WHERE afl_global=0 AND afl_filter_id IN ({})", ids.join(",")),
+            vec![],
+        );
+        self.sql_timestamps(&mut sql, "afl_timestamp");
+        Some(sql)
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+static DEFAULT_CONTRIBUTIONS_DAYS: i64 = 30;
+static MAX_CONTRIBUTIONS_WIKIS: usize = 30;
+static CONTRIBUTIONS_CONCURRENT_WIKIS: usize = 5;
+/// Per wiki
+static MAX_CONTRIBUTIONS_PAGES: usize = 100000;
+
+/// Pages edited by `contributions_user` between `contributions_since` (by default 30 days ago) and `contributions_until`.
+/// With `contributions_wikis` (a list of wikis) or `contributions_global` (all wikis the account is attached to,
+/// via CentralAuth), the wikis are queried in parallel, and the results merged to the main wiki via Wikidata;
+/// pages on other wikis without a counterpart there are dropped.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceUserContributions {
+    wiki: String,
+    user: String,
+    wikis: Vec<String>,
+    global: bool,
+    since: String,
+    until: String,
+}
+
+#[async_trait]
+impl DataSource for SourceUserContributions {
+    fn name(&self) -> String {
+        "user_contributions".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        platform.has_param("contributions_user")
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform
+            .get_main_wiki()
+            .ok_or_else(|| "SourceUserContributions::parse_params: no wiki".to_string())?;
+        self.user = platform.get_param_blank("contributions_user").trim().replace('_', " ");
+        if self.user.is_empty() {
+            return Err("No user name given for user contributions".to_string());
+        }
+        self.global = platform.has_param("contributions_global");
+        self.wikis = platform
+            .get_param_as_vec("contributions_wikis", "\n")
+            .iter()
+            .flat_map(|s| s.split(','))
+            .map(|wiki| wiki.trim().to_string())
+            .filter(|wiki| !wiki.is_empty())
+            .collect();
+        if self.wikis.len() > MAX_CONTRIBUTIONS_WIKIS {
+            return Err(format!("Too many wikis for user contributions, maximum is {}", MAX_CONTRIBUTIONS_WIKIS));
+        }
+        self.since = platform.get_param_blank("contributions_since");
+        if self.since.is_empty() {
+            let utc: DateTime<Utc> = Utc::now() - Duration::days(DEFAULT_CONTRIBUTIONS_DAYS);
+            self.since = utc.format("%Y%m%d%H%M%S").to_string();
+        }
+        self.until = platform.get_param_blank("contributions_until");
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        match self.global || !self.wikis.is_empty() {
+            true => 50,
+            false => 10,
+        }
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let mut wikis = self.wikis.clone();
+        if self.global {
+            wikis.append(&mut self.attached_wikis(platform).await?);
+        }
+        if wikis.is_empty() {
+            wikis.push(self.wiki.to_owned());
+        }
+        wikis.sort();
+        wikis.dedup();
+
+        let ret = PageList::new_from_wiki(&self.wiki);
+        for group in wikis.chunks(CONTRIBUTIONS_CONCURRENT_WIKIS) {
+            let futures: Vec<_> = group.iter().map(|wiki| self.run_for_wiki(platform, wiki)).collect();
+            for result in join_all(futures).await {
+                ret.union(&result?, Some(platform)).await?;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl SourceUserContributions {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    async fn run_for_wiki(&self, platform: &Platform, wiki: &str) -> Result<PageList, String> {
+        let wiki = platform.state().fix_wiki_name(wiki);
+        let sql = self.sql_contributions();
+        let mut conn = platform.state().get_wiki_db(&wiki).await?;
+        let rows = conn.query_as::<(Vec<u8>, NamespaceID)>(sql).await?;
+        conn.close().await?;
+        if rows.len() >= MAX_CONTRIBUTIONS_PAGES {
+            platform.warn(format!("<span tt='warn_contributions_pages'></span> {} ({})", MAX_CONTRIBUTIONS_PAGES, wiki))?;
+        }
+
+        let ret = PageList::new_from_wiki_with_capacity(&wiki, rows.len());
+        rows.iter()
+            .map(|(title, namespace)| PageListEntry::new(Title::new(&String::from_utf8_lossy(title), *namespace)))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
+        Ok(ret)
+    }
+
+    /// Wikis with edits by the global account; the ones with most edits, if there are too many
+    async fn attached_wikis(&self, platform: &Platform) -> Result<Vec<String>, String> {
+        let http = platform.state().http_client();
+        let api_url = platform.state().get_api_url_for_wiki("metawiki")?;
+        let params = [
+            ("action", "query".to_string()),
+            ("meta", "globaluserinfo".to_string()),
+            ("guiuser", self.user.to_owned()),
+            ("guiprop", "merged".to_string()),
+            ("format", "json".to_string()),
+        ];
+        let json: Value = http
+            .send(http.get(&api_url).query(&params))
+            .await
+            .map_err(|e| format!("Global user info: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Global user info: {:?}", e))?;
+        let wikis = Self::wikis_from_globaluserinfo(&json)?;
+        if wikis.len() > MAX_CONTRIBUTIONS_WIKIS {
+            platform.warn(format!("<span tt='warn_contributions_wikis'></span> {}", MAX_CONTRIBUTIONS_WIKIS))?;
+        }
+        Ok(wikis.into_iter().take(MAX_CONTRIBUTIONS_WIKIS).collect())
+    }
+
+    /// Wikis with edits, most edits first
+    fn wikis_from_globaluserinfo(json: &Value) -> Result<Vec<String>, String> {
+        if json["query"]["globaluserinfo"]["missing"].as_str().is_some() {
+            return Err("No such global account".to_string());
+        }
+        let mut wikis: Vec<(u64, String)> = json["query"]["globaluserinfo"]["merged"]
+            .as_array()
+            .map(|merged| {
+                merged
+                    .iter()
+                    .filter_map(|m| Some((m["editcount"].as_u64()?, m["wiki"].as_str()?.to_string())))
+                    .filter(|(editcount, _)| *editcount > 0)
+                    .collect()
+            })
+            .unwrap_or_default();
+        wikis.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(wikis.into_iter().map(|(_, wiki)| wiki).collect())
+    }
+
+    /// revision_userindex and actor_revision are the replica views with the indexes for queries by user
+    fn sql_contributions(&self) -> SQLtuple {
+        let mut sql: SQLtuple = (
+            "SELECT DISTINCT page_title,page_namespace FROM page,revision_userindex WHERE rev_page=page_id AND rev_actor=(SELECT actor_id FROM actor_revision WHERE actor_name=?) AND rev_timestamp>=?".to_string(),
+            vec![
+                MyValue::Bytes(self.user.to_owned().into()),
+                MyValue::Bytes(self.since.to_owned().into()),
+            ],
+        );
+        if !self.until.is_empty() {
+            sql.0 += " AND rev_timestamp<=?";
+            sql.1.push(MyValue::Bytes(self.until.to_owned().into()));
+        }
+        sql.0 += &format!(" LIMIT {}", MAX_CONTRIBUTIONS_PAGES);
+        sql
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// Deleted pages from the archive table, for undeletion review; admins only (`admin_token`).
+/// `deleted_title_pattern` matches the title, with "*" as wildcard; `deleted_since`/`deleted_until` restrict the
+/// time of deletion, from the deletion log. Pages that have been restored or recreated are left out.
+/// The categories of a page at deletion time are not in the replicas, so they can not be used here.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceDeletedPages {
+    wiki: String,
+    namespaces: Vec<NamespaceID>,
+    title_pattern: String,
+    since: String,
+    until: String,
+}
+
+#[async_trait]
+impl DataSource for SourceDeletedPages {
+    fn name(&self) -> String {
+        "deleted_pages".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        platform.has_param("deleted_pages")
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        if !platform.state().is_admin_token(&platform.get_param_blank("admin_token")) {
+            return Err("Deleted pages are only available to admins".to_string());
+        }
+        self.wiki = platform
+            .get_main_wiki()
+            .ok_or_else(|| "SourceDeletedPages::parse_params: no wiki".to_string())?;
+        self.namespaces = platform
+            .get_param_blank("deleted_namespaces")
+            .split(',')
+            .map(|ns| ns.trim())
+            .filter(|ns| !ns.is_empty())
+            .map(|ns| ns.parse::<NamespaceID>().map_err(|_| format!("Invalid namespace '{}'", ns)))
+            .collect::<Result<Vec<NamespaceID>, String>>()?;
+        if self.namespaces.is_empty() {
+            self.namespaces.push(0);
+        }
+        self.title_pattern = platform.get_param_blank("deleted_title_pattern").trim().to_string();
+        self.since = platform.get_param_blank("deleted_since");
+        self.until = platform.get_param_blank("deleted_until");
+        if self.title_pattern.is_empty() && self.since.is_empty() {
+            return Err("Deleted pages need a title pattern or a start date".to_string());
+        }
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        30
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let sql = self.sql_deleted_pages();
+        let mut conn = platform.state().get_wiki_db(&self.wiki).await?;
+        let rows = conn.query_as::<(Vec<u8>, NamespaceID)>(sql).await?;
+        conn.close().await?;
+
+        let ret = PageList::new_from_wiki_with_capacity(&self.wiki, rows.len());
+        rows.iter()
+            .map(|(title, namespace)| PageListEntry::new(Title::new(&String::from_utf8_lossy(title), *namespace)))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
+        Ok(ret)
+    }
+}
+
+impl SourceDeletedPages {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// "Foo *" => "Foo\_%"
+    fn title_pattern_as_like(pattern: &str) -> String {
+        safe_sql::escape_like(&pattern.replace(' ', "_")).replace('*', "%")
+    }
+
+    fn sql_deleted_pages(&self) -> SQLtuple {
+        let namespaces: Vec<String> = self.namespaces.iter().map(|ns| ns.to_string()).collect();
+        let mut sql: SQLtuple = (
+            format!("SELECT DISTINCT ar_title,ar_namespace FROM archive WHERE ar_namespace IN ({})", namespaces.join(",")),
+            vec![],
+        );
+        if !self.title_pattern.is_empty() {
+            sql.0 += " AND ar_title LIKE ?";
+            sql.1.push(MyValue::Bytes(Self::title_pattern_as_like(&self.title_pattern).into()));
+        }
+        if !self.since.is_empty() || !self.until.is_empty() {
+            sql.0 += " AND EXISTS (SELECT * FROM logging WHERE log_type='delete' AND log_action='delete' AND log_namespace=ar_namespace AND log_title=ar_title";
+            if !self.since.is_empty() {
+                sql.0 += " AND log_timestamp>=?";
+                sql.1.push(MyValue::Bytes(self.since.to_owned().into()));
+            }
+            if !self.until.is_empty() {
+                sql.0 += " AND log_timestamp<=?";
+                sql.1.push(MyValue::Bytes(self.until.to_owned().into()));
+            }
+            sql.0 += ")";
+        }
+        sql.0 += " AND NOT EXISTS (SELECT * FROM page WHERE page_namespace=ar_namespace AND page_title=ar_title)";
+        sql
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+static DEFAULT_LOG_DAYS: i64 = 30;
+
+/// Pages from the logging table: log entries of `log_type` (eg "move", "protect", "delete"), optionally with `log_action`
+/// (eg "move_prot"), by `log_user`, between `log_since` (by default 30 days ago) and `log_until`.
+/// For moves, this is the old title.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceLog {
+    wiki: String,
+    log_type: String,
+    log_action: String,
+    user: String,
+    since: String,
+    until: String,
+}
+
+#[async_trait]
+impl DataSource for SourceLog {
+    fn name(&self) -> String {
+        "log".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        platform.has_param("log_type")
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform
+            .get_main_wiki()
+            .ok_or_else(|| "SourceLog::parse_params: no wiki".to_string())?;
+        self.log_type = platform.get_param_blank("log_type").trim().to_string();
+        self.log_action = platform.get_param_blank("log_action").trim().to_string();
+        self.user = platform.get_param_blank("log_user").trim().replace('_', " ");
+        self.since = platform.get_param_blank("log_since");
+        if self.since.is_empty() {
+            let utc: DateTime<Utc> = Utc::now() - Duration::days(DEFAULT_LOG_DAYS);
+            self.since = utc.format("%Y%m%d%H%M%S").to_string();
+        }
+        self.until = platform.get_param_blank("log_until");
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        Some(self.wiki.to_owned())
+    }
+
+    fn cost(&self) -> usize {
+        20
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        let sql = self.sql_log();
+        let mut conn = platform.state().get_wiki_db(&self.wiki).await?;
+        let rows = conn.query_as::<(Vec<u8>, NamespaceID)>(sql).await?;
+        conn.close().await?;
+
+        let ret = PageList::new_from_wiki_with_capacity(&self.wiki, rows.len());
+        rows.iter()
+            .map(|(title, namespace)| PageListEntry::new(Title::new(&String::from_utf8_lossy(title), *namespace)))
+            .for_each(|entry| ret.add_entry(entry).unwrap_or(()));
+        Ok(ret)
+    }
+}
+
+impl SourceLog {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    fn sql_log(&self) -> SQLtuple {
+        let mut sql: SQLtuple = (
+            "SELECT DISTINCT log_title,log_namespace FROM logging WHERE log_type=? AND log_timestamp>=?".to_string(),
+            vec![
+                MyValue::Bytes(self.log_type.to_owned().into()),
+                MyValue::Bytes(self.since.to_owned().into()),
+            ],
+        );
+        if !self.until.is_empty() {
+            sql.0 += " AND log_timestamp<=?";
+            sql.1.push(MyValue::Bytes(self.until.to_owned().into()));
+        }
+        if !self.log_action.is_empty() {
+            sql.0 += " AND log_action=?";
+            sql.1.push(MyValue::Bytes(self.log_action.to_owned().into()));
+        }
+        if !self.user.is_empty() {
+            sql.0 += " AND log_actor IN (SELECT actor_id FROM actor WHERE actor_name=?)";
+            sql.1.push(MyValue::Bytes(self.user.to_owned().into()));
+        }
+        // Suppressed or deleted targets
+        sql.0 += " AND log_deleted&1=0";
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_url_extract_titles() {
+        let mut source = SourceUrl::new();
+        assert_eq!(source.extract_titles("Foo bar\n\nBaz\n").unwrap(), vec!["Foo_bar", "Baz"]);
+        source.line_regex = Regex::new(r"^\* \[\[(.+?)\]\]").ok();
+        assert_eq!(source.extract_titles("* [[Foo]]\nnope\n* [[Bar baz]] x").unwrap(), vec!["Foo", "Bar_baz"]);
+        source.json_path = JsonPath::parse("$.pages[*].title").ok();
+        assert_eq!(source.extract_titles(r#"{"pages":[{"title":"A b"}]}"#).unwrap(), vec!["A_b"]);
+        assert!(source.extract_titles("not json").is_err());
+    }
+
+    #[test]
+    fn edit_tags_sql() {
+        assert_eq!(SourceEditTags::parse_filter_ids("12, 7\n3").unwrap(), vec![12, 7, 3]);
+        assert!(SourceEditTags::parse_filter_ids("12,x").is_err());
+        let source = SourceEditTags {
+            tags: vec!["mw-reverted".to_string()],
+            abuse_filters: vec![12, 7],
+            since: "20200101000000".to_string(),
+            ..Default::default()
+        };
+        let sql = source.sql_tags().unwrap();
+        assert!(sql.0.ends_with("ctd_name IN (?) AND rev_timestamp>=?"));
+        assert_eq!(sql.1.len(), 2);
+        let sql = source.sql_abuse_filters().unwrap();
+        assert!(sql.0.contains("afl_filter_id IN (12,7) AND afl_timestamp>=?"));
+        assert!(SourceEditTags::new().sql_tags().is_none());
+    }
+
+    #[test]
+    fn expand_items_placeholder() {
+        let items = vec!["Q1".to_string(), "Q42".to_string()];
+        assert_eq!(
+            SourceSparql::expand_items_placeholder("SELECT ?item { %PETSCAN_ITEMS% ?item wdt:P31 wd:Q5 }", &items),
+            "SELECT ?item { VALUES ?item { wd:Q1 wd:Q42 } ?item wdt:P31 wd:Q5 }"
+        );
+        assert_eq!(
+            SourceSparql::expand_items_placeholder("SELECT ?q { %PETSCAN_ITEMS:q% }", &items),
+            "SELECT ?q { VALUES ?q { wd:Q1 wd:Q42 } }"
+        );
+        assert!(SourceSparql::uses_items_placeholder("%PETSCAN_ITEMS:x%"));
+        assert!(!SourceSparql::uses_items_placeholder("SELECT ?item {}"));
+    }
+
+    #[test]
+    fn user_contributions() {
+        let j = json!({"query":{"globaluserinfo":{"merged":[
+            {"wiki":"dewiki","editcount":12},{"wiki":"metawiki","editcount":0},{"wiki":"enwiki","editcount":340}
+        ]}}});
+        assert_eq!(SourceUserContributions::wikis_from_globaluserinfo(&j).unwrap(), vec!["enwiki", "dewiki"]);
+        assert!(SourceUserContributions::wikis_from_globaluserinfo(&json!({"query":{"globaluserinfo":{"missing":""}}})).is_err());
+        let source = SourceUserContributions {
+            user: "Foo Bar".to_string(),
+            since: "20200101000000".to_string(),
+            until: "20200201000000".to_string(),
+            ..Default::default()
+        };
+        let sql = source.sql_contributions();
+        assert!(sql.0.contains("FROM page,revision_userindex WHERE"));
+        assert!(sql.0.ends_with("actor_name=?) AND rev_timestamp>=? AND rev_timestamp<=? LIMIT 100000"));
+        assert_eq!(sql.1.len(), 3);
+    }
+
+    #[test]
+    fn deleted_pages_sql() {
+        assert_eq!(SourceDeletedPages::title_pattern_as_like("Foo 100%*"), "Foo\\_100\\%%");
+        let source = SourceDeletedPages {
+            namespaces: vec![0, 2],
+            title_pattern: "Foo*".to_string(),
+            since: "20200101000000".to_string(),
+            ..Default::default()
+        };
+        let sql = source.sql_deleted_pages();
+        assert!(sql.0.starts_with("SELECT DISTINCT ar_title,ar_namespace FROM archive WHERE ar_namespace IN (0,2) AND ar_title LIKE ?"));
+        assert!(sql.0.contains("AND log_timestamp>=?)"));
+        assert_eq!(sql.1.len(), 2);
+    }
+
+    #[test]
+    fn log_sql() {
+        let source = SourceLog {
+            log_type: "protect".to_string(),
+            log_action: "move_prot".to_string(),
+            user: "Foo Bar".to_string(),
+            since: "20200101000000".to_string(),
+            ..Default::default()
+        };
+        let sql = source.sql_log();
+        assert!(sql.0.starts_with("SELECT DISTINCT log_title,log_namespace FROM logging WHERE log_type=? AND log_timestamp>=? AND log_action=? AND log_actor IN"));
+        assert_eq!(sql.1.len(), 4);
     }
 }
@@ -0,0 +1,420 @@
+use crate::datasource::DataSource;
+use crate::datasource_database::{SourceDatabaseParameters, UNLIMITED_CATEGORY_DEPTH};
+use crate::pagelist::*;
+use crate::platform::Platform;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wikibase::mediawiki::api::{Api, NamespaceID};
+use wikibase::mediawiki::title::Title;
+
+/// Each list request stops after this many pages; lists that reach it are incomplete
+pub static MAX_API_SOURCE_PAGES: usize = 50000;
+/// Subcategories are one request each, so trees are much smaller than with the database
+pub static MAX_API_CATEGORY_TREE_NODES: usize = 1000;
+/// Database filters that this source can not apply; it warns if any is set
+static DATABASE_ONLY_PARAMS: &[&str] = &[
+    "larger",
+    "smaller",
+    "minlinks",
+    "maxlinks",
+    "max_age",
+    "only_new",
+    "before",
+    "after",
+    "created_before",
+    "created_after",
+    "edited_before",
+    "edited_after",
+    "last_edit_summary",
+    "last_edit_tag",
+    "ores_type",
+    "orphans_only",
+    "dead_ends_only",
+    "content_model",
+    "page_lang",
+    "since_rev0",
+];
+
+/// The category, template, and link lists via the MediaWiki API (list=categorymembers, list=embeddedin,
+/// list=backlinks, generator=links), for wikis without a replica database, like third-party installations.
+/// Used instead of SourceDatabase for those wikis; filters that need the database are not applied.
+#[derive(Debug, Clone, Default)]
+pub struct SourceApi {
+    wiki: Option<String>,
+    combine_union: bool,
+    categories: Vec<(String, u16)>,
+    negcats: Vec<(String, u16)>,
+    templates_yes: Vec<String>,
+    templates_any: Vec<String>,
+    templates_no: Vec<String>,
+    links_to_all: Vec<String>,
+    links_to_any: Vec<String>,
+    links_to_no: Vec<String>,
+    linked_from_all: Vec<String>,
+    linked_from_any: Vec<String>,
+    linked_from_none: Vec<String>,
+    namespace_ids: Vec<usize>,
+    category_tree_truncated: bool,
+    /// Lists are being loaded for exclusion; an incomplete list would leave pages in that should be removed
+    excluding: bool,
+    /// A list for inclusion was cut at MAX_API_SOURCE_PAGES
+    list_truncated: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl DataSource for SourceApi {
+    fn name(&self) -> String {
+        "api".to_string()
+    }
+
+    fn can_run(&self, platform: &Platform) -> bool {
+        let has_lists = platform.has_param("categories")
+            || platform.has_param("templates_yes")
+            || platform.has_param("templates_any")
+            || platform.has_param("outlinks_yes")
+            || platform.has_param("outlinks_any")
+            || platform.has_param("links_to_all")
+            || platform.has_param("links_to_any");
+        has_lists && !platform.has_replica_db()
+    }
+
+    fn parse_params(&mut self, platform: &Platform) -> Result<(), String> {
+        self.wiki = platform.get_main_wiki();
+        if self.wiki.is_none() {
+            return Err("SourceApi: No wiki".to_string());
+        }
+        let depth = platform
+            .get_param("depth")
+            .and_then(|depth| SourceDatabaseParameters::parse_depth(&depth))
+            .unwrap_or(0);
+        let depth_neg = platform
+            .get_param("negcats_depth")
+            .and_then(|depth| SourceDatabaseParameters::parse_depth(&depth))
+            .unwrap_or(depth);
+        self.categories = Self::parse_categories(&platform.get_param_as_vec("categories", "\n"), depth);
+        self.negcats = Self::parse_categories(&platform.get_param_as_vec("negcats", "\n"), depth_neg);
+        self.combine_union = self.categories.len() == 1 || platform.get_param_blank("combination") == "union";
+        self.templates_yes = platform.get_param_as_vec("templates_yes", "\n");
+        self.templates_any = platform.get_param_as_vec("templates_any", "\n");
+        self.templates_no = platform.get_param_as_vec("templates_no", "\n");
+        self.links_to_all = platform.get_param_as_vec("links_to_all", "\n");
+        self.links_to_any = platform.get_param_as_vec("links_to_any", "\n");
+        self.links_to_no = platform.get_param_as_vec("links_to_no", "\n");
+        self.linked_from_all = platform.get_param_as_vec("outlinks_yes", "\n");
+        self.linked_from_any = platform.get_param_as_vec("outlinks_any", "\n");
+        self.linked_from_none = platform.get_param_as_vec("outlinks_no", "\n");
+        self.namespace_ids = platform.form_parameters().ns.iter().cloned().collect();
+        Ok(())
+    }
+
+    fn wiki(&self) -> Option<String> {
+        self.wiki.to_owned()
+    }
+
+    fn cost(&self) -> usize {
+        let lists = self.categories.len()
+            + self.templates_yes.len()
+            + self.templates_any.len()
+            + self.links_to_all.len()
+            + self.links_to_any.len()
+            + self.linked_from_all.len()
+            + self.linked_from_any.len();
+        200 + 50 * lists
+    }
+
+    async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
+        platform.set_stage("loading lists from the API...");
+        let wiki = match &self.wiki {
+            Some(wiki) => wiki.to_owned(),
+            None => return Err("SourceApi::run: No wiki".to_string()),
+        };
+        let api = platform.state().get_api_for_wiki(wiki.to_owned()).await?;
+        if DATABASE_ONLY_PARAMS.iter().any(|param| platform.has_param(param)) {
+            platform.warn("<span tt='warn_api_source_filters'></span>".to_string())?;
+        }
+
+        let mut ret: Option<HashSet<Title>> = None;
+        let mut category_sets = vec![];
+        for (category, depth) in self.categories.clone() {
+            category_sets.push(self.get_category_members(&api, &category, depth).await?);
+        }
+        if !category_sets.is_empty() {
+            ret = Some(Self::combine(category_sets, self.combine_union));
+        }
+        for template in &self.templates_yes {
+            let pages = self.get_list(&api, "embeddedin", "ei", &Self::full_title(&api, template, 10)).await?;
+            ret = Self::intersect(ret, Some(pages));
+        }
+        ret = Self::intersect(ret, self.get_any(&api, "embeddedin", "ei", &self.templates_any, 10).await?);
+        for target in &self.links_to_all {
+            let pages = self.get_list(&api, "backlinks", "bl", &Self::full_title(&api, target, 0)).await?;
+            ret = Self::intersect(ret, Some(pages));
+        }
+        ret = Self::intersect(ret, self.get_any(&api, "backlinks", "bl", &self.links_to_any, 0).await?);
+        for source in &self.linked_from_all {
+            let pages = self.get_links_from(&api, source).await?;
+            ret = Self::intersect(ret, Some(pages));
+        }
+        if !self.linked_from_any.is_empty() {
+            let mut any = HashSet::new();
+            for source in &self.linked_from_any {
+                any.extend(self.get_links_from(&api, source).await?);
+            }
+            ret = Self::intersect(ret, Some(any));
+        }
+
+        let mut ret = ret.unwrap_or_default();
+        self.excluding = true;
+        for (category, depth) in self.negcats.clone() {
+            let members = self.get_category_members(&api, &category, depth).await?;
+            ret.retain(|title| !members.contains(title));
+        }
+        for template in &self.templates_no {
+            let pages = self.get_list(&api, "embeddedin", "ei", &Self::full_title(&api, template, 10)).await?;
+            ret.retain(|title| !pages.contains(title));
+        }
+        for target in &self.links_to_no {
+            let pages = self.get_list(&api, "backlinks", "bl", &Self::full_title(&api, target, 0)).await?;
+            ret.retain(|title| !pages.contains(title));
+        }
+        for source in &self.linked_from_none {
+            let pages = self.get_links_from(&api, source).await?;
+            ret.retain(|title| !pages.contains(title));
+        }
+        if !self.namespace_ids.is_empty() {
+            ret.retain(|title| self.namespace_ids.contains(&(title.namespace_id() as usize)));
+        }
+
+        if self.category_tree_truncated {
+            platform.warn("<span tt='warn_category_tree_truncated'></span>".to_string())?;
+        }
+        if self.list_truncated.load(Ordering::Relaxed) {
+            platform.warn(format!("<span tt='warn_api_source_truncated'></span> {}", MAX_API_SOURCE_PAGES))?;
+        }
+        let pagelist = PageList::new_from_wiki(&wiki);
+        pagelist.set_entries(ret.into_iter().map(PageListEntry::new).collect())?;
+        Ok(pagelist)
+    }
+}
+
+impl SourceApi {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// "Name|depth" lines, as for the database source
+    fn parse_categories(lines: &[String], default_depth: u16) -> Vec<(String, u16)> {
+        lines
+            .iter()
+            .filter_map(|line| {
+                let mut parts = line.split('|');
+                let name = parts.next()?.trim().to_string();
+                let depth = match parts.next() {
+                    Some(depth) => SourceDatabaseParameters::parse_depth(depth)?,
+                    None => default_depth,
+                };
+                Some((name, depth)).filter(|(name, _)| !name.is_empty())
+            })
+            .collect()
+    }
+
+    fn full_title(api: &Api, name: &str, namespace_id: NamespaceID) -> String {
+        let title = Title::new(name.trim(), namespace_id);
+        title.full_pretty(api).unwrap_or_else(|| name.trim().to_string())
+    }
+
+    fn namespace_param(&self) -> String {
+        self.namespace_ids
+            .iter()
+            .map(|ns| ns.to_string())
+            .collect::<Vec<String>>()
+            .join("|")
+    }
+
+    /// Intersection of the sets, or with `union` their union
+    fn combine(sets: Vec<HashSet<Title>>, union: bool) -> HashSet<Title> {
+        let mut sets = sets.into_iter();
+        let first = sets.next().unwrap_or_default();
+        sets.fold(first, |acc, set| match union {
+            true => acc.union(&set).cloned().collect(),
+            false => acc.intersection(&set).cloned().collect(),
+        })
+    }
+
+    /// `set` restricted to `acc`; `None` stands for "no restriction yet", in both
+    fn intersect(acc: Option<HashSet<Title>>, set: Option<HashSet<Title>>) -> Option<HashSet<Title>> {
+        match (acc, set) {
+            (Some(acc), Some(set)) => Some(acc.intersection(&set).cloned().collect()),
+            (acc, None) => acc,
+            (None, set) => set,
+        }
+    }
+
+    /// Pages on any of the lists; `None` if there are no lists
+    async fn get_any(
+        &self,
+        api: &Api,
+        list: &str,
+        prefix: &str,
+        names: &[String],
+        namespace_id: NamespaceID,
+    ) -> Result<Option<HashSet<Title>>, String> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        let mut ret = HashSet::new();
+        for name in names {
+            ret.extend(self.get_list(api, list, prefix, &Self::full_title(api, name, namespace_id)).await?);
+        }
+        Ok(Some(ret))
+    }
+
+    /// Runs list=`list` (with the parameter `prefix`, eg "ei" for embeddedin) for the page `title`
+    async fn get_list(&self, api: &Api, list: &str, prefix: &str, title: &str) -> Result<HashSet<Title>, String> {
+        self.query_titles(api, list, &self.list_params(list, prefix, title, true)).await
+    }
+
+    fn list_params(&self, list: &str, prefix: &str, title: &str, with_namespaces: bool) -> Vec<(String, String)> {
+        let mut params = vec![
+            ("action".to_string(), "query".to_string()),
+            ("list".to_string(), list.to_string()),
+            (format!("{}title", prefix), title.to_string()),
+            (format!("{}limit", prefix), "max".to_string()),
+        ];
+        if with_namespaces && !self.namespace_ids.is_empty() {
+            params.push((format!("{}namespace", prefix), self.namespace_param()));
+        }
+        params
+    }
+
+    async fn query_titles(&self, api: &Api, key: &str, params: &[(String, String)]) -> Result<HashSet<Title>, String> {
+        let params: HashMap<String, String> = params.iter().cloned().collect();
+        let result = api
+            .get_query_api_json_limit(&params, Some(MAX_API_SOURCE_PAGES))
+            .await
+            .map_err(|e| format!("SourceApi: {:?}", e))?;
+        let titles = Self::titles_from_result(&result, key);
+        if titles.len() >= MAX_API_SOURCE_PAGES {
+            if self.excluding {
+                return Err(format!(
+                    "SourceApi: A list to exclude has more than {} pages, so the result would be wrong",
+                    MAX_API_SOURCE_PAGES
+                ));
+            }
+            self.list_truncated.store(true, Ordering::Relaxed);
+        }
+        Ok(titles.iter().map(|title| Title::new_from_full(title, api)).collect())
+    }
+
+    /// The "title"s in `result["query"][key]`, which is an array for lists, and an object for generators
+    fn titles_from_result(result: &Value, key: &str) -> Vec<String> {
+        let pages: Vec<&Value> = match &result["query"][key] {
+            Value::Array(pages) => pages.iter().collect(),
+            Value::Object(pages) => pages.values().collect(),
+            _ => vec![],
+        };
+        pages
+            .iter()
+            .filter(|page| page.get("missing").is_none())
+            .filter_map(|page| page["title"].as_str())
+            .map(|title| title.to_string())
+            .collect()
+    }
+
+    /// The pages `source` links to (generator=links)
+    async fn get_links_from(&self, api: &Api, source: &str) -> Result<HashSet<Title>, String> {
+        let mut params = vec![
+            ("action".to_string(), "query".to_string()),
+            ("generator".to_string(), "links".to_string()),
+            ("titles".to_string(), Self::full_title(api, source, 0)),
+            ("gpllimit".to_string(), "max".to_string()),
+        ];
+        if !self.namespace_ids.is_empty() {
+            params.push(("gplnamespace".to_string(), self.namespace_param()));
+        }
+        self.query_titles(api, "pages", &params).await
+    }
+
+    /// Members of the category and its subcategories down to `depth`, breadth-first
+    async fn get_category_members(&mut self, api: &Api, category: &str, depth: u16) -> Result<HashSet<Title>, String> {
+        let root = Self::full_title(api, category, 14);
+        let mut visited: HashSet<String> = HashSet::new();
+        visited.insert(root.to_owned());
+        let mut level = vec![root];
+        let mut depth_left = depth;
+        while depth_left > 0 && !level.is_empty() {
+            let mut next_level = vec![];
+            for category in &level {
+                let mut params = self.list_params("categorymembers", "cm", category, false);
+                params.push(("cmtype".to_string(), "subcat".to_string()));
+                for subcat in self.query_titles(api, "categorymembers", &params).await? {
+                    let subcat = subcat.full_pretty(api).unwrap_or_default();
+                    if visited.len() >= MAX_API_CATEGORY_TREE_NODES {
+                        if self.excluding {
+                            return Err(format!(
+                                "SourceApi: The category tree to exclude has more than {} categories, so the result would be wrong",
+                                MAX_API_CATEGORY_TREE_NODES
+                            ));
+                        }
+                        self.category_tree_truncated = true;
+                        break;
+                    }
+                    if !subcat.is_empty() && visited.insert(subcat.to_owned()) {
+                        next_level.push(subcat);
+                    }
+                }
+            }
+            level = next_level;
+            if depth_left != UNLIMITED_CATEGORY_DEPTH {
+                depth_left -= 1;
+            }
+        }
+        let mut ret = HashSet::new();
+        for category in &visited {
+            let params = self.list_params("categorymembers", "cm", category, true);
+            ret.extend(self.query_titles(api, "categorymembers", &params).await?);
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_categories() {
+        let lines = vec!["Foo".to_string(), "Bar|2".to_string(), " |3".to_string(), "Baz|*".to_string()];
+        assert_eq!(
+            SourceApi::parse_categories(&lines, 1),
+            vec![
+                ("Foo".to_string(), 1),
+                ("Bar".to_string(), 2),
+                ("Baz".to_string(), UNLIMITED_CATEGORY_DEPTH)
+            ]
+        );
+    }
+
+    #[test]
+    fn combine() {
+        let set = |titles: &[&str]| -> HashSet<Title> { titles.iter().map(|t| Title::new(t, 0)).collect() };
+        let sets = vec![set(&["A", "B"]), set(&["B", "C"])];
+        assert_eq!(SourceApi::combine(sets.clone(), false), set(&["B"]));
+        assert_eq!(SourceApi::combine(sets, true), set(&["A", "B", "C"]));
+        assert_eq!(SourceApi::intersect(None, Some(set(&["A"]))), Some(set(&["A"])));
+        assert_eq!(SourceApi::intersect(Some(set(&["A", "B"])), Some(set(&["B"]))), Some(set(&["B"])));
+        assert_eq!(SourceApi::intersect(None, None), None);
+    }
+
+    #[test]
+    fn titles_from_result() {
+        let list = json!({"query":{"embeddedin":[{"pageid":1,"ns":0,"title":"Foo"},{"ns":14,"title":"Category:Bar"}]}});
+        assert_eq!(SourceApi::titles_from_result(&list, "embeddedin"), vec!["Foo", "Category:Bar"]);
+        let generator = json!({"query":{"pages":{"12":{"ns":0,"title":"Foo"},"-1":{"ns":0,"title":"Red","missing":""}}}});
+        assert_eq!(SourceApi::titles_from_result(&generator, "pages"), vec!["Foo"]);
+        assert!(SourceApi::titles_from_result(&json!({}), "pages").is_empty());
+    }
+}
@@ -1,21 +1,23 @@
-use async_recursion::async_recursion;
 use futures::future::join_all;
 use async_trait::async_trait;
 use crate::app_state::AppState;
+use crate::category_tree::{CategoryTraversal, CategoryTree};
 use crate::datasource::DataSource;
 use crate::datasource::SQLtuple;
 use crate::pagelist::*;
 use crate::platform::{Platform, PAGE_BATCH_SIZE};
+use crate::progress::Progress;
+use crate::safe_sql;
+use crate::title_normalization;
+use crate::wiki_db::{column, WikiDbConnection};
 use chrono::prelude::*;
 use chrono::Duration;
 use core::ops::Sub;
-use mysql_async::prelude::Queryable;
+use htmlescape::encode_minimal;
 use mysql_async::Value as MyValue;
-use mysql_async::from_row;
-use mysql_async as my;
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::sync::RwLock;
+use std::sync::Arc;
 use wikibase::mediawiki::api::{Api, NamespaceID};
 use wikibase::mediawiki::title::Title;
 
@@ -30,6 +32,9 @@ struct DsdbParams {
     is_before_after_done: bool,
 }
 
+/// Category depth for "depth=*"; the tree is then only limited by `category_tree_max_nodes`
+pub static UNLIMITED_CATEGORY_DEPTH: u16 = u16::MAX;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceDatabaseCatDepth {
     name: String,
@@ -60,10 +65,30 @@ pub struct SourceDatabaseParameters {
     last_edit_bot: String,
     last_edit_anon: String,
     last_edit_flagged: String,
+    last_edit_minor: String,
+    last_edit_summary_regex: String,
+    last_edit_tag: String,
     redirects: String,
     soft_redirects: String,
     disambiguation_pages: String,
+    uncategorized: String,
+    uncategorized_ignore_hidden: bool,
+    ignore_hidden_categories: bool,
+    /// Keep the traversed category tree, for `format=category_graph`
+    record_category_tree: bool,
+    /// Keep the trees of the positive categories, for `add_category_membership`
+    record_category_trees: bool,
+    orphans_only: bool,
+    dead_ends_only: bool,
+    title_prefixes: Vec<String>,
+    title_not_prefixes: Vec<String>,
+    /// "page", "subcat", and/or "file" (categorylinks.cl_type); empty for all
+    category_member_types: Vec<String>,
     page_wikidata_item: String,
+    /// page_content_model values, eg "wikitext", "Scribunto"; empty for all
+    content_models: Vec<String>,
+    /// page_lang values; "default" for pages in the wiki language (NULL)
+    page_languages: Vec<String>,
     larger: Option<usize>,
     smaller: Option<usize>,
     since_rev0: Option<usize>,
@@ -73,11 +98,16 @@ pub struct SourceDatabaseParameters {
     gather_link_count: bool,
     cat_pos: Vec<String>,
     cat_neg: Vec<String>,
+    /// Default depth for positive categories
     depth: u16,
+    /// Default depth for negative categories; eg 0 to exclude only direct members, while positive ones use subcategories
+    depth_neg: u16,
     max_age: Option<i64>,
     only_new_since: bool,
-    before: String,
-    after: String,
+    created_before: String,
+    created_after: String,
+    edited_before: String,
+    edited_after: String,
     use_new_category_mode: bool,
     category_namespace_is_case_insensitive: bool,
     template_namespace_is_case_insensitive: bool,
@@ -93,6 +123,7 @@ impl SourceDatabaseParameters {
             last_edit_bot: "both".to_string(),
             last_edit_anon: "both".to_string(),
             last_edit_flagged: "both".to_string(),
+            last_edit_minor: "both".to_string(),
             use_new_category_mode: true,
             category_namespace_is_case_insensitive: true,
             template_namespace_is_case_insensitive: true,
@@ -101,16 +132,14 @@ impl SourceDatabaseParameters {
     }
 
     pub async fn db_params(platform: &Platform) -> SourceDatabaseParameters {
-        let depth_signed: i32 = platform
+        let depth: u16 = platform
             .get_param("depth")
-            .unwrap_or_else(|| "0".to_string())
-            .parse::<i32>()
+            .and_then(|depth| Self::parse_depth(&depth))
             .unwrap_or(0);
-        let depth: u16 = if depth_signed < 0 {
-            999
-        } else {
-            depth_signed as u16
-        };
+        let depth_neg: u16 = platform
+            .get_param("negcats_depth")
+            .and_then(|depth| Self::parse_depth(&depth))
+            .unwrap_or(depth);
         let mut combine = match platform.form_parameters().params.get("combination") {
             Some(x) => {
                 if x == "union" {
@@ -133,8 +162,10 @@ impl SourceDatabaseParameters {
             max_age: platform
                 .get_param("max_age")
                 .map(|x| x.parse::<i64>().unwrap_or(0)),
-            before: platform.get_param_blank("before"),
-            after: platform.get_param_blank("after"),
+            created_before: platform.get_param_blank("created_before"),
+            created_after: platform.get_param_blank("created_after"),
+            edited_before: platform.get_param_blank("edited_before"),
+            edited_after: platform.get_param_blank("edited_after"),
             templates_yes: vec![],
             templates_any: vec![],
             templates_no: vec![],
@@ -150,12 +181,18 @@ impl SourceDatabaseParameters {
             last_edit_bot: platform.get_param_default("edits[bots]", "both"),
             last_edit_anon: platform.get_param_default("edits[anons]", "both"),
             last_edit_flagged: platform.get_param_default("edits[flagged]", "both"),
-            gather_link_count: platform.has_param("minlinks") || platform.has_param("maxlinks"),
+            last_edit_minor: platform.get_param_default("edits[minor]", "both"),
+            last_edit_summary_regex: platform.get_param_blank("last_edit_summary"),
+            last_edit_tag: platform.get_param_blank("last_edit_tag").trim().to_string(),
+            gather_link_count: platform.has_param("minlinks")
+                || platform.has_param("maxlinks")
+                || platform.has_param("add_outlink_count"),
             page_image: platform.get_param_default("page_image", "any"),
             page_wikidata_item: platform.get_param_default("wikidata_item", "any"),
             ores_type: platform.get_param_blank("ores_type"),
             ores_prediction: platform.get_param_default("ores_prediction", "any"),
             depth,
+            depth_neg,
             cat_pos,
             cat_neg: platform.get_param_as_vec("negcats", "\n"),
             ores_prob_from: platform
@@ -167,6 +204,27 @@ impl SourceDatabaseParameters {
             redirects: platform.get_param_blank("show_redirects"),
             soft_redirects: platform.get_param_blank("show_soft_redirects"),
             disambiguation_pages: platform.get_param_blank("show_disambiguation_pages"),
+            uncategorized: platform.get_param_blank("show_uncategorized"),
+            uncategorized_ignore_hidden: platform.has_param("uncategorized_ignore_hidden")
+                || platform.has_param("ignore_hidden_categories"),
+            ignore_hidden_categories: platform.has_param("ignore_hidden_categories"),
+            record_category_tree: platform.get_param_blank("format") == "category_graph",
+            record_category_trees: platform.has_param("add_category_membership"),
+            orphans_only: platform.has_param("orphans_only"),
+            dead_ends_only: platform.has_param("dead_ends_only"),
+            title_prefixes: platform.get_title_prefixes("title_prefix").await,
+            title_not_prefixes: platform.get_title_prefixes("title_not_prefix").await,
+            category_member_types: Self::parse_category_member_types(&|key| platform.has_param(key)),
+            content_models: platform
+                .get_param_as_vec("content_model", ",")
+                .iter()
+                .map(|model| Self::content_model_name(model))
+                .collect(),
+            page_languages: platform
+                .get_param_as_vec("page_lang", ",")
+                .iter()
+                .map(|lang| lang.to_lowercase())
+                .collect(),
             minlinks: platform.usize_option_from_param("minlinks"),
             maxlinks: platform.usize_option_from_param("maxlinks"),
             larger: platform.usize_option_from_param("larger"),
@@ -183,6 +241,7 @@ impl SourceDatabaseParameters {
             category_namespace_is_case_insensitive: !ns14_case_sensitive,
             template_namespace_is_case_insensitive: !ns10_case_sensitive,
         };
+        ret.apply_legacy_before_after(platform.get_param_blank("before"), platform.get_param_blank("after"));
         ret.templates_yes = Self::vec_to_ucfirst(
             platform.get_param_as_vec("templates_yes", "\n"),
             ret.template_namespace_is_case_insensitive,
@@ -198,13 +257,106 @@ impl SourceDatabaseParameters {
         ret
     }
 
-    pub fn s2u_ucfirst(s: &str, is_case_insensitive: bool) -> String {
-        match is_case_insensitive {
-            true => Title::spaces_to_underscores(&Title::first_letter_uppercase(s)),
-            false => Title::spaces_to_underscores(s),
+    /// The legacy "before"/"after" apply to the creation date with "only_new", otherwise to the last edit;
+    /// the explicit created_*/edited_* parameters take precedence
+    fn apply_legacy_before_after(&mut self, before: String, after: String) {
+        let (target_before, target_after) = if self.only_new_since {
+            (&mut self.created_before, &mut self.created_after)
+        } else {
+            (&mut self.edited_before, &mut self.edited_after)
+        };
+        if target_before.is_empty() {
+            *target_before = before;
+        }
+        if target_after.is_empty() {
+            *target_after = after;
+        }
+    }
+
+    /// Joins the creation (`rev_parent_id=0`) or latest (`rev_id=page_latest`) revision, bounded by timestamps;
+    /// empty if there are no bounds
+    fn sql_revision_timestamps(alias: &str, revision: &str, before: &str, after: &str) -> SQLtuple {
+        let mut sql = Platform::sql_tuple();
+        if before.is_empty() && after.is_empty() {
+            return sql;
+        }
+        sql.0 = format!(
+            " INNER JOIN (revision {}) ON {}.rev_page=p.page_id AND {}.{}",
+            alias, alias, alias, revision
+        );
+        if !before.is_empty() {
+            sql.0 += &format!(" AND {}.rev_timestamp<=?", alias);
+            sql.1.push(MyValue::Bytes(before.into()));
+        }
+        if !after.is_empty() {
+            sql.0 += &format!(" AND {}.rev_timestamp>=?", alias);
+            sql.1.push(MyValue::Bytes(after.into()));
+        }
+        sql.0 += " ";
+        sql
+    }
+
+    /// Category member types from the "cm_type_*" checkboxes; none, or all, checked means all types
+    /// The spelling page_content_model uses, for case-insensitive input
+    fn content_model_name(model: &str) -> String {
+        match model.to_lowercase().as_str() {
+            "scribunto" | "lua" => "Scribunto".to_string(),
+            "js" => "javascript".to_string(),
+            other => other.to_string(),
         }
     }
 
+    /// Conditions on page_content_model and page_lang; empty if there are none
+    fn sql_content_model_page_lang(content_models: &[String], page_languages: &[String]) -> SQLtuple {
+        let mut sql = Platform::sql_tuple();
+        if !content_models.is_empty() {
+            sql.0 += &format!(" AND p.page_content_model IN ({})", Platform::get_questionmarks(content_models.len()));
+            content_models.iter().for_each(|model| sql.1.push(MyValue::Bytes(model.to_owned().into())));
+        }
+        if !page_languages.is_empty() {
+            let languages: Vec<&String> = page_languages.iter().filter(|lang| *lang != "default").collect();
+            let mut conditions = vec![];
+            if languages.len() < page_languages.len() {
+                conditions.push("p.page_lang IS NULL".to_string());
+            }
+            if !languages.is_empty() {
+                conditions.push(format!("p.page_lang IN ({})", Platform::get_questionmarks(languages.len())));
+                languages.iter().for_each(|lang| sql.1.push(MyValue::Bytes(lang.to_string().into())));
+            }
+            sql.0 += &format!(" AND ({})", conditions.join(" OR "));
+        }
+        sql
+    }
+
+    pub fn parse_category_member_types(has_param: &dyn Fn(&str) -> bool) -> Vec<String> {
+        let ret: Vec<String> = [("cm_type_page", "page"), ("cm_type_subcat", "subcat"), ("cm_type_file", "file")]
+            .iter()
+            .filter(|(key, _)| has_param(key))
+            .map(|(_, cl_type)| cl_type.to_string())
+            .collect();
+        if ret.len() == 3 {
+            vec![]
+        } else {
+            ret
+        }
+    }
+
+    /// Parses a category depth; "*" or a negative number means unlimited (bounded by the category tree node limit)
+    pub fn parse_depth(depth: &str) -> Option<u16> {
+        match depth.trim() {
+            "*" | "\u{221E}" => Some(UNLIMITED_CATEGORY_DEPTH),
+            depth => match depth.parse::<i32>().ok()? {
+                d if d < 0 => Some(UNLIMITED_CATEGORY_DEPTH),
+                d if d >= UNLIMITED_CATEGORY_DEPTH as i32 => Some(UNLIMITED_CATEGORY_DEPTH),
+                d => Some(d as u16),
+            },
+        }
+    }
+
+    pub fn s2u_ucfirst(s: &str, is_case_insensitive: bool) -> String {
+        title_normalization::normalize_ucfirst(s, is_case_insensitive)
+    }
+
     fn vec_to_ucfirst(input: Vec<String>, is_case_insensitive: bool) -> Vec<String> {
         input
             .iter()
@@ -225,6 +377,13 @@ pub struct SourceDatabase {
     has_pos_linked_from: bool,
     params: SourceDatabaseParameters,
     talk_namespace_ids: String,
+    category_tree_truncated: bool,
+    category_traversal: Option<CategoryTraversal>,
+    /// The positive input categories with their trees, if `record_category_trees` is set
+    category_trees: Vec<(String, Vec<String>)>,
+    progress: Progress,
+    /// The category batches add their pages here, and can be drained if the run was cancelled
+    gathered: Option<Arc<PageList>>,
 }
 
 #[async_trait]
@@ -234,22 +393,88 @@ impl DataSource for SourceDatabase {
     }
 
     fn can_run(&self, platform: &Platform) -> bool {
-        platform.has_param("categories")
+        (platform.has_param("categories")
             || platform.has_param("templates_yes")
             || platform.has_param("templates_any")
             || platform.has_param("outlinks_yes")
             || platform.has_param("outlinks_any")
             || platform.has_param("links_to_all")
-            || platform.has_param("links_to_any")
+            || platform.has_param("links_to_any"))
+            && platform.has_replica_db()
+    }
+
+    fn parse_params(&mut self, _platform: &Platform) -> Result<(), String> {
+        // Parameters are read in SourceDatabaseParameters::db_params, which needs to be async
+        match &self.params.wiki {
+            Some(wiki) if wiki != "wiki" => Ok(()),
+            _ => Err(format!("SourceDatabase: Bad wiki '{:?}'", self.params.wiki)),
+        }
+    }
+
+    fn wiki(&self) -> Option<String> {
+        self.params.wiki.to_owned()
+    }
+
+    fn cost(&self) -> usize {
+        let lists = self.params.cat_pos.len()
+            + self.params.templates_yes.len()
+            + self.params.templates_any.len()
+            + self.params.linked_from_all.len()
+            + self.params.linked_from_any.len()
+            + self.params.links_to_all.len()
+            + self.params.links_to_any.len();
+        let depth = if self.params.depth == UNLIMITED_CATEGORY_DEPTH {
+            10
+        } else {
+            self.params.depth as usize
+        };
+        100 + 10 * lists * (depth + 1)
     }
 
     async fn run(&mut self, platform: &Platform) -> Result<PageList, String> {
-        let ret = self.get_pages(&platform.state(), None).await?;
+        self.progress = platform.stage();
+        let wikis = Self::get_multi_wikis(platform)?;
+        let ret = if wikis.is_empty() {
+            self.get_pages(&platform.state(), None).await?
+        } else {
+            self.get_pages_multi_wiki(platform, &wikis).await?
+        };
+        if self.category_tree_truncated {
+            platform.warn("<span tt=\'warn_category_tree_truncated\'></span>".to_string())?;
+        }
+        if let Some(traversal) = self.category_traversal.take() {
+            platform.set_category_traversal(traversal)?;
+        }
+        if wikis.is_empty() && self.params.record_category_trees {
+            if let Some(wiki) = &self.params.wiki {
+                platform.set_category_trees(wiki, std::mem::take(&mut self.category_trees))?;
+            }
+        }
         if ret.is_empty()? {
             platform.warn("<span tt=\'warn_categories\'></span>".to_string())?;
         }
+        if wikis.is_empty() {
+            self.warn_about_missing_titles(platform).await?;
+        }
         Ok(ret)
     }
+
+    async fn estimate_count(&mut self, platform: &Platform) -> Result<Option<usize>, String> {
+        self.progress = platform.stage();
+        if !Self::get_multi_wikis(platform)?.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.count_pages(&platform.state()).await?))
+    }
+
+    fn partial_result(&mut self) -> Option<PageList> {
+        // The batches that were still running are dropped, so this is the only reference left
+        let gathered = Arc::try_unwrap(self.gathered.take()?).ok()?;
+        match gathered.is_empty() {
+            Ok(false) => Some(gathered),
+            _ => None,
+        }
+    }
 }
 
 impl SourceDatabase {
@@ -261,7 +486,144 @@ impl SourceDatabase {
             has_pos_linked_from: false,
             params,
             talk_namespace_ids: String::new(),
+            category_tree_truncated: false,
+            category_traversal: None,
+            category_trees: vec![],
+            progress: Progress::default(),
+            gathered: None,
+        }
+    }
+
+    /// Returns the list of wikis to run the query on, from "multi_wiki" (database names)
+    /// and "multi_wiki_languages" (language codes, combined with "project")
+    fn get_multi_wikis(platform: &Platform) -> Result<Vec<String>, String> {
+        let project = platform.get_param_default("project", "wikipedia");
+        let mut ret: Vec<String> = platform
+            .get_param_blank("multi_wiki")
+            .split(|c| c == ',' || c == '\n')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|wiki| platform.state().fix_wiki_name(wiki))
+            .collect();
+        for language in platform
+            .get_param_blank("multi_wiki_languages")
+            .split(|c| c == ',' || c == '\n')
+            .map(|s| s.trim().replace("_", "-"))
+            .filter(|s| !s.is_empty())
+        {
+            let wiki = platform
+                .get_wiki_for_language_project(&language, &project)
+                .ok_or_else(|| format!("multi_wiki_languages: no {} wiki for language '{}'", project, language))?;
+            ret.push(platform.state().fix_wiki_name(&wiki));
+        }
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    /// Runs the query on several wikis in parallel, and merges the results on the main wiki
+    async fn get_pages_multi_wiki(
+        &self,
+        platform: &Platform,
+        wikis: &[String],
+    ) -> Result<PageList, String> {
+        let main_wiki = platform
+            .get_main_wiki()
+            .ok_or_else(|| "SourceDatabase::get_pages_multi_wiki: No main wiki".to_string())?;
+        let limit = platform.usize_option_from_param("multi_wiki_limit");
+        let state = platform.state();
+        let mut sources: Vec<SourceDatabase> = wikis
+            .iter()
+            .map(|wiki| {
+                let mut params = self.params.clone();
+                params.set_wiki(Some(wiki.to_string()));
+                SourceDatabase::new(params)
+            })
+            .collect();
+        let futures: Vec<_> = sources
+            .iter_mut()
+            .map(|source| source.get_pages(&state, None))
+            .collect();
+        let results = join_all(futures).await;
+
+        let ret = PageList::new_from_wiki(&main_wiki);
+        for (wiki, result) in wikis.iter().zip(results) {
+            let pagelist = result.map_err(|e| format!("Query on {} failed: {}", wiki, e))?;
+            if let Some(limit) = limit {
+                pagelist.limit_entries(limit)?;
+            }
+            ret.union(&pagelist, Some(platform)).await?;
+        }
+        Ok(ret)
+    }
+
+    /// Warns about input categories and templates that do not exist on the wiki, usually typos
+    async fn warn_about_missing_titles(&self, platform: &Platform) -> Result<(), String> {
+        let wiki = match &self.params.wiki {
+            Some(wiki) => wiki.to_owned(),
+            None => return Ok(()),
+        };
+        let mut categories: Vec<String> = self
+            .parse_category_depth(&self.params.cat_pos, 0)
+            .iter()
+            .chain(self.parse_category_depth(&self.params.cat_neg, 0).iter())
+            .map(|c| SourceDatabaseParameters::s2u_ucfirst(&c.name, self.params.category_namespace_is_case_insensitive))
+            .collect();
+        categories.sort();
+        categories.dedup();
+        let mut templates: Vec<String> = vec![];
+        templates.extend(self.params.templates_yes.iter().cloned());
+        templates.extend(self.params.templates_any.iter().cloned());
+        templates.extend(self.params.templates_no.iter().cloned());
+        templates.sort();
+        templates.dedup();
+        if categories.is_empty() && templates.is_empty() {
+            return Ok(());
+        }
+
+        let mut existing_categories: HashSet<String> = HashSet::new();
+        let mut existing_templates: HashSet<String> = HashSet::new();
+        let mut conn = platform.state().get_wiki_db(&wiki).await?;
+        if !categories.is_empty() {
+            // A category can have members without having a page
+            let mut sql : SQLtuple = ("SELECT page_title FROM page WHERE page_namespace=14 AND page_title IN (".to_string(),vec![]);
+            Platform::append_sql(&mut sql, Platform::prep_quote(&categories));
+            sql.0 += ") UNION SELECT cat_title FROM category WHERE cat_pages>0 AND cat_title IN (";
+            Platform::append_sql(&mut sql, Platform::prep_quote(&categories));
+            sql.0 += ")";
+            for row in conn.query(sql).await? {
+                let title = column::<Vec<u8>>(&row, 0)?;
+                existing_categories.insert(String::from_utf8_lossy(&title).into_owned());
+            }
+        }
+        if !templates.is_empty() {
+            let mut sql : SQLtuple = ("SELECT page_title FROM page WHERE page_namespace=10 AND page_title IN (".to_string(),vec![]);
+            Platform::append_sql(&mut sql, Platform::prep_quote(&templates));
+            sql.0 += ")";
+            for row in conn.query(sql).await? {
+                let title = column::<Vec<u8>>(&row, 0)?;
+                existing_templates.insert(String::from_utf8_lossy(&title).into_owned());
+            }
+        }
+        conn.close().await?;
+
+        let missing_categories: Vec<String> = categories
+            .iter()
+            .filter(|c| !existing_categories.contains(*c))
+            .map(|c| encode_minimal(&Title::underscores_to_spaces(c)))
+            .collect();
+        if !missing_categories.is_empty() {
+            platform.warn(format!("<span tt='warn_missing_categories'></span> {}", missing_categories.join(", ")))?;
+        }
+        let missing_templates: Vec<String> = templates
+            .iter()
+            .filter(|t| !existing_templates.contains(*t))
+            .map(|t| encode_minimal(&Title::underscores_to_spaces(t)))
+            .collect();
+        if !missing_templates.is_empty() {
+            platform.warn(format!("<span tt='warn_missing_templates'></span> {}", missing_templates.join(", ")))?;
         }
+        Ok(())
     }
 
     fn parse_category_depth(
@@ -277,14 +639,7 @@ impl SourceDatabase {
                     None => return None,
                 };
                 let depth = match parts.next() {
-                    Some(depth) => {
-                        let depth_signed = depth.parse::<i32>().ok()?;
-                        if depth_signed < 0 {
-                            999
-                        } else {
-                            depth_signed as u16
-                        }
-                    }
+                    Some(depth) => SourceDatabaseParameters::parse_depth(depth)?,
                     None => default_depth,
                 };
                 Some(SourceDatabaseCatDepth { name , depth })
@@ -292,163 +647,53 @@ impl SourceDatabase {
             .collect()
     }
 
-    async fn go_depth_batch(
-        &self,
-        state: &AppState,
-        wiki: &str,
-        categories_batch: Vec<String>,
-        categories_done: &RwLock<HashSet<String>>,
-        new_categories: &RwLock<Vec<String>>,
-    ) -> Result<(), String> {
-        let mut sql : SQLtuple = ("SELECT DISTINCT page_title FROM page,categorylinks WHERE cl_from=page_id AND cl_type='subcat' AND cl_to IN (".to_string(),vec![]);
-        categories_batch.iter().for_each(|c| {
-            // Don't par_iter, already in pool!
-            if let Ok(mut cd) = categories_done.write() {
-                cd.insert(c.to_string());
-            }
-        });
-        Platform::append_sql(&mut sql, Platform::prep_quote(&categories_batch));
-        sql.0 += ")";
-
-        let mut conn = state
-            .get_wiki_db_connection(&wiki)
-            .await? ;
-        let result = conn
-            .exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<Vec<u8>>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
-
-        let mut err : Option<String> = None ;
-        result
-            .iter()
-            .map(|row| String::from_utf8_lossy(&row).into_owned())
-            .for_each(|page_title| {
-                let do_add = match categories_done.read() {
-                    Ok(cd) => !cd.contains(&page_title),
-                    _ => false,
-                };
-                if do_add {
-                    match new_categories.write() {
-                        Ok(mut nc) => { nc.push(page_title.to_owned()); }
-                        Err(e) => { err = Some(e.to_string()); }
-                    }
-                    match categories_done.write() {
-                        Ok(mut cd) => { cd.insert(page_title); }
-                        Err(e) => { err = Some(e.to_string()); }
-                    }
-                }
-            });
-        match err {
-            Some(e) => Err(e),
-            None => Ok(())
-        }
-    }
-
-    #[async_recursion]
-    async fn go_depth(
-        &self,
-        state: &AppState,
-        wiki: &str,
-        categories_done: &RwLock<HashSet<String>>,
-        categories_to_check: &[String],
-        depth: u16,
-    ) -> Result<(), String> {
-        if depth == 0 || categories_to_check.is_empty() {
-            return Ok(());
-        }
-        Platform::profile("DSDB::do_depth begin", Some(categories_to_check.len()));
-
-        let new_categories: Vec<String> = vec![];
-        let new_categories = RwLock::new(new_categories);
-
-        let category_batches = categories_to_check
-            .par_iter()
-            .map(|s|s.to_string())
-            .chunks(PAGE_BATCH_SIZE)
-            .collect::<Vec<Vec<String>>>();
-        let mut futures = vec![] ;
-        for categories_batch in category_batches {
-            let future = self.go_depth_batch(
-                &state,
-                wiki,
-                categories_batch,
-                &categories_done,
-                &new_categories,
-            ) ;
-            futures.push(future);
-        }
-        join_all(futures).await;
-
-        let new_categories = new_categories
-            .into_inner()
-            .map_err(|e| format!("{:?}", e))?;
-
-        Platform::profile("DSDB::do_depth new categories", Some(new_categories.len()));
-
-        Platform::profile(
-            "DSDB::do_depth end, categories done",
-            Some(
-                categories_done
-                    .read()
-                    .map_err(|e| format!("{:?}", e))?
-                    .len(),
-            ),
-        );
-
-        self.go_depth(&state, wiki, categories_done, &new_categories, depth - 1).await?;
-        Ok(())
-    }
-
     async fn get_categories_in_tree(
         &self,
         state: &AppState,
-        wiki: &str,
+        tree: &CategoryTree,
         title: &str,
         depth: u16,
     ) -> Result<Vec<String>, String> {
-        let categories_done = RwLock::new(HashSet::new());
         let title = SourceDatabaseParameters::s2u_ucfirst(
             title,
             self.params.category_namespace_is_case_insensitive,
         );
-        (*categories_done.write().map_err(|e| format!("{:?}", e))?).insert(title.to_owned());
-        self.go_depth(&state, wiki, &categories_done, &[title.to_string()], depth).await?;
-        let mut tmp = categories_done
-            .into_inner()
-            .map_err(|e| format!("{:?}", e))?;
-        Ok(tmp.drain().collect())
+        tree.expand(state, &title, depth).await
     }
 
+    /// Each input category (as a title with underscores), with all categories in its tree; categories that could not
+    /// be expanded are left out
     pub async fn parse_category_list(
         &self,
         state: &AppState,
-        wiki: &str,
+        tree: &CategoryTree,
         input: &[SourceDatabaseCatDepth],
-    ) -> Result<Vec<Vec<String>>, String> {
+    ) -> Result<Vec<(String, Vec<String>)>, String> {
         let mut futures = vec![] ;
         for i in input {
-            let future = self.get_categories_in_tree(&state, wiki, &i.name, i.depth) ;
+            let future = self.get_categories_in_tree(&state, tree, &i.name, i.depth) ;
             futures.push(future) ;
         }
 
         Ok(join_all(futures)
             .await
-            .into_par_iter()
-            .filter_map(|i|i.ok())
-            .filter(|i|!i.is_empty())
+            .into_iter()
+            .zip(input.iter())
+            .filter_map(|(categories, i)| {
+                let name = SourceDatabaseParameters::s2u_ucfirst(&i.name, self.params.category_namespace_is_case_insensitive);
+                Some((name, categories.ok()?))
+            })
+            .filter(|(_, categories)| !categories.is_empty())
             .collect())
     }
 
-    async fn get_talk_namespace_ids(&self, conn: &mut my::Conn) -> Result<String, String> {
-        let rows = conn.exec_iter("SELECT DISTINCT page_namespace FROM page WHERE MOD(page_namespace,2)=1",()).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<NamespaceID>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-        Ok(rows.iter().map(|ns|ns.to_string()).collect::<Vec<String>>().join(","))
+    async fn get_talk_namespace_ids(&self, conn: &mut dyn WikiDbConnection) -> Result<String, String> {
+        let sql = ("SELECT DISTINCT page_namespace FROM page WHERE MOD(page_namespace,2)=1".to_string(), vec![]);
+        let mut ids = vec![];
+        for row in conn.query(sql).await? {
+            ids.push(column::<NamespaceID>(&row, 0)?.to_string());
+        }
+        Ok(ids.join(","))
     }
 
     fn template_subquery(
@@ -494,6 +739,14 @@ impl SourceDatabase {
         sql
     }
 
+    /// Restricts category members to pages, subcategories, and/or files
+    fn sql_category_member_types(&self, sql: &mut SQLtuple) {
+        if !self.params.category_member_types.is_empty() {
+            sql.0 += " AND cl_type";
+            self.sql_in(&self.params.category_member_types, sql);
+        }
+    }
+
     fn sql_in(&self, input: &[String], sql: &mut SQLtuple) {
         if input.len() == 1 {
             sql.0 += "=";
@@ -576,13 +829,8 @@ impl SourceDatabase {
         ret
     }
 
-    async fn get_pages_for_category_batch(
-        &self,
-        params: &DsdbParams,
-        category_batch: &[Vec<String>],
-        state: &AppState,
-        ret: &PageList,
-    ) -> Result<(), String> {
+    /// The pages in a batch of categories, before the other filters
+    fn category_batch_sql(&self, params: &DsdbParams, category_batch: &[Vec<String>]) -> Result<SQLtuple, String> {
         let mut sql = Platform::sql_tuple();
         match self.params.combine.as_str() {
             "subset" => {
@@ -590,7 +838,9 @@ impl SourceDatabase {
                 sql.0 += &params.link_count_sql;
                 sql.0 += " FROM ( SELECT * from categorylinks WHERE cl_to IN (";
                 Platform::append_sql(&mut sql, Platform::prep_quote(&category_batch[0]));
-                sql.0 += ")) cl0";
+                sql.0 += ")";
+                self.sql_category_member_types(&mut sql);
+                sql.0 += ") cl0";
                 for (a, item) in category_batch.iter().enumerate().skip(1) {
                     sql.0 += format!(" INNER JOIN categorylinks cl{} ON cl0.cl_from=cl{}.cl_from and cl{}.cl_to IN (",a,a,a).as_str();
                     Platform::append_sql(&mut sql, Platform::prep_quote(&item));
@@ -612,7 +862,9 @@ impl SourceDatabase {
                 sql.0 += &params.link_count_sql;
                 sql.0 += " FROM ( SELECT * FROM categorylinks WHERE cl_to IN (";
                 Platform::append_sql(&mut sql, Platform::prep_quote(&tmp));
-                sql.0 += ")) cl0";
+                sql.0 += ")";
+                self.sql_category_member_types(&mut sql);
+                sql.0 += ") cl0";
             }
             other => {
                 return Err(format!("self.params.combine is '{}'", &other));
@@ -620,6 +872,17 @@ impl SourceDatabase {
         }
         sql.0 += " INNER JOIN (page p";
         sql.0 += ") ON p.page_id=cl0.cl_from";
+        Ok(sql)
+    }
+
+    async fn get_pages_for_category_batch(
+        &self,
+        params: &DsdbParams,
+        category_batch: &[Vec<String>],
+        state: &AppState,
+        ret: &PageList,
+    ) -> Result<(), String> {
+        let sql = self.category_batch_sql(params, category_batch)?;
         let mut pl2 = PageList::new_from_wiki(&params.wiki.clone());
         let api = state.get_api_for_wiki(params.wiki.clone()).await?;
         Platform::profile(
@@ -664,23 +927,46 @@ impl SourceDatabase {
             None => return Err("SourceDatabase::get_pages: No wiki in params".to_string()),
         };
 
+        // One tree for both, so subcategories are only loaded once
+        let mut tree = CategoryTree::new(
+            &wiki,
+            state.get_category_tree_max_nodes(),
+            self.params.ignore_hidden_categories,
+        );
+        if self.params.record_category_tree {
+            tree = tree.record_traversal();
+        }
+
         // Get positive categories serial list
-        self.cat_pos = self.parse_category_list(
+        let category_trees = self.parse_category_list(
             &state,
-            &wiki,
+            &tree,
             &self.parse_category_depth(&self.params.cat_pos, self.params.depth),
         ).await?;
+        if self.params.record_category_trees {
+            self.category_trees = category_trees.clone();
+        }
+        self.cat_pos = category_trees.into_iter().map(|(_, categories)| categories).collect();
 
         // Get negative categories serial list
         self.cat_neg = self.parse_category_list(
             &state,
-            &wiki,
-            &self.parse_category_depth(&self.params.cat_neg, self.params.depth),
-        ).await?;
+            &tree,
+            &self.parse_category_depth(&self.params.cat_neg, self.params.depth_neg),
+        ).await?
+            .into_iter()
+            .map(|(_, categories)| categories)
+            .collect();
+        self.category_tree_truncated = tree.is_truncated();
+        self.category_traversal = tree.traversal();
+        let categories: usize = self.cat_pos.iter().chain(self.cat_neg.iter()).map(|cats| cats.len()).sum();
+        if categories > 0 {
+            self.progress.set(&format!("expanding categories: {} found", Progress::format_count(categories)));
+        }
 
-        let mut conn = state.get_wiki_db_connection(&wiki).await?;
-        self.talk_namespace_ids = self.get_talk_namespace_ids(&mut conn).await?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        let mut conn = state.get_wiki_db(&wiki).await?;
+        self.talk_namespace_ids = self.get_talk_namespace_ids(conn.as_mut()).await?;
+        conn.close().await?;
 
         self.has_pos_templates =
             !self.params.templates_yes.is_empty() || !self.params.templates_any.is_empty();
@@ -689,14 +975,15 @@ impl SourceDatabase {
             || !self.params.links_to_all.is_empty()
             || !self.params.links_to_any.is_empty();
 
-        let primary = if !self.cat_pos.is_empty() {
+        // A given page list is usually much smaller than the other options, so use it first
+        let primary = if primary_pagelist.is_some() {
+            "pagelist"
+        } else if !self.cat_pos.is_empty() {
             "categories"
         } else if self.has_pos_templates {
             "templates"
         } else if self.has_pos_linked_from {
             "links_from"
-        } else if primary_pagelist.is_some() {
-            "pagelist"
         } else if self.params.page_wikidata_item == "without" {
             "no_wikidata"
         } else {
@@ -709,37 +996,37 @@ impl SourceDatabase {
             ",0 AS link_count" // Dummy
         };
 
-        let mut sql_before_after = Platform::sql_tuple();
-        let mut before: String = self.params.before.clone();
-        let mut after: String = self.params.after.clone();
-        let mut is_before_after_done: bool = false;
+        let mut created_after = self.params.created_after.clone();
+        let mut edited_after = self.params.edited_after.clone();
         if let Some(max_age) = self.params.max_age {
             let utc: DateTime<Utc> = Utc::now();
             let utc = utc.sub(Duration::hours(max_age));
-            before = String::new();
-            after = utc.format("%Y%m%d%H%M%S").to_string();
-        }
-
-        if before.is_empty() && after.is_empty() {
-            is_before_after_done = true;
-        } else {
-            sql_before_after.0 = " INNER JOIN (revision r) ON r.rev_page=p.page_id".to_string();
+            let after = utc.format("%Y%m%d%H%M%S").to_string();
             if self.params.only_new_since {
-                sql_before_after.0 += " AND r.rev_parent_id=0";
+                created_after = after;
             } else {
-                sql_before_after.0 += " AND r.rev_id=p.page_latest";
+                edited_after = after;
             }
-            if !before.is_empty() {
-                sql_before_after.0 += " AND r.rev_timestamp<=?";
-                sql_before_after.1.push(MyValue::Bytes(before.into()));
-            }
-            if !after.is_empty() {
-                sql_before_after.0 += " AND r.rev_timestamp>=?";
-                sql_before_after.1.push(MyValue::Bytes(after.into()));
-            }
-            sql_before_after.0 += " ";
         }
 
+        // Creation and last edit are independent stages, on separate revision joins
+        let mut sql_before_after = SourceDatabaseParameters::sql_revision_timestamps(
+            "rc",
+            "rev_parent_id=0",
+            &self.params.created_before,
+            &created_after,
+        );
+        Platform::append_sql(
+            &mut sql_before_after,
+            SourceDatabaseParameters::sql_revision_timestamps(
+                "re",
+                "rev_id=p.page_latest",
+                &self.params.edited_before,
+                &edited_after,
+            ),
+        );
+        let is_before_after_done = sql_before_after.0.is_empty();
+
         Ok(DsdbParams {
             link_count_sql: link_count_sql.to_string(),
             wiki,
@@ -749,22 +1036,27 @@ impl SourceDatabase {
         })
     }
 
+    fn category_batches(&self) -> Vec<Vec<Vec<String>>> {
+        if self.params.use_new_category_mode {
+            self.iterate_category_batches(&self.cat_pos, 0)
+        } else {
+            vec![self.cat_pos.to_owned()]
+        }
+    }
+
     async fn get_pages_categories(
         &mut self,
         params: &DsdbParams,
         state: &AppState,
     ) -> Result<PageList, String> {
-        let category_batches = if self.params.use_new_category_mode {
-            self.iterate_category_batches(&self.cat_pos, 0)
-        } else {
-            vec![self.cat_pos.to_owned()]
-        };
+        let category_batches = self.category_batches();
 
         Platform::profile(
             "DSDB::get_pages [primary:categories] BATCHES begin",
             Some(category_batches.len()),
         );
-        let ret = PageList::new_from_wiki(&params.wiki);
+        let ret = Arc::new(PageList::new_from_wiki(&params.wiki));
+        self.gathered = Some(ret.clone());
 
         let futures : Vec<_> = category_batches
             .iter()
@@ -772,6 +1064,7 @@ impl SourceDatabase {
             .collect();
 
         let results = join_all(futures).await;
+        self.gathered = None;
 
         // Check for errors
         for result in results {
@@ -782,7 +1075,7 @@ impl SourceDatabase {
             "DSDB::get_pages [primary:categories] RESULTS end",
             Some(ret.len()?),
         );
-        Ok(ret)
+        Arc::try_unwrap(ret).map_err(|_| "SourceDatabase::get_pages_categories: result is still in use".to_string())
     }
 
     async fn get_pages_pagelist(
@@ -845,13 +1138,13 @@ impl SourceDatabase {
         state:&AppState,
         params:&DsdbParams,
     ) -> Result<PageList,String> {
-        let mut conn = state.get_wiki_db_connection(&wiki).await?;
+        let mut conn = state.get_wiki_db(&wiki).await?;
         let sql_before_after = params.sql_before_after.clone();
         let mut is_before_after_done = params.is_before_after_done;
         let mut pl2 = PageList::new_from_wiki(&wiki.clone());
         let api = state.get_api_for_wiki(wiki.clone()).await?;
         self.get_pages_for_primary(
-            &mut conn,
+            conn.as_mut(),
             &params.primary.to_string(),
             sql,
             sql_before_after,
@@ -871,8 +1164,6 @@ impl SourceDatabase {
         let mut params =
             self.get_pages_initialize_query(state, primary_pagelist).await?;
 
-        let mut sql = Platform::sql_tuple();
-
         match params.primary.as_str() {
             "categories" => {
                 return self.get_pages_categories(&params, &state).await;
@@ -880,6 +1171,56 @@ impl SourceDatabase {
             "pagelist" => {
                 return self.get_pages_pagelist(params, &state, primary_pagelist).await;
             }
+            _ => {}
+        }
+        let sql = self.primary_sql(&mut params)?;
+
+        let mut ret = PageList::new_from_wiki(&params.wiki);
+        let mut conn = state.get_wiki_db(&params.wiki).await?;
+        self.get_pages_for_primary(
+            conn.as_mut(),
+            &params.primary.to_string(),
+            sql,
+            params.sql_before_after,
+            &mut ret,
+            &mut params.is_before_after_done,
+            state.get_api_for_wiki(params.wiki.clone()).await?,
+        ).await?;
+        conn.close().await?;
+        Ok(ret)
+    }
+
+    /// The number of pages `get_pages` would return, with COUNT(*) over the same SQL (per category batch, so
+    /// overlapping batches can count a page twice)
+    pub async fn count_pages(&mut self, state: &AppState) -> Result<usize, String> {
+        let mut params = self.get_pages_initialize_query(state, None).await?;
+        let sqls = match params.primary.as_str() {
+            "categories" => self
+                .category_batches()
+                .iter()
+                .map(|category_batch| self.category_batch_sql(&params, category_batch))
+                .collect::<Result<Vec<SQLtuple>, String>>()?,
+            _ => vec![self.primary_sql(&mut params)?],
+        };
+        let api = state.get_api_for_wiki(params.wiki.clone()).await?;
+        let mut conn = state.get_wiki_db(&params.wiki).await?;
+        let mut ret = 0;
+        for sql in sqls {
+            let mut is_before_after_done = params.is_before_after_done;
+            let sql = self.filter_sql(&params.primary, sql, params.sql_before_after.clone(), &mut is_before_after_done, &api);
+            let sql = (format!("SELECT COUNT(*) FROM ({}) AS counted", sql.0), sql.1);
+            if let Some(row) = conn.query(sql).await?.first() {
+                ret += column::<u64>(row, 0)? as usize;
+            }
+        }
+        conn.close().await?;
+        Ok(ret)
+    }
+
+    /// The pages for a primary other than categories or a page list, before the other filters
+    fn primary_sql(&self, params: &mut DsdbParams) -> Result<SQLtuple, String> {
+        let mut sql = Platform::sql_tuple();
+        match params.primary.as_str() {
             "no_wikidata" => {
                 sql.0 = "SELECT DISTINCT p.page_id,p.page_title,p.page_namespace,(SELECT rev_timestamp FROM revision WHERE rev_id=p.page_latest LIMIT 1) AS page_touched,p.page_len".to_string() ;
                 sql.0 += &params.link_count_sql;
@@ -907,20 +1248,7 @@ impl SourceDatabase {
                 ));
             }
         }
-
-        let mut ret = PageList::new_from_wiki(&params.wiki);
-        let mut conn = state.get_wiki_db_connection(&params.wiki).await?;
-        self.get_pages_for_primary(
-            &mut conn,
-            &params.primary.to_string(),
-            sql,
-            params.sql_before_after,
-            &mut ret,
-            &mut params.is_before_after_done,
-            state.get_api_for_wiki(params.wiki.clone()).await?,
-        ).await?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
-        Ok(ret)
+        Ok(sql)
     }
 
     async fn get_pages_for_primary_new_connection(
@@ -934,13 +1262,13 @@ impl SourceDatabase {
         is_before_after_done: &mut bool,
         api: Api,
     ) -> Result<(), String> {
-        let mut conn = state.get_wiki_db_connection(&wiki).await?;
+        let mut conn = state.get_wiki_db(&wiki).await?;
         Platform::profile(
             "DSDB::get_pages_for_primary_new_connection STARTING",
             Some(sql.1.len()),
         );
         let ret = self.get_pages_for_primary(
-            &mut conn,
+            conn.as_mut(),
             primary,
             sql,
             sql_before_after.clone(),
@@ -948,22 +1276,19 @@ impl SourceDatabase {
             is_before_after_done,
             api,
         ).await;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        conn.close().await?;
         ret
     }
 
-    async fn get_pages_for_primary(
+    /// Adds the filters (namespaces, negative categories, templates, links etc.) to the SQL for a primary
+    fn filter_sql(
         &self,
-        conn: &mut my::Conn,
-        primary: &String,
+        primary: &str,
         mut sql: SQLtuple,
         sql_before_after: SQLtuple,
-        pages_sublist: &mut PageList,
         is_before_after_done: &mut bool,
-        api: Api,
-    ) -> Result<(), String> {
-        Platform::profile("DSDB::get_pages_for_primary STARTING", Some(sql.1.len()));
-
+        api: &Api,
+    ) -> SQLtuple {
         // Namespaces
         if !self.params.namespace_ids.is_empty() {
             let namespace_ids = &self
@@ -976,6 +1301,26 @@ impl SourceDatabase {
             self.sql_in(&namespace_ids, &mut sql);
         }
 
+        // Positive categories, if the page list was used as primary
+        if primary == "pagelist" && !self.cat_pos.is_empty() {
+            if self.params.combine == "union" {
+                let mut cats : Vec<String> = self.cat_pos.iter().flatten().cloned().collect();
+                cats.sort_unstable();
+                cats.dedup();
+                sql.0 += " AND p.page_id IN (SELECT DISTINCT cl_from FROM categorylinks WHERE cl_to";
+                self.sql_in(&cats, &mut sql);
+                self.sql_category_member_types(&mut sql);
+                sql.0 += ")";
+            } else {
+                for cats in &self.cat_pos {
+                    sql.0 += " AND p.page_id IN (SELECT DISTINCT cl_from FROM categorylinks WHERE cl_to";
+                    self.sql_in(cats, &mut sql);
+                    self.sql_category_member_types(&mut sql);
+                    sql.0 += ")";
+                }
+            }
+        }
+
         // Negative categories
         let negative_categories_use_not_exists = false ;
         if !self.cat_neg.is_empty() {
@@ -1128,6 +1473,19 @@ impl SourceDatabase {
             }
             _ => {}
         }
+        match self.params.last_edit_minor.as_str() {
+            "yes" => sql.0 += " AND EXISTS (SELECT * FROM revision WHERE rev_id=page_latest AND rev_page=page_id AND rev_minor_edit=1)",
+            "no" => sql.0 += " AND EXISTS (SELECT * FROM revision WHERE rev_id=page_latest AND rev_page=page_id AND rev_minor_edit=0)",
+            _ => {}
+        }
+        if !self.params.last_edit_summary_regex.is_empty() {
+            sql.0 += " AND EXISTS (SELECT * FROM revision,comment WHERE rev_id=page_latest AND rev_page=page_id AND rev_comment_id=comment_id AND comment_text REGEXP ?)";
+            sql.1.push(MyValue::Bytes(self.params.last_edit_summary_regex.to_owned().into()));
+        }
+        if !self.params.last_edit_tag.is_empty() {
+            sql.0 += " AND EXISTS (SELECT * FROM change_tag,change_tag_def WHERE ct_rev_id=page_latest AND ct_tag_id=ctd_id AND ctd_name=?)";
+            sql.1.push(MyValue::Bytes(self.params.last_edit_tag.to_owned().into()));
+        }
 
         // Misc page types
         // TODO FIXME get local "Soft_redirect" page title from Wikidata Q4844001
@@ -1151,6 +1509,47 @@ impl SourceDatabase {
             _ => {}
         }
 
+        // Pages in no category
+        let category_exists_sql = if self.params.uncategorized_ignore_hidden {
+            "EXISTS (SELECT * FROM categorylinks WHERE cl_from=p.page_id AND NOT EXISTS (SELECT * FROM page hc,page_props WHERE hc.page_namespace=14 AND hc.page_title=cl_to AND pp_page=hc.page_id AND pp_propname='hiddencat'))"
+        } else {
+            "EXISTS (SELECT * FROM categorylinks WHERE cl_from=p.page_id)"
+        };
+        match self.params.uncategorized.as_str() {
+            "yes" => sql.0 += &format!(" AND NOT {}", category_exists_sql),
+            "no" => sql.0 += &format!(" AND {}", category_exists_sql),
+            _ => {}
+        }
+
+        // Orphans: no incoming links from articles
+        if self.params.orphans_only {
+            sql.0 += " AND NOT EXISTS (SELECT * FROM pagelinks WHERE pl_namespace=p.page_namespace AND pl_title=p.page_title AND pl_from_namespace=0)";
+        }
+
+        // Dead ends: no outgoing links to existing pages
+        if self.params.dead_ends_only {
+            sql.0 += " AND NOT EXISTS (SELECT * FROM pagelinks,page p2 WHERE pl_from=p.page_id AND p2.page_namespace=pl_namespace AND p2.page_title=pl_title)";
+        }
+
+        // Title prefixes
+        if !self.params.title_prefixes.is_empty() {
+            let likes: Vec<&str> = self.params.title_prefixes.iter().map(|_| "p.page_title LIKE ?").collect();
+            sql.0 += &format!(" AND ({})", likes.join(" OR "));
+            self.params.title_prefixes.iter().for_each(|prefix| {
+                sql.1.push(MyValue::Bytes(safe_sql::like_prefix(prefix).into()))
+            });
+        }
+        self.params.title_not_prefixes.iter().for_each(|prefix| {
+            sql.0 += " AND p.page_title NOT LIKE ?";
+            sql.1.push(MyValue::Bytes(safe_sql::like_prefix(prefix).into()));
+        });
+
+        // Content model, page language
+        Platform::append_sql(
+            &mut sql,
+            SourceDatabaseParameters::sql_content_model_page_lang(&self.params.content_models, &self.params.page_languages),
+        );
+
         // Size
         if let Some(i) = self.params.larger {
             sql.0 += " AND p.page_len>=";
@@ -1189,6 +1588,22 @@ impl SourceDatabase {
                 Platform::append_sql(&mut sql, h);
             }
         }
+        sql
+    }
+
+    async fn get_pages_for_primary(
+        &self,
+        conn: &mut dyn WikiDbConnection,
+        primary: &String,
+        sql: SQLtuple,
+        sql_before_after: SQLtuple,
+        pages_sublist: &mut PageList,
+        is_before_after_done: &mut bool,
+        api: Api,
+    ) -> Result<(), String> {
+        Platform::profile("DSDB::get_pages_for_primary STARTING", Some(sql.1.len()));
+
+        let sql = self.filter_sql(primary, sql, sql_before_after, is_before_after_done, &api);
 
         let wiki = match &self.params.wiki {
             Some(wiki) => wiki,
@@ -1205,11 +1620,21 @@ impl SourceDatabase {
         //println!("{:?}",&sql);
 
         let sql_1_len = sql.1.len() ;
-        let rows = conn.exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<(u32, Vec<u8>, NamespaceID, Vec<u8>, u32, LinkCount)>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
+        let rows = conn
+            .query(sql)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok((
+                    column::<u32>(row, 0)?,
+                    column::<Vec<u8>>(row, 1)?,
+                    column::<NamespaceID>(row, 2)?,
+                    column::<Vec<u8>>(row, 3)?,
+                    column::<u32>(row, 4)?,
+                    column::<LinkCount>(row, 5)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
 
         Platform::profile(
             "DSDB::get_pages_for_primary RUN FINISHED",
@@ -1252,9 +1677,14 @@ mod tests {
     use super::*;
     use crate::app_state::AppState;
     use crate::form_parameters::FormParameters;
+    use crate::wiki_db::MockDatabase;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{header, Body, Response, Server};
     use serde_json::Value;
+    use std::convert::Infallible;
     use std::env;
     use std::fs::File;
+    use std::net::SocketAddr;
     use std::sync::Arc;
 
     async fn get_state() -> Arc<AppState> {
@@ -1270,8 +1700,49 @@ mod tests {
         Arc::new(AppState::new_from_config(&petscan_config).await)
     }
 
+    /// Serves `fixtures/enwiki_siteinfo.json` on a local port, as the answer to any API request; returns the server URL
+    async fn serve_site_info() -> String {
+        let site_info = Arc::new(std::fs::read("./fixtures/enwiki_siteinfo.json").expect("Can not read site info fixture"));
+        let make_service = make_service_fn(move |_conn| {
+            let site_info = site_info.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_req| {
+                    let site_info = site_info.clone();
+                    async move {
+                        Response::builder()
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .body(Body::from((*site_info).clone()))
+                    }
+                }))
+            }
+        });
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let server = Server::bind(&addr).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    /// The state with the fixtures in `fixtures/mock_db.json` instead of the replicas, a site matrix with only enwiki,
+    /// and the enwiki site info from `fixtures/enwiki_siteinfo.json`; needs neither config.json nor the network
+    async fn get_mock_state() -> (Arc<AppState>, Arc<MockDatabase>) {
+        let file = File::open("./fixtures/mock_db.json").expect("Can not open mock database fixtures");
+        let fixtures: Value = serde_json::from_reader(file).expect("Can not parse mock database fixtures");
+        let db = Arc::new(MockDatabase::new_from_json(&fixtures).unwrap());
+        let config = json!({"user":"test","password":"test","mysql":[["test","test",2]],
+            "endpoints":{"servers":{"enwiki":serve_site_info().await}}});
+        let site_matrix = json!({"sitematrix":{"count":1,"0":{"code":"en","name":"English","site":[
+            {"url":"https://en.wikipedia.org","dbname":"enwiki","code":"wiki","sitename":"Wikipedia"}
+        ]}}});
+        let state = AppState::new_from_config_and_site_matrix(&config, site_matrix).await.with_mock_db(db.clone());
+        (Arc::new(state), db)
+    }
+
     async fn simulate_category_query(url_params: Vec<(&str, &str)>) -> Result<PageList, String> {
-        let state = get_state().await;
+        simulate_query_with_state(url_params, get_state().await).await
+    }
+
+    async fn simulate_query_with_state(url_params: Vec<(&str, &str)>, state: Arc<AppState>) -> Result<PageList, String> {
         let mut fp = FormParameters::new();
         fp.params = url_params
             .iter()
@@ -1283,6 +1754,152 @@ mod tests {
         dbs.get_pages(&state, None).await
     }
 
+    #[test]
+    fn parse_depth() {
+        assert_eq!(SourceDatabaseParameters::parse_depth("3"), Some(3));
+        assert_eq!(SourceDatabaseParameters::parse_depth(" 0 "), Some(0));
+        assert_eq!(SourceDatabaseParameters::parse_depth("*"), Some(UNLIMITED_CATEGORY_DEPTH));
+        assert_eq!(SourceDatabaseParameters::parse_depth("-1"), Some(UNLIMITED_CATEGORY_DEPTH));
+        assert_eq!(SourceDatabaseParameters::parse_depth("99999"), Some(UNLIMITED_CATEGORY_DEPTH));
+        assert_eq!(SourceDatabaseParameters::parse_depth("x"), None);
+    }
+
+    #[test]
+    fn category_depth_per_group() {
+        let params = SourceDatabaseParameters {
+            cat_pos: vec!["Physicists".to_string(), "Chemists|0".to_string()],
+            cat_neg: vec!["Nobel laureates".to_string()],
+            depth: 3,
+            depth_neg: 0,
+            ..Default::default()
+        };
+        let db = SourceDatabase::new(params);
+        let depths = |cats: &[String], default_depth| {
+            db.parse_category_depth(cats, default_depth)
+                .iter()
+                .map(|c| c.depth)
+                .collect::<Vec<u16>>()
+        };
+        assert_eq!(depths(&db.params.cat_pos, db.params.depth), vec![3, 0]);
+        assert_eq!(depths(&db.params.cat_neg, db.params.depth_neg), vec![0]);
+    }
+
+    #[test]
+    fn partial_result() {
+        let mut db = SourceDatabase::new(SourceDatabaseParameters::new());
+        assert_eq!(db.partial_result(), None);
+        let gathered = Arc::new(PageList::new_from_wiki("enwiki"));
+        gathered.add_entry(PageListEntry::new(Title::new("Foo", 0))).unwrap();
+        db.gathered = Some(gathered.clone());
+        // Still in use by a running batch
+        assert_eq!(db.partial_result(), None);
+        db.gathered = Some(gathered);
+        assert_eq!(db.partial_result().unwrap().len().unwrap(), 1);
+        assert_eq!(db.partial_result(), None);
+    }
+
+    #[test]
+    fn before_after() {
+        let mut params = SourceDatabaseParameters::new();
+        params.edited_after = "20200101".to_string();
+        params.apply_legacy_before_after("20210101".to_string(), "20190101".to_string());
+        assert_eq!(params.edited_before, "20210101");
+        assert_eq!(params.edited_after, "20200101");
+        assert!(params.created_before.is_empty());
+
+        let mut params = SourceDatabaseParameters::new();
+        params.only_new_since = true;
+        params.apply_legacy_before_after(String::new(), "20190101".to_string());
+        assert_eq!(params.created_after, "20190101");
+        assert!(params.edited_after.is_empty());
+
+        assert!(SourceDatabaseParameters::sql_revision_timestamps("rc", "rev_parent_id=0", "", "").0.is_empty());
+        let sql = SourceDatabaseParameters::sql_revision_timestamps("re", "rev_id=p.page_latest", "", "20190101");
+        assert_eq!(sql.0, " INNER JOIN (revision re) ON re.rev_page=p.page_id AND re.rev_id=p.page_latest AND re.rev_timestamp>=? ");
+        assert_eq!(sql.1.len(), 1);
+    }
+
+    #[test]
+    fn sql_content_model_page_lang() {
+        assert!(SourceDatabaseParameters::sql_content_model_page_lang(&[], &[]).0.is_empty());
+        let models = vec![SourceDatabaseParameters::content_model_name("scribunto"), "json".to_string()];
+        let sql = SourceDatabaseParameters::sql_content_model_page_lang(&models, &["default".to_string(), "de".to_string()]);
+        assert_eq!(sql.0, " AND p.page_content_model IN (?,?) AND (p.page_lang IS NULL OR p.page_lang IN (?))");
+        assert_eq!(sql.1.len(), 3);
+        assert_eq!(sql.1[0], MyValue::Bytes("Scribunto".into()));
+    }
+
+    #[test]
+    fn parse_category_member_types() {
+        let types = |keys: &[&str]| SourceDatabaseParameters::parse_category_member_types(&|key| keys.contains(&key));
+        assert!(types(&[]).is_empty());
+        assert_eq!(types(&["cm_type_subcat"]), vec!["subcat".to_string()]);
+        assert_eq!(types(&["cm_type_file", "cm_type_page"]), vec!["page".to_string(), "file".to_string()]);
+        assert!(types(&["cm_type_page", "cm_type_subcat", "cm_type_file"]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_db_templates() {
+        let (state, db) = get_mock_state().await;
+        let params = vec![
+            ("templates_yes", "Infobox scientist"),
+            ("language", "en"),
+            ("project", "wikipedia"),
+            ("ns[0]", "1"),
+        ];
+        let result = simulate_query_with_state(params, state).await.unwrap();
+        assert_eq!(result.wiki(), Ok(Some("enwiki".to_string())));
+        assert_eq!(result.len(), Ok(3));
+        let entries = result.entries().read().unwrap();
+        let einstein = entries.iter().find(|entry| entry.page_id == Some(736)).unwrap();
+        assert_eq!(einstein.title().pretty(), "Albert Einstein");
+        assert_eq!(einstein.page_bytes, Some(190000));
+        drop(entries);
+
+        let queries = db.queries();
+        assert!(queries.iter().all(|(wiki, _)| wiki == "enwiki"));
+        let (sql, values) = &queries.last().unwrap().1;
+        assert!(sql.contains("FROM page p"));
+        assert!(sql.contains("lt_namespace=10"));
+        assert!(sql.contains("p.page_namespace=?"));
+        assert!(values.contains(&MyValue::Bytes("Infobox_scientist".into())));
+    }
+
+    #[tokio::test]
+    async fn test_mock_db_count_pages() {
+        let (state, db) = get_mock_state().await;
+        let mut fp = FormParameters::new();
+        fp.params = vec![("templates_yes", "Infobox scientist"), ("language", "en"), ("project", "wikipedia")]
+            .iter()
+            .map(|pair| (pair.0.to_string(), pair.1.to_string()))
+            .collect();
+        let platform = Platform::new_from_parameters(&fp, state.clone());
+        let mut dbs = SourceDatabase::new(SourceDatabaseParameters::db_params(&platform).await);
+        assert_eq!(dbs.count_pages(&state).await, Ok(3));
+        let (sql, _) = &db.queries().last().unwrap().1;
+        assert!(sql.starts_with("SELECT COUNT(*) FROM (SELECT DISTINCT p.page_id"));
+        assert!(sql.contains("lt_namespace=10"));
+    }
+
+    #[tokio::test]
+    async fn test_mock_db_convert_to_wikidata() {
+        let (state, db) = get_mock_state().await;
+        let platform = Platform::new_from_parameters(&FormParameters::new(), state);
+        let pagelist = PageList::new_from_wiki("enwiki");
+        for title in &["Albert_Einstein", "Marie_Curie"] {
+            pagelist.add_entry(PageListEntry::new(Title::new(title, 0))).unwrap();
+        }
+        pagelist.convert_to_wiki("wikidatawiki", &platform).await.unwrap();
+        assert_eq!(pagelist.wiki(), Ok(Some("wikidatawiki".to_string())));
+        let mut items: Vec<String> = pagelist.entries().read().unwrap().iter().map(|e| e.title().with_underscores()).collect();
+        items.sort();
+        assert_eq!(items, vec!["Q7186".to_string(), "Q937".to_string()]);
+        let queries = db.queries();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].0, "enwiki");
+        assert!((queries[0].1).0.contains("pp_propname='wikibase_item'"));
+    }
+
     #[tokio::test]
     async fn test_category_subset() {
         let params = vec![
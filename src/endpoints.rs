@@ -0,0 +1,122 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub static DEFAULT_API_PATH: &str = "/w/api.php";
+pub static DEFAULT_WDQS_URL: &str = "https://query.wikidata.org/sparql";
+pub static DEFAULT_LIFTWING_URL: &str = "https://api.wikimedia.org/service/lw/inference/v1/models";
+pub static DEFAULT_PAGEPILE_URL: &str = "https://pagepile.toolforge.org/api.php";
+
+/// External services, so deployments outside Wikimedia production (eg a third-party wiki farm) can use their own.
+/// Configured via "endpoints":{"servers":{"mywiki":"https://wiki.example.org"},"api_path":"/w/api.php",
+/// "wdqs":"https://query.example.org/sparql","liftwing":"...","pagepile":"https://pagepile.example.org/api.php"}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    /// wiki => server URL, before the site matrix
+    servers: HashMap<String, String>,
+    api_path: String,
+    /// None: the SPARQL source uses the one in the Wikidata site info
+    wdqs: Option<String>,
+    liftwing: String,
+    pagepile: String,
+}
+
+impl Endpoints {
+    pub fn new_from_config(config: &Value) -> Self {
+        let endpoints = &config["endpoints"];
+        let url = |key: &str| {
+            endpoints[key]
+                .as_str()
+                .map(|s| s.trim().trim_end_matches('/').to_string())
+                .filter(|s| !s.is_empty())
+        };
+        let servers = match endpoints["servers"].as_object() {
+            Some(servers) => servers
+                .iter()
+                .filter_map(|(wiki, server)| Some((wiki.to_string(), server.as_str()?.trim_end_matches('/').to_string())))
+                .collect(),
+            None => HashMap::new(),
+        };
+        Self {
+            servers,
+            api_path: url("api_path").unwrap_or_else(|| DEFAULT_API_PATH.to_string()),
+            wdqs: url("wdqs"),
+            liftwing: url("liftwing").unwrap_or_else(|| DEFAULT_LIFTWING_URL.to_string()),
+            pagepile: url("pagepile").unwrap_or_else(|| DEFAULT_PAGEPILE_URL.to_string()),
+        }
+    }
+
+    /// The configured server for a wiki, if any, eg "https://wiki.example.org"
+    pub fn server_url(&self, wiki: &str) -> Option<String> {
+        self.servers.get(wiki).cloned()
+    }
+
+    /// The configured wiki for a server URL, if any
+    pub fn wiki_for_server_url(&self, server_url: &str) -> Option<String> {
+        let server_url = server_url.trim_end_matches('/');
+        self.servers
+            .iter()
+            .find(|(_, url)| url.as_str() == server_url)
+            .map(|(wiki, _)| wiki.to_string())
+    }
+
+    /// The API URL for a server, eg "https://en.wikipedia.org/w/api.php"
+    pub fn api_url(&self, server_url: &str) -> String {
+        format!("{}{}", server_url.trim_end_matches('/'), self.api_path)
+    }
+
+    /// The configured SPARQL endpoint, if any
+    pub fn configured_wdqs(&self) -> Option<&str> {
+        self.wdqs.as_deref()
+    }
+
+    pub fn wdqs(&self) -> &str {
+        self.configured_wdqs().unwrap_or(DEFAULT_WDQS_URL)
+    }
+
+    /// Base URL of the LiftWing (formerly ORES) models
+    pub fn liftwing(&self) -> &str {
+        &self.liftwing
+    }
+
+    pub fn pagepile(&self) -> &str {
+        &self.pagepile
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self::new_from_config(&json!({}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let endpoints = Endpoints::default();
+        assert_eq!(endpoints.server_url("enwiki"), None);
+        assert_eq!(endpoints.api_url("https://en.wikipedia.org"), "https://en.wikipedia.org/w/api.php");
+        assert_eq!(endpoints.configured_wdqs(), None);
+        assert_eq!(endpoints.wdqs(), DEFAULT_WDQS_URL);
+        assert_eq!(endpoints.pagepile(), DEFAULT_PAGEPILE_URL);
+    }
+
+    #[test]
+    fn new_from_config() {
+        let endpoints = Endpoints::new_from_config(&json!({"endpoints":{
+            "servers":{"mywiki":"https://wiki.example.org/"},
+            "api_path":"/api.php",
+            "wdqs":"https://query.example.org/sparql",
+            "liftwing":" ",
+            "pagepile":"https://pagepile.example.org/api.php"
+        }}));
+        assert_eq!(endpoints.server_url("mywiki"), Some("https://wiki.example.org".to_string()));
+        assert_eq!(endpoints.wiki_for_server_url("https://wiki.example.org/"), Some("mywiki".to_string()));
+        assert_eq!(endpoints.api_url("https://wiki.example.org"), "https://wiki.example.org/api.php");
+        assert_eq!(endpoints.wdqs(), "https://query.example.org/sparql");
+        assert_eq!(endpoints.liftwing(), DEFAULT_LIFTWING_URL);
+        assert_eq!(endpoints.pagepile(), "https://pagepile.example.org/api.php");
+    }
+}
@@ -1,19 +1,69 @@
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use regex::Regex;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use url::*;
 use std::fmt;
 
+/// How the value of a deprecated parameter becomes the value of its replacement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AliasValue {
+    /// The value is used as it is, unless the new parameter has a value already
+    Same,
+    /// The new parameter is set to this value, whatever the old one was (eg flags like `comb_union`)
+    Fixed(&'static str),
+    /// Old values and their new ones; other values are left alone. Old and new name can be the same.
+    Mapped(&'static [(&'static str, &'static str)]),
+}
+
+/// A deprecated parameter (from CatScan, QuickIntersection, or older PetScan versions), and what it means now
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParameterAlias {
+    pub old: &'static str,
+    pub new: &'static str,
+    pub value: AliasValue,
+}
+
+/// All deprecated parameters; renaming a parameter only needs a line here. Applied in order.
+pub static PARAMETER_ALIASES: &[ParameterAlias] = &[
+    ParameterAlias { old: "lang", new: "language", value: AliasValue::Same },
+    ParameterAlias { old: "cats", new: "categories", value: AliasValue::Same },
+    ParameterAlias { old: "templates", new: "templates_yes", value: AliasValue::Same },
+    ParameterAlias { old: "outlinks", new: "outlinks_yes", value: AliasValue::Same },
+    ParameterAlias { old: "format", new: "format", value: AliasValue::Mapped(LEGACY_FORMAT_NAMES) },
+    ParameterAlias { old: "comb_subset", new: "combination", value: AliasValue::Fixed("subset") },
+    ParameterAlias { old: "comb_union", new: "combination", value: AliasValue::Fixed("union") },
+    ParameterAlias { old: "get_q", new: "wikidata_item", value: AliasValue::Fixed("any") },
+    ParameterAlias { old: "wikidata", new: "wikidata_item", value: AliasValue::Fixed("any") },
+    ParameterAlias { old: "wikidata_no_item", new: "wikidata_item", value: AliasValue::Fixed("without") },
+];
+
+/// Version of the `format=query_json` export
+pub static QUERY_JSON_VERSION: u64 = 1;
+
+/// Parameters about a single request, rather than the query itself; not part of a query export
+static NON_QUERY_PARAMETERS: &[&str] = &["doit", "format", "norun", "psid", "preset"];
+
+/// Never stored (eg in a PSID or the query log), or shown in links: credentials, and IDs of a single request
+static SECRET_PARAMETERS: &[&str] = &["admin_token", "progress_id"];
+
+/// Old output format names (from QuickIntersection), and their current names
+static LEGACY_FORMAT_NAMES: &[(&str, &str)] = &[("jsonfm", "json")];
+
 #[derive(Debug, Clone, Default)]
 pub struct FormParameters {
     pub params: HashMap<String, String>,
+    /// All non-empty values for each key, in order; only differs from `params` for repeated keys
+    pub multi_params: HashMap<String, Vec<String>>,
     pub ns: HashSet<usize>,
+    /// Deprecated parameters used in this query, and their replacements, eg ("lang", "language")
+    deprecated: Vec<(String, String)>,
 }
 
 impl fmt::Display for FormParameters {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let ret = self.params
+        let ret = self
+            .pairs()
             .iter()
             .map(|(k, v)| Self::percent_encode(k) + "=" + &Self::percent_encode(v))
             .collect::<Vec<String>>()
@@ -27,17 +77,53 @@ impl FormParameters {
         Self { ..Default::default() }
     }
 
-    pub fn new_from_pairs(parameter_pairs: Vec<(&str, &str)>) -> Self {
+    /// Creates a new object from decoded key/value pairs.
+    /// For repeated keys, the last non-empty value is used.
+    pub fn new_from_pairs(parameter_pairs: Vec<(String, String)>) -> Self {
         let mut ret = Self::new();
-        ret.params = parameter_pairs
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string().replace("+", " ")))
-            .collect();
-        ret.ns = Self::ns_from_params(&ret.params);
+        for (k, v) in parameter_pairs {
+            let values = ret.multi_params.entry(k.to_owned()).or_insert_with(Vec::new);
+            if !v.is_empty() {
+                values.push(v.to_owned());
+            }
+            if v.is_empty() && ret.has_param_with_value(&k) {
+                continue;
+            }
+            ret.params.insert(k, v);
+        }
         ret.legacy_parameters();
+        ret.ns = Self::ns_from_params(&ret.params);
         ret
     }
 
+    /// Parses a query string (or POST body) into a new object
+    pub fn new_from_query(query: &str) -> Self {
+        Self::new_from_pairs(Self::parse_query(query))
+    }
+
+    /// Decodes an application/x-www-form-urlencoded string into key/value pairs, in order, including repeated keys
+    pub fn parse_query(query: &str) -> Vec<(String, String)> {
+        query
+            .trim_start_matches('?')
+            .split('&')
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let mut key_value = part.splitn(2, '=');
+                let key = key_value.next().unwrap_or("");
+                let value = key_value.next().unwrap_or("");
+                (Self::decode_component(key), Self::decode_component(value))
+            })
+            .filter(|(key, _value)| !key.is_empty())
+            .collect()
+    }
+
+    /// "+" is a space, but only before percent-decoding; "%2B" is a literal "+"
+    fn decode_component(s: &str) -> String {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+    }
+
     /// Extracts namespaces from parameter list
     fn ns_from_params(params: &HashMap<String, String>) -> HashSet<usize> {
         lazy_static! {
@@ -64,15 +150,7 @@ impl FormParameters {
 
     /// Parses a query string into a new object
     pub fn outcome_from_query(query: &str) -> Result<Self, String> {
-        let parsed_url = match Url::parse(&("https://127.0.0.1/?".to_string() + query)) {
-            Ok(url) => url,
-            Err(e) => return Err(format!("{:?}", &e)),
-        };
-        let params: HashMap<_, _> = parsed_url.query_pairs().into_owned().collect();
-        let ns = Self::ns_from_params(&params);
-        let mut ret = FormParameters { params , ns } ;
-        ret.legacy_parameters();
-        Ok(ret)
+        Ok(Self::new_from_query(query))
     }
 
     /// Amends a an object based on a previous one (used for PSID in main.rs)
@@ -86,9 +164,11 @@ impl FormParameters {
                     .is_empty()
                 {
                     self.params.insert(k.to_string(), v.to_string());
+                    self.multi_params.insert(k.to_string(), base.get_all(k));
                 }
             } else {
                 self.params.insert(k.to_string(), v.to_string());
+                self.multi_params.insert(k.to_string(), base.get_all(k));
             }
         });
         self.legacy_parameters();
@@ -106,7 +186,7 @@ impl FormParameters {
     */
 
     pub fn to_string_no_doit(&self) -> String {
-        self.params
+        self.pairs()
             .iter()
             .filter(|(k, _v)| *k != "doit")
             .filter(|(k, _v)| *k != "format")
@@ -115,6 +195,119 @@ impl FormParameters {
             .join("&")
     }
 
+    /// The query in a normalized form, for grouping equivalent queries (eg in the query statistics): sorted, without
+    /// empty values, deprecated names (their value is under the new name), or request-only parameters like `doit`
+    pub fn canonical_query(&self) -> String {
+        self.pairs()
+            .iter()
+            .filter(|(k, v)| !v.is_empty() && !NON_QUERY_PARAMETERS.contains(&k.as_str()))
+            .filter(|(k, _v)| !PARAMETER_ALIASES.iter().any(|alias| alias.old == k.as_str() && alias.new != alias.old))
+            .map(|(k, v)| Self::percent_encode(k) + "=" + &Self::percent_encode(v))
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+
+    /// Returns all values for a key, including repeats
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        match self.multi_params.get(key) {
+            Some(values) => values.to_owned(),
+            None => match self.params.get(key) {
+                Some(value) if !value.is_empty() => vec![value.to_owned()],
+                _ => vec![],
+            },
+        }
+    }
+
+    /// Key/value pairs for serialization, with repeated keys expanded. Sorted by key, so identical queries give
+    /// identical strings (eg for coalescing them).
+    fn pairs(&self) -> Vec<(String, String)> {
+        let mut ret = vec![];
+        let mut keys: Vec<&String> = self.params.keys().collect();
+        keys.sort();
+        for k in keys {
+            let v = &self.params[k];
+            if SECRET_PARAMETERS.contains(&k.as_str()) {
+                continue;
+            }
+            match self.multi_params.get(k) {
+                Some(values) if values.len() > 1 => {
+                    values.iter().for_each(|value| ret.push((k.to_owned(), value.to_owned())))
+                }
+                _ => ret.push((k.to_owned(), v.to_owned())),
+            }
+        }
+        ret
+    }
+
+    /// The query as JSON, for version control and sharing: `{"petscan_query":1,"parameters":{...}}`.
+    /// Keys are sorted, empty values are left out, and repeated keys become arrays.
+    pub fn as_query_json(&self) -> Value {
+        let keys: Vec<&String> = self
+            .params
+            .keys()
+            .filter(|k| !NON_QUERY_PARAMETERS.contains(&k.as_str()))
+            .filter(|k| !SECRET_PARAMETERS.contains(&k.as_str()))
+            .collect();
+        json!({"petscan_query": QUERY_JSON_VERSION, "parameters": self.parameters_as_json(keys)})
+    }
+
+    /// The parameters as they were used, after legacy mapping and PSID rebase: sorted, without empty values,
+    /// secrets, or deprecated names (their value is under the new name). Repeated keys become arrays.
+    pub fn effective_parameters(&self) -> Value {
+        let keys: Vec<&String> = self
+            .params
+            .keys()
+            .filter(|k| !SECRET_PARAMETERS.contains(&k.as_str()))
+            .filter(|k| !PARAMETER_ALIASES.iter().any(|alias| alias.old == k.as_str() && alias.new != alias.old))
+            .collect();
+        json!(self.parameters_as_json(keys))
+    }
+
+    fn parameters_as_json(&self, mut keys: Vec<&String>) -> serde_json::Map<String, Value> {
+        keys.sort();
+        keys.iter()
+            .filter_map(|k| {
+                let mut values = self.get_all(k);
+                let value = match values.len() {
+                    0 => return None,
+                    1 => json!(values.remove(0)),
+                    _ => json!(values),
+                };
+                Some((k.to_string(), value))
+            })
+            .collect()
+    }
+
+    /// Reads a query from `as_query_json` output; numbers are accepted as values, and `true` as "1"
+    pub fn new_from_query_json(json: &Value) -> Result<Self, String> {
+        match json["petscan_query"].as_u64() {
+            Some(version) if version <= QUERY_JSON_VERSION => {}
+            Some(version) => return Err(format!("Unsupported query version {}", version)),
+            None => return Err("Not a PetScan query (no 'petscan_query' version)".to_string()),
+        }
+        let parameters = json["parameters"]
+            .as_object()
+            .ok_or_else(|| "No 'parameters' object in query".to_string())?;
+        let mut pairs = vec![];
+        for (k, v) in parameters {
+            let values = match v.as_array() {
+                Some(values) => values.to_owned(),
+                None => vec![v.to_owned()],
+            };
+            for value in values {
+                let value = match value {
+                    Value::String(s) => s,
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(true) => "1".to_string(),
+                    Value::Bool(false) | Value::Null => continue,
+                    _ => return Err(format!("Invalid value for parameter '{}'", k)),
+                };
+                pairs.push((k.to_string(), value));
+            }
+        }
+        Ok(Self::new_from_pairs(pairs))
+    }
+
     pub fn percent_encode(s: &str) -> String {
         utf8_percent_encode(s, NON_ALPHANUMERIC).to_string()
     }
@@ -132,25 +325,66 @@ impl FormParameters {
 
     pub fn set_param(&mut self, key: &str, value: &str) {
         self.params.insert(key.to_string(), value.to_string());
+        let values = if value.is_empty() { vec![] } else { vec![value.to_string()] };
+        self.multi_params.insert(key.to_string(), values);
+    }
+
+    /// Deprecated parameters used in this query, and their replacements, eg ("format=jsonfm", "format=json")
+    pub fn deprecated_parameters(&self) -> &Vec<(String, String)> {
+        &self.deprecated
     }
 
-    fn fallback(&mut self, key_primary: &str, key_fallback: &str) {
-        if !self.has_param(key_fallback) {
-            return;
+    /// One warning per deprecated parameter, for the output
+    pub fn deprecation_warnings(&self) -> Vec<String> {
+        self.deprecated
+            .iter()
+            .map(|(old, new)| format!("<span tt='warn_deprecated_parameter'></span> {} &rarr; {}", old, new))
+            .collect()
+    }
+
+    fn note_deprecated(&mut self, old: String, new: String) {
+        if !self.deprecated.iter().any(|(o, _)| *o == old) {
+            self.deprecated.push((old, new));
         }
-        if !self.has_param(key_primary) || self.params.get(key_primary) == Some(&String::new()) {
-            let value = self
-                .params
-                .get(key_fallback)
-                .expect("FormParameters::fallback")
-                .to_owned();
-            self.set_param(key_primary, &value);
+    }
+
+    fn apply_alias(&mut self, alias: &ParameterAlias) {
+        let old_value = match self.params.get(alias.old) {
+            Some(value) => value.to_owned(),
+            None => return,
+        };
+        match alias.value {
+            AliasValue::Same => {
+                if !self.has_param_with_value(alias.new) {
+                    self.set_param(alias.new, &old_value);
+                }
+                self.note_deprecated(alias.old.to_string(), alias.new.to_string());
+            }
+            AliasValue::Fixed(value) => {
+                self.set_param(alias.new, value);
+                self.note_deprecated(alias.old.to_string(), format!("{}={}", alias.new, value));
+            }
+            AliasValue::Mapped(values) => {
+                let old_value = old_value.trim().to_lowercase();
+                if let Some((_, value)) = values.iter().find(|(old, _)| *old == old_value) {
+                    self.set_param(alias.new, value);
+                    self.note_deprecated(
+                        format!("{}={}", alias.old, old_value),
+                        format!("{}={}", alias.new, value),
+                    );
+                }
+            }
         }
     }
 
     fn legacy_parameters(&mut self) {
-        self.fallback("language", "lang");
-        self.fallback("categories", "cats");
+        // "jsonfm" is pretty-printed JSON; the format name itself is an alias
+        if self.params.get("format").map(|f| f.trim().to_lowercase()) == Some("jsonfm".to_string()) {
+            self.set_param("json-pretty", "1");
+        }
+        for alias in PARAMETER_ALIASES {
+            self.apply_alias(alias);
+        }
 
         // Old hack using manual wiki with no pages as "common wiki"
         if self.has_param_with_value("manual_list_wiki")
@@ -164,38 +398,239 @@ impl FormParameters {
             }
         }
 
-        // query originally from QuickIntersection
+        // query originally from QuickIntersection, with "ns=*" or a single namespace number instead of ns[0]=1 etc.
         if self.has_param("max") {
-            if self.params.get("format").unwrap_or(&String::new()) == "jsonfm" {
-                self.set_param("json-pretty", "1");
-            }
             self.set_param("output_compatability", "quick-intersection");
-            match self.params.get("ns") {
-                None => {}
-                Some(num) => {
-                    if num == "*" {
-                        self.set_param("ns[0]", "1");
-                    } else if let Ok(ns_num) = num.parse::<usize>() {
+            match self.params.get("ns").map(|ns| ns.trim().to_string()) {
+                Some(ns) if ns == "*" => self.set_param("ns[0]", "1"),
+                Some(ns) => {
+                    if let Ok(ns_num) = ns.parse::<usize>() {
                         self.set_param(format!("ns[{}]", ns_num).as_str(), "1");
                     }
                 }
+                None => {}
             }
         }
 
-        if self.has_param("comb_subset") {
-            self.set_param("combination", "subset");
-        }
-        if self.has_param("comb_union") {
-            self.set_param("combination", "union");
+        // Unconnected pages mode, see Platform::process_unconnected
+        if self.has_param("unconnected") {
+            self.set_param("wikidata_item", "without");
         }
-        if self.has_param("get_q") {
-            self.set_param("wikidata_item", "any");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn param(query: &str, key: &str) -> Option<String> {
+        FormParameters::new_from_query(query).params.get(key).cloned()
+    }
+
+    #[test]
+    fn plus_and_encoded_plus() {
+        assert_eq!(param("a=b+c", "a"), Some("b c".to_string()));
+        assert_eq!(param("a=b%2Bc", "a"), Some("b+c".to_string()));
+        assert_eq!(param("a=C%2B%2B+programmers", "a"), Some("C++ programmers".to_string()));
+    }
+
+    #[test]
+    fn decode_once_only() {
+        assert_eq!(param("a=%2520", "a"), Some("%20".to_string()));
+    }
+
+    #[test]
+    fn newlines_and_unicode() {
+        assert_eq!(param("categories=A%0D%0AB", "categories"), Some("A\r\nB".to_string()));
+        assert_eq!(param("categories=A%0AB", "categories"), Some("A\nB".to_string()));
+        assert_eq!(param("a=%C3%BCber", "a"), Some("\u{00FC}ber".to_string()));
+        assert_eq!(param("a=\u{00FC}ber", "a"), Some("\u{00FC}ber".to_string()));
+    }
+
+    #[test]
+    fn malformed_input() {
+        assert_eq!(param("a=%ZZ", "a"), Some("%ZZ".to_string()));
+        assert_eq!(param("a=50%", "a"), Some("50%".to_string()));
+        assert_eq!(param("a=b=c", "a"), Some("b=c".to_string()));
+        assert_eq!(param("&&a=1&&", "a"), Some("1".to_string()));
+        assert_eq!(param("=1", ""), None);
+        assert_eq!(param("flag", "flag"), Some(String::new()));
+    }
+
+    #[test]
+    fn repeated_keys() {
+        assert_eq!(param("a=1&a=2", "a"), Some("2".to_string()));
+        assert_eq!(param("a=1&a=", "a"), Some("1".to_string()));
+        assert_eq!(param("regexp_filter=&regexp_filter=Jimbo.*", "regexp_filter"), Some("Jimbo.*".to_string()));
+    }
+
+    #[test]
+    fn leading_question_mark_and_namespaces() {
+        let fp = FormParameters::new_from_query("?language=en&ns%5B0%5D=1&ns[14]=1&ns[10]=0");
+        assert_eq!(fp.params.get("language"), Some(&"en".to_string()));
+        assert!(fp.ns.contains(&0));
+        assert!(fp.ns.contains(&14));
+        assert!(!fp.ns.contains(&10));
+    }
+
+    #[test]
+    fn sorted_string() {
+        let fp = FormParameters::new_from_query("depth=3&categories=A&templates_yes=X&templates_yes=Y&admin_token=secret");
+        let fp2 = FormParameters::new_from_query("templates_yes=X&admin_token=other&categories=A&templates_yes=Y&depth=3");
+        assert_eq!(fp.to_string(), "categories=A&depth=3&templates%5Fyes=X&templates%5Fyes=Y");
+        assert_eq!(fp.to_string(), fp2.to_string());
+    }
+
+    #[test]
+    fn canonical_query() {
+        let fp = FormParameters::new_from_query("doit=1&lang=de&depth=3&negcats=&format=json");
+        let fp2 = FormParameters::new_from_query("depth=3&language=de");
+        assert_eq!(fp.canonical_query(), "depth=3&language=de");
+        assert_eq!(fp.canonical_query(), fp2.canonical_query());
+    }
+
+    #[test]
+    fn round_trip() {
+        let fp = FormParameters::new_from_query("categories=A+b%0AC%2B%2B&depth=3");
+        let fp2 = FormParameters::new_from_query(&fp.to_string());
+        assert_eq!(fp.params, fp2.params);
+    }
+
+    #[test]
+    fn multi_valued() {
+        let fp = FormParameters::new_from_query("templates_yes=A&templates_yes=&templates_yes=B%0AC&depth=3");
+        assert_eq!(fp.params.get("templates_yes"), Some(&"B\nC".to_string()));
+        assert_eq!(fp.get_all("templates_yes"), vec!["A".to_string(), "B\nC".to_string()]);
+        assert_eq!(fp.get_all("depth"), vec!["3".to_string()]);
+        assert!(fp.get_all("negcats").is_empty());
+        let fp2 = FormParameters::new_from_query(&fp.to_string());
+        assert_eq!(fp.get_all("templates_yes"), fp2.get_all("templates_yes"));
+    }
+
+    #[test]
+    fn legacy_links() {
+        let fp = FormParameters::new_from_query("lang=de&project=wikipedia&cats=Mann%0AFrau&templates=Personendaten&outlinks=Berlin&ns[0]=1&ns[14]=1&doit=1");
+        assert_eq!(fp.params.get("language"), Some(&"de".to_string()));
+        assert_eq!(fp.params.get("categories"), Some(&"Mann\nFrau".to_string()));
+        assert_eq!(fp.params.get("templates_yes"), Some(&"Personendaten".to_string()));
+        assert_eq!(fp.params.get("outlinks_yes"), Some(&"Berlin".to_string()));
+        assert!(fp.ns.contains(&0));
+        assert!(fp.ns.contains(&14));
+
+        // QuickIntersection
+        let fp = FormParameters::new_from_query("lang=en&project=wikipedia&cats=Physicists&ns=0&depth=3&max=30&start=0&format=jsonfm");
+        assert_eq!(fp.params.get("format"), Some(&"json".to_string()));
+        assert!(fp.params.contains_key("json-pretty"));
+        assert_eq!(fp.params.get("output_compatability"), Some(&"quick-intersection".to_string()));
+        assert!(fp.ns.contains(&0));
+
+        let fp = FormParameters::new_from_query("lang=en&project=wikipedia&cats=Physicists&ns=*&max=30&format=html");
+        assert!(fp.ns.contains(&0));
+
+        // The current names take precedence
+        let fp = FormParameters::new_from_query("language=fr&lang=de&templates_yes=A&templates=B&ns=0,14");
+        assert_eq!(fp.params.get("language"), Some(&"fr".to_string()));
+        assert_eq!(fp.params.get("templates_yes"), Some(&"A".to_string()));
+        assert!(fp.ns.is_empty());
+        assert_eq!(param("format=html", "format"), Some("html".to_string()));
+    }
+
+    #[test]
+    fn secret_parameters() {
+        let fp = FormParameters::new_from_query("language=de&admin_token=secret&deleted_pages=1");
+        assert_eq!(fp.params.get("admin_token"), Some(&"secret".to_string()));
+        assert!(!fp.to_string().contains("secret"));
+        assert!(!fp.to_string_no_doit().contains("admin_token"));
+        assert!(fp.as_query_json()["parameters"]["admin_token"].is_null());
+    }
+
+    #[test]
+    fn query_json() {
+        let fp = FormParameters::new_from_query(
+            "language=de&templates_yes=A&templates_yes=B&depth=3&negcats=&doit=1&format=query_json",
+        );
+        let j = fp.as_query_json();
+        assert_eq!(
+            j,
+            json!({"petscan_query":1,"parameters":{"depth":"3","language":"de","templates_yes":["A","B"]}})
+        );
+        assert_eq!(j["parameters"].as_object().unwrap().keys().next(), Some(&"depth".to_string()));
+        let fp2 = FormParameters::new_from_query_json(&j).unwrap();
+        assert_eq!(fp2.get_all("templates_yes"), fp.get_all("templates_yes"));
+        assert_eq!(fp2.as_query_json(), j);
+
+        let fp = FormParameters::new_from_query_json(&json!({"petscan_query":1,"parameters":{"depth":3,"only_new":true,"ns[0]":"1"}})).unwrap();
+        assert_eq!(fp.params.get("depth"), Some(&"3".to_string()));
+        assert_eq!(fp.params.get("only_new"), Some(&"1".to_string()));
+        assert!(fp.ns.contains(&0));
+        assert!(FormParameters::new_from_query_json(&json!({"parameters":{}})).is_err());
+        assert!(FormParameters::new_from_query_json(&json!({"petscan_query":99,"parameters":{}})).is_err());
+        assert!(FormParameters::new_from_query_json(&json!({"petscan_query":1,"parameters":{"a":{"b":1}}})).is_err());
+    }
+
+    #[test]
+    fn multi_valued_set_and_rebase() {
+        let mut fp = FormParameters::new_from_query("categories=A&categories=B");
+        fp.set_param("categories", "C");
+        assert_eq!(fp.get_all("categories"), vec!["C".to_string()]);
+        let base = FormParameters::new_from_query("negcats=X&negcats=Y");
+        let mut fp = FormParameters::new_from_query("negcats=");
+        fp.rebase(&base);
+        assert_eq!(fp.get_all("negcats"), vec!["X".to_string(), "Y".to_string()]);
+    }
+
+    #[test]
+    fn deprecated_parameters() {
+        let fp = FormParameters::new_from_query("lang=de&language=fr&comb_union=1&format=jsonfm");
+        assert_eq!(fp.params.get("language"), Some(&"fr".to_string()));
+        assert_eq!(fp.params.get("combination"), Some(&"union".to_string()));
+        assert_eq!(
+            fp.deprecated_parameters(),
+            &vec![
+                ("lang".to_string(), "language".to_string()),
+                ("format=jsonfm".to_string(), "format=json".to_string()),
+                ("comb_union".to_string(), "combination=union".to_string()),
+            ]
+        );
+        assert_eq!(fp.deprecation_warnings()[0], "<span tt='warn_deprecated_parameter'></span> lang &rarr; language");
+
+        let mut fp = FormParameters::new_from_query("language=de&sortorder=descending&format=json");
+        assert!(fp.deprecated_parameters().is_empty());
+        fp.rebase(&FormParameters::new_from_query("cats=Foo"));
+        assert_eq!(fp.params.get("categories"), Some(&"Foo".to_string()));
+        assert_eq!(fp.deprecated_parameters().len(), 1);
+    }
+
+    #[test]
+    fn effective_parameters() {
+        let mut fp = FormParameters::new_from_query("lang=de&cats=A&depth=&admin_token=secret&format=wiki&doit=1");
+        fp.rebase(&FormParameters::new_from_query("templates_yes=X&templates_yes=Y"));
+        assert_eq!(
+            fp.effective_parameters(),
+            json!({"categories":"A","doit":"1","format":"wiki","language":"de","templates_yes":["X","Y"]})
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn parse_any_query(query in ".*") {
+            let fp = FormParameters::outcome_from_query(&query).unwrap();
+            let _ = fp.to_string();
         }
-        if self.has_param("wikidata") {
-            self.set_param("wikidata_item", "any");
+
+        #[test]
+        fn parse_query_like(query in "[a-z_\\[\\]0-9=&+%A-F ]{0,60}") {
+            let fp = FormParameters::outcome_from_query(&query).unwrap();
+            let _ = FormParameters::outcome_from_query(&fp.to_string()).unwrap();
         }
-        if self.has_param("wikidata_no_item") {
-            self.set_param("wikidata_item", "without");
+
+        /// Keys have a prefix, so no legacy parameter handling applies
+        #[test]
+        fn query_round_trip(pairs in proptest::collection::hash_map("p_[a-z0-9_]{0,10}", ".*", 0..10)) {
+            let fp = FormParameters::new_from_pairs(pairs.clone().into_iter().collect());
+            let fp = FormParameters::outcome_from_query(&fp.to_string()).unwrap();
+            prop_assert_eq!(fp.params, pairs);
         }
     }
 }
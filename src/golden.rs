@@ -0,0 +1,217 @@
+use crate::pagelist::PageList;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use wikibase::mediawiki::api::NamespaceID;
+
+pub static DEFAULT_GOLDEN_PATH: &str = "./fixtures/golden";
+/// Relative difference in result size that is still accepted; live wikis change between recording and replay
+pub static DEFAULT_COUNT_TOLERANCE: f64 = 0.05;
+/// Titles sampled from a PetScan v2 result when recording a snapshot
+static GOLDEN_SAMPLE_SIZE: usize = 20;
+/// The queries to record, one per line: name, wiki, and query string, tab-separated
+pub static GOLDEN_QUERIES_FILE: &str = "./fixtures/golden/queries.tsv";
+
+/// A recorded PetScan v2 query: the result size, and a sample of the titles it returned. Replaying the query must give
+/// about the same number of pages, and all sampled titles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenSnapshot {
+    pub name: String,
+    pub query: String,
+    pub wiki: String,
+    pub count: usize,
+    pub count_tolerance: f64,
+    pub titles: Vec<(NamespaceID, String)>,
+}
+
+impl GoldenSnapshot {
+    pub fn new_from_json(j: &Value) -> Result<Self, String> {
+        let string = |key: &str| {
+            j[key]
+                .as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| format!("Golden snapshot: missing '{}'", key))
+        };
+        let titles = j["titles"]
+            .as_array()
+            .map(|titles| {
+                titles
+                    .iter()
+                    .filter_map(|t| Some((t[0].as_i64()? as NamespaceID, t[1].as_str()?.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            name: string("name")?,
+            query: string("query")?,
+            wiki: string("wiki")?,
+            count: j["count"]
+                .as_u64()
+                .ok_or_else(|| "Golden snapshot: missing 'count'".to_string())? as usize,
+            count_tolerance: j["count_tolerance"].as_f64().unwrap_or(DEFAULT_COUNT_TOLERANCE),
+            titles,
+        })
+    }
+
+    /// Records a snapshot from the output of a PetScan v2 query with `format=json`
+    pub fn new_from_v2_json(name: &str, query: &str, wiki: &str, v2: &Value) -> Result<Self, String> {
+        let pages = v2["*"][0]["a"]["*"]
+            .as_array()
+            .ok_or_else(|| "Golden snapshot: not a PetScan v2 JSON result".to_string())?;
+        let step = (pages.len() / GOLDEN_SAMPLE_SIZE).max(1);
+        let titles = pages
+            .iter()
+            .step_by(step)
+            .take(GOLDEN_SAMPLE_SIZE)
+            .filter_map(|page| Some((page["namespace"].as_i64()? as NamespaceID, page["title"].as_str()?.to_string())))
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            query: query.to_string(),
+            wiki: wiki.to_string(),
+            count: pages.len(),
+            count_tolerance: DEFAULT_COUNT_TOLERANCE,
+            titles,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        let titles: Vec<Value> = self.titles.iter().map(|(ns, title)| json!([ns, title])).collect();
+        json!({
+            "name":self.name,
+            "query":self.query,
+            "wiki":self.wiki,
+            "count":self.count,
+            "count_tolerance":self.count_tolerance,
+            "titles":titles
+        })
+    }
+
+    /// The queries to record in `path` (see GOLDEN_QUERIES_FILE), as (name, wiki, query)
+    pub fn load_queries(path: &str) -> Result<Vec<(String, String, String)>, String> {
+        fs::read_to_string(path)
+            .map_err(|e| format!("Golden queries: {}: {}", path, e))?
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let parts: Vec<&str> = line.splitn(3, '\t').collect();
+                match parts.as_slice() {
+                    [name, wiki, query] => Ok((name.to_string(), wiki.to_string(), query.to_string())),
+                    _ => Err(format!("Bad line in {}: {}", path, line)),
+                }
+            })
+            .collect()
+    }
+
+    /// All `*.json` snapshots in `path`, sorted by file name
+    pub fn load_directory(path: &str) -> Result<Vec<Self>, String> {
+        let mut files: Vec<_> = fs::read_dir(path)
+            .map_err(|e| format!("Golden snapshots: {}: {}", path, e))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|ext| ext == "json").unwrap_or(false))
+            .collect();
+        files.sort();
+        files
+            .iter()
+            .map(|file| {
+                let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+                let j: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", file.display(), e))?;
+                Self::new_from_json(&j).map_err(|e| format!("{}: {}", file.display(), e))
+            })
+            .collect()
+    }
+
+    /// Differences between the recorded v2 result and `result`
+    pub fn compare(&self, result: &PageList) -> Result<Vec<String>, String> {
+        let wiki = result.wiki()?.unwrap_or_default();
+        let titles: HashSet<(NamespaceID, String)> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .map(|entry| (entry.title().namespace_id(), entry.title().with_underscores()))
+            .collect();
+        Ok(self.differences(&wiki, &titles))
+    }
+
+    fn differences(&self, wiki: &str, titles: &HashSet<(NamespaceID, String)>) -> Vec<String> {
+        let mut ret = vec![];
+        if wiki != self.wiki {
+            ret.push(format!("{}: wiki is {}, not {}", self.name, wiki, self.wiki));
+        }
+        let allowed = (self.count as f64 * self.count_tolerance).ceil() as usize;
+        let difference = (titles.len() as i64 - self.count as i64).abs() as usize;
+        if difference > allowed {
+            ret.push(format!("{}: {} pages, v2 had {}", self.name, titles.len(), self.count));
+        }
+        self.titles
+            .iter()
+            .filter(|title| !titles.contains(*title))
+            .for_each(|(ns, title)| ret.push(format!("{}: missing [{}] {}", self.name, ns, title)));
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the snapshots in GOLDEN_QUERIES_FILE from the PetScan v2 instance at $GOLDEN_V2_URL (eg
+    /// "https://v2.petscan.example.org/"); run with `cargo test record_golden_snapshots -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn record_golden_snapshots() {
+        let v2_url = std::env::var("GOLDEN_V2_URL").expect("GOLDEN_V2_URL is not set");
+        for (name, wiki, query) in GoldenSnapshot::load_queries(GOLDEN_QUERIES_FILE).unwrap() {
+            let url = format!("{}?{}&format=json&doit=1", v2_url, query);
+            let v2: Value = reqwest::get(&url).await.unwrap().json().await.unwrap();
+            let snapshot = GoldenSnapshot::new_from_v2_json(&name, &query, &wiki, &v2).unwrap();
+            let path = format!("{}/{}.json", DEFAULT_GOLDEN_PATH, name);
+            fs::write(&path, serde_json::to_string_pretty(&snapshot.to_json()).unwrap()).unwrap();
+        }
+    }
+
+    fn snapshot() -> GoldenSnapshot {
+        GoldenSnapshot::new_from_v2_json(
+            "births",
+            "language=en&project=wikipedia&categories=1974_births",
+            "enwiki",
+            &json!({"n":"result","a":{"querytime_sec":1.2},"*":[{"n":"combination","a":{"type":"subset","*":[
+                {"n":"page","title":"Magnus_Manske","id":1,"namespace":0,"nstext":""},
+                {"n":"page","title":"Foo","id":2,"namespace":0,"nstext":""},
+                {"n":"page","title":"Bar","id":3,"namespace":14,"nstext":"Category"}
+            ]}}]}),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn load_queries() {
+        let queries = GoldenSnapshot::load_queries(GOLDEN_QUERIES_FILE).unwrap();
+        assert!(queries.iter().any(|(name, wiki, _)| name == "manual_list_enwiki" && wiki == "enwiki"));
+    }
+
+    #[test]
+    fn new_from_v2_json() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.count, 3);
+        assert_eq!(snapshot.titles.len(), 3);
+        assert_eq!(snapshot.titles[2], (14, "Bar".to_string()));
+        assert_eq!(GoldenSnapshot::new_from_json(&snapshot.to_json()), Ok(snapshot));
+        assert!(GoldenSnapshot::new_from_v2_json("x", "", "enwiki", &json!({})).is_err());
+    }
+
+    #[test]
+    fn differences() {
+        let snapshot = snapshot();
+        let mut titles: HashSet<(NamespaceID, String)> = snapshot.titles.iter().cloned().collect();
+        assert!(snapshot.differences("enwiki", &titles).is_empty());
+        titles.remove(&(0, "Foo".to_string()));
+        titles.insert((0, "Baz".to_string()));
+        assert_eq!(snapshot.differences("enwiki", &titles), vec!["births: missing [0] Foo".to_string()]);
+        titles.insert((0, "Qux".to_string()));
+        titles.insert((0, "Quux".to_string()));
+        assert_eq!(snapshot.differences("dewiki", &titles).len(), 3);
+    }
+}
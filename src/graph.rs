@@ -0,0 +1,211 @@
+use crate::platform::ContentType;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    GraphML,
+    JSON,
+}
+
+impl GraphFormat {
+    /// From the `graph_format` parameter; DOT (Graphviz) by default
+    pub fn new_from_param(s: &str) -> Self {
+        match s.trim().to_lowercase().as_str() {
+            "graphml" => Self::GraphML,
+            "json" => Self::JSON,
+            _ => Self::Dot,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Dot => "dot",
+            Self::GraphML => "graphml",
+            Self::JSON => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            Self::Dot => ContentType::Dot,
+            Self::GraphML => ContentType::GraphML,
+            Self::JSON => ContentType::JSON,
+        }
+    }
+}
+
+/// A directed graph with labelled nodes, eg pages and the wikilinks between them; edges can have labels too
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Graph {
+    nodes: Vec<String>,
+    node_ids: HashMap<String, usize>,
+    edges: Vec<(usize, usize)>,
+    edge_set: HashSet<(usize, usize)>,
+    edge_labels: HashMap<(usize, usize), String>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node, unless one with that label exists; returns its index
+    pub fn add_node(&mut self, label: &str) -> usize {
+        if let Some(id) = self.node_ids.get(label) {
+            return *id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(label.to_string());
+        self.node_ids.insert(label.to_string(), id);
+        id
+    }
+
+    /// Adds an edge between two nodes, which are added as needed; self-loops and duplicates are skipped
+    pub fn add_edge(&mut self, from: &str, to: &str) {
+        let from = self.add_node(from);
+        let to = self.add_node(to);
+        if from != to && self.edge_set.insert((from, to)) {
+            self.edges.push((from, to));
+        }
+    }
+
+    /// As `add_edge`, with a label, eg the depth in a category tree
+    pub fn add_labelled_edge(&mut self, from: &str, to: &str, label: &str) {
+        self.add_edge(from, to);
+        let edge = (self.add_node(from), self.add_node(to));
+        if edge.0 != edge.1 {
+            self.edge_labels.insert(edge, label.to_string());
+        }
+    }
+
+    pub fn nodes(&self) -> &Vec<String> {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &Vec<(usize, usize)> {
+        &self.edges
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphML => self.to_graphml(),
+            GraphFormat::JSON => self.as_json().to_string(),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut ret = "digraph petscan {\n".to_string();
+        self.nodes.iter().enumerate().for_each(|(id, label)| {
+            ret += &format!("  n{} [label=\"{}\"];\n", id, Self::escape_dot(label));
+        });
+        self.edges.iter().for_each(|(from, to)| match self.edge_labels.get(&(*from, *to)) {
+            Some(label) => ret += &format!("  n{} -> n{} [label=\"{}\"];\n", from, to, Self::escape_dot(label)),
+            None => ret += &format!("  n{} -> n{};\n", from, to),
+        });
+        ret + "}\n"
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut ret = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+        ret += "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n";
+        ret += "<key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n";
+        ret += "<key id=\"edge_label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n";
+        ret += "<graph id=\"petscan\" edgedefault=\"directed\">\n";
+        self.nodes.iter().enumerate().for_each(|(id, label)| {
+            ret += &format!("<node id=\"n{}\"><data key=\"label\">{}</data></node>\n", id, Self::escape_xml(label));
+        });
+        self.edges.iter().for_each(|(from, to)| match self.edge_labels.get(&(*from, *to)) {
+            Some(label) => {
+                ret += &format!(
+                    "<edge source=\"n{}\" target=\"n{}\"><data key=\"edge_label\">{}</data></edge>\n",
+                    from,
+                    to,
+                    Self::escape_xml(label)
+                )
+            }
+            None => ret += &format!("<edge source=\"n{}\" target=\"n{}\"/>\n", from, to),
+        });
+        ret + "</graph>\n</graphml>\n"
+    }
+
+    pub fn as_json(&self) -> Value {
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, label)| json!({"id":id,"label":label}))
+            .collect();
+        let edges: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|(from, to)| match self.edge_labels.get(&(*from, *to)) {
+                Some(label) => json!({"source":from,"target":to,"label":label}),
+                None => json!({"source":from,"target":to}),
+            })
+            .collect();
+        json!({"nodes":nodes,"edges":edges})
+    }
+
+    fn escape_dot(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    fn escape_xml(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        let mut graph = Graph::new();
+        graph.add_node("Lonely");
+        graph.add_edge("Foo", "Bar \"B&B\"");
+        graph.add_edge("Foo", "Bar \"B&B\"");
+        graph.add_edge("Bar \"B&B\"", "Foo");
+        graph.add_edge("Foo", "Foo");
+        graph
+    }
+
+    #[test]
+    fn add_edge() {
+        let graph = graph();
+        assert_eq!(graph.nodes().len(), 3);
+        assert_eq!(graph.edges(), &vec![(1, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn render() {
+        let graph = graph();
+        let dot = graph.render(GraphFormat::new_from_param(""));
+        assert!(dot.starts_with("digraph petscan {\n"));
+        assert!(dot.contains("  n2 [label=\"Bar \\\"B&B\\\"\"];\n  n1 -> n2;\n"));
+        let graphml = graph.render(GraphFormat::new_from_param("GraphML"));
+        assert!(graphml.contains("<data key=\"label\">Bar &quot;B&amp;B&quot;</data>"));
+        assert!(graphml.contains("<edge source=\"n2\" target=\"n1\"/>"));
+        assert_eq!(
+            graph.as_json()["edges"],
+            json!([{"source":1,"target":2},{"source":2,"target":1}])
+        );
+        assert_eq!(GraphFormat::new_from_param("json").extension(), "json");
+    }
+
+    #[test]
+    fn edge_labels() {
+        let mut graph = Graph::new();
+        graph.add_labelled_edge("A", "B", "1");
+        graph.add_edge("B", "C");
+        assert!(graph.to_dot().contains("  n0 -> n1 [label=\"1\"];\n  n1 -> n2;\n"));
+        assert!(graph.to_graphml().contains("<edge source=\"n0\" target=\"n1\"><data key=\"edge_label\">1</data></edge>"));
+        assert_eq!(graph.as_json()["edges"][0]["label"], json!("1"));
+        assert_eq!(graph.as_json()["edges"][1].get("label"), None);
+    }
+}
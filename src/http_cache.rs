@@ -0,0 +1,97 @@
+use chrono::prelude::*;
+use regex::Regex;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Entity tag for a response; changes whenever the result does, but not with the query time or the result token.
+/// The query still runs for every request, so a matching tag only saves the download, not the work.
+pub fn etag_for(body: &str, content_type: &str) -> String {
+    let body = canonical_body(body);
+    let mut hasher = DefaultHasher::new();
+    content_type.hash(&mut hasher);
+    body.hash(&mut hasher);
+    format!("\"{:016x}{:08x}\"", hasher.finish(), body.len())
+}
+
+/// The output without the parts that differ between runs of the same query
+fn canonical_body(body: &str) -> String {
+    lazy_static! {
+        static ref RE_JSON_QUERYTIME: Regex =
+            Regex::new(r#""querytime(_sec)?"\s*:\s*[-+0-9.eE]+\s*,?"#).expect("http_cache RE_JSON_QUERYTIME");
+        static ref RE_HTML_QUERYTIME: Regex =
+            Regex::new(r#"<div[^>]*id='query_length'[^>]*></div>"#).expect("http_cache RE_HTML_QUERYTIME");
+        static ref RE_HTML_RESULT_TOKEN: Regex =
+            Regex::new(r#"(?s)<div[^>]*id='result_token'.*?</div>"#).expect("http_cache RE_HTML_RESULT_TOKEN");
+    }
+    let body = RE_JSON_QUERYTIME.replace_all(body, "");
+    let body = RE_HTML_QUERYTIME.replace_all(&body, "");
+    RE_HTML_RESULT_TOKEN.replace_all(&body, "").to_string()
+}
+
+/// Does an If-None-Match header match `etag`? Weak comparison, as for GET requests
+pub fn if_none_match_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Timestamp in the format for Last-Modified, eg "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etag_for() {
+        let etag = super::etag_for("a\tb", "text/plain");
+        assert_eq!(etag, super::etag_for("a\tb", "text/plain"));
+        assert_ne!(etag, super::etag_for("a\tc", "text/plain"));
+        assert_ne!(etag, super::etag_for("a\tb", "text/csv"));
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+    }
+
+    #[test]
+    fn etag_for_ignores_volatile_output() {
+        let json1 = r#"{"n":"result","a":{"query":"psid=1","querytime_sec":1.25,"partial":false},"*":[]}"#;
+        let json2 = r#"{"n":"result","a":{"query":"psid=1","querytime_sec":0.5,"partial":false},"*":[]}"#;
+        assert_eq!(super::etag_for(json1, "application/json"), super::etag_for(json2, "application/json"));
+        let pretty = "{\n  \"querytime\": 3.1,\n  \"pages\": []\n}";
+        assert_eq!(
+            super::etag_for(pretty, "application/json"),
+            super::etag_for(&pretty.replace("3.1", "12"), "application/json")
+        );
+        assert_ne!(
+            super::etag_for(json1, "application/json"),
+            super::etag_for(&json1.replace("psid=1", "psid=2"), "application/json")
+        );
+
+        let html = |sec: &str, token: &str| {
+            format!(
+                "<table>x</table>\n<div style='font-size:8pt' id='query_length' sec='{}'></div>\n\
+                <div style='font-size:8pt' id='result_token' data-token='{}'><a href='/result/{}?format=csv'>csv</a></div>\n<script src='results.js'></script>",
+                sec, token, token
+            )
+        };
+        assert_eq!(super::etag_for(&html("1.5", "abc"), "text/html"), super::etag_for(&html("2", "def"), "text/html"));
+    }
+
+    #[test]
+    fn if_none_match_matches() {
+        assert!(super::if_none_match_matches("\"abc\"", "\"abc\""));
+        assert!(super::if_none_match_matches("\"x\", W/\"abc\"", "\"abc\""));
+        assert!(super::if_none_match_matches("*", "\"abc\""));
+        assert!(!super::if_none_match_matches("\"abd\"", "\"abc\""));
+        assert!(!super::if_none_match_matches("", "\"abc\""));
+    }
+
+    #[test]
+    fn http_date() {
+        let time = DateTime::parse_from_rfc3339("1994-11-06T08:49:37Z").unwrap().with_timezone(&Utc);
+        assert_eq!(super::http_date(&time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
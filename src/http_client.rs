@@ -0,0 +1,145 @@
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+
+pub static DEFAULT_USER_AGENT: &str = concat!(
+    "PetScan/",
+    env!("CARGO_PKG_VERSION"),
+    " (https://petscan.wmflabs.org; https://github.com/magnusmanske/petscan_rs)"
+);
+static DEFAULT_HTTP_TIMEOUT_SECONDS: u64 = 120;
+static DEFAULT_HTTP_MAX_RETRIES: u64 = 3;
+static DEFAULT_HTTP_BACKOFF_MS: u64 = 500;
+static MAX_HTTP_BACKOFF_MS: u64 = 30_000;
+
+/// Shared client for external HTTP calls (SPARQL, PagePile, pageviews, LiftWing etc.).
+/// Connections are reused; requests are retried with exponential backoff on 429, 5xx, and connection errors.
+/// Configured via "http":{"timeout_seconds":120,"max_retries":3,"backoff_ms":500,"user_agent":"..."}
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    user_agent: String,
+    timeout: Duration,
+    max_retries: u64,
+    backoff: Duration,
+}
+
+impl HttpClient {
+    pub fn new_from_config(config: &Value) -> Result<Self, String> {
+        let http = &config["http"];
+        let user_agent = http["user_agent"]
+            .as_str()
+            .filter(|ua| !ua.is_empty())
+            .unwrap_or(DEFAULT_USER_AGENT)
+            .to_string();
+        let timeout = Duration::from_secs(http["timeout_seconds"].as_u64().unwrap_or(DEFAULT_HTTP_TIMEOUT_SECONDS));
+        let client = reqwest::ClientBuilder::new()
+            .timeout(timeout)
+            .user_agent(&user_agent)
+            .build()
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(Self {
+            client,
+            user_agent,
+            timeout,
+            max_retries: http["max_retries"].as_u64().unwrap_or(DEFAULT_HTTP_MAX_RETRIES),
+            backoff: Duration::from_millis(http["backoff_ms"].as_u64().unwrap_or(DEFAULT_HTTP_BACKOFF_MS)),
+        })
+    }
+
+    /// With the same user agent and timeout, for clients that need different settings (eg no redirects); use with `send`
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::ClientBuilder::new()
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent)
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Sends the request, retrying it if it failed temporarily. The last response is returned, even if unsuccessful.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let mut attempt = 0;
+        loop {
+            // Requests with a streaming body can not be cloned, so they are not retried
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < self.max_retries => retry,
+                _ => return request.send().await.map_err(|e| format!("{:?}", e)),
+            };
+            let wait = match retry.send().await {
+                Ok(response) => match Self::is_temporary_failure(response.status()) {
+                    true => Self::retry_after(&response).unwrap_or_else(|| self.backoff_for(attempt)),
+                    false => return Ok(response),
+                },
+                Err(e) if e.is_connect() || e.is_timeout() => self.backoff_for(attempt),
+                Err(e) => return Err(format!("{:?}", e)),
+            };
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+
+    fn is_temporary_failure(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    fn backoff_for(&self, attempt: u64) -> Duration {
+        let ms = (self.backoff.as_millis() as u64).saturating_mul(1 << attempt.min(16));
+        Duration::from_millis(ms.min(MAX_HTTP_BACKOFF_MS))
+    }
+
+    fn retry_after(response: &Response) -> Option<Duration> {
+        Self::parse_retry_after(response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?)
+    }
+
+    /// Retry-After in seconds, capped
+    fn parse_retry_after(value: &str) -> Option<Duration> {
+        let seconds = value.trim().parse::<u64>().ok()?;
+        Some(Duration::from_millis(seconds.saturating_mul(1000).min(MAX_HTTP_BACKOFF_MS)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_and_backoff() {
+        let http = HttpClient::new_from_config(&json!({"http":{"backoff_ms":1000,"user_agent":"Test"}})).unwrap();
+        assert_eq!(http.user_agent(), "Test");
+        assert_eq!(http.max_retries, DEFAULT_HTTP_MAX_RETRIES);
+        assert_eq!(http.backoff_for(0), Duration::from_secs(1));
+        assert_eq!(http.backoff_for(3), Duration::from_secs(8));
+        assert_eq!(http.backoff_for(60), Duration::from_millis(MAX_HTTP_BACKOFF_MS));
+        let http = HttpClient::new_from_config(&json!({})).unwrap();
+        assert!(http.user_agent().starts_with("PetScan/"));
+    }
+
+    #[test]
+    fn parse_retry_after() {
+        assert_eq!(HttpClient::parse_retry_after(" 2 "), Some(Duration::from_secs(2)));
+        assert_eq!(HttpClient::parse_retry_after("18446744073709551615"), Some(Duration::from_millis(MAX_HTTP_BACKOFF_MS)));
+        assert_eq!(HttpClient::parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn temporary_failures() {
+        assert!(HttpClient::is_temporary_failure(StatusCode::TOO_MANY_REQUESTS));
+        assert!(HttpClient::is_temporary_failure(StatusCode::BAD_GATEWAY));
+        assert!(!HttpClient::is_temporary_failure(StatusCode::NOT_FOUND));
+        assert!(!HttpClient::is_temporary_failure(StatusCode::OK));
+    }
+}
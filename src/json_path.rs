@@ -0,0 +1,101 @@
+use serde_json::Value;
+
+/// A small JSONPath subset, for extracting titles from JSON: `$`, `.key`, `['key']`, `[0]`, `[*]` and `.*`
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let path = path.trim();
+        let mut rest = path.strip_prefix('$').unwrap_or(path);
+        let mut steps = vec![];
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('.') {
+                let end = r.find(|c| c == '.' || c == '[').unwrap_or_else(|| r.len());
+                let key = &r[..end];
+                match key {
+                    "" => return Err(format!("Empty key in JSONPath '{}'", path)),
+                    "*" => steps.push(Step::Wildcard),
+                    key => steps.push(Step::Key(key.to_string())),
+                }
+                rest = &r[end..];
+            } else if let Some(r) = rest.strip_prefix('[') {
+                let end = r
+                    .find(']')
+                    .ok_or_else(|| format!("Missing ']' in JSONPath '{}'", path))?;
+                let inner = r[..end].trim();
+                if inner == "*" {
+                    steps.push(Step::Wildcard);
+                } else if let Ok(index) = inner.parse::<usize>() {
+                    steps.push(Step::Index(index));
+                } else if inner.len() >= 2
+                    && ((inner.starts_with('\'') && inner.ends_with('\''))
+                        || (inner.starts_with('"') && inner.ends_with('"')))
+                {
+                    steps.push(Step::Key(inner[1..inner.len() - 1].to_string()));
+                } else {
+                    return Err(format!("Unsupported JSONPath element '[{}]'", inner));
+                }
+                rest = &r[end + 1..];
+            } else {
+                return Err(format!("Unsupported JSONPath '{}'", path));
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    /// All values at the path
+    pub fn select<'a>(&self, json: &'a Value) -> Vec<&'a Value> {
+        let mut current = vec![json];
+        for step in &self.steps {
+            current = current
+                .into_iter()
+                .flat_map(|v| -> Vec<&'a Value> {
+                    match step {
+                        Step::Key(key) => v.get(key.as_str()).into_iter().collect(),
+                        Step::Index(index) => v.get(*index).into_iter().collect(),
+                        Step::Wildcard => match v {
+                            Value::Array(a) => a.iter().collect(),
+                            Value::Object(o) => o.values().collect(),
+                            _ => vec![],
+                        },
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+
+    /// The string values at the path
+    pub fn select_strings(&self, json: &Value) -> Vec<String> {
+        self.select(json)
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|s| s.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select() {
+        let j = json!({"items":[{"title":"A"},{"title":"B"},{"title":3}],"x y":{"a":"C","b":"D"}});
+        assert_eq!(JsonPath::parse("$.items[*].title").unwrap().select_strings(&j), vec!["A", "B"]);
+        assert_eq!(JsonPath::parse("$.items[1].title").unwrap().select_strings(&j), vec!["B"]);
+        assert_eq!(JsonPath::parse("$['x y'].*").unwrap().select_strings(&j).len(), 2);
+        assert!(JsonPath::parse("$.items[?(@.x)]").is_err());
+        assert!(JsonPath::parse("$..title").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::safe_sql;
+use regex::Regex;
+
+/// One line of the labels_yes/any/no fields. A line can start with a match mode:
+/// "exact:", "prefix:", "like:" (with % and _ wildcards), or "regex:" (applied after the query).
+/// Lines without a mode match literally, or as "like:" with `allow_wildcards` (the `label_wildcards` parameter).
+#[derive(Debug, Clone)]
+pub enum LabelPattern {
+    Like(String),
+    Regex(Regex),
+}
+
+impl LabelPattern {
+    pub fn parse(line: &str, allow_wildcards: bool) -> Result<Self, String> {
+        let line = line.trim();
+        if let Some(s) = line.strip_prefix("exact:") {
+            return Ok(LabelPattern::Like(safe_sql::escape_like(s.trim())));
+        }
+        if let Some(s) = line.strip_prefix("prefix:") {
+            return Ok(LabelPattern::Like(safe_sql::like_prefix(s.trim())));
+        }
+        if let Some(s) = line.strip_prefix("like:") {
+            return Ok(LabelPattern::Like(s.trim().to_string()));
+        }
+        if let Some(s) = line.strip_prefix("regex:") {
+            return Regex::new(s.trim())
+                .map(LabelPattern::Regex)
+                .map_err(|e| format!("Invalid label regular expression '{}': {}", s.trim(), e));
+        }
+        Ok(LabelPattern::Like(safe_sql::like_pattern(line, allow_wildcards)))
+    }
+
+    /// The LIKE pattern for SQL; None for regular expressions, which can only be checked after the query
+    pub fn sql_like(&self) -> Option<String> {
+        match self {
+            LabelPattern::Like(s) => Some(s.to_owned()),
+            LabelPattern::Regex(_) => None,
+        }
+    }
+
+    pub fn is_regex(&self) -> bool {
+        matches!(self, LabelPattern::Regex(_))
+    }
+
+    /// Checks a label/alias/description text, with the same semantics as the SQL LIKE on binary text
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            LabelPattern::Like(s) => match Regex::new(&Self::like_to_regex(s)) {
+                Ok(re) => re.is_match(text),
+                Err(_) => false,
+            },
+            LabelPattern::Regex(re) => re.is_match(text),
+        }
+    }
+
+    /// A LIKE pattern that matches at least everything a regular expression does, from the literal text after a
+    /// leading "^" (eg "Foo %" for "^Foo \d+"); None if there is no such text, or for other patterns
+    pub fn sql_like_prefix(&self) -> Option<String> {
+        let re = match self {
+            LabelPattern::Regex(re) => re.as_str(),
+            LabelPattern::Like(_) => return None,
+        };
+        // An alternative could match anything after all
+        if re.contains('|') {
+            return None;
+        }
+        let mut prefix = String::new();
+        let mut chars = re.strip_prefix('^')?.chars().peekable();
+        while let Some(c) = chars.peek() {
+            if "\\.+*?()[]{}^$".contains(*c) {
+                break;
+            }
+            prefix.push(*c);
+            chars.next();
+        }
+        // A quantifier applies to the character before it
+        if let Some('*') | Some('?') | Some('{') = chars.peek() {
+            prefix.pop();
+        }
+        match prefix.is_empty() {
+            true => None,
+            false => Some(safe_sql::like_prefix(&prefix)),
+        }
+    }
+
+    /// The literal text before the first wildcard, for prefix searches (eg of lexemes via the API); None for
+    /// regular expressions
+    pub fn search_prefix(&self) -> Option<String> {
+        let s = match self {
+            LabelPattern::Like(s) => s,
+            LabelPattern::Regex(_) => return None,
+        };
+        let mut ret = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' | '_' => break,
+                '\\' => ret.extend(chars.next()),
+                c => ret.push(c),
+            }
+        }
+        Some(ret)
+    }
+
+    fn like_to_regex(s: &str) -> String {
+        let mut ret = "^".to_string();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => ret += ".*",
+                '_' => ret += ".",
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        ret += &regex::escape(&c.to_string());
+                    }
+                }
+                c => ret += &regex::escape(&c.to_string()),
+            }
+        }
+        ret + "$"
+    }
+}
+
+/// What the labels source looks for, from the `entity_type` parameter. Items and properties are in the term store
+/// tables; lexemes and forms are not, and are searched via the API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelEntityType {
+    Item,
+    Property,
+    /// Lexeme lemmas
+    Lexeme,
+    /// Form representations; results are the lexemes of the forms
+    Form,
+}
+
+impl Default for LabelEntityType {
+    fn default() -> Self {
+        Self::Item
+    }
+}
+
+impl LabelEntityType {
+    pub fn new_from_param(s: &str) -> Self {
+        match s.trim().to_lowercase().as_str() {
+            "property" => Self::Property,
+            "lexeme" => Self::Lexeme,
+            "form" => Self::Form,
+            _ => Self::Item,
+        }
+    }
+
+    /// The entity type in the Wikibase API, eg for wbsearchentities
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Item => "item",
+            Self::Property => "property",
+            Self::Lexeme => "lexeme",
+            Self::Form => "form",
+        }
+    }
+
+    pub fn in_term_store(&self) -> bool {
+        matches!(self, Self::Item | Self::Property)
+    }
+
+    /// Term store table, entity ID field, term ID field, and ID prefix; None for lexemes and forms.
+    /// These are "database safe".
+    pub fn term_store(&self) -> Option<(&'static str, &'static str, &'static str, &'static str)> {
+        match self {
+            Self::Item => Some(("wbt_item_terms", "wbit_item_id", "wbit_term_in_lang_id", "Q")),
+            Self::Property => Some(("wbt_property_terms", "wbpt_property_id", "wbpt_term_in_lang_id", "P")),
+            Self::Lexeme | Self::Form => None,
+        }
+    }
+
+    /// The lexeme of a search result, eg "L7-F2" => "L7"
+    pub fn lexeme_id(id: &str) -> String {
+        id.split('-').next().unwrap_or(id).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!(LabelPattern::parse("Foo%", true).unwrap().sql_like(), Some("Foo%".to_string()));
+        assert_eq!(LabelPattern::parse("exact:50%_off", false).unwrap().sql_like(), Some("50\\%\\_off".to_string()));
+        assert_eq!(LabelPattern::parse("prefix: Foo ", false).unwrap().sql_like(), Some("Foo%".to_string()));
+        assert_eq!(LabelPattern::parse("like:F_o", false).unwrap().sql_like(), Some("F_o".to_string()));
+        assert!(LabelPattern::parse("regex:^Foo \\d+$", false).unwrap().is_regex());
+        assert!(LabelPattern::parse("regex:(", false).is_err());
+    }
+
+    #[test]
+    fn sql_like_prefix() {
+        let prefix = |s: &str| LabelPattern::parse(s, false).unwrap().sql_like_prefix();
+        assert_eq!(prefix("regex:^Foo \\d+$"), Some("Foo %".to_string()));
+        assert_eq!(prefix("regex:^50%_off.*"), Some("50\\%\\_off%".to_string()));
+        assert_eq!(prefix("regex:^Foos?"), Some("Foo%".to_string()));
+        assert_eq!(prefix("regex:^F*"), None);
+        assert_eq!(prefix("regex:Foo"), None);
+        assert_eq!(prefix("regex:^Foo|Bar"), None);
+        assert_eq!(prefix("regex:^(?i)foo"), None);
+        assert_eq!(prefix("prefix:Foo"), None);
+    }
+
+    #[test]
+    fn parse_without_wildcards() {
+        assert_eq!(LabelPattern::parse("Foo%", false).unwrap().sql_like(), Some("Foo\\%".to_string()));
+        assert_eq!(LabelPattern::parse("50%_off", false).unwrap().sql_like(), Some("50\\%\\_off".to_string()));
+        assert_eq!(LabelPattern::parse("like:F_o", false).unwrap().sql_like(), Some("F_o".to_string()));
+        assert!(LabelPattern::parse("F_o", false).unwrap().is_match("F_o"));
+        assert!(!LabelPattern::parse("F_o", false).unwrap().is_match("Fxo"));
+        assert!(LabelPattern::parse("x' OR '1'='1", false).unwrap().is_match("x' OR '1'='1"));
+    }
+
+    #[test]
+    fn is_match() {
+        assert!(LabelPattern::parse("Foo%", true).unwrap().is_match("Foo bar"));
+        assert!(!LabelPattern::parse("Foo%", true).unwrap().is_match("The Foo"));
+        assert!(LabelPattern::parse("F_o", true).unwrap().is_match("Fxo"));
+        assert!(LabelPattern::parse("exact:50%_off", false).unwrap().is_match("50%_off"));
+        assert!(!LabelPattern::parse("exact:50%_off", false).unwrap().is_match("50% off"));
+        assert!(LabelPattern::parse("regex:^Foo \\d+$", false).unwrap().is_match("Foo 42"));
+        assert!(!LabelPattern::parse("regex:^Foo \\d+$", false).unwrap().is_match("Foo bar"));
+    }
+
+    #[test]
+    fn entity_type() {
+        assert_eq!(LabelEntityType::new_from_param(""), LabelEntityType::Item);
+        assert_eq!(LabelEntityType::new_from_param(" Lexeme"), LabelEntityType::Lexeme);
+        assert_eq!(LabelEntityType::Property.term_store().map(|t| t.0), Some("wbt_property_terms"));
+        assert!(!LabelEntityType::Form.in_term_store());
+        assert_eq!(LabelEntityType::lexeme_id("L7-F2"), "L7");
+        assert_eq!(LabelEntityType::lexeme_id("L7"), "L7");
+    }
+
+    #[test]
+    fn search_prefix() {
+        assert_eq!(LabelPattern::parse("prefix:Foo", false).unwrap().search_prefix(), Some("Foo".to_string()));
+        assert_eq!(LabelPattern::parse("exact:50%_off", false).unwrap().search_prefix(), Some("50%_off".to_string()));
+        assert_eq!(LabelPattern::parse("%Foo", true).unwrap().search_prefix(), Some(String::new()));
+        assert_eq!(LabelPattern::parse("regex:^Foo", false).unwrap().search_prefix(), None);
+    }
+}
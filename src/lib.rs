@@ -0,0 +1,73 @@
+//! PetScan query execution as a library, for bots and other tools that want to run queries without the web server.
+//!
+//! ```ignore
+//! let state = Arc::new(AppState::new_from_config(&config).await);
+//! let form_parameters = FormParameters::new_from_query("language=en&project=wikipedia&categories=Physics&depth=1");
+//! let mut platform = Platform::new_from_parameters(&form_parameters, state);
+//! platform.run().await?;
+//! let entries = platform.result().as_ref().map(|result| result.len());
+//! let response = platform.get_response().await?; // Rendered in the "format" parameter
+//! ```
+//!
+//! The web server (src/main.rs) is a thin hyper layer over this.
+
+#![type_length_limit="4276799"]
+
+extern crate chrono;
+extern crate reqwest;
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+#[macro_use]
+extern crate serde_json;
+
+pub mod app_state;
+pub mod category_tree;
+pub mod compression;
+pub mod datasource;
+pub mod datasource_api;
+pub mod datasource_database;
+pub mod endpoints;
+pub mod form_parameters;
+#[cfg(test)]
+pub mod golden;
+pub mod graph;
+pub mod http_cache;
+pub mod http_client;
+pub mod json_path;
+pub mod label_pattern;
+pub mod liftwing;
+pub mod oauth;
+pub mod page_url;
+pub mod pagelist;
+pub mod pageviews;
+pub mod platform;
+pub mod preferences;
+pub mod presets;
+pub mod progress;
+pub mod property_harvest;
+pub mod references;
+pub mod rate_limit;
+pub mod render;
+pub mod request_context;
+pub mod result_diff;
+pub mod safe_sql;
+pub mod scheduler;
+pub mod sdc_filter;
+pub mod statement_filter;
+pub mod thumbnails;
+pub mod time_budget;
+pub mod title_normalization;
+pub mod translations;
+pub mod upload;
+pub mod watchers;
+pub mod wdfist;
+pub mod wiki_db;
+pub mod wiki_edit;
+pub mod wikidata_search;
+
+pub use app_state::AppState;
+pub use form_parameters::FormParameters;
+pub use pagelist::{PageList, PageListEntry};
+pub use platform::{ContentType, MyResponse, Platform};
+pub use request_context::RequestContext;
@@ -0,0 +1,87 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+static LIFTWING_CONCURRENT_REQUESTS: usize = 10;
+pub static DEFAULT_TOPIC_THRESHOLD: f64 = 0.5;
+
+/// Predicted article topics (eg "STEM.Biology") from the LiftWing articletopic model
+pub struct ArticleTopics {
+    http: Arc<HttpClient>,
+    models_url: String,
+    wiki: String,
+}
+
+impl ArticleTopics {
+    /// `models_url` is the base URL of the LiftWing models, from Endpoints::liftwing
+    pub fn new(http: Arc<HttpClient>, models_url: &str, wiki: &str) -> Self {
+        Self {
+            http,
+            models_url: models_url.to_string(),
+            wiki: wiki.to_string(),
+        }
+    }
+
+    /// Returns topic => probability for each revision, in order; None if the prediction failed
+    pub async fn get_topics(&self, rev_ids: &[u64]) -> Vec<Option<HashMap<String, f64>>> {
+        let mut ret = vec![];
+        for chunk in rev_ids.chunks(LIFTWING_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = chunk.iter().map(|rev_id| self.get_topics_for_revision(*rev_id)).collect();
+            ret.append(&mut join_all(futures).await);
+        }
+        ret
+    }
+
+    async fn get_topics_for_revision(&self, rev_id: u64) -> Option<HashMap<String, f64>> {
+        let url = format!("{}/{}-articletopic:predict", self.models_url, self.wiki);
+        let response = self
+            .http
+            .send(self.http.post(&url).json(&json!({ "rev_id": rev_id })))
+            .await
+            .ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        let json: Value = response.json().await.ok()?;
+        Self::parse_probabilities(&json, &self.wiki, rev_id)
+    }
+
+    fn parse_probabilities(json: &Value, wiki: &str, rev_id: u64) -> Option<HashMap<String, f64>> {
+        let probability = json[wiki]["scores"][rev_id.to_string()]["articletopic"]["score"]["probability"].as_object()?;
+        Some(
+            probability
+                .iter()
+                .filter_map(|(topic, p)| Some((topic.to_owned(), p.as_f64()?)))
+                .collect(),
+        )
+    }
+
+    /// A wanted topic ending in "*" matches by prefix, eg "STEM.*"
+    pub fn matches(topics: &HashMap<String, f64>, wanted: &[String], threshold: f64) -> bool {
+        topics.iter().filter(|(_, p)| **p >= threshold).any(|(topic, _)| {
+            wanted.iter().any(|w| match w.strip_suffix('*') {
+                Some(prefix) => topic.starts_with(prefix),
+                None => topic == w,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_match() {
+        let j = json!({"enwiki":{"models":{"articletopic":{"version":"1.2.0"}},"scores":{"123":{"articletopic":{"score":{"prediction":["STEM.Biology"],"probability":{"STEM.Biology":0.91,"Culture.Media.Media*":0.02}}}}}}});
+        let topics = ArticleTopics::parse_probabilities(&j, "enwiki", 123).unwrap();
+        assert_eq!(topics.len(), 2);
+        assert!(ArticleTopics::parse_probabilities(&j, "enwiki", 124).is_none());
+        assert!(ArticleTopics::matches(&topics, &["STEM.Biology".to_string()], 0.5));
+        assert!(ArticleTopics::matches(&topics, &["STEM.*".to_string()], 0.5));
+        assert!(!ArticleTopics::matches(&topics, &["STEM.Biology".to_string()], 0.95));
+        assert!(!ArticleTopics::matches(&topics, &["Culture.*".to_string()], 0.5));
+    }
+}
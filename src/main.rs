@@ -1,43 +1,45 @@
 #![type_length_limit="4276799"]
 
-extern crate chrono;
-extern crate reqwest;
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
 #[macro_use]
 extern crate serde_json;
 
-pub mod app_state;
-pub mod datasource;
-pub mod datasource_database;
-pub mod form_parameters;
-pub mod pagelist;
-pub mod platform;
-pub mod render;
-pub mod wdfist;
-
 use tokio::fs::File as TokioFile;
 use tokio_util::codec::{BytesCodec, FramedRead};
-use qstring::QString;
-use crate::form_parameters::FormParameters;
-use app_state::AppState;
-use platform::{MyResponse, Platform, ContentType};
+use petscan_rs::app_state::{AppState, QueryFlight};
+use petscan_rs::form_parameters::FormParameters;
+use petscan_rs::oauth::OAuth;
+use petscan_rs::platform::{MyResponse, Platform, ContentType};
+use petscan_rs::preferences::UserPreferences;
+use petscan_rs::request_context::RequestContext;
+use petscan_rs::result_diff::ResultDiff;
+use petscan_rs::scheduler::{ScheduleInterval, ScheduledQuery, Scheduler};
+use petscan_rs::wiki_edit::WikiEditor;
+use petscan_rs::compression::{self, ContentEncoding, MIN_COMPRESSED_BYTES};
+use petscan_rs::{http_cache, result_diff, upload};
 use serde_json::Value;
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::sync::Arc;
 use std::{net::SocketAddr};
 use hyper::{header, Body, Request, Response, Server, Error, StatusCode, Method};
+use hyper::body::HttpBody;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 //type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
 static NOTFOUND: &[u8] = b"Not Found";
+static MAX_PROGRESS_ID_LENGTH: usize = 64;
+/// How long /progress/ID waits for the query to start
+static PROGRESS_WAIT_SECONDS: u64 = 30;
+static PROGRESS_POLL_INTERVAL_MS: u64 = 500;
+/// Set on responses that only have some of the pages, as the query ran out of time
+static PARTIAL_RESULT_HEADER: &str = "X-PetScan-Partial-Result";
+/// Admin pages that change something take the admin token from this header, or from the POST body, never the URL
+static ADMIN_TOKEN_HEADER: &str = "X-PetScan-Admin-Token";
 
-async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
-    let parameter_pairs = QString::from(parameters) ;
-    let parameter_pairs = parameter_pairs.to_pairs() ;
-    let mut form_parameters = FormParameters::new_from_pairs ( parameter_pairs ) ;
+async fn process_form(parameters:&str, context: &RequestContext, state: Arc<AppState>) -> MyResponse {
+    let mut form_parameters = FormParameters::new_from_query(parameters) ;
 
     // Restart command?
     if let Some(code) = form_parameters.params.get("restart") {
@@ -55,6 +57,8 @@ async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
         return MyResponse {
             s: "Temporary maintenance".to_string(),
             content_type: ContentType::Plain,
+            filename: None,
+            partial_result: false,
         };
     }
 
@@ -69,9 +73,21 @@ async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
             s: state
                 .get_main_page(interface_language),
             content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
         };
     }
 
+    // "preset" parameter? Patch in the named parameters, before any PSID
+    if let Some(name) = form_parameters.params.get("preset").cloned() {
+        if !name.trim().is_empty() {
+            match state.get_preset(&name) {
+                Some(preset) => form_parameters.rebase(&preset.parameters),
+                None => return state.render_error(format!("Unknown preset '{}'", name), &form_parameters),
+            }
+        }
+    }
+
     // "psid" parameter? Load, and patch in, existing query
     let mut single_psid: Option<u64> = None;
     if let Some(psid) = form_parameters.params.get("psid") {
@@ -92,6 +108,20 @@ async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
         }
     }
 
+    // Defaults from the preferences cookie, for parameters the query does not set
+    let is_form = !form_parameters.params.contains_key("doit") || form_parameters.params.contains_key("norun");
+    context.preferences.apply(&mut form_parameters, is_form);
+
+    // The query itself, as JSON; can be imported again via /import
+    if form_parameters.params.get("format").map(|s|s.as_str()) == Some("query_json") {
+        return MyResponse {
+            s: format!("{:#}", form_parameters.as_query_json()),
+            content_type: ContentType::JSON,
+            filename: None,
+            partial_result: false,
+        };
+    }
+
     // No "doit" parameter, just display the HTML form with the current query
     if form_parameters
         .params
@@ -109,37 +139,51 @@ async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
         return MyResponse {
             s: html,
             content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
         };
     }
 
-    let started_query_id = match state.log_query_start(&form_parameters.to_string()).await {
-        Ok(id) => id,
-        Err(e) => {
-            println!("Could not log query start: {}\n{}",e,form_parameters.to_string());
-            0
-        }
-    };
+    // The flight key leaves out admin_token, so admin queries (eg deleted pages) are never shared with others
+    if form_parameters.params.contains_key("admin_token") {
+        return run_query(form_parameters, single_psid, context, state).await;
+    }
 
-    // Actually do something useful!
-    state.modify_threads_running(1);
-    let mut platform = Platform::new_from_parameters(&form_parameters, state.clone());
-    Platform::profile("platform initialized", None);
-    let platform_result = platform.run().await;
-    match state.log_query_end(started_query_id).await {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Could not log query {} end:{}\n{}",started_query_id,e,form_parameters.to_string());
+    // An identical query is already running? Wait for it, and use its response
+    let query = form_parameters.to_string();
+    match state.join_query_flight(&query) {
+        Some(QueryFlight::Leader(sender)) => {
+            let response = run_query(form_parameters, single_psid, context, state.clone()).await;
+            state.finish_query_flight(&query, sender, &response);
+            response
         }
+        Some(QueryFlight::Follower(mut receiver)) => {
+            while receiver.borrow().is_none() {
+                if receiver.changed().await.is_err() {
+                    // The other request went away without a response; run the query here
+                    return run_query(form_parameters, single_psid, context, state).await;
+                }
+            }
+            match receiver.borrow().as_ref() {
+                Some(response) => (**response).clone(),
+                None => state.render_error("Coalesced query returned no response".to_string(), &form_parameters),
+            }
+        }
+        None => run_query(form_parameters, single_psid, context, state).await,
     }
-    state.modify_threads_running(-1);
-    Platform::profile("platform run complete", None);
+}
+
+async fn run_query(form_parameters: FormParameters, single_psid: Option<u64>, context: &RequestContext, state: Arc<AppState>) -> MyResponse {
+    // Actually do something useful!
+    let progress_id = form_parameters.params.get("progress_id").filter(|id|is_valid_progress_id(id)).cloned();
+    let (mut platform, platform_result) = Platform::run_logged(&form_parameters, context, progress_id, state.clone()).await;
 
     // Successful run?
     match platform_result {
         Ok(_) => {}
         Err(error) => {
             drop(platform);
-            return state.render_error(error, &form_parameters);
+            return state.render_error(AppState::user_visible_db_error(error), &form_parameters);
         }
     }
 
@@ -149,15 +193,13 @@ async fn process_form(parameters:&str, state: Arc<AppState>) -> MyResponse {
         Some(psid) => Some(psid),
         None => match state.get_or_create_psid_for_query(&form_parameters.to_string()).await {
             Ok(psid) => Some(psid),
-            Err(e) => {
-                if state.log_query_end(started_query_id).await.is_err() {
-                    // Ignore error
-                }
-                return state.render_error(e, &form_parameters);
-            }
+            Err(e) => return state.render_error(e, &form_parameters),
         },
     };
     Platform::profile("PSID set", None);
+    if let Err(e) = platform.store_result() {
+        println!("Could not store result: {}", e);
+    }
 
     // Render response
     let response = match platform.get_response().await {
@@ -200,36 +242,784 @@ async fn serve_file_path(filename:&str) -> Result<Response<Body>,Error> {
         "/index.html" => simple_file_send(filename,"text/html; charset=utf-8").await,
         "/autolist.js" => simple_file_send(filename,"application/javascript; charset=utf-8").await,
         "/main.js" => simple_file_send(filename,"application/javascript; charset=utf-8").await,
+        "/results.js" => simple_file_send(filename,"application/javascript; charset=utf-8").await,
         "/favicon.ico" => simple_file_send(filename,"image/x-icon; charset=utf-8").await,
         "/robots.txt" => simple_file_send(filename,"text/plain; charset=utf-8").await,
         _ => not_found()
     }
 }
 
-async fn process_from_query(query:&str,app_state:Arc<AppState>) -> Result<Response<Body>,Error> {
-    let ret = process_form(query,app_state).await;
-    let response = Response::builder()
+/// Admin-only summary of popular queries from the audit log
+async fn process_stats(query:&str, state: Arc<AppState>) -> Response<Body> {
+    let form_parameters = FormParameters::new_from_query(query);
+    if !state.is_admin_token(form_parameters.params.get("token").map(|s|s.as_str()).unwrap_or("")) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Forbidden"))
+            .unwrap();
+    }
+    let days = form_parameters.params.get("days").and_then(|s|s.parse::<i64>().ok()).unwrap_or(7).max(1);
+    let html = match state.get_query_log_stats(days, 50).await {
+        Ok(rows) => {
+            let total: u64 = rows.iter().map(|row|row.1).sum();
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|(querystring,runs,avg_ms,failed)| {
+                    format!("<tr><td><a href='/?{}'>{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                        htmlescape::encode_attribute(querystring),htmlescape::encode_minimal(querystring),runs,avg_ms,failed)
+                })
+                .collect();
+            format!("<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan statistics</title></head><body><h1>Popular queries, last {} days</h1><p>{} runs of the top queries</p><table border='1'><tr><th>Query</th><th>Runs</th><th>Average ms</th><th>Failed</th></tr>{}</table></body></html>",
+                days,total,rows.join(""))
+        }
+        Err(e) => format!("<!DOCTYPE html><html><body>{}</body></html>",htmlescape::encode_minimal(&e)),
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+/// The POST body of an admin page, and the admin token from the header or the body; None if the body is too large
+async fn read_admin_form(req: &mut Request<Body>) -> Option<(FormParameters, String)> {
+    let body = match req.method() == Method::POST {
+        true => read_post_body(req).await?,
+        false => vec![],
+    };
+    let form_parameters = FormParameters::new_from_query(&String::from_utf8_lossy(&body));
+    let token = req.headers().get(ADMIN_TOKEN_HEADER)
+        .and_then(|v|v.to_str().ok())
+        .map(|s|s.to_string())
+        .or_else(|| form_parameters.params.get("token").cloned())
+        .unwrap_or_default();
+    Some((form_parameters, token))
+}
+
+/// Admin-only list of running queries, with a button to stop each. The token is sent via POST (the form shown
+/// without it), or in the X-PetScan-Admin-Token header; so is `kill`.
+async fn process_admin_running(form_parameters: &FormParameters, token: &str, state: Arc<AppState>) -> Response<Body> {
+    if !state.is_admin_token(token) {
+        let html = "<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan running queries</title></head><body><form method='post' action='/admin/running'><input type='password' name='token'><button type='submit'>Show running queries</button></form></body></html>";
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(html))
+            .unwrap();
+    }
+    let mut message = String::new();
+    if let Some(id) = form_parameters.params.get("kill").and_then(|s|s.parse::<u64>().ok()) {
+        message = if state.kill_running_query(id) {
+            format!("<p>Query #{} was stopped.</p>",id)
+        } else {
+            format!("<p>Query #{} is not running.</p>",id)
+        };
+    }
+    let rows: Vec<String> = state
+        .get_running_queries()
+        .iter()
+        .map(|(id,rq)| {
+            let stage = rq.stage.get();
+            format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><form method='post' action='/admin/running'><input type='hidden' name='token' value='{}'><input type='hidden' name='kill' value='{}'><button type='submit'>Kill</button></form></td></tr>",
+                id,htmlescape::encode_minimal(&rq.query),htmlescape::encode_minimal(&rq.client),rq.started.elapsed().as_secs(),htmlescape::encode_minimal(&stage),htmlescape::encode_attribute(token),id)
+        })
+        .collect();
+    let html = format!("<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan running queries</title></head><body><h1>Running queries</h1>{}<table border='1'><tr><th>ID</th><th>Query</th><th>Client</th><th>Seconds</th><th>Stage</th><th></th></tr>{}</table></body></html>",
+        message,rows.join(""));
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+/// Registration of a scheduled query run by a logged-in user, via POST of eg
+/// `csrf_token=...&psid=123&interval=weekly&report_wiki=enwiki&report_page=User:Name/Report`. `csrf_token` is needed if
+/// the user is logged in via `/oauth/login`, rather than via an authenticating proxy. The report page has to be in the
+/// user's own userspace; `email` has to be the confirmed email address of their wiki account (via `/oauth/login`).
+async fn process_schedule(body:&str, context: &RequestContext, state: Arc<AppState>) -> Response<Body> {
+    let form_parameters = FormParameters::new_from_query(body);
+    let user = match &context.user {
+        Some(user) => user.to_owned(),
+        None => {
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Please log in first"))
+                .unwrap()
+        }
+    };
+    let session = state.get_oauth_session(context);
+    if let Some(session) = &session {
+        if form_parameters.params.get("csrf_token") != Some(&session.csrf_token) {
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Forbidden"))
+                .unwrap();
+        }
+    }
+    let confirmed_email = session.and_then(|session| session.email);
+    let param = |key: &str| form_parameters.params.get(key).map(|s|s.trim().to_string()).unwrap_or_default();
+    let mut sq = ScheduledQuery {
+        id: 0,
+        psid: param("psid").parse::<u64>().unwrap_or(0),
+        interval: ScheduleInterval::parse(&param("interval")).unwrap_or(ScheduleInterval::Daily),
+        snapshot: None,
+        report_wiki: param("report_wiki"),
+        report_page: param("report_page"),
+        email: param("email"),
+        user,
+    };
+    let result = if sq.psid == 0 {
+        Err("Parameter 'psid' is required".to_string())
+    } else if !sq.has_notification() {
+        Err("A report page (report_wiki and report_page), or an email address, is required".to_string())
+    } else if !sq.report_page.is_empty() && !ScheduledQuery::is_own_user_page(&sq.report_page, &sq.user) {
+        Err(format!("The report page has to be in your userspace, eg User:{}/Report", sq.user))
+    } else if !sq.email.is_empty()
+        && confirmed_email.map(|email| email.to_lowercase()) != Some(sq.email.to_lowercase())
+    {
+        Err("The email address has to be the confirmed email address of your wiki account".to_string())
+    } else {
+        state.get_query_from_psid(&sq.psid.to_string()).await.map(|_|())
+    };
+    let j = match result {
+        Ok(_) => match state.add_scheduled_query(&sq).await {
+            Ok(id) => {
+                sq.id = id;
+                json!({"status":"OK","id":sq.id,"psid":sq.psid,"interval_days":sq.interval.days()})
+            }
+            Err(e) => json!({"status":e}),
+        },
+        Err(e) => json!({"status":e}),
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(j.to_string()))
+        .unwrap()
+}
+
+/// Runs a PSID, and writes its wikitext output to a report page as the logged-in user (see `/oauth/login`), via
+/// POST of eg `csrf_token=...&psid=123&wiki=enwiki&page=Wikipedia:WikiProject_X/Report&summary=...`
+async fn process_write(body:&str, context: &RequestContext, state: Arc<AppState>) -> Response<Body> {
+    let form_parameters = FormParameters::new_from_query(body);
+    let j = match write_report(&form_parameters, context, state).await {
+        Ok((wiki, page)) => json!({"status":"OK","wiki":wiki,"page":page}),
+        Err(e) => json!({"status":e}),
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(j.to_string()))
+        .unwrap()
+}
+
+async fn write_report(form_parameters: &FormParameters, context: &RequestContext, state: Arc<AppState>) -> Result<(String, String), String> {
+    let session = state.get_oauth_session(context).ok_or_else(|| "Please log in first".to_string())?;
+    if form_parameters.params.get("csrf_token") != Some(&session.csrf_token) {
+        return Err("Forbidden".to_string());
+    }
+    let param = |key: &str| form_parameters.params.get(key).map(|s|s.trim().to_string()).unwrap_or_default();
+    let psid = param("psid").parse::<u64>().map_err(|_|"Parameter 'psid' is required".to_string())?;
+    let page = param("page");
+    if page.is_empty() {
+        return Err("Parameter 'page' is required".to_string());
+    }
+    let editor = WikiEditor::new_for_session(state.clone(), &session);
+
+    let mut query_parameters = FormParameters::outcome_from_query(&state.get_query_from_psid(&psid.to_string()).await?)?;
+    query_parameters.set_param("format", "wiki");
+    let (platform, platform_result) = Platform::run_logged(&query_parameters, context, None, state.clone()).await;
+    platform_result.map_err(AppState::user_visible_db_error)?;
+    // The report page is on the wiki of the query, unless given
+    let wiki = match param("wiki").as_str() {
+        "" => platform.get_main_wiki().ok_or_else(|| "Query has no wiki".to_string())?,
+        wiki => wiki.to_string(),
+    };
+    let text = platform.get_response().await?.s;
+    drop(platform);
+
+    let summary = match param("summary").as_str() {
+        "" => format!("Update from PetScan PSID {}", psid),
+        summary => summary.to_string(),
+    };
+    editor.write_report(&wiki, &page, &text, &summary, form_parameters.params.contains_key("force")).await?;
+    Ok((wiki, page))
+}
+
+/// OAuth login (`/oauth/login`, and the `/oauth/callback` from the wiki), `/oauth/logout`, and `/oauth/session`, which
+/// tells the page the user name and the `csrf_token` for requests that change something
+async fn process_oauth(action: &str, query: &str, context: &RequestContext, state: Arc<AppState>) -> Response<Body> {
+    let oauth = match state.get_oauth() {
+        Some(oauth) => oauth,
+        None => return not_found().unwrap(),
+    };
+    let redirect = |location: &str| Response::builder().status(StatusCode::SEE_OTHER).header(header::LOCATION, location);
+    let form_parameters = FormParameters::new_from_query(query);
+    let param = |key: &str| form_parameters.params.get(key).map(|s|s.as_str()).unwrap_or("");
+    let result = match action {
+        "login" => oauth.authorize_url().map(|url| redirect(&url).body(Body::empty()).unwrap()),
+        "callback" => oauth.login(param("code"), param("state")).await.map(|session_id| {
+            redirect("/")
+                .header(header::SET_COOKIE, OAuth::set_cookie_header(Some(&session_id)))
+                .body(Body::empty())
+                .unwrap()
+        }),
+        "logout" => {
+            if let Some(session_id) = &context.session_id {
+                oauth.logout(session_id);
+            }
+            Ok(redirect("/").header(header::SET_COOKIE, OAuth::set_cookie_header(None)).body(Body::empty()).unwrap())
+        }
+        "session" => {
+            // No CORS header: other sites must not read the CSRF token
+            let j = match state.get_oauth_session(context) {
+                Some(session) => json!({"user":session.user,"csrf_token":session.csrf_token}),
+                None => json!({}),
+            };
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+                .header(header::CACHE_CONTROL, "no-store")
+                .body(Body::from(j.to_string()))
+                .unwrap())
+        }
+        _ => return not_found().unwrap(),
+    };
+    result.unwrap_or_else(|e| {
+        Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(e))
+            .unwrap()
+    })
+}
+
+/// Admin-only switch for maintenance mode, via POST of eg `enable=1&message=...`, with the token in the
+/// X-PetScan-Admin-Token header (or `token` in the body). Without `enable`, it shows the current state.
+async fn process_admin_maintenance(form_parameters: &FormParameters, token: &str, state: Arc<AppState>) -> Response<Body> {
+    if !state.is_admin_token(token) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from("Forbidden"))
+            .unwrap();
+    }
+    if let Some(enable) = form_parameters.params.get("enable") {
+        let message = form_parameters.params.get("message").map(|s|s.to_string()).unwrap_or_default();
+        state.set_maintenance(enable == "1", &message);
+    }
+    let j = json!({
+        "maintenance":state.get_maintenance_message().is_some(),
+        "message":state.get_maintenance_message(),
+        "running_queries":state.get_threads_running(),
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .body(Body::from(j.to_string()))
+        .unwrap()
+}
+
+/// New queries are rejected during maintenance, with the message as HTML, or JSON for JSON output
+fn maintenance_response(message: &str, query: &str) -> Response<Body> {
+    let form_parameters = FormParameters::new_from_query(query);
+    let (content_type, body) = match form_parameters.params.get("format").map(|s|s.as_str()) {
+        Some("json") => ("application/json; charset=utf-8", json!({"error":message}).to_string()),
+        _ => ("text/html; charset=utf-8", format!("<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan</title></head><body><h1>Maintenance</h1><p>{}</p></body></html>",htmlescape::encode_minimal(message))),
+    };
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::RETRY_AFTER, "600")
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Renders a stored result again, with output parameters (format etc.) from the query
+async fn process_result_token(token:&str, query:&str, state: Arc<AppState>) -> MyResponse {
+    let overrides = FormParameters::new_from_query(query);
+    let stored = match state.get_stored_result(token) {
+        Some(stored) => stored,
+        None => return state.render_error("Unknown or expired result token".to_string(), &overrides),
+    };
+    let mut form_parameters = stored.form_parameters.clone();
+    for (k, v) in &overrides.params {
+        form_parameters.set_param(k, v);
+    }
+    let platform = match Platform::new_from_stored_result(&stored, &form_parameters, state.clone()) {
+        Ok(platform) => platform,
+        Err(e) => return state.render_error(e, &form_parameters),
+    };
+    match platform.get_response().await {
+        Ok(response) => response,
+        Err(error) => state.render_error(error, &form_parameters),
+    }
+}
+
+/// Renders only the selected titles (`titles`, one per line, POSTed from the HTML result table) of a stored result.
+/// `action=pagepile` creates a PagePile from the selection; otherwise the selection is downloaded in `format`.
+async fn process_result_selection(token:&str, body:&str, state: Arc<AppState>) -> MyResponse {
+    let overrides = FormParameters::new_from_query(body);
+    let stored = match state.get_stored_result(token) {
+        Some(stored) => stored,
+        None => return state.render_error("Unknown or expired result token".to_string(), &overrides),
+    };
+    let selected: HashSet<String> = overrides
+        .get_all("titles")
+        .iter()
+        .flat_map(|titles| titles.split('\n'))
+        .map(|title| title.trim().replace(' ', "_"))
+        .filter(|title| !title.is_empty())
+        .collect();
+    if selected.is_empty() {
+        return state.render_error("No titles selected".to_string(), &overrides);
+    }
+    let api = match state.get_api_for_wiki(stored.wiki.to_owned()).await {
+        Ok(api) => api,
+        Err(e) => return state.render_error(e, &overrides),
+    };
+    let subset = stored.subset(&selected, &api);
+    let mut form_parameters = subset.form_parameters.clone();
+    for (k, v) in &overrides.params {
+        if k != "titles" && k != "action" {
+            form_parameters.set_param(k, v);
+        }
+    }
+    if overrides.params.get("action").map(|s|s.as_str()) == Some("pagepile") {
+        form_parameters.set_param("format", "pagepile");
+    }
+    let platform = match Platform::new_from_stored_result(&subset, &form_parameters, state.clone()) {
+        Ok(platform) => platform,
+        Err(e) => return state.render_error(e, &form_parameters),
+    };
+    match platform.get_response().await {
+        Ok(response) => response,
+        Err(error) => state.render_error(error, &form_parameters),
+    }
+}
+
+/// Stores a query from `format=query_json` output (the POST body), and redirects to its PSID
+async fn process_import(body: &[u8], state: Arc<AppState>) -> Response<Body> {
+    let result = serde_json::from_slice::<Value>(body)
+        .map_err(|e| format!("Invalid JSON: {}", e))
+        .and_then(|json| FormParameters::new_from_query_json(&json));
+    let psid = match result {
+        // Canonical, so importing the same query twice gives the same PSID
+        Ok(form_parameters) => state.get_or_create_psid_for_query(&form_parameters.canonical_query()).await,
+        Err(e) => Err(e),
+    };
+    match psid {
+        Ok(psid) => Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(header::LOCATION, format!("/?psid={}", psid))
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(json!({"status":"OK","psid":psid}).to_string()))
+            .unwrap(),
+        Err(e) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(json!({"status":e}).to_string()))
+            .unwrap(),
+    }
+}
+
+/// Pages added and removed between the results of two PSIDs, eg `/diff?a=123&b=456&format=json`
+async fn process_diff(query:&str, context: &RequestContext, state: Arc<AppState>) -> MyResponse {
+    let form_parameters = FormParameters::new_from_query(query);
+    let psid_a = form_parameters.params.get("a").and_then(|s|s.trim().parse::<u64>().ok());
+    let psid_b = form_parameters.params.get("b").and_then(|s|s.trim().parse::<u64>().ok());
+    let (psid_a, psid_b) = match (psid_a, psid_b) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return state.render_error("Parameters 'a' and 'b' need to be PSIDs".to_string(), &form_parameters),
+    };
+    let (result_a, result_b) = tokio::join!(
+        result_diff::titles_for_psid(psid_a, context, state.clone()),
+        result_diff::titles_for_psid(psid_b, context, state.clone())
+    );
+    let ((wiki_a, titles_a), (wiki_b, titles_b)) = match (result_a, result_b) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => return state.render_error(e, &form_parameters),
+    };
+    if !wiki_a.is_empty() && !wiki_b.is_empty() && wiki_a != wiki_b {
+        return state.render_error(format!("PSID {} is on {}, but PSID {} is on {}", psid_a, wiki_a, psid_b, wiki_b), &form_parameters);
+    }
+    let mut diff = ResultDiff::new(psid_a, &titles_a, psid_b, &titles_b);
+    diff.wiki = if wiki_b.is_empty() { wiki_a } else { wiki_b };
+    diff.server = state.get_server_url_for_wiki(&diff.wiki).unwrap_or_default();
+    match form_parameters.params.get("format").map(|s|s.as_str()) {
+        Some("json") => MyResponse {
+            s: diff.as_json().to_string(),
+            content_type: ContentType::JSON,
+            filename: None,
+            partial_result: false,
+        },
+        _ => MyResponse {
+            s: diff.as_html(),
+            content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
+        },
+    }
+}
+
+/// The file name is already restricted to safe characters, see `Platform::download_filename`
+fn content_disposition(filename: &str) -> String {
+    format!("attachment; filename=\"{}\"", filename)
+}
+
+/// `if_none_match` is the If-None-Match header of a GET request; responses to PSID queries get an ETag and Last-Modified,
+/// so clients polling a saved query only download it again when the result has changed
+async fn process_from_query(query:&str,context:&RequestContext,if_none_match:Option<&str>,app_state:Arc<AppState>) -> Result<Response<Body>,Error> {
+    let is_psid_query = FormParameters::new_from_query(query).params.get("psid").map(|psid|!psid.trim().is_empty()).unwrap_or(false);
+    let ret = process_form(query,context,app_state.clone()).await;
+    let mut builder = Response::builder()
         .header(header::CONTENT_TYPE, ret.content_type.as_str())
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+    if let Some(filename) = &ret.filename {
+        builder = builder.header(header::CONTENT_DISPOSITION, content_disposition(filename));
+    }
+    if ret.partial_result {
+        builder = builder
+            .header(PARTIAL_RESULT_HEADER, "1")
+            .header(header::ACCESS_CONTROL_EXPOSE_HEADERS, PARTIAL_RESULT_HEADER);
+    }
+    if is_psid_query {
+        let etag = http_cache::etag_for(&ret.s, ret.content_type.as_str());
+        let last_modified = app_state.get_last_modified(query, &etag);
+        builder = builder
+            .header(header::ETAG, etag.as_str())
+            .header(header::LAST_MODIFIED, http_cache::http_date(&last_modified))
+            .header(header::CACHE_CONTROL, "no-cache");
+        if if_none_match.map(|inm|http_cache::if_none_match_matches(inm, &etag)).unwrap_or(false) {
+            return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+        }
+    }
+    Ok(builder.body(Body::from(ret.s)).unwrap())
+}
+
+fn is_valid_progress_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_PROGRESS_ID_LENGTH && id.chars().all(|c|c.is_ascii_alphanumeric())
+}
+
+/// Server-sent events with the stage of the query that was started with `progress_id`, until it has finished
+fn process_progress(progress_id: &str, state: Arc<AppState>) -> Response<Body> {
+    if !is_valid_progress_id(progress_id) {
+        return Response::builder().status(StatusCode::BAD_REQUEST).body(Body::from("Bad progress ID")).unwrap();
+    }
+    let progress_id = progress_id.to_string();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let started = std::time::Instant::now();
+        let mut last_stage: Option<String> = None;
+        loop {
+            match state.get_query_progress(&progress_id) {
+                Some(stage) => {
+                    if last_stage.as_ref() != Some(&stage) {
+                        let event = format!("data: {}\n\n", stage.replace('\n', " "));
+                        if sender.send_data(event.into()).await.is_err() {
+                            return; // Client went away
+                        }
+                        last_stage = Some(stage);
+                    }
+                }
+                None => {
+                    // The query has finished, or was never started (the form request may also arrive after this one)
+                    if last_stage.is_some() || started.elapsed().as_secs() > PROGRESS_WAIT_SECONDS {
+                        let _ = sender.send_data("event: done\ndata: done\n\n".into()).await;
+                        return;
+                    }
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(PROGRESS_POLL_INTERVAL_MS)).await;
+        }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// Shows the preferences from the cookie; with a query (eg "language=de&ns=0,14"), stores those instead. "clear=1"
+/// deletes the cookie.
+fn process_preferences(query: &str, context: &RequestContext) -> Response<Body> {
+    let builder = Response::builder().header(header::CONTENT_TYPE, "application/json; charset=utf-8");
+    if query.is_empty() {
+        return builder.body(Body::from(context.preferences.to_json().to_string())).unwrap();
+    }
+    let preferences = match FormParameters::new_from_query(query).params.contains_key("clear") {
+        true => UserPreferences::default(),
+        false => UserPreferences::new_from_query(query),
+    };
+    builder
+        .header(header::SET_COOKIE, preferences.set_cookie_header())
+        .body(Body::from(preferences.to_json().to_string()))
+        .unwrap()
+}
+
+fn too_many_requests(retry_after: std::time::Duration) -> Response<Body> {
+    let seconds = retry_after.as_secs() + 1;
+    let html = format!("<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan</title></head><body><h1>Too many requests</h1><p>You have sent too many queries in a short time. Please wait {} seconds, and try again.</p></body></html>",seconds);
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::RETRY_AFTER, seconds.to_string())
         .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .body(Body::from(ret.s))
-        .unwrap();
-    Ok(response)
+        .body(Body::from(html))
+        .unwrap()
+}
+
+/// Reads the POST body; None if it is larger than allowed
+async fn read_post_body(req: &mut Request<Body>) -> Option<Vec<u8>> {
+    let mut ret = vec![];
+    while let Some(chunk) = req.body_mut().data().await {
+        ret.extend_from_slice(&chunk.ok()?);
+        if ret.len() > upload::MAX_POST_BYTES {
+            return None;
+        }
+    }
+    Some(ret)
+}
+
+async fn process_request(req: Request<Body>,app_state:Arc<AppState>,remote_addr:SocketAddr) -> Result<Response<Body>,Error> {
+    let accept_encoding = req.headers().get(header::ACCEPT_ENCODING).and_then(|v|v.to_str().ok()).map(|v|v.to_string());
+    let response = route_request(req, app_state, remote_addr).await?;
+    match accept_encoding.as_deref().and_then(ContentEncoding::negotiate) {
+        Some(encoding) => compress_response(response, encoding).await,
+        None => Ok(response),
+    }
+}
+
+/// Compresses text responses, if the client accepts it
+async fn compress_response(response: Response<Body>, encoding: ContentEncoding) -> Result<Response<Body>,Error> {
+    let is_compressible = response.status() == StatusCode::OK
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+        && response.headers().get(header::CONTENT_TYPE).and_then(|v|v.to_str().ok()).map(compression::is_compressible).unwrap_or(false);
+    if !is_compressible {
+        return Ok(response);
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    parts.headers.append(header::VARY, header::HeaderValue::from_static("Accept-Encoding"));
+    if body.len() < MIN_COMPRESSED_BYTES {
+        return Ok(Response::from_parts(parts, Body::from(body)));
+    }
+    let compressed = match tokio::task::spawn_blocking(move || encoding.compress(&body).map_err(|e|(e,body))).await {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            println!("Compression failed: {}", e);
+            return Ok(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::from("Compression failed")).unwrap());
+        }
+    };
+    match compressed {
+        Ok(compressed) => {
+            parts.headers.insert(header::CONTENT_ENCODING, header::HeaderValue::from_static(encoding.as_str()));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            // The compressed body is a different representation; the ETag of the uncompressed one only matches weakly
+            let weak_etag = parts.headers.get(header::ETAG)
+                .and_then(|v|v.to_str().ok())
+                .filter(|v|v.starts_with('"'))
+                .and_then(|etag|header::HeaderValue::from_str(&format!("W/{}", etag)).ok());
+            if let Some(weak_etag) = weak_etag {
+                parts.headers.insert(header::ETAG, weak_etag);
+            }
+            Ok(Response::from_parts(parts, Body::from(compressed)))
+        }
+        Err((e, body)) => {
+            println!("{}", e);
+            Ok(Response::from_parts(parts, Body::from(body)))
+        }
+    }
 }
 
-async fn process_request(mut req: Request<Body>,app_state:Arc<AppState>) -> Result<Response<Body>,Error> {
+async fn route_request(mut req: Request<Body>,app_state:Arc<AppState>,remote_addr:SocketAddr) -> Result<Response<Body>,Error> {
+    let mut context = RequestContext::new_from_headers(req.headers(), &remote_addr, app_state.get_user_header(), &app_state.get_trusted_proxies());
+    if context.user.is_none() {
+        context.user = app_state.get_oauth_session(&context).map(|session| session.user);
+    }
+
+    // Only query routes are rate limited, not static files
+    let is_query = req.uri().path().starts_with("/result/")
+        || req.uri().path() == "/diff"
+        || req.uri().query().map(|q|!q.is_empty()).unwrap_or(false)
+        || req.method() == Method::POST;
+    if is_query {
+        if let Err(retry_after) = app_state.check_rate_limit(&context.rate_limit_key()) {
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+
+    // Admin statistics
+    if req.uri().path() == "/stats" {
+        return Ok(process_stats(req.uri().query().unwrap_or(""), app_state).await);
+    }
+
+    if let Some(action) = req.uri().path().strip_prefix("/oauth/") {
+        return Ok(process_oauth(action, req.uri().query().unwrap_or(""), &context, app_state).await);
+    }
+
+    if let Some(progress_id) = req.uri().path().strip_prefix("/progress/") {
+        return Ok(process_progress(progress_id, app_state));
+    }
+
+    if req.uri().path() == "/preferences" {
+        return Ok(process_preferences(req.uri().query().unwrap_or(""), &context));
+    }
+
+    if req.uri().path() == "/presets" {
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(app_state.get_presets_json().to_string()))
+            .unwrap();
+        return Ok(response);
+    }
+
+    if req.uri().path() == "/admin/running" || req.uri().path() == "/admin/maintenance" {
+        if req.uri().path() == "/admin/maintenance" && req.method() != Method::POST {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "POST")
+                .body(Body::from("Please use POST"))
+                .unwrap());
+        }
+        let (form_parameters, token) = match read_admin_form(&mut req).await {
+            Some(form) => form,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                    .unwrap())
+            }
+        };
+        return Ok(match req.uri().path() {
+            "/admin/running" => process_admin_running(&form_parameters, &token, app_state).await,
+            _ => process_admin_maintenance(&form_parameters, &token, app_state).await,
+        });
+    }
+
+    // Maintenance mode: no new queries (or scheduled queries, or report edits)
+    if is_query {
+        if let Some(message) = app_state.get_maintenance_message() {
+            return Ok(maintenance_response(&message, req.uri().query().unwrap_or("")));
+        }
+    }
+
+    if req.uri().path() == "/schedule" {
+        if req.method() != Method::POST {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "POST")
+                .body(Body::from("Please use POST"))
+                .unwrap());
+        }
+        return Ok(match read_post_body(&mut req).await {
+            Some(body) => process_schedule(&String::from_utf8_lossy(&body), &context, app_state).await,
+            None => Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                .unwrap(),
+        });
+    }
+
+    if req.uri().path() == "/write" {
+        if req.method() != Method::POST {
+            return Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "POST")
+                .body(Body::from("Please use POST"))
+                .unwrap());
+        }
+        return Ok(match read_post_body(&mut req).await {
+            Some(body) => process_write(&String::from_utf8_lossy(&body), &context, app_state).await,
+            None => Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                .unwrap(),
+        });
+    }
+
+    // Diff between two PSIDs
+    if req.uri().path() == "/diff" {
+        let ret = process_diff(req.uri().query().unwrap_or(""), &context, app_state).await;
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, ret.content_type.as_str())
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(ret.s))
+            .unwrap();
+        return Ok(response);
+    }
+
+    // Import of a query exported with format=query_json
+    if req.uri().path() == "/import" && req.method() == Method::POST {
+        return Ok(match read_post_body(&mut req).await {
+            Some(body) => process_import(&body, app_state).await,
+            None => Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                .unwrap(),
+        });
+    }
+
+    // Selected rows of a stored result
+    if let Some(token) = req.uri().path().strip_prefix("/result/").and_then(|t|t.strip_suffix("/selection")) {
+        let token = token.to_string();
+        let body = match read_post_body(&mut req).await {
+            Some(body) => body,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                    .unwrap())
+            }
+        };
+        let ret = process_result_selection(&token, &String::from_utf8_lossy(&body), app_state).await;
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, ret.content_type.as_str())
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(ret.s))
+            .unwrap();
+        return Ok(response);
+    }
+
+    // Stored result, in another format
+    if let Some(token) = req.uri().path().strip_prefix("/result/") {
+        let ret = process_result_token(token, req.uri().query().unwrap_or(""), app_state).await;
+        let mut builder = Response::builder()
+            .header(header::CONTENT_TYPE, ret.content_type.as_str())
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*");
+        if let Some(filename) = &ret.filename {
+            builder = builder.header(header::CONTENT_DISPOSITION, content_disposition(filename));
+        }
+        let response = builder.body(Body::from(ret.s)).unwrap();
+        return Ok(response);
+    }
+
     // URL GET query
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|v|v.to_str().ok()).map(|v|v.to_string());
     if let Some(query) = req.uri().query() {
         if !query.is_empty() {
-            return process_from_query(query,app_state).await;
+            return process_from_query(query,&context,if_none_match.as_deref(),app_state).await;
         }
     } ;
 
     // POST
     if req.method() == Method::POST {
-        let query = hyper::body::to_bytes(req.body_mut()).await.unwrap();
+        let query = match read_post_body(&mut req).await {
+            Some(query) => query,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::from(format!("POST data is limited to {} bytes", upload::MAX_POST_BYTES)))
+                    .unwrap())
+            }
+        };
+        let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|v|v.to_str().ok()).unwrap_or("");
+        if let Some(boundary) = upload::multipart_boundary(content_type) {
+            let query = match upload::multipart_to_query(&query, &boundary) {
+                Ok(query) => query,
+                Err(e) => return Ok(Response::builder().status(StatusCode::BAD_REQUEST).body(Body::from(e)).unwrap()),
+            };
+            return process_from_query(&query,&context,None,app_state).await;
+        }
         if !query.is_empty() {
             let query = String::from_utf8_lossy(&query);
-            return process_from_query(&query,app_state).await;
+            return process_from_query(&query,&context,None,app_state).await;
         }
     }
 
@@ -254,16 +1044,22 @@ async fn main() -> Result<(),Error> {
     let port = petscan_config["http_port"].as_u64().unwrap_or(80) as u16;    
     let app_state = Arc::new(AppState::new_from_config(&petscan_config).await) ;
 
+    if app_state.is_scheduler_enabled() {
+        let scheduler = Scheduler::new(app_state.clone());
+        tokio::spawn(async move { scheduler.run_forever().await });
+    }
+
     let ip_address : Vec<u8> = ip_address.split('.').map(|s|s.parse::<u8>().unwrap()).collect();
     let ip_address = std::net::Ipv4Addr::new(ip_address[0],ip_address[1],ip_address[2],ip_address[3],);
     let addr = SocketAddr::from((ip_address, port));
 
-    let make_service = make_service_fn(move |_| {
+    let make_service = make_service_fn(move |conn: &AddrStream| {
         let app_state = app_state.clone();
+        let remote_addr = conn.remote_addr();
         
-        async {
+        async move {
             Ok::<_, Error>(service_fn(move |req|  {
-                process_request(req,app_state.to_owned())
+                process_request(req,app_state.to_owned(),remote_addr)
             }))
         }
     });
@@ -0,0 +1,284 @@
+use crate::form_parameters::FormParameters;
+use crate::http_client::HttpClient;
+use hyper::header::{HeaderMap, COOKIE};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+pub static SESSION_COOKIE: &str = "petscan_session";
+static DEFAULT_OAUTH_URL: &str = "https://meta.wikimedia.org/w/rest.php";
+/// Access tokens that do not say when they expire are kept this long
+static DEFAULT_TOKEN_SECONDS: u64 = 4 * 3600;
+/// A login has to come back from the wiki within this time
+static OAUTH_STATE_SECONDS: u64 = 600;
+static MAX_OAUTH_SESSIONS: usize = 10000;
+
+/// A logged-in user, with the OAuth access token for edits on their behalf. Requests that change something also have
+/// to send `csrf_token`, which other sites can not read.
+#[derive(Debug, Clone)]
+pub struct OAuthSession {
+    pub user: String,
+    /// The email address of the wiki account, if it is confirmed (needs the "User identity verification only, with
+    /// access to real name and email address" grant)
+    pub email: Option<String>,
+    pub access_token: String,
+    pub csrf_token: String,
+    expires: Instant,
+}
+
+/// Login via OAuth 2.0 on the wikis (Special:OAuthConsumerRegistration, with the "Edit existing pages" and
+/// "Create, edit, and move pages" grants). Configured via "oauth":{"client_id":"...","client_secret":"..."},
+/// optionally with "url" for the REST API of another wiki farm.
+#[derive(Debug)]
+pub struct OAuth {
+    http: Arc<HttpClient>,
+    url: String,
+    client_id: String,
+    client_secret: String,
+    /// Login state => when the login was started
+    states: RwLock<HashMap<String, Instant>>,
+    /// Session ID (in the cookie) => session
+    sessions: RwLock<HashMap<String, OAuthSession>>,
+}
+
+impl OAuth {
+    /// None if OAuth is not configured
+    pub fn new_from_config(config: &Value, http: Arc<HttpClient>) -> Option<Self> {
+        let client_id = config["oauth"]["client_id"].as_str().filter(|s| !s.is_empty())?;
+        let client_secret = config["oauth"]["client_secret"].as_str().filter(|s| !s.is_empty())?;
+        Some(Self {
+            http,
+            url: config["oauth"]["url"]
+                .as_str()
+                .filter(|s| !s.is_empty())
+                .unwrap_or(DEFAULT_OAUTH_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            states: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Where to send the user to log in; the wiki redirects back to the registered callback URL with `code` and `state`
+    pub fn authorize_url(&self) -> Result<String, String> {
+        let state = Self::random_token();
+        let mut states = self.states.write().map_err(|e| format!("{:?}", e))?;
+        states.retain(|_, started| started.elapsed() < Duration::from_secs(OAUTH_STATE_SECONDS));
+        states.insert(state.to_owned(), Instant::now());
+        Ok(format!(
+            "{}/oauth2/authorize?response_type=code&client_id={}&state={}",
+            self.url,
+            FormParameters::percent_encode(&self.client_id),
+            state
+        ))
+    }
+
+    /// Finishes a login from the callback parameters; returns the new session ID, for the cookie
+    pub async fn login(&self, code: &str, state: &str) -> Result<String, String> {
+        let started = self
+            .states
+            .write()
+            .map_err(|e| format!("{:?}", e))?
+            .remove(state)
+            .ok_or_else(|| "Unknown or expired login; please log in again".to_string())?;
+        if started.elapsed() >= Duration::from_secs(OAUTH_STATE_SECONDS) {
+            return Err("Unknown or expired login; please log in again".to_string());
+        }
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        let json: Value = self
+            .http
+            .post(&format!("{}/oauth2/access_token", self.url))
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| format!("OAuth: {:?}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("OAuth: {:?}", e))?;
+        let (access_token, seconds) = Self::access_token_from_result(&json)?;
+        let (user, email) = self.get_profile(&access_token).await?;
+        self.add_session(user, email, access_token, Duration::from_secs(seconds))
+    }
+
+    pub fn get_session(&self, session_id: &str) -> Option<OAuthSession> {
+        let sessions = self.sessions.read().ok()?;
+        sessions.get(session_id).filter(|s| s.expires > Instant::now()).cloned()
+    }
+
+    pub fn logout(&self, session_id: &str) {
+        if let Ok(mut sessions) = self.sessions.write() {
+            sessions.remove(session_id);
+        }
+    }
+
+    /// The session ID from the cookie, if there is one
+    pub fn session_id_from_headers(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+            .map(|id| id.to_string())
+            .find(|id| !id.is_empty())
+    }
+
+    /// Value of a Set-Cookie header for a session; None deletes the cookie
+    pub fn set_cookie_header(session_id: Option<&str>) -> String {
+        let max_age = match session_id {
+            Some(_) => DEFAULT_TOKEN_SECONDS,
+            None => 0,
+        };
+        format!(
+            "{}={}; Path=/; Max-Age={}; SameSite=Lax; HttpOnly; Secure",
+            SESSION_COOKIE,
+            session_id.unwrap_or_default(),
+            max_age
+        )
+    }
+
+    fn add_session(
+        &self,
+        user: String,
+        email: Option<String>,
+        access_token: String,
+        valid_for: Duration,
+    ) -> Result<String, String> {
+        let mut sessions = self.sessions.write().map_err(|e| format!("{:?}", e))?;
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        if sessions.len() >= MAX_OAUTH_SESSIONS {
+            return Err("Too many users logged in, please try again later".to_string());
+        }
+        let session_id = Self::random_token();
+        sessions.insert(
+            session_id.to_owned(),
+            OAuthSession {
+                user,
+                email,
+                access_token,
+                csrf_token: Self::random_token(),
+                expires: now + valid_for,
+            },
+        );
+        Ok(session_id)
+    }
+
+    /// The user name, and the confirmed email address
+    async fn get_profile(&self, access_token: &str) -> Result<(String, Option<String>), String> {
+        let json: Value = self
+            .http
+            .send(self.http.get(&format!("{}/oauth2/resource/profile", self.url)).bearer_auth(access_token))
+            .await
+            .map_err(|e| format!("OAuth: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("OAuth: {:?}", e))?;
+        Self::profile_from_result(&json)
+    }
+
+    fn profile_from_result(json: &Value) -> Result<(String, Option<String>), String> {
+        let user = json["username"]
+            .as_str()
+            .ok_or_else(|| format!("OAuth: no user name in profile: {}", json))?;
+        let email = match json["confirmed_email"].as_bool() {
+            Some(true) => json["email"].as_str().filter(|email| !email.is_empty()).map(|email| email.to_string()),
+            _ => None,
+        };
+        Ok((user.to_string(), email))
+    }
+
+    /// The access token, and for how many seconds it is valid
+    fn access_token_from_result(json: &Value) -> Result<(String, u64), String> {
+        match json["access_token"].as_str() {
+            Some(token) if !token.is_empty() => Ok((
+                token.to_string(),
+                json["expires_in"].as_u64().unwrap_or(DEFAULT_TOKEN_SECONDS),
+            )),
+            _ => Err(format!(
+                "OAuth: login failed: {}",
+                json["message"].as_str().or_else(|| json["error"].as_str()).unwrap_or("no access token")
+            )),
+        }
+    }
+
+    fn random_token() -> String {
+        (0..4).map(|_| format!("{:08x}", rand::random::<u32>())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oauth() -> OAuth {
+        let config = json!({"oauth":{"client_id":"abc 123","client_secret":"secret"}});
+        OAuth::new_from_config(&config, Arc::new(HttpClient::new_from_config(&config).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn new_from_config() {
+        assert!(OAuth::new_from_config(&json!({}), Arc::new(HttpClient::new_from_config(&json!({})).unwrap())).is_none());
+        let url = oauth().authorize_url().unwrap();
+        assert!(url.starts_with("https://meta.wikimedia.org/w/rest.php/oauth2/authorize?response_type=code&client_id=abc%20123&state="));
+    }
+
+    #[test]
+    fn sessions() {
+        let oauth = oauth();
+        let id = oauth
+            .add_session("Foo".to_string(), None, "token".to_string(), Duration::from_secs(60))
+            .unwrap();
+        let session = oauth.get_session(&id).unwrap();
+        assert_eq!(session.user, "Foo");
+        assert_eq!(session.csrf_token.len(), 32);
+        assert_ne!(session.csrf_token, id);
+        assert!(oauth.get_session("nope").is_none());
+        oauth.logout(&id);
+        assert!(oauth.get_session(&id).is_none());
+        let expired = oauth
+            .add_session("Foo".to_string(), None, "token".to_string(), Duration::from_secs(0))
+            .unwrap();
+        assert!(oauth.get_session(&expired).is_none());
+    }
+
+    #[test]
+    fn cookie_round_trip() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(OAuth::session_id_from_headers(&headers), None);
+        let cookie = OAuth::set_cookie_header(Some("abc"));
+        headers.insert(COOKIE, format!("other=1; {}", cookie.split(';').next().unwrap()).parse().unwrap());
+        assert_eq!(OAuth::session_id_from_headers(&headers), Some("abc".to_string()));
+        assert!(OAuth::set_cookie_header(None).contains("Max-Age=0"));
+    }
+
+    #[test]
+    fn profile_from_result() {
+        assert_eq!(
+            OAuth::profile_from_result(&json!({"username":"Foo","confirmed_email":true,"email":"foo@example.org"})),
+            Ok(("Foo".to_string(), Some("foo@example.org".to_string())))
+        );
+        assert_eq!(
+            OAuth::profile_from_result(&json!({"username":"Foo","confirmed_email":false,"email":"foo@example.org"})),
+            Ok(("Foo".to_string(), None))
+        );
+        assert!(OAuth::profile_from_result(&json!({"error":"x"})).is_err());
+    }
+
+    #[test]
+    fn access_token_from_result() {
+        assert_eq!(
+            OAuth::access_token_from_result(&json!({"token_type":"Bearer","expires_in":14400,"access_token":"xyz"})),
+            Ok(("xyz".to_string(), 14400))
+        );
+        assert!(OAuth::access_token_from_result(&json!({"error":"invalid_grant","message":"Bad code"})).is_err());
+    }
+}
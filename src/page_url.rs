@@ -0,0 +1,45 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters MediaWiki leaves as they are in article URLs, like its wfUrlencode()
+const TITLE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~')
+    .remove(b';')
+    .remove(b'@')
+    .remove(b'$')
+    .remove(b'!')
+    .remove(b'*')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b',')
+    .remove(b'/')
+    .remove(b':');
+
+/// Percent-encodes a full title (with spaces or underscores) for the path of an article URL
+pub fn encode_title(full_title: &str) -> String {
+    utf8_percent_encode(&full_title.replace(' ', "_"), TITLE_ENCODE_SET).to_string()
+}
+
+/// The absolute article URL; `server` is eg "https://de.wikipedia.org", from AppState::get_server_url_for_wiki
+pub fn page_url(server: &str, full_title: &str) -> String {
+    format!("{}/wiki/{}", server.trim_end_matches('/'), encode_title(full_title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        assert_eq!(encode_title("Kategorie:Berlin Mitte"), "Kategorie:Berlin_Mitte");
+        assert_eq!(encode_title("AC/DC (band)"), "AC/DC_(band)");
+        assert_eq!(encode_title("Q&A? 100%"), "Q%26A%3F_100%25");
+        assert_eq!(encode_title("Zürich's \"Tram\""), "Z%C3%BCrich%27s_%22Tram%22");
+        assert_eq!(
+            page_url("https://de.wikipedia.org/", "Straße #1"),
+            "https://de.wikipedia.org/wiki/Stra%C3%9Fe_%231"
+        );
+    }
+}
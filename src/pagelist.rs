@@ -2,9 +2,9 @@ use futures::future::join_all;
 use crate::app_state::AppState;
 use crate::datasource::SQLtuple;
 use crate::platform::{Platform, PAGE_BATCH_SIZE};
+use crate::wiki_db::{column, from_db_row, DbRow};
 use mysql_async::Value as MyValue;
 use mysql_async as my;
-use mysql_async::prelude::Queryable;
 use rayon::prelude::*;
 use regex::Regex;
 use serde_json::Value;
@@ -12,9 +12,12 @@ use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::sync::RwLock;
-use wikibase::mediawiki::api::NamespaceID;
+use wikibase::mediawiki::api::{Api, NamespaceID};
 use wikibase::mediawiki::title::Title;
 
+static SEARCH_FILTER_BATCH_SIZE: usize = 50;
+static SEARCH_FILTER_CONCURRENT_REQUESTS: usize = 5;
+
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,9 +30,13 @@ pub enum PageListSort {
     RedlinksCount(bool),
     IncomingLinks(bool),
     DefaultSort(bool),
+    SortKey(bool),
     FileSize(bool),
     UploadDate(bool),
     Sitelinks(bool),
+    Views(bool),
+    LastEditAge(bool),
+    Watchers(bool),
     Random(bool),
 }
 
@@ -43,9 +50,13 @@ impl PageListSort {
             "redlinks" => Self::RedlinksCount(descending),
             "incoming_links" => Self::IncomingLinks(descending),
             "defaultsort" => Self::DefaultSort(descending),
+            "sortkey" => Self::SortKey(descending),
             "filesize" => Self::FileSize(descending),
             "uploaddate" => Self::UploadDate(descending),
             "sitelinks" => Self::Sitelinks(descending),
+            "views" => Self::Views(descending),
+            "last_edit_age" => Self::LastEditAge(descending),
+            "watchers" => Self::Watchers(descending),
             "random" => Self::Random(descending),
             _ => Self::Default(descending),
         }
@@ -141,6 +152,144 @@ impl PageCoordinates {
 
 //________________________________________________________________________________________________________________________
 
+/// Redirects are followed this far before a chain counts as circular
+pub static MAX_REDIRECT_HOPS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectProblemKind {
+    /// Points to another redirect
+    Double,
+    /// Points to a page that does not exist, directly or via other redirects
+    Broken,
+    /// Points back to itself, directly or via other redirects
+    Loop,
+}
+
+impl RedirectProblemKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Double => "double",
+            Self::Broken => "broken",
+            Self::Loop => "loop",
+        }
+    }
+}
+
+/// What is wrong with a redirect, and for double redirects, the page it should point to instead
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectProblem {
+    pub kind: RedirectProblemKind,
+    pub target: Title,
+    /// The page at the end of the chain, with section
+    pub fix: Option<(Title, String)>,
+}
+
+impl RedirectProblem {
+    /// Follows `redirect` to `target` (with section) through `pages`, the known existing pages with their own target
+    /// if they are redirects; `None` if the redirect is fine
+    pub fn new_for_redirect(
+        redirect: &Title,
+        target: &(Title, String),
+        pages: &HashMap<Title, Option<(Title, String)>>,
+    ) -> Option<Self> {
+        let mut current = target.to_owned();
+        let mut seen: HashSet<Title> = vec![redirect.to_owned(), target.0.to_owned()].into_iter().collect();
+        let mut hops = 1;
+        let kind = loop {
+            match pages.get(&current.0) {
+                None => break RedirectProblemKind::Broken,
+                Some(None) if hops == 1 => return None,
+                Some(None) => break RedirectProblemKind::Double,
+                Some(Some(next)) => {
+                    hops += 1;
+                    if hops > MAX_REDIRECT_HOPS || !seen.insert(next.0.to_owned()) {
+                        break RedirectProblemKind::Loop;
+                    }
+                    // Keep the section of the earlier redirect if the next one has none
+                    current = match next.1.is_empty() {
+                        true => (next.0.to_owned(), current.1),
+                        false => next.to_owned(),
+                    };
+                }
+            }
+        };
+        Some(Self {
+            kind,
+            target: target.0.to_owned(),
+            fix: match kind {
+                RedirectProblemKind::Double => Some(current),
+                _ => None,
+            },
+        })
+    }
+
+    fn full_title(title: &Title, api: &Api) -> String {
+        title.full_pretty(api).unwrap_or_else(|| title.pretty().to_string())
+    }
+
+    pub fn target_title(&self, api: &Api) -> String {
+        Self::full_title(&self.target, api)
+    }
+
+    /// The new target for the redirect, eg "Foo#Bar"
+    pub fn fix_title(&self, api: &Api) -> Option<String> {
+        let (title, section) = self.fix.as_ref()?;
+        let title = Self::full_title(title, api);
+        match section.is_empty() {
+            true => Some(title),
+            false => Some(format!("{}#{}", title, section.replace('_', " "))),
+        }
+    }
+
+    pub fn describe(&self, api: &Api) -> String {
+        let target = self.target_title(api);
+        match (self.kind, self.fix_title(api)) {
+            (RedirectProblemKind::Double, Some(fix)) => format!("double redirect via {}, to {}", target, fix),
+            (RedirectProblemKind::Loop, _) => format!("circular redirect via {}", target),
+            _ => format!("broken redirect to {}", target),
+        }
+    }
+
+    pub fn as_json(&self, api: &Api) -> Value {
+        json!({"problem":self.kind.as_str(),"target":self.target_title(api),"fix":self.fix_title(api)})
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// WikiProject assessments of a page, over all projects
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageAssessment {
+    pub classes: Vec<String>,
+    pub importances: Vec<String>,
+}
+
+impl PageAssessment {
+    pub fn add(&mut self, class: &str, importance: &str) {
+        let class = class.trim();
+        if !class.is_empty() && !self.classes.iter().any(|c| c == class) {
+            self.classes.push(class.to_string());
+        }
+        let importance = importance.trim();
+        if !importance.is_empty() && !self.importances.iter().any(|i| i == importance) {
+            self.importances.push(importance.to_string());
+        }
+    }
+
+    /// Empty filters match anything; comparison is case-insensitive
+    pub fn matches(&self, classes: &[String], importances: &[String]) -> bool {
+        let any_match = |have: &[String], want: &[String]| {
+            want.is_empty()
+                || have
+                    .iter()
+                    .any(|h| want.iter().any(|w| h.to_lowercase() == w.to_lowercase()))
+        };
+        any_match(&self.classes, classes) && any_match(&self.importances, importances)
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
 pub type LinkCount = u32;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -177,15 +326,40 @@ pub struct PageListEntry {
     pub incoming_links: Option<LinkCount>,
     pub link_count: Option<LinkCount>,
     pub redlink_count: Option<LinkCount>,
+    pub redlinks_in_page: Option<LinkCount>,
     pub sitelink_count: Option<LinkCount>,
+    pub page_views: Option<u64>,
+    /// Days since the latest revision
+    pub last_edit_age_days: Option<u64>,
+    /// 0 if the number is below the threshold the API shows
+    pub watchers: Option<u64>,
+    /// Number of wikis using the (Commons) file, from globalimagelinks
+    pub global_usage_wikis: Option<u64>,
+    /// Number of pages, on all wikis, using the (Commons) file
+    pub global_usage_pages: Option<u64>,
+    /// Number of <ref> tags in the wikitext
+    pub references: Option<u64>,
+    /// Number of "citation needed" template uses in the wikitext
+    pub citation_needed: Option<u64>,
     page_timestamp: Option<Box<String>>,
     page_image: Option<Box<String>>,
+    image_url: Option<Box<String>>,
     wikidata_item: Option<Box<String>>,
     wikidata_label: Option<Box<String>>,
     wikidata_description: Option<Box<String>>,
     defaultsort: Option<Box<String>>,
+    sortkey: Option<Box<String>>,
     coordinates: Option<Box<PageCoordinates>>,
     file_info: Option<Box<FileInfo>>,
+    assessment: Option<Box<PageAssessment>>,
+    property_values: Option<Box<HashMap<String, Vec<String>>>>,
+    infoboxes: Option<Box<Vec<String>>>,
+    short_description: Option<Box<String>>,
+    duplicate_titles: Option<Box<Vec<String>>>,
+    category_membership: Option<Box<Vec<String>>>,
+    redirect_target: Option<Box<String>>,
+    redirect_problem: Option<Box<RedirectProblem>>,
+    file_duplicates: Option<Box<Vec<String>>>,
 }
 
 impl Hash for PageListEntry {
@@ -212,9 +386,11 @@ impl PageListEntry {
             page_bytes: None,
             page_timestamp: None,
             defaultsort: None,
+            sortkey: None,
             disambiguation: TriState::Unknown,
             incoming_links: None,
             page_image: None,
+            image_url: None,
             coordinates: None,
             link_count: None,
             sitelink_count: None,
@@ -222,7 +398,70 @@ impl PageListEntry {
             wikidata_label: None,
             wikidata_description: None,
             redlink_count: None,
+            redlinks_in_page: None,
+            page_views: None,
+            last_edit_age_days: None,
+            watchers: None,
+            global_usage_wikis: None,
+            global_usage_pages: None,
+            references: None,
+            citation_needed: None,
+            assessment: None,
+            property_values: None,
+            infoboxes: None,
+            short_description: None,
+            duplicate_titles: None,
+            category_membership: None,
+            redirect_target: None,
+            redirect_problem: None,
+            file_duplicates: None,
+        }
+    }
+
+    /// Fills in metadata this entry is missing from another entry for the same page.
+    /// Where both have a value, the one of this entry is kept.
+    pub fn merge_metadata(&mut self, other: &PageListEntry) {
+        fn fill<T: Clone>(mine: &mut Option<T>, theirs: &Option<T>) {
+            if mine.is_none() {
+                *mine = theirs.clone();
+            }
         }
+        if self.disambiguation == TriState::Unknown {
+            self.disambiguation = other.disambiguation.clone();
+        }
+        fill(&mut self.page_id, &other.page_id);
+        fill(&mut self.page_bytes, &other.page_bytes);
+        fill(&mut self.incoming_links, &other.incoming_links);
+        fill(&mut self.link_count, &other.link_count);
+        fill(&mut self.redlink_count, &other.redlink_count);
+        fill(&mut self.redlinks_in_page, &other.redlinks_in_page);
+        fill(&mut self.sitelink_count, &other.sitelink_count);
+        fill(&mut self.page_views, &other.page_views);
+        fill(&mut self.last_edit_age_days, &other.last_edit_age_days);
+        fill(&mut self.watchers, &other.watchers);
+        fill(&mut self.global_usage_wikis, &other.global_usage_wikis);
+        fill(&mut self.global_usage_pages, &other.global_usage_pages);
+        fill(&mut self.references, &other.references);
+        fill(&mut self.citation_needed, &other.citation_needed);
+        fill(&mut self.page_timestamp, &other.page_timestamp);
+        fill(&mut self.page_image, &other.page_image);
+        fill(&mut self.image_url, &other.image_url);
+        fill(&mut self.wikidata_item, &other.wikidata_item);
+        fill(&mut self.wikidata_label, &other.wikidata_label);
+        fill(&mut self.wikidata_description, &other.wikidata_description);
+        fill(&mut self.defaultsort, &other.defaultsort);
+        fill(&mut self.sortkey, &other.sortkey);
+        fill(&mut self.coordinates, &other.coordinates);
+        fill(&mut self.file_info, &other.file_info);
+        fill(&mut self.assessment, &other.assessment);
+        fill(&mut self.property_values, &other.property_values);
+        fill(&mut self.infoboxes, &other.infoboxes);
+        fill(&mut self.short_description, &other.short_description);
+        fill(&mut self.duplicate_titles, &other.duplicate_titles);
+        fill(&mut self.category_membership, &other.category_membership);
+        fill(&mut self.redirect_target, &other.redirect_target);
+        fill(&mut self.redirect_problem, &other.redirect_problem);
+        fill(&mut self.file_duplicates, &other.file_duplicates);
     }
 
     pub fn get_file_info(&self) -> Option<FileInfo> {
@@ -253,6 +492,20 @@ impl PageListEntry {
         }
     }
 
+    pub fn get_assessment(&self) -> Option<PageAssessment> {
+        match &self.assessment {
+            Some(assessment) => Some(*(assessment.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_assessment(&mut self, assessment_option: Option<PageAssessment>) {
+        self.assessment = match assessment_option {
+            Some(assessment) => Some(Box::new(assessment)),
+            None => None,
+        }
+    }
+
     pub fn get_defaultsort(&self) -> Option<String> {
         match &self.defaultsort {
             Some(defaultsort) => Some(*(defaultsort.clone())),
@@ -267,6 +520,21 @@ impl PageListEntry {
         }
     }
 
+    /// The category sort key prefix, as set by `[[Category:X|key]]` or DEFAULTSORT
+    pub fn get_sortkey(&self) -> Option<String> {
+        match &self.sortkey {
+            Some(sortkey) => Some(*(sortkey.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_sortkey(&mut self, sortkey_option: Option<String>) {
+        self.sortkey = match sortkey_option {
+            Some(sortkey) => Some(Box::new(sortkey)),
+            None => None,
+        }
+    }
+
     pub fn get_wikidata_description(&self) -> Option<String> {
         match &self.wikidata_description {
             Some(wikidata_description) => Some(*(wikidata_description.clone())),
@@ -309,6 +577,121 @@ impl PageListEntry {
         }
     }
 
+    /// Harvested values of a Wikidata property (eg "P31") of the item of this page
+    pub fn get_property_values(&self, property: &str) -> Option<Vec<String>> {
+        self.property_values.as_ref()?.get(property).cloned()
+    }
+
+    pub fn set_property_values(&mut self, property_values_option: Option<HashMap<String, Vec<String>>>) {
+        self.property_values = match property_values_option {
+            Some(property_values) => Some(Box::new(property_values)),
+            None => None,
+        }
+    }
+
+    /// Which of the requested infobox templates this page transcludes
+    pub fn get_infoboxes(&self) -> Option<Vec<String>> {
+        match &self.infoboxes {
+            Some(infoboxes) => Some(*(infoboxes.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_infoboxes(&mut self, infoboxes_option: Option<Vec<String>>) {
+        self.infoboxes = match infoboxes_option {
+            Some(infoboxes) => Some(Box::new(infoboxes)),
+            None => None,
+        }
+    }
+
+    /// Other result pages whose titles differ from this one only by namespace, case, or diacritics
+    pub fn get_duplicate_titles(&self) -> Option<Vec<String>> {
+        match &self.duplicate_titles {
+            Some(duplicates) => Some(*(duplicates.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_duplicate_titles(&mut self, duplicates_option: Option<Vec<String>>) {
+        self.duplicate_titles = match duplicates_option {
+            Some(duplicates) => Some(Box::new(duplicates)),
+            None => None,
+        }
+    }
+
+    /// Which of the input categories this page is in, eg "Physicists (via German physicists)"
+    pub fn get_category_membership(&self) -> Option<Vec<String>> {
+        match &self.category_membership {
+            Some(membership) => Some(*(membership.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_category_membership(&mut self, membership_option: Option<Vec<String>>) {
+        self.category_membership = match membership_option {
+            Some(membership) => Some(Box::new(membership)),
+            None => None,
+        }
+    }
+
+    /// Where this redirect points to, eg "Category:Foo#Bar"
+    pub fn get_redirect_target(&self) -> Option<String> {
+        match &self.redirect_target {
+            Some(target) => Some(*(target.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_redirect_target(&mut self, target_option: Option<String>) {
+        self.redirect_target = match target_option {
+            Some(target) => Some(Box::new(target)),
+            None => None,
+        }
+    }
+
+    pub fn get_redirect_problem(&self) -> Option<RedirectProblem> {
+        match &self.redirect_problem {
+            Some(problem) => Some(*(problem.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_redirect_problem(&mut self, problem_option: Option<RedirectProblem>) {
+        self.redirect_problem = match problem_option {
+            Some(problem) => Some(Box::new(problem)),
+            None => None,
+        }
+    }
+
+    /// Other files with the same content (SHA1), eg "File:Foo.jpg" or "commons:File:Foo.jpg"
+    pub fn get_file_duplicates(&self) -> Option<Vec<String>> {
+        match &self.file_duplicates {
+            Some(duplicates) => Some(*(duplicates.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_file_duplicates(&mut self, duplicates_option: Option<Vec<String>>) {
+        self.file_duplicates = match duplicates_option {
+            Some(duplicates) => Some(Box::new(duplicates)),
+            None => None,
+        }
+    }
+
+    pub fn get_short_description(&self) -> Option<String> {
+        match &self.short_description {
+            Some(short_description) => Some(*(short_description.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_short_description(&mut self, short_description_option: Option<String>) {
+        self.short_description = match short_description_option {
+            Some(short_description) => Some(Box::new(short_description)),
+            None => None,
+        }
+    }
+
     pub fn get_page_image(&self) -> Option<String> {
         match &self.page_image {
             Some(page_image) => Some(*(page_image.clone())),
@@ -323,6 +706,21 @@ impl PageListEntry {
         }
     }
 
+    /// The thumbnail URL of the page image
+    pub fn get_image_url(&self) -> Option<String> {
+        match &self.image_url {
+            Some(image_url) => Some(*(image_url.clone())),
+            None => None,
+        }
+    }
+
+    pub fn set_image_url(&mut self, image_url_option: Option<String>) {
+        self.image_url = match image_url_option {
+            Some(image_url) => Some(Box::new(image_url)),
+            None => None,
+        }
+    }
+
     pub fn get_page_timestamp(&self) -> Option<String> {
         match &self.page_timestamp {
             Some(page_timestamp) => Some(*(page_timestamp.clone())),
@@ -355,11 +753,17 @@ impl PageListEntry {
             PageListSort::Size(d) => self.compare_by_size(other, *d),
             PageListSort::IncomingLinks(d) => self.compare_by_incoming(other, *d),
             PageListSort::DefaultSort(d) => self.compare_by_defaultsort(other, *d, is_wikidata),
+            PageListSort::SortKey(d) => self.compare_by_sortkey(other, *d, is_wikidata),
             PageListSort::Date(d) => self.compare_by_date(other, *d),
             PageListSort::UploadDate(d) => self.compare_by_upload_date(other, *d),
             PageListSort::FileSize(d) => self.compare_by_file_size(other, *d),
             PageListSort::RedlinksCount(d) => self.compare_by_redlinks(other, *d),
             PageListSort::Sitelinks(d) => self.compare_by_sitelinks(other, *d),
+            PageListSort::Views(d) => self.compare_by_opt(&self.page_views, &other.page_views, *d),
+            PageListSort::LastEditAge(d) => {
+                self.compare_by_opt(&self.last_edit_age_days, &other.last_edit_age_days, *d)
+            }
+            PageListSort::Watchers(d) => self.compare_by_opt(&self.watchers, &other.watchers, *d),
             PageListSort::Random(d) => self.compare_by_random(other, *d),
         }
     }
@@ -428,6 +832,18 @@ impl PageListEntry {
         self.compare_by_opt(&ds_mine, &ds_other, descending)
     }
 
+    /// Pages without a sort key are sorted the way MediaWiki would: by DEFAULTSORT, or by title
+    fn compare_by_sortkey(
+        self: &PageListEntry,
+        other: &PageListEntry,
+        descending: bool,
+        is_wikidata: bool,
+    ) -> Ordering {
+        let sk_mine = self.get_sortkey().or_else(|| self.get_defaultsort_with_fallback(is_wikidata));
+        let sk_other = other.get_sortkey().or_else(|| other.get_defaultsort_with_fallback(is_wikidata));
+        self.compare_by_opt(&sk_mine, &sk_other, descending)
+    }
+
     fn compare_by_sitelinks(
         self: &PageListEntry,
         other: &PageListEntry,
@@ -542,6 +958,37 @@ impl PageListEntry {
 
 //________________________________________________________________________________________________________________________
 
+/// Summary of page sizes for a result; pages without a known size are counted, but not measured
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageLengthStatistics {
+    pub count: usize,
+    pub total_bytes: u64,
+    pub median_bytes: Option<u32>,
+}
+
+impl PageLengthStatistics {
+    pub fn new_from_entries(entries: &[PageListEntry]) -> Self {
+        let mut sizes: Vec<u32> = entries.iter().filter_map(|e| e.page_bytes).collect();
+        sizes.sort_unstable();
+        let median_bytes = match sizes.len() {
+            0 => None,
+            len if len % 2 == 1 => Some(sizes[len / 2]),
+            len => Some(((sizes[len / 2 - 1] as u64 + sizes[len / 2] as u64) / 2) as u32),
+        };
+        Self {
+            count: entries.len(),
+            total_bytes: sizes.iter().map(|s| *s as u64).sum(),
+            median_bytes,
+        }
+    }
+
+    pub fn as_json(&self) -> Value {
+        json!({"count":self.count,"total_bytes":self.total_bytes,"median_bytes":self.median_bytes})
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
 #[derive(Debug)]
 pub struct PageList {
     wiki: RwLock<Option<String>>,
@@ -618,6 +1065,16 @@ impl PageList {
         Ok(())
     }
 
+    /// Reduces the list to (an arbitrary selection of) at most `limit` entries
+    pub fn limit_entries(&self, limit: usize) -> Result<(), String> {
+        let mut entries = self.entries.write().map_err(|e| format!("{:?}", e))?;
+        if entries.len() > limit {
+            let kept: HashSet<PageListEntry> = entries.drain().take(limit).collect();
+            *entries = kept;
+        }
+        Ok(())
+    }
+
     pub fn retain_entries(&self, f: &dyn Fn(&PageListEntry) -> bool) -> Result<(), String> {
         self.entries
             .write()
@@ -743,8 +1200,14 @@ impl PageList {
             .read()
             .map_err(|e| format!("{:?}", e))?
             .iter()
-            .for_each(|x| {
-                me.insert(x.to_owned());
+            .for_each(|x| match me.take(x) {
+                Some(mut entry) => {
+                    entry.merge_metadata(x);
+                    me.insert(entry);
+                }
+                None => {
+                    me.insert(x.to_owned());
+                }
             });
         Platform::profile("PageList::union UNION DONE", None);
         Ok(())
@@ -758,10 +1221,14 @@ impl PageList {
         self.check_before_merging(&pagelist, platform).await?;
         let other_entries = pagelist.entries();
         let other_entries = other_entries.read().map_err(|e| format!("{:?}", e))?;
-        self.entries
-            .write()
-            .map_err(|e| format!("{:?}", e))?
-            .retain(|x| other_entries.contains(&x));
+        let mut me = self.entries.write().map_err(|e| format!("{:?}", e))?;
+        *me = me
+            .drain()
+            .filter_map(|mut entry| {
+                entry.merge_metadata(other_entries.get(&entry)?);
+                Some(entry)
+            })
+            .collect();
         Ok(())
     }
 
@@ -843,17 +1310,14 @@ impl PageList {
         state: &AppState,
         sql: SQLtuple,
         wiki: &str,
-    ) -> Result<Vec<my::Row>, String> {
+    ) -> Result<Vec<DbRow>, String> {
         let mut conn = state
-            .get_wiki_db_connection(&wiki)
+            .get_wiki_db(&wiki)
             .await
-            .map_err(|e| format!("PageList::run_batch_query: get_wiki_db_connection: {:?}", e))?;
-        let rows = conn.exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await // TODO fix to_owned
-            .map_err(|e|format!("PageList::run_batch_query: SQL query error[1]: {:?}",e))?
-            .collect_and_drop()
-            .await
-            .map_err(|e|format!("PageList::run_batch_query: SQL query error[2]: {:?}",e))?;
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+            .map_err(|e| format!("PageList::run_batch_query: get_wiki_db: {:?}", e))?;
+        let rows = conn.query(sql).await
+            .map_err(|e|format!("PageList::run_batch_query: SQL query error: {}",e))?;
+        conn.close().await?;
 
         Ok(rows)
     }
@@ -863,7 +1327,7 @@ impl PageList {
         &self,
         state: &AppState,
         batches: Vec<SQLtuple>,
-    ) -> Result<Vec<my::Row>, String> {
+    ) -> Result<Vec<DbRow>, String> {
         let wiki = self
             .wiki()?
             .ok_or_else(|| "PageList::run_batch_queries: No wiki".to_string())?;
@@ -882,9 +1346,9 @@ impl PageList {
         state: &AppState,
         batches: Vec<SQLtuple>,
         wiki: String,
-    ) -> Result<Vec<my::Row>, String> {
+    ) -> Result<Vec<DbRow>, String> {
         // TODO?: "SET STATEMENT max_statement_time = 300 FOR SELECT..."
-        let mut rows: Vec<my::Row> = vec![];
+        let mut rows: Vec<DbRow> = vec![];
         for sql in batches {
             let mut data = self.run_batch_query(state, sql, &wiki).await?;
             rows.append(&mut data);
@@ -899,7 +1363,7 @@ impl PageList {
         state: &AppState,
         batches: Vec<SQLtuple>,
         wiki: String,
-    ) -> Result<Vec<my::Row>, String> {
+    ) -> Result<Vec<DbRow>, String> {
         // TODO?: "SET STATEMENT max_statement_time = 300 FOR SELECT..."
 
         // TODO parallel
@@ -915,22 +1379,22 @@ impl PageList {
         Ok(ret)
     }
 
-    pub fn string_from_row(row: &my::Row, col_num: usize) -> Option<String> {
+    pub fn string_from_row(row: &DbRow, col_num: usize) -> Option<String> {
         match row.get(col_num)? {
-            my::Value::Bytes(uv) => String::from_utf8(uv).ok(),
+            my::Value::Bytes(uv) => String::from_utf8(uv.to_owned()).ok(),
             _ => None,
         }
     }
 
     pub fn entry_from_row(
         &self,
-        row: &my::Row,
+        row: &DbRow,
         col_title: usize,
         col_ns: usize,
     ) -> Option<PageListEntry> {
         let page_title = Self::string_from_row(row, col_title)?;
         let namespace_id = match row.get(col_ns)? {
-            my::Value::Int(i) => i,
+            my::Value::Int(i) => *i,
             _ => return None,
         };
         Some(PageListEntry::new(Title::new(&page_title, namespace_id)))
@@ -959,7 +1423,7 @@ impl PageList {
                 })
                 .collect::<Vec<SQLtuple>>();
 
-            let the_f = |row: my::Row, entry: &mut PageListEntry| match my::from_row_opt::<(
+            let the_f = |row: &DbRow, entry: &mut PageListEntry| match from_db_row::<(
                 Vec<u8>,
                 NamespaceID,
                 u32,
@@ -997,7 +1461,7 @@ impl PageList {
                     }
                 })
                 .for_each(|(row, mut entry)| {
-                    the_f(row.clone(), &mut entry);
+                    the_f(row, &mut entry);
                     self.add_entry(entry).unwrap_or(());
                 });
         }
@@ -1087,7 +1551,7 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
             })
             .collect::<Vec<SQLtuple>>();
 
-        let the_f = |row: my::Row, entry: &mut PageListEntry| if let Ok((_page_title, _page_namespace, term_text, term_type)) = my::from_row_opt::<(
+        let the_f = |row: &DbRow, entry: &mut PageListEntry| if let Ok((_page_title, _page_namespace, term_text, term_type)) = from_db_row::<(
                 Vec<u8>,
                 NamespaceID,
                 Vec<u8>,
@@ -1115,7 +1579,7 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
                 }
             })
             .for_each(|(row, mut entry)| {
-                the_f(row.clone(), &mut entry);
+                the_f(row, &mut entry);
                 self.add_entry(entry).unwrap_or(());
             });
         Ok(())
@@ -1147,8 +1611,8 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
             .collect::<Vec<SQLtuple>>();
         self.clear_entries()?;
         let state = platform.state();
-        let the_f = |row: my::Row| {
-            match my::from_row_opt::<Vec<u8>>(row) {
+        let the_f = |row: &DbRow| {
+            match column::<Vec<u8>>(row, 0) {
                 Ok(pp_value) => {
                     let pp_value = String::from_utf8_lossy(&pp_value).into_owned();
                     Some(PageListEntry::new(Title::new(&pp_value, 0)))
@@ -1161,7 +1625,7 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
         let results = results.await?;
         results
             .iter()
-            .filter_map(|row| the_f(row.to_owned()))
+            .filter_map(the_f)
             .for_each(|entry| self.add_entry(entry).unwrap_or(()));
 
         self.set_wiki(Some("wikidatawiki".to_string()))?;
@@ -1199,8 +1663,8 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
             futures.push ( future ) ;
         }
             
-        let the_fn = |row: my::Row| {
-                let ips_site_page = my::from_row_opt::<Vec<u8>>(row).ok()?;
+        let the_fn = |row: DbRow| {
+                let ips_site_page = column::<Vec<u8>>(&row, 0).ok()?;
                 let ips_site_page = String::from_utf8_lossy(&ips_site_page).into_owned();
                 Some(PageListEntry::new(Title::new_from_full(
                     &ips_site_page,
@@ -1222,6 +1686,18 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
         Ok(())
     }
 
+    /// Keeps pages whose title (without namespace) starts with any of `prefixes`, and with none of `not_prefixes`
+    pub fn title_prefix_filter(&self, prefixes: &[String], not_prefixes: &[String]) -> Result<(), String> {
+        if (prefixes.is_empty() && not_prefixes.is_empty()) || self.is_wikidata() {
+            return Ok(());
+        }
+        self.retain_entries(&|entry: &PageListEntry| {
+            let title = entry.title().with_underscores();
+            (prefixes.is_empty() || prefixes.iter().any(|prefix| title.starts_with(prefix.as_str())))
+                && !not_prefixes.iter().any(|prefix| title.starts_with(prefix.as_str()))
+        })
+    }
+
     pub fn regexp_filter(&self, regexp: &str) -> Result<(), String> {
         let regexp_all = "^".to_string() + regexp + "$";
         let is_wikidata = self.is_wikidata();
@@ -1235,22 +1711,36 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
         Ok(())
     }
 
-    async fn search_entry(&self, api: &wikibase::mediawiki::api::Api, search: &str, page_id: u32 ) -> Result<bool,String> {
+    /// Runs the search restricted to a batch of pages (CirrusSearch `pageid:1|2|3`); returns the page IDs that match
+    async fn search_batch(api: &wikibase::mediawiki::api::Api, search: &str, page_ids: &[u32]) -> Result<Vec<u32>,String> {
+        let page_ids_string : Vec<String> = page_ids.iter().map(|page_id|page_id.to_string()).collect();
         let params = [
             (format!("action"), format!("query")),
             (format!("list"), format!("search")),
             (format!("srnamespace"), format!("*")),
-            (format!("srlimit"), format!("1")),
-            (format!("srsearch"), format!("pageid:{} {}", page_id, search))
+            (format!("srprop"), format!("")),
+            (format!("srlimit"), format!("{}", page_ids.len())),
+            (format!("srsearch"), format!("pageid:{} {}", page_ids_string.join("|"), search))
         ].iter().cloned().collect() ;
         let result = match api.get_query_api_json(&params).await {
             Ok(result) => result,
             Err(e) => return Err(format!("{:?}", e)),
         };
-        let titles = wikibase::mediawiki::api::Api::result_array_to_titles(&result);
-        Ok(!titles.is_empty())
+        Ok(Self::page_ids_from_search_result(&result))
+    }
+
+    fn page_ids_from_search_result(result: &Value) -> Vec<u32> {
+        match result["query"]["search"].as_array() {
+            Some(results) => results
+                .iter()
+                .filter_map(|r|r["pageid"].as_u64())
+                .map(|page_id|page_id as u32)
+                .collect(),
+            None => vec![],
+        }
     }
 
+    /// Keeps only pages that match a text search, run on the wiki with the result pages in batches
     pub async fn search_filter(&self, platform: &Platform, search: &str) -> Result<(), String> {
         let max_page_number : usize = 10000 ;
         if self.len()? > max_page_number {
@@ -1270,28 +1760,16 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
             .filter_map(|entry|entry.page_id)
             .collect();
         let api = platform.state().get_api_for_wiki(wiki).await?;
-        let mut futures = vec![] ;
-        page_ids.iter().for_each(|page_id| {
-            let fut = self.search_entry(&api,search,page_id.to_owned()) ;
-            futures.push(fut);
-        });
-        let results = join_all(futures).await;
-
-        let mut searches_failed = false;
-        let retain_page_ids : Vec<u32> = page_ids
-            .iter()
-            .zip(results.iter())
-            .filter_map(|(page_id,result)|match result {
-                Ok(true) => Some(page_id.to_owned()),
-                Err(_) => {
-                    searches_failed = true ;
-                    None
+        let mut retain_page_ids : HashSet<u32> = HashSet::new();
+        let batches : Vec<&[u32]> = page_ids.chunks(SEARCH_FILTER_BATCH_SIZE).collect();
+        for group in batches.chunks(SEARCH_FILTER_CONCURRENT_REQUESTS) {
+            let futures : Vec<_> = group.iter().map(|batch|Self::search_batch(&api,search,batch)).collect();
+            for result in join_all(futures).await {
+                match result {
+                    Ok(found) => retain_page_ids.extend(found),
+                    Err(_) => return Err("Filter searches have failed".to_string()),
                 }
-                _ => None
-            })
-            .collect();
-        if searches_failed {
-            return Err(format!("Filter searches have failed"));
+            }
         }
 
         self.retain_entries(&|entry: &PageListEntry|{
@@ -1312,6 +1790,38 @@ WHERE {} IN ({})",prefix,&field_name,namespace_id,table,term_in_lang_id,&field_n
 mod tests {
     use super::*;
 
+    #[test]
+    fn search_result_page_ids() {
+        let j = json!({"batchcomplete":"","query":{"searchinfo":{"totalhits":2},"search":[{"ns":0,"title":"A","pageid":12},{"ns":14,"title":"Category:B","pageid":345}]}});
+        assert_eq!(PageList::page_ids_from_search_result(&j), vec![12, 345]);
+        assert!(PageList::page_ids_from_search_result(&json!({})).is_empty());
+    }
+
+    #[test]
+    fn redirect_problems() {
+        let t = |title: &str| Title::new(title, 0);
+        let to = |title: &str| Some((t(title), String::new()));
+        let pages: HashMap<Title, Option<(Title, String)>> = vec![
+            (t("A"), to("B")),
+            (t("B"), Some((t("C"), "Section".to_string()))),
+            (t("C"), None),
+            (t("D"), to("Missing")),
+            (t("E"), to("F")),
+            (t("F"), to("E")),
+            (t("G"), to("C")),
+        ]
+        .into_iter()
+        .collect();
+        let problem = |title: &str| {
+            let target = pages.get(&t(title)).unwrap().as_ref().unwrap();
+            RedirectProblem::new_for_redirect(&t(title), target, &pages).map(|p| (p.kind, p.fix))
+        };
+        assert_eq!(problem("A"), Some((RedirectProblemKind::Double, Some((t("C"), "Section".to_string())))));
+        assert_eq!(problem("D"), Some((RedirectProblemKind::Broken, None)));
+        assert_eq!(problem("E"), Some((RedirectProblemKind::Loop, None)));
+        assert_eq!(problem("G"), None);
+    }
+
     #[test]
     fn page_list_sort() {
         assert_eq!(
@@ -1322,12 +1832,29 @@ mod tests {
             PageListSort::new_from_params(&"ns_title".to_string(), false),
             PageListSort::NsTitle(false)
         );
+        assert_eq!(
+            PageListSort::new_from_params(&"sortkey".to_string(), false),
+            PageListSort::SortKey(false)
+        );
         assert_eq!(
             PageListSort::new_from_params(&"this is not a sort parameter".to_string(), true),
             PageListSort::Default(true)
         );
     }
 
+    #[test]
+    fn compare_by_sortkey() {
+        let mut einstein = PageListEntry::new(Title::new("Albert Einstein", 0));
+        einstein.set_sortkey(Some("Einstein, Albert".to_string()));
+        let mut bohr = PageListEntry::new(Title::new("Niels Bohr", 0));
+        bohr.set_defaultsort(Some("Bohr, Niels".to_string()));
+        let curie = PageListEntry::new(Title::new("Curie", 0));
+        let sorter = PageListSort::SortKey(false);
+        assert_eq!(bohr.compare(&curie, &sorter, false), Ordering::Less);
+        assert_eq!(curie.compare(&einstein, &sorter, false), Ordering::Less);
+        assert_eq!(einstein.compare(&bohr, &PageListSort::SortKey(true), false), Ordering::Less);
+    }
+
     #[test]
     fn file_usage() {
         // 3 instead of 4 parts
@@ -1361,6 +1888,41 @@ mod tests {
         assert_eq!(fi.file_usage, vec![fu]);
     }
 
+    #[test]
+    fn page_length_statistics() {
+        let entry = |title: &str, bytes: Option<u32>| {
+            let mut ret = PageListEntry::new(Title::new(title, 0));
+            ret.page_bytes = bytes;
+            ret
+        };
+        assert_eq!(
+            PageLengthStatistics::new_from_entries(&[]),
+            PageLengthStatistics { count: 0, total_bytes: 0, median_bytes: None }
+        );
+        let entries = vec![entry("A", Some(300)), entry("B", Some(100)), entry("C", None), entry("D", Some(u32::MAX))];
+        assert_eq!(
+            PageLengthStatistics::new_from_entries(&entries),
+            PageLengthStatistics { count: 4, total_bytes: 400 + u32::MAX as u64, median_bytes: Some(300) }
+        );
+        let entries = vec![entry("A", Some(300)), entry("B", Some(100))];
+        assert_eq!(PageLengthStatistics::new_from_entries(&entries).median_bytes, Some(200));
+    }
+
+    #[test]
+    fn page_assessment() {
+        let mut pa = PageAssessment::default();
+        pa.add("Stub", "Low");
+        pa.add("Stub", "");
+        pa.add("Start", "Mid");
+        assert_eq!(pa.classes, vec!["Stub".to_string(), "Start".to_string()]);
+        assert_eq!(pa.importances, vec!["Low".to_string(), "Mid".to_string()]);
+        assert!(pa.matches(&[], &[]));
+        assert!(pa.matches(&["stub".to_string()], &[]));
+        assert!(pa.matches(&["FA".to_string(), "Start".to_string()], &["mid".to_string()]));
+        assert!(!pa.matches(&["FA".to_string()], &[]));
+        assert!(!pa.matches(&["Stub".to_string()], &["Top".to_string()]));
+    }
+
     #[test]
     fn lat_lon() {
         assert_eq!(
@@ -1383,4 +1945,71 @@ mod tests {
             })
         );
     }
+
+    fn numbered_list(from: usize, to: usize) -> PageList {
+        let ret = PageList::new_from_wiki_with_capacity("enwiki", to - from);
+        (from..to).for_each(|i| ret.add_entry(PageListEntry::new(Title::new(&format!("Page {}", i), 0))).unwrap());
+        ret
+    }
+
+    #[tokio::test]
+    async fn combine() {
+        let pl = numbered_list(0, 10);
+        pl.union(&numbered_list(5, 15), None).await.unwrap();
+        assert_eq!(pl.len().unwrap(), 15);
+        pl.intersection(&numbered_list(10, 20), None).await.unwrap();
+        assert_eq!(pl.len().unwrap(), 5);
+        pl.difference(&numbered_list(12, 13), None).await.unwrap();
+        assert_eq!(pl.len().unwrap(), 4);
+        assert!(pl.difference(&PageList::new_from_wiki("dewiki"), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn combine_merges_metadata() {
+        let entry = |page_id: Option<u32>, page_bytes: Option<u32>, q: Option<&str>| {
+            let mut ret = PageListEntry::new(Title::new("A", 0));
+            ret.page_id = page_id;
+            ret.page_bytes = page_bytes;
+            ret.set_wikidata_item(q.map(|q| q.to_string()));
+            ret
+        };
+        let list = |entry: PageListEntry| {
+            let ret = PageList::new_from_wiki("enwiki");
+            ret.add_entry(entry).unwrap();
+            ret
+        };
+        for intersect in [false, true].iter() {
+            let pl = list(entry(Some(1), None, None));
+            let other = list(entry(Some(2), Some(300), Some("Q1")));
+            if *intersect {
+                pl.intersection(&other, None).await.unwrap();
+            } else {
+                pl.union(&other, None).await.unwrap();
+            }
+            let merged = pl.entries().read().unwrap().iter().next().unwrap().clone();
+            assert_eq!(merged.page_id, Some(1));
+            assert_eq!(merged.page_bytes, Some(300));
+            assert_eq!(merged.get_wikidata_item(), Some("Q1".to_string()));
+        }
+    }
+
+    /// Combining two 500k lists needs to be linear; run with `cargo test --release -- --ignored combine_large --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn combine_large_lists() {
+        let size = 500_000;
+        let (a, b) = (numbered_list(0, size), numbered_list(size / 2, size * 3 / 2));
+        let start = std::time::Instant::now();
+        a.union(&b, None).await.unwrap();
+        println!("union: {:?}", start.elapsed());
+        assert_eq!(a.len().unwrap(), size * 3 / 2);
+        let start = std::time::Instant::now();
+        a.intersection(&b, None).await.unwrap();
+        println!("intersection: {:?}", start.elapsed());
+        assert_eq!(a.len().unwrap(), size);
+        let start = std::time::Instant::now();
+        a.difference(&numbered_list(0, size), None).await.unwrap();
+        println!("difference: {:?}", start.elapsed());
+        assert_eq!(a.len().unwrap(), size / 2);
+    }
 }
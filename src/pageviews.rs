@@ -0,0 +1,93 @@
+use crate::http_client::HttpClient;
+use chrono::{Duration, Utc};
+use futures::future::join_all;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::Value;
+use std::sync::Arc;
+
+static PAGEVIEWS_API: &str = "https://wikimedia.org/api/rest_v1/metrics/pageviews/per-article";
+static PAGEVIEWS_CONCURRENT_REQUESTS: usize = 25;
+pub static DEFAULT_PAGEVIEWS_DAYS: i64 = 30;
+pub static MAX_PAGEVIEWS_DAYS: i64 = 90;
+
+/// Fetches pageview sums for pages on a single wiki from the Wikimedia pageviews REST API
+pub struct Pageviews {
+    http: Arc<HttpClient>,
+    project: String,
+    start: String,
+    end: String,
+}
+
+impl Pageviews {
+    /// `server_url` is eg "https://en.wikipedia.org"; views are summed over the last `days` full days
+    pub fn new(http: Arc<HttpClient>, server_url: &str, days: i64) -> Self {
+        let days = days.max(1).min(MAX_PAGEVIEWS_DAYS);
+        let end = Utc::now() - Duration::days(1);
+        let start = end - Duration::days(days - 1);
+        Self {
+            http,
+            project: server_url.trim_start_matches("https://").trim_start_matches("http://").to_string(),
+            start: start.format("%Y%m%d").to_string(),
+            end: end.format("%Y%m%d").to_string(),
+        }
+    }
+
+    /// Returns the views for each full title (with underscores), in order; None if the lookup failed
+    pub async fn get_views(&self, titles: &[String]) -> Vec<Option<u64>> {
+        let mut ret = vec![];
+        for chunk in titles.chunks(PAGEVIEWS_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = chunk.iter().map(|title| self.get_views_for_title(title)).collect();
+            ret.append(&mut join_all(futures).await);
+        }
+        ret
+    }
+
+    async fn get_views_for_title(&self, title: &str) -> Option<u64> {
+        let url = format!(
+            "{}/{}/all-access/user/{}/daily/{}/{}",
+            PAGEVIEWS_API,
+            self.project,
+            utf8_percent_encode(title, NON_ALPHANUMERIC),
+            self.start,
+            self.end
+        );
+        let response = self.http.send(self.http.get(&url)).await.ok()?;
+        // No data recorded, for pages without any views in the time range
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Some(0);
+        }
+        if !response.status().is_success() {
+            return None;
+        }
+        let json: Value = response.json().await.ok()?;
+        Some(Self::sum_views(&json))
+    }
+
+    fn sum_views(json: &Value) -> u64 {
+        match json["items"].as_array() {
+            Some(items) => items.iter().filter_map(|item| item["views"].as_u64()).sum(),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum_views() {
+        let j = json!({"items":[{"article":"A","views":3},{"article":"A","views":39},{"article":"A"}]});
+        assert_eq!(Pageviews::sum_views(&j), 42);
+        assert_eq!(Pageviews::sum_views(&json!({})), 0);
+    }
+
+    #[test]
+    fn project_and_range() {
+        let http = Arc::new(HttpClient::new_from_config(&json!({})).unwrap());
+        let pv = Pageviews::new(http, "https://en.wikipedia.org", 500);
+        assert_eq!(pv.project, "en.wikipedia.org");
+        assert_eq!(pv.start.len(), 8);
+        assert!(pv.start < pv.end);
+    }
+}
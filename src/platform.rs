@@ -1,28 +1,61 @@
 use std::fmt;
 use tokio::sync::Mutex as TokioMutex;
-use futures::future::join_all;
-use crate::app_state::AppState;
+use futures::future::{join_all, AbortHandle, Abortable, Aborted};
+use crate::app_state::{AppState, MAX_RESULT_TOKEN_ENTRIES};
+use crate::category_tree::CategoryTraversal;
 use crate::datasource::*;
+use crate::datasource_api::SourceApi;
 use crate::datasource_database::{SourceDatabase, SourceDatabaseParameters};
 use crate::form_parameters::FormParameters;
+use crate::graph::GraphFormat;
+use crate::label_pattern::{LabelEntityType, LabelPattern};
+use crate::liftwing::{ArticleTopics, DEFAULT_TOPIC_THRESHOLD};
 use crate::pagelist::*;
+use crate::pageviews::{Pageviews, DEFAULT_PAGEVIEWS_DAYS};
+use crate::progress::Progress;
+use crate::property_harvest::PropertyHarvest;
+use crate::references::{References, DEFAULT_CITATION_NEEDED_TEMPLATES};
 use crate::render::*;
+use crate::request_context::RequestContext;
+use crate::safe_sql;
+use crate::sdc_filter::{SdcFilter, SdcPresence};
+use crate::statement_filter::{StatementFilter, StatementRank};
+use crate::thumbnails::{Thumbnails, DEFAULT_THUMBNAIL_WIDTH};
+use crate::time_budget::{TimeBudget, SOURCES_BUDGET_SHARE, STAGE_BUDGET_SHARE};
+use crate::title_normalization;
+use crate::watchers::Watchers;
 use crate::wdfist::*;
-use chrono::Local;
-use mysql_async::from_row;
+use crate::wikidata_search::WikidataSearch;
+use crate::wiki_db::{column, from_db_row, DbRow, WikiDbConnection};
+use chrono::{Local, Utc};
 use mysql_async as my;
 use mysql_async::Value as MyValue;
-use mysql_async::prelude::Queryable;
 use rayon::prelude::*;
 use regex::Regex;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
-use wikibase::mediawiki::api::NamespaceID;
+use wikibase::mediawiki::api::{Api, NamespaceID};
 use wikibase::mediawiki::title::Title;
 
 pub static PAGE_BATCH_SIZE: usize = 20000;
+/// Smaller, as the creator edit count is a subquery per page
+static CREATOR_BATCH_SIZE: usize = 5000;
+static MAX_DATABASE_PREFILTER_SIZE: usize = 50000;
+static DATABASE_PREFILTER_SOURCES: &[&str] = &["manual", "pagepile", "sparql", "url"];
+static MAX_PAGEVIEWS_PAGES: usize = 10000;
+static LAST_EDIT_AGE_BATCH_SIZE: usize = 5000;
+static MAX_PROPERTY_HARVEST_PAGES: usize = 10000;
+static MAX_WATCHERS_PAGES: usize = 10000;
+static MAX_REFERENCES_PAGES: usize = 5000;
+static MAX_IMAGE_URL_PAGES: usize = 10000;
+static MAX_ORES_TOPIC_PAGES: usize = 5000;
+static MAX_CREATOR_DEDUP_PAGES: usize = 250;
+static MAX_STATEMENT_FILTER_PAGES: usize = 50000;
+static MAX_SDC_FILTER_PAGES: usize = 20000;
+static MAX_DOWNLOAD_FILENAME_LENGTH: usize = 100;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ContentType {
@@ -33,6 +66,8 @@ pub enum ContentType {
     CSV,
     TSV,
     KML,
+    Dot,
+    GraphML,
 }
 
 impl ContentType {
@@ -45,6 +80,8 @@ impl ContentType {
             Self::CSV => "text/csv; charset=utf-8",
             Self::TSV => "text/tab-separated-values; charset=utf-8",
             Self::KML => "application/vnd.google-earth.kml+xml",
+            Self::Dot => "text/vnd.graphviz; charset=utf-8",
+            Self::GraphML => "application/graphml+xml",
         }
     }
 }
@@ -53,6 +90,10 @@ impl ContentType {
 pub struct MyResponse {
     pub s: String,
     pub content_type: ContentType,
+    /// Sent as a download with this file name (Content-Disposition), if set
+    pub filename: Option<String>,
+    /// Only some of the pages, as the query ran out of time (X-PetScan-Partial-Result header)
+    pub partial_result: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -76,6 +117,35 @@ impl fmt::Display for Combination {
     }
 }
 
+impl Combination {
+    /// Sources that are subtracted (on the right of an odd number of NOTs). A partial result there would remove too
+    /// little, so the combined result would be wrong, not just incomplete.
+    pub fn negated_sources(&self) -> Vec<String> {
+        let mut ret = vec![];
+        self.collect_negated_sources(false, &mut ret);
+        ret
+    }
+
+    fn collect_negated_sources(&self, negated: bool, ret: &mut Vec<String>) {
+        match self {
+            Combination::None => {}
+            Combination::Source(s) => {
+                if negated {
+                    ret.push(s.to_string());
+                }
+            }
+            Combination::Intersection((a, b)) | Combination::Union((a, b)) => {
+                a.collect_negated_sources(negated, ret);
+                b.collect_negated_sources(negated, ret);
+            }
+            Combination::Not((a, b)) => {
+                a.collect_negated_sources(negated, ret);
+                b.collect_negated_sources(!negated, ret);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CombinationSequential {
     Source(String),
@@ -84,38 +154,140 @@ pub enum CombinationSequential {
     Not,
 }
 
+/// A finished, post-processed result, for rendering again in another format
+#[derive(Debug, Clone)]
+pub struct StoredResult {
+    pub form_parameters: FormParameters,
+    pub wiki: String,
+    pub entries: Vec<PageListEntry>,
+    pub has_sitelink_counts: bool,
+    pub warnings: Vec<String>,
+    pub output_redlinks: bool,
+    pub query_time: Option<Duration>,
+    pub partial_result: bool,
+}
+
+impl StoredResult {
+    /// The stored result, restricted to the given full titles (with underscores), in the original order
+    pub fn subset(&self, full_titles: &HashSet<String>, api: &Api) -> Self {
+        let mut ret = self.clone();
+        ret.entries.retain(|entry| match entry.title().full_with_underscores(api) {
+            Some(title) => full_titles.contains(&title),
+            None => false,
+        });
+        ret
+    }
+}
+
 #[derive(Debug)]
 pub struct Platform {
     form_parameters: FormParameters,
     state: Arc<AppState>,
     result: Option<PageList>,
     pub psid: Option<u64>,
+    pub result_token: Option<String>,
     existing_labels: RwLock<HashSet<String>>,
+    /// label => items found by searching Wikidata, that might be the same as a page without item
+    possible_duplicates: RwLock<HashMap<String, Vec<String>>>,
     combination: Combination,
     output_redlinks: bool,
     query_time: Option<Duration>,
     wiki_by_source: HashMap<String, String>,
     wdfist_result: Option<Value>,
+    /// Estimated result sizes per source, for `count_only`
+    count_estimate: Option<Value>,
     warnings: RwLock<Vec<String>>,
+    /// The category tree the database source traversed, for `format=category_graph`
+    category_traversal: RwLock<Option<CategoryTraversal>>,
+    /// The wiki, and the positive input categories with the trees the database source expanded, for
+    /// `add_category_membership`
+    category_trees: RwLock<Option<(String, Vec<(String, Vec<String>)>)>>,
+    /// A source was cancelled by the time budget; the result only has the pages found until then
+    partial_result: bool,
     namespace_case_sensitivity_cache: RwLock<HashMap<(String, NamespaceID), bool>>,
+    /// What the run is currently doing, for the admin list of running queries and the progress events
+    stage: Progress,
+    time_budget: TimeBudget,
+    /// Who sent the query; blank when not run via the web server
+    request_context: RequestContext,
 }
 
 impl Platform {
     pub fn new_from_parameters(form_parameters: &FormParameters, state: Arc<AppState>) -> Self {
         Self {
             form_parameters: (*form_parameters).clone(),
-            state,
             result: None,
             psid: None,
+            result_token: None,
             existing_labels: RwLock::new(HashSet::new()),
+            possible_duplicates: RwLock::new(HashMap::new()),
             combination: Combination::None,
             output_redlinks: false,
             query_time: None,
             wiki_by_source: HashMap::new(),
             wdfist_result: None,
-            warnings: RwLock::new(vec![]),
+            count_estimate: None,
+            warnings: RwLock::new(form_parameters.deprecation_warnings()),
+            category_traversal: RwLock::new(None),
+            category_trees: RwLock::new(None),
+            partial_result: false,
             namespace_case_sensitivity_cache: RwLock::new(HashMap::new()),
+            stage: Progress::new("starting"),
+            time_budget: TimeBudget::new(state.get_query_time_budget()),
+            request_context: RequestContext::default(),
+            state,
+        }
+    }
+
+    /// Re-creates a platform with the result of an earlier run; `form_parameters` can change the output format etc.
+    pub fn new_from_stored_result(
+        stored: &StoredResult,
+        form_parameters: &FormParameters,
+        state: Arc<AppState>,
+    ) -> Result<Self, String> {
+        let mut ret = Self::new_from_parameters(form_parameters, state);
+        let result = PageList::new_from_wiki(&stored.wiki);
+        result.set_entries(stored.entries.iter().cloned().collect())?;
+        result.set_has_sitelink_counts(stored.has_sitelink_counts)?;
+        ret.result = Some(result);
+        ret.output_redlinks = stored.output_redlinks;
+        ret.query_time = stored.query_time;
+        ret.partial_result = stored.partial_result;
+        *ret.warnings.write().map_err(|e| format!("{:?}", e))? = stored.warnings.clone();
+        Ok(ret)
+    }
+
+    /// Keeps a copy of the result in the app state, so it can be downloaded in other formats without running the query again
+    pub fn store_result(&mut self) -> Result<(), String> {
+        let result = match &self.result {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+        if self.wdfist_result.is_some() || result.len()? > MAX_RESULT_TOKEN_ENTRIES {
+            return Ok(());
         }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Ok(()),
+        };
+        let stored = StoredResult {
+            form_parameters: self.form_parameters.clone(),
+            wiki,
+            entries: result
+                .entries()
+                .read()
+                .map_err(|e| format!("{:?}", e))?
+                .iter()
+                .cloned()
+                .collect(),
+            has_sitelink_counts: result.has_sitelink_counts()?,
+            warnings: self.warnings()?,
+            output_redlinks: self.output_redlinks,
+            query_time: self.query_time,
+            partial_result: self.partial_result,
+        };
+        self.result_token = self.state.store_result(stored);
+        Ok(())
     }
 
     pub fn warnings(&self) -> Result<Vec<String>, String> {
@@ -126,6 +298,22 @@ impl Platform {
             .clone())
     }
 
+    pub fn request_context(&self) -> &RequestContext {
+        &self.request_context
+    }
+
+    pub fn set_request_context(&mut self, context: RequestContext) {
+        self.request_context = context;
+    }
+
+    pub fn stage(&self) -> Progress {
+        self.stage.clone()
+    }
+
+    pub fn set_stage(&self, stage: &str) {
+        self.stage.set(stage);
+    }
+
     pub fn warn(&self, s: String) -> Result<(), String> {
         self.warnings
             .write()
@@ -134,6 +322,20 @@ impl Platform {
         Ok(())
     }
 
+    pub fn set_category_traversal(&self, traversal: CategoryTraversal) -> Result<(), String> {
+        *self.category_traversal.write().map_err(|e| format!("{:?}", e))? = Some(traversal);
+        Ok(())
+    }
+
+    pub fn category_traversal(&self) -> Result<Option<CategoryTraversal>, String> {
+        Ok(self.category_traversal.read().map_err(|e| format!("{:?}", e))?.clone())
+    }
+
+    pub fn set_category_trees(&self, wiki: &str, trees: Vec<(String, Vec<String>)>) -> Result<(), String> {
+        *self.category_trees.write().map_err(|e| format!("{:?}", e))? = Some((wiki.to_string(), trees));
+        Ok(())
+    }
+
     pub fn label_exists(&self, label: &str) -> bool {
         // TODO normalization?
         match self.existing_labels.read() {
@@ -142,6 +344,14 @@ impl Platform {
         }
     }
 
+    /// Items that might already exist for a label, for creator mode
+    pub fn possible_duplicates(&self, label: &str) -> Vec<String> {
+        match self.possible_duplicates.read() {
+            Ok(pd) => pd.get(label).cloned().unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
     pub fn combination(&self) -> Combination {
         self.combination.clone()
     }
@@ -150,6 +360,10 @@ impl Platform {
         self.output_redlinks
     }
 
+    pub fn is_partial_result(&self) -> bool {
+        self.partial_result
+    }
+
     pub fn query_time(&self) -> Option<Duration> {
         self.query_time.to_owned()
     }
@@ -194,76 +408,166 @@ impl Platform {
         ret
     }
 
+    /// Runs a query the way every query is run: logged (start, end, and in the audit log), counted in the running
+    /// threads, and listed in /admin/running, where an administrator can stop it. Returns the platform with the outcome
+    /// of the run. No queries are run in maintenance mode.
+    pub async fn run_logged(
+        form_parameters: &FormParameters,
+        context: &RequestContext,
+        progress_id: Option<String>,
+        state: Arc<AppState>,
+    ) -> (Self, Result<(), String>) {
+        if let Some(message) = state.get_maintenance_message() {
+            let platform = Platform::new_from_parameters(form_parameters, state.clone());
+            return (platform, Err(format!("PetScan is in maintenance mode. {}", message).trim().to_string()));
+        }
+        let query = form_parameters.to_string();
+        let started_query_id = match state.log_query_start(&query).await {
+            Ok(id) => id,
+            Err(e) => {
+                println!("Could not log query start from {}: {}\n{}", context.description(), e, query);
+                0
+            }
+        };
+
+        state.modify_threads_running(1);
+        let mut platform = Platform::new_from_parameters(form_parameters, state.clone());
+        platform.set_request_context(context.to_owned());
+        Platform::profile("platform initialized", None);
+        let run_start = std::time::Instant::now();
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let running_query_id =
+            state.register_running_query(&query, platform.request_context(), platform.stage(), progress_id, abort_handle);
+        let platform_result = match Abortable::new(platform.run(), abort_registration).await {
+            Ok(result) => result,
+            Err(Aborted) => Err("Query was stopped by an administrator".to_string()),
+        };
+        state.unregister_running_query(running_query_id);
+        let result_count = platform.result().as_ref().and_then(|result| result.len().ok());
+        let status = if platform_result.is_ok() { "ok" } else { "error" };
+        if let Err(e) = state
+            .log_query_audit(form_parameters, platform.request_context(), run_start.elapsed(), result_count, status)
+            .await
+        {
+            println!("Could not write query audit log: {}", e);
+        }
+        if let Err(e) = state.log_query_end(started_query_id).await {
+            println!("Could not log query {} end:{}\n{}", started_query_id, e, query);
+        }
+        state.modify_threads_running(-1);
+        Platform::profile("platform run complete", None);
+        (platform, platform_result)
+    }
+
     pub async fn run(&mut self) -> Result<(), String> {
         Platform::profile("begin run", None);
         let start_time = SystemTime::now();
         self.output_redlinks = self.has_param("show_redlinks");
 
-        let mut s_db = SourceDatabase::new(SourceDatabaseParameters::db_params(self).await);
-        let mut s_sparql = SourceSparql::new();
-        let mut s_manual = SourceManual::new();
-        let mut s_pagepile = SourcePagePile::new();
-        let mut s_search = SourceSearch::new();
-        let mut s_wikidata = SourceWikidata::new();
-        let mut s_labels = SourceLabels::new();
-        let mut s_sitelinks = SourceSitelinks::new();
-
-        let mut futures = vec![] ;
-        let mut available_sources = vec![] ;
-
-        if s_db.can_run(&self) {
-            available_sources.push(s_db.name());
-            futures.push ( s_db.run(&self) ) ;
-        }
-        if s_sparql.can_run(&self) {
-            available_sources.push(s_sparql.name());
-            futures.push ( s_sparql.run(&self) ) ;
-        }
-        if s_manual.can_run(&self) {
-            available_sources.push(s_manual.name());
-            futures.push ( s_manual.run(&self) ) ;
+        let mut sources: Vec<Box<dyn DataSource + Send + Sync>> = vec![
+            Box::new(SourceDatabase::new(SourceDatabaseParameters::db_params(self).await)),
+            Box::new(SourceApi::new()),
+            Box::new(SourceSparql::new()),
+            Box::new(SourceManual::new()),
+            Box::new(SourceUrl::new()),
+            Box::new(SourcePagePile::new()),
+            Box::new(SourceSearch::new()),
+            Box::new(SourceWikidata::new()),
+            Box::new(SourceEditTags::new()),
+            Box::new(SourceUserContributions::new()),
+            Box::new(SourceDeletedPages::new()),
+            Box::new(SourceLog::new()),
+        ];
+        sources.retain(|source| source.can_run(&self));
+        if sources.is_empty() {
+            // Only use these if there is nothing else
+            let mut fallback: Vec<Box<dyn DataSource + Send + Sync>> =
+                vec![Box::new(SourceSitelinks::new()), Box::new(SourceLabels::new())];
+            fallback.retain(|source| source.can_run(&self));
+            fallback.truncate(1);
+            sources = fallback;
         }
-        if s_pagepile.can_run(&self) {
-            available_sources.push(s_pagepile.name());
-            futures.push ( s_pagepile.run(&self) ) ;
-        }
-        if s_search.can_run(&self) {
-            available_sources.push(s_search.name());
-            futures.push ( s_search.run(&self) ) ;
+        if sources.is_empty() {
+            return Err("No possible data source found in parameters".to_string());
         }
-        if s_wikidata.can_run(&self) {
-            available_sources.push(s_wikidata.name());
-            futures.push ( s_wikidata.run(&self) ) ;
+
+        for source in sources.iter_mut() {
+            source.parse_params(&self)?;
         }
-        if futures.is_empty() && s_sitelinks.can_run(&self){
-            available_sources.push(s_sitelinks.name());
-            futures.push ( s_sitelinks.run(&self) ) ;   
+        if self.has_param("count_only") {
+            self.count_estimate = Some(self.estimate_counts(&mut sources).await);
+            self.query_time = start_time.elapsed().ok();
+            return Ok(());
         }
-        if futures.is_empty() && s_labels.can_run(&self){
-            available_sources.push(s_labels.name());
-            futures.push ( s_labels.run(&self) ) ;   
+        let available_sources: Vec<String> = sources.iter().map(|source| source.name()).collect();
+
+        // SPARQL with the items placeholder runs after the other sources
+        let sparql_uses_items = available_sources.contains(&"sparql".to_string())
+            && SourceSparql::uses_items_placeholder(&self.get_param_blank("sparql"));
+        if sparql_uses_items {
+            sources.retain(|source| source.name() != "sparql");
+            if sources.is_empty() {
+                return Err(format!("The SPARQL query uses {}%, but there is no other source for the items", SPARQL_ITEMS_PLACEHOLDER));
+            }
         }
-        if futures.is_empty() {
-            return Err("No possible data source found in parameters".to_string());
+
+        // Run the database last, restricted to a small list from another source
+        let use_database_prefilter = !sparql_uses_items && self.can_use_database_prefilter(&available_sources);
+        if use_database_prefilter {
+            sources.retain(|source| source.name() != "categories");
         }
 
+        // Start the cheap ones first
+        sources.sort_by_key(|source| source.cost());
+        let mut names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+
         Platform::profile("begin futures 1", None);
+        self.set_stage(&format!("running sources: {}", names.join(", ")));
 
-        let mut tmp_results = join_all(futures).await;
+        let platform: &Platform = self;
+        let futures: Vec<_> = sources
+            .iter_mut()
+            .zip(names.iter())
+            .map(|(source, name)| platform.time_budget.run(name, SOURCES_BUDGET_SHARE, source.run(platform)))
+            .collect();
+        let tmp_results = join_all(futures).await;
 
         let mut results: HashMap<String, PageList> = HashMap::new() ;
-        let mut names = available_sources.clone();
-        while !tmp_results.is_empty() {
-            let result = tmp_results.remove(0);
-            if names.is_empty() {
-                panic!("Platform::run names is empty");
-            }
-            let name = names.remove(0);
-            if let Ok(r) = result {
-                results.insert(name,r);
+        let mut partial_sources: Vec<String> = vec![];
+        for ((source, name), result) in sources.iter_mut().zip(names.drain(..)).zip(tmp_results) {
+            match result {
+                Ok(Ok(r)) => {
+                    results.insert(name,r);
+                }
+                Ok(Err(_)) => {}
+                Err(e) => {
+                    self.warn(format!("<span tt='warn_time_budget'></span> {}", e))?;
+                    // Continue with what the source found so far, rather than without it
+                    self.partial_result = true;
+                    if let Some(partial) = source.partial_result() {
+                        partial_sources.push(name.to_owned());
+                        results.insert(name, partial);
+                    }
+                }
             }
         }
-        drop(tmp_results);
+        drop(sources);
+
+        if use_database_prefilter {
+            let result = self
+                .time_budget
+                .run("categories", SOURCES_BUDGET_SHARE, self.run_database_prefiltered(&results))
+                .await;
+            self.add_second_round_result(&mut results, "categories", result)?;
+        }
+
+        if sparql_uses_items {
+            let result = self
+                .time_budget
+                .run("sparql", SOURCES_BUDGET_SHARE, self.run_sparql_with_items(&results))
+                .await;
+            self.add_second_round_result(&mut results, "sparql", result)?;
+        }
 
         self.wiki_by_source = results
             .iter()
@@ -275,14 +579,22 @@ impl Platform {
         Platform::profile("end futures 1", None);
 
         self.combination = self.get_combination(&available_sources);
+        if let Some(source) = self.combination.negated_sources().into_iter().find(|s| partial_sources.contains(s)) {
+            return Err(format!(
+                "Source '{}' ran out of time, and its partial result can not be subtracted from the others",
+                source
+            ));
+        }
 
         Platform::profile("before combine_results", None);
+        self.set_stage("combining results");
         let serialized_combination = self.serialize_combine_results(&self.combination)? ;
         let result = self.combine_results(&mut results, serialized_combination).await?;
         drop(results);
 
         self.result = Some(result);
         Platform::profile("after combine_results", None);
+        self.set_stage("post-processing");
         self.post_process_result(&available_sources).await?;
         Platform::profile("after post_process_result", None);
 
@@ -299,6 +611,7 @@ impl Platform {
                 None => return Err("No result set for WDfist".to_string()),
             }
             //self.result = Some(pagelist);
+            self.set_stage("WDfist");
             let mut wdfist =
                 WDfist::new(&self, &self.result).ok_or_else(|| "Cannot create WDfist".to_string())?;
             self.result = None; // Safe space
@@ -306,11 +619,132 @@ impl Platform {
         }
 
         self.query_time = start_time.elapsed().ok();
+        self.set_stage("done");
         Platform::profile("after run", None);
 
         Ok(())
     }
 
+    /// For `count_only`: the estimated number of pages per source, instead of running them
+    async fn estimate_counts(&self, sources: &mut [Box<dyn DataSource + Send + Sync>]) -> Value {
+        self.set_stage("estimating result sizes");
+        let names: Vec<String> = sources.iter().map(|source| source.name()).collect();
+        let futures: Vec<_> = sources
+            .iter_mut()
+            .zip(names.iter())
+            .map(|(source, name)| self.time_budget.run(name, SOURCES_BUDGET_SHARE, source.estimate_count(self)))
+            .collect();
+        let estimates: Vec<Value> = names
+            .iter()
+            .zip(join_all(futures).await)
+            .map(|(name, estimate)| match estimate {
+                Ok(Ok(count)) => json!({"source":name,"estimate":count}),
+                Ok(Err(e)) | Err(e) => json!({"source":name,"estimate":null,"error":e}),
+            })
+            .collect();
+        json!({"status":"OK","count_only":true,"sources":estimates})
+    }
+
+    /// Checks if the database source is only intersected with lists that might be small
+    fn can_use_database_prefilter(&self, available_sources: &[String]) -> bool {
+        if !available_sources.contains(&"categories".to_string())
+            || self.has_param("multi_wiki")
+            || self.has_param("multi_wiki_languages")
+        {
+            return false;
+        }
+        if !available_sources
+            .iter()
+            .any(|source| DATABASE_PREFILTER_SOURCES.contains(&source.as_str()))
+        {
+            return false;
+        }
+        Self::is_intersection_only(&self.get_combination(available_sources))
+    }
+
+    fn is_intersection_only(combination: &Combination) -> bool {
+        match combination {
+            Combination::Source(_) => true,
+            Combination::Intersection((a, b)) => {
+                Self::is_intersection_only(a) && Self::is_intersection_only(b)
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs SPARQL with the items placeholder filled in from all other source results
+    async fn run_sparql_with_items(&self, results: &HashMap<String, PageList>) -> Result<PageList, String> {
+        let items = PageList::new_from_wiki("wikidatawiki");
+        for pagelist in results.values() {
+            let wiki = match pagelist.wiki()? {
+                Some(wiki) => wiki,
+                None => continue,
+            };
+            let tmp = PageList::new_from_wiki(&wiki);
+            tmp.union(pagelist, None).await?;
+            tmp.convert_to_wiki("wikidatawiki", self).await?;
+            items.union(&tmp, None).await?;
+        }
+        let mut items: Vec<String> = items.group_by_namespace()?.get(&0).cloned().unwrap_or_default();
+        items.sort();
+        let mut sparql = SourceSparql::new();
+        sparql.parse_params(self)?;
+        sparql.set_items(&items)?;
+        sparql.run(self).await
+    }
+
+    /// For the sources that run after the others; one that ran out of time is left out with a warning, as in the
+    /// first round (without a partial result, as the source is not kept)
+    fn add_second_round_result(
+        &mut self,
+        results: &mut HashMap<String, PageList>,
+        name: &str,
+        result: Result<Result<PageList, String>, String>,
+    ) -> Result<(), String> {
+        match result {
+            Ok(result) => {
+                results.insert(name.to_string(), result?);
+            }
+            Err(e) => {
+                self.warn(format!("<span tt='warn_time_budget'></span> {}", e))?;
+                self.partial_result = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the database source on the smallest list result, instead of loading all pages in the category trees
+    async fn run_database_prefiltered(
+        &self,
+        results: &HashMap<String, PageList>,
+    ) -> Result<PageList, String> {
+        let mut db = SourceDatabase::new(SourceDatabaseParameters::db_params(self).await);
+        db.parse_params(self)?;
+        let wiki = db
+            .wiki()
+            .ok_or_else(|| "Platform::run_database_prefiltered: No wiki".to_string())?;
+        let smallest = results
+            .iter()
+            .filter(|(name, _)| DATABASE_PREFILTER_SOURCES.contains(&name.as_str()))
+            .filter_map(|(_, pagelist)| Some((pagelist.len().ok()?, pagelist)))
+            .min_by_key(|(len, _)| *len);
+        let (len, pagelist) = match smallest {
+            Some(x) => x,
+            None => return db.run(self).await,
+        };
+        if len > MAX_DATABASE_PREFILTER_SIZE {
+            return db.run(self).await;
+        }
+        let list_wiki = pagelist
+            .wiki()?
+            .ok_or_else(|| "Platform::run_database_prefiltered: No wiki for list".to_string())?;
+        let prefilter = PageList::new_from_wiki(&list_wiki);
+        prefilter.union(pagelist, None).await?;
+        prefilter.convert_to_wiki(&wiki, self).await?;
+        Platform::profile("run_database_prefiltered", Some(prefilter.len()?));
+        db.get_pages(&self.state, Some(&prefilter)).await
+    }
+
     pub fn profile(label: &str, num: Option<usize>) {
         if false {
             println!(
@@ -333,6 +767,8 @@ impl Platform {
         Platform::profile("before filter_wikidata", Some(result.len()?));
         self.filter_wikidata(&result).await?;
         Platform::profile("after filter_wikidata", Some(result.len()?));
+        self.process_statement_filter(&result).await?;
+        Platform::profile("after process_statement_filter", Some(result.len()?));
         if available_sources.to_vec() != vec!["sitelinks".to_string()] {
             self.process_sitelinks(&result).await?;
             Platform::profile("after process_sitelinks", None);
@@ -345,7 +781,12 @@ impl Platform {
         self.convert_to_common_wiki(&result).await?;
         Platform::profile("after convert_to_common_wiki", Some(result.len()?));
 
-        if !available_sources.contains(&"categories".to_string()) {
+        // Wikis without a replica only have what the API source found
+        let has_replica_db = match result.wiki()? {
+            Some(wiki) => self.state.has_replica_db(&wiki),
+            None => true,
+        };
+        if !available_sources.contains(&"categories".to_string()) && has_replica_db {
             self.process_missing_database_filters(&result).await?;
             Platform::profile(
                 "after process_missing_database_filters",
@@ -354,14 +795,26 @@ impl Platform {
         }
         self.process_by_wikidata_item(&result).await?;
         Platform::profile("after process_by_wikidata_item", Some(result.len()?));
+        self.process_unconnected(&result).await?;
+        Platform::profile("after process_unconnected", Some(result.len()?));
         self.process_files(&result).await?;
         Platform::profile("after process_files", Some(result.len()?));
+        self.process_file_duplicates(&result).await?;
+        Platform::profile("after process_file_duplicates", Some(result.len()?));
+        self.process_global_usage(&result).await?;
+        Platform::profile("after process_global_usage", Some(result.len()?));
         self.process_pages(&result).await?;
         Platform::profile("after process_pages", Some(result.len()?));
         self.process_namespace_conversion(&result).await?;
         Platform::profile("after process_namespace_conversion", Some(result.len()?));
         self.process_subpages(&result).await?;
         Platform::profile("after process_subpages", Some(result.len()?));
+        self.process_translations(&result).await?;
+        Platform::profile("after process_translations", Some(result.len()?));
+        self.process_redirect_problems(&result).await?;
+        Platform::profile("after process_redirect_problems", Some(result.len()?));
+        self.process_redirect_targets(&result).await?;
+        Platform::profile("after process_redirect_targets", Some(result.len()?));
         self.annotate_with_wikidata_item(result).await?;
         Platform::profile("after annotate_with_wikidata_item [2]", Some(result.len()?));
 
@@ -369,18 +822,87 @@ impl Platform {
             "wikidata_label_language",
             &self.get_param_default("interface_language", "en"),
         );
-        result.load_missing_metadata(Some(wikidata_label_language), &self).await?;
+        self.set_stage("loading metadata");
+        if has_replica_db {
+            result.load_missing_metadata(Some(wikidata_label_language), &self).await?;
+        }
         Platform::profile("after load_missing_metadata", Some(result.len()?));
         if let Some(regexp) = self.get_param("regexp_filter") { result.regexp_filter(&regexp)?; }
+        result.title_prefix_filter(&self.get_title_prefixes("title_prefix").await, &self.get_title_prefixes("title_not_prefix").await)?;
         if let Some(search) = self.get_param("search_filter") { result.search_filter(self,&search).await?; }
+        self.set_stage("annotating results");
+        self.process_pages_with_redlinks(&result).await?;
+        Platform::profile("after process_pages_with_redlinks", Some(result.len()?));
+        self.run_stage_within_budget("assessments", self.process_assessments(&result)).await?;
+        Platform::profile("after process_assessments", Some(result.len()?));
+        self.run_stage_within_budget("creator_experience", self.process_creator_experience(&result)).await?;
+        Platform::profile("after process_creator_experience", Some(result.len()?));
+        self.run_stage_within_budget("infoboxes", self.process_infoboxes(&result)).await?;
+        Platform::profile("after process_infoboxes", Some(result.len()?));
+        self.run_stage_within_budget("short_descriptions", self.process_short_descriptions(&result)).await?;
+        Platform::profile("after process_short_descriptions", Some(result.len()?));
+        self.run_stage_within_budget("sdc_filter", self.process_sdc_filter(&result)).await?;
+        Platform::profile("after process_sdc_filter", Some(result.len()?));
+        self.run_stage_within_budget("ores_topics", self.process_ores_topics(&result)).await?;
+        Platform::profile("after process_ores_topics", Some(result.len()?));
+        self.run_stage_within_budget("pageviews", self.process_pageviews(&result)).await?;
+        Platform::profile("after process_pageviews", Some(result.len()?));
+        self.run_stage_within_budget("last_edit_age", self.process_last_edit_age(&result)).await?;
+        Platform::profile("after process_last_edit_age", Some(result.len()?));
+        self.run_stage_within_budget("watchers", self.process_watchers(&result)).await?;
+        Platform::profile("after process_watchers", Some(result.len()?));
+        self.run_stage_within_budget("references", self.process_references(&result)).await?;
+        Platform::profile("after process_references", Some(result.len()?));
+        self.run_stage_within_budget("image_urls", self.process_image_urls(&result)).await?;
+        Platform::profile("after process_image_urls", Some(result.len()?));
+        self.run_stage_within_budget("property_harvest", self.process_property_harvest(&result)).await?;
+        Platform::profile("after process_property_harvest", Some(result.len()?));
         self.process_redlinks(&result).await?;
         Platform::profile("after process_redlinks", Some(result.len()?));
         self.process_creator(&result).await?;
         Platform::profile("after process_creator", Some(result.len()?));
+        self.process_duplicate_titles(&result).await?;
+        Platform::profile("after process_duplicate_titles", Some(result.len()?));
+        self.run_stage_within_budget("category_membership", self.process_category_membership(&result)).await?;
+        Platform::profile("after process_category_membership", Some(result.len()?));
 
         Ok(())
     }
 
+    /// Runs an optional post-processing stage within its slice of the time budget. If a stage that only adds
+    /// columns is cancelled, the result stays as it was before the stage, with a warning. If the stage would remove
+    /// entries, that is an error; the unfiltered result would look filtered.
+    async fn run_stage_within_budget<F>(&self, stage: &str, future: F) -> Result<(), String>
+    where
+        F: Future<Output = Result<(), String>>,
+    {
+        match self.time_budget.run(stage, STAGE_BUDGET_SHARE, future).await {
+            Ok(result) => result,
+            Err(e) if self.stage_filters(stage) => Err(format!("Filter '{}' ran out of time: {}", stage, e)),
+            Err(e) => self.warn(format!("<span tt='warn_time_budget'></span> {}", e)),
+        }
+    }
+
+    /// Would this post-processing stage remove entries, with the current parameters?
+    fn stage_filters(&self, stage: &str) -> bool {
+        let any = |params: &[&str]| params.iter().any(|param| !self.is_param_blank(param));
+        match stage {
+            "assessments" => any(&["assessment_class", "assessment_importance"]),
+            "creator_experience" => any(&["creator_registered_days", "creator_max_edits", "creator_group"]),
+            "infoboxes" => matches!(self.get_param_blank("infobox_count").as_str(), "none" | "one" | "multiple"),
+            "short_descriptions" => {
+                matches!(self.get_param_blank("shortdesc").as_str(), "with" | "without")
+                    || any(&["shortdesc_min_length", "shortdesc_max_length"])
+            }
+            "sdc_filter" | "ores_topics" => true,
+            "pageviews" => any(&["min_views", "max_views"]),
+            "last_edit_age" => any(&["not_edited_for"]),
+            "watchers" => any(&["min_watchers", "max_watchers"]),
+            "references" => any(&["min_references", "max_references", "min_citation_needed", "max_citation_needed"]),
+            _ => false,
+        }
+    }
+
     pub fn state(&self) -> Arc<AppState> {
         self.state.clone()
     }
@@ -468,14 +990,10 @@ impl Platform {
             .collect::<Vec<SQLtuple>>();
 
         let state = self.state();
-        let mut conn = state.get_wiki_db_connection(&"wikidatawiki".to_string()).await?;
+        let mut conn = state.get_wiki_db(&"wikidatawiki".to_string()).await?;
 
         for sql in batches {
-            let rows = conn.exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-                .map_err(|e|format!("{:?}",e))?
-                .map_and_drop(from_row::<Vec<u8>>)
-                .await
-                .map_err(|e|format!("{:?}",e))?;
+            let rows = conn.query_as::<Vec<u8>>(sql).await?;
 
             let mut el = match self.existing_labels.write() {
                 Ok(el) => el,
@@ -486,7 +1004,42 @@ impl Platform {
                 el.insert(label.to_string());
             }
         }
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        conn.close().await?;
+        self.process_creator_duplicates(result).await
+    }
+
+    /// Searches Wikidata for items matching titles without an exact label, so creator mode does not blindly create duplicates
+    async fn process_creator_duplicates(&self, result: &PageList) -> Result<(), String> {
+        let language = match result.wiki()?.and_then(|wiki| WikidataSearch::language_for_wiki(&wiki)) {
+            Some(language) => language,
+            None => return Ok(()),
+        };
+        let mut labels: Vec<String> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .map(|entry| entry.title().pretty().to_string())
+            .filter(|label| !label.contains('(') && !self.label_exists(label))
+            .collect();
+        labels.sort();
+        labels.dedup();
+        if labels.len() > MAX_CREATOR_DEDUP_PAGES {
+            self.warn(format!("<span tt='warn_creator_dedup_limit'></span> {}", MAX_CREATOR_DEDUP_PAGES))?;
+            labels.truncate(MAX_CREATOR_DEDUP_PAGES);
+        }
+        let api_url = self.state.get_api_url_for_wiki("wikidatawiki")?;
+        let search = WikidataSearch::new(self.state.http_client(), &api_url, &language);
+        let found = search.find_items(&labels).await;
+        let mut pd = self.possible_duplicates.write().map_err(|e| format!("{:?}", e))?;
+        labels
+            .into_iter()
+            .zip(found)
+            .filter_map(|(label, items)| Some((label, items?)))
+            .filter(|(_, items)| !items.is_empty())
+            .for_each(|(label, items)| {
+                pd.insert(label, items);
+            });
         Ok(())
     }
 
@@ -527,14 +1080,14 @@ impl Platform {
 
         let mut conn = self
             .state
-            .get_wiki_db_connection(&wiki)
+            .get_wiki_db(&wiki)
             .await
             .map_err(|e| format!("{:?}", e))?;
 
         for sql in batches {
-            self.process_redlinks_batch(&mut conn,sql,&mut redlink_counter).await?;
+            self.process_redlinks_batch(conn.as_mut(),sql,&mut redlink_counter).await?;
         }
-        conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+        conn.close().await?;
 
         let min_redlinks = self
             .get_param_default("min_redlink_count", "1")
@@ -555,99 +1108,1235 @@ impl Platform {
         Ok(())
     }
 
-    async fn process_redlinks_batch(&self,conn:&mut mysql_async::Conn,sql:SQLtuple,redlink_counter: &mut HashMap<Title, LinkCount>) -> Result<(), String> {
-        let rows = conn.exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-            .map_err(|e|format!("{:?}",e))?
-            .map_and_drop(from_row::<(Vec<u8>,i64,usize)>)
-            .await
-            .map_err(|e|format!("{:?}",e))?;
-
-        for (page_title,namespace_id,_count) in rows {
-            let page_title = String::from_utf8_lossy(&page_title).to_string() ;
-            let title = Title::new(&page_title, namespace_id);
-            *redlink_counter.entry(title).or_insert_with(||0) += 1 ;
-        }
-        Ok(())
-    }
-
-    async fn process_namespace_conversion(&self, result: &PageList) -> Result<(), String> {
-        let namespace_conversion = self.get_param_default("namespace_conversion", "keep");
-        let add = match namespace_conversion.as_str() {
-            "topic" => 0,
-            "talk" => 1,
-            _ => return Ok(())
-        } ;
-        // Need tmp to avoid permanent double-lock on entries
-        let tmp = result
-        .entries()
-        .read()
-        .map_err(|e| format!("{:?}", e))?
-        .par_iter()
-        .map(|entry| {
-            let mut nsid = entry.title().namespace_id() ;
-            nsid = nsid - (nsid&1) + add; // Change "talk" bit
-            let t = entry.title().pretty();
-            let new_title = Title::new(t, nsid);
-            PageListEntry::new(new_title)
-        })
-        .collect();
-        *(result.entries().write().map_err(|e| format!("{:?}", e))?) = tmp ;
-        Ok(())
-    }
-
-    async fn process_subpages(&self, result: &PageList) -> Result<(), String> {
-        let add_subpages = self.has_param("add_subpages");
-        let subpage_filter = self.get_param_default("subpage_filter", "either");
-        if !add_subpages && subpage_filter != "subpages" && subpage_filter != "no_subpages" {
+    /// Keeps only pages that link to at least `min_redlinks_per_page` (default 1) nonexistent pages ("wanted links")
+    async fn process_pages_with_redlinks(&self, result: &PageList) -> Result<(), String> {
+        if result.is_empty()? || !self.has_param("pages_with_redlinks") || result.is_wikidata() {
             return Ok(());
         }
+        let ns0_only = self.has_param("article_redlinks_only");
+        let min_redlinks = self
+            .get_param_default("min_redlinks_per_page", "1")
+            .parse::<LinkCount>()
+            .unwrap_or(1);
 
-        if add_subpages {
-            let title_ns: Vec<(String, NamespaceID)> = result
-                .entries()
-                .read()
-                .map_err(|e| format!("{:?}", e))?
-                .par_iter()
-                .map(|entry| {
-                    (
-                        entry.title().with_underscores(),
-                        entry.title().namespace_id(),
-                    )
-                })
-                .collect();
-
-            let wiki = match result.wiki()? {
-                Some(wiki) => wiki.to_owned(),
-                None => return Err("Platform::process_redlinks: no wiki set in result".to_string()),
-            };
-            let mut conn = self.state.get_wiki_db_connection(&wiki).await?;
-
-            for (title, namespace_id) in title_ns {
-                let sql: SQLtuple = (
-                    "SELECT page_title,page_namespace FROM page WHERE page_namespace=? AND page_title LIKE ?"
-                        .to_string(),
-                    vec![MyValue::Int(namespace_id), MyValue::Bytes(format!("{}/%", &title).into())],
-                );
-
-                let rows = conn.exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-                    .map_err(|e|format!("{:?}",e))?
-                    .map_and_drop(from_row::<(Vec<u8>,i64)>)
-                    .await
-                    .map_err(|e|format!("{:?}",e))?;
+        let batches: Vec<SQLtuple> = result
+            .to_sql_batches(PAGE_BATCH_SIZE)?
+            .par_iter_mut()
+            .map(|mut sql_batch| {
+                let mut sql = "SELECT p0.page_title,p0.page_namespace,COUNT(DISTINCT pl0.pl_namespace,pl0.pl_title) AS cnt FROM page p0 INNER JOIN pagelinks pl0 ON pl0.pl_from=p0.page_id LEFT JOIN page p1 ON p1.page_namespace=pl0.pl_namespace AND p1.page_title=pl0.pl_title WHERE p1.page_id IS NULL".to_string() ;
+                if ns0_only {sql += " AND pl0.pl_namespace=0" ;}
+                else {sql += " AND pl0.pl_namespace>=0" ;}
+                sql += " AND p0.page_id IN (SELECT page_id FROM page WHERE " ;
+                sql += &sql_batch.0 ;
+                sql += ") GROUP BY p0.page_id HAVING cnt>=" ;
+                sql += &min_redlinks.to_string() ;
+                sql_batch.0 = sql ;
+                sql_batch.to_owned()
+            })
+            .collect::<Vec<SQLtuple>>();
 
-                for (page_title,page_namespace) in rows {
-                    let page_title = String::from_utf8_lossy(&page_title);
-                    result.add_entry(PageListEntry::new(Title::new(&page_title,page_namespace))).unwrap_or(());
-                }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki.to_owned(),
+            None => return Err("Platform::process_pages_with_redlinks: no wiki set in result".to_string()),
+        };
+        let mut redlinks_in_page: HashMap<Title, LinkCount> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for sql in batches {
+            let rows = conn.query_as::<(Vec<u8>,i64,LinkCount)>(sql).await?;
+            for (page_title,namespace_id,count) in rows {
+                let page_title = String::from_utf8_lossy(&page_title).to_string() ;
+                redlinks_in_page.insert(Title::new(&page_title, namespace_id), count);
             }
-            conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
-            // TODO if new pages were added, they should get some of the post_process_result treatment as well
         }
+        conn.close().await?;
 
-        if subpage_filter != "subpages" && subpage_filter != "no_subpages" {
+        let entries = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .filter_map(|entry| {
+                let count = redlinks_in_page.get(entry.title())?;
+                let mut entry = entry.to_owned();
+                entry.redlinks_in_page = Some(*count);
+                Some(entry)
+            })
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// Adds WikiProject assessments, and applies the `assessment_class`/`assessment_importance` filters.
+    /// Uses the page_assessments table where it exists, or assessment categories on the talk page otherwise.
+    async fn process_assessments(&self, result: &PageList) -> Result<(), String> {
+        let split = |param: &str| -> Vec<String> {
+            self.get_param_as_vec(param, "\n")
+                .iter()
+                .flat_map(|s| s.split(','))
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        let classes = split("assessment_class");
+        let importances = split("assessment_importance");
+        let project = self.get_param("assessment_project").map(|p| p.trim().replace('_', " "));
+        if classes.is_empty() && importances.is_empty() && !self.has_param("add_assessment") {
             return Ok(());
         }
-
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_assessments: no wiki set in result".to_string()),
+        };
+
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        let has_table = !conn.query_as::<Vec<u8>>(("SHOW TABLES LIKE 'page_assessments'".to_string(), vec![])).await?
+            .is_empty();
+
+        let mut assessments: HashMap<Title, PageAssessment> = HashMap::new();
+        if has_table {
+            for batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+                let mut sql: SQLtuple = ("SELECT page_title,page_namespace,pa_class,pa_importance FROM page,page_assessments".to_string(), vec![]);
+                if let Some(project) = &project {
+                    sql.0 += ",page_assessments_projects WHERE pap_project_id=pa_project_id AND pap_project_title=? AND";
+                    sql.1.push(MyValue::Bytes(project.to_owned().into()));
+                } else {
+                    sql.0 += " WHERE";
+                }
+                sql.0 += " pa_page_id=page_id AND ";
+                Platform::append_sql(&mut sql, batch);
+                let rows = conn.query_as::<(Vec<u8>,NamespaceID,Option<Vec<u8>>,Option<Vec<u8>>)>(sql).await?;
+                for (page_title, namespace_id, class, importance) in rows {
+                    let page_title = String::from_utf8_lossy(&page_title);
+                    let class = String::from_utf8_lossy(&class.unwrap_or_default()).to_string();
+                    let importance = String::from_utf8_lossy(&importance.unwrap_or_default()).to_string();
+                    assessments
+                        .entry(Title::new(&page_title, namespace_id))
+                        .or_insert_with(PageAssessment::default)
+                        .add(&class, &importance);
+                }
+            }
+        } else {
+            lazy_static! {
+                static ref RE_CLASS: Regex = Regex::new(r#"^([A-Za-z]+)-[Cc]lass_"#).expect("Platform::process_assessments:RE_CLASS");
+                static ref RE_IMPORTANCE: Regex = Regex::new(r#"^([A-Za-z]+)-importance_"#).expect("Platform::process_assessments:RE_IMPORTANCE");
+            }
+            for (namespace_id, titles) in result.group_by_namespace()? {
+                if namespace_id < 0 || namespace_id % 2 == 1 {
+                    continue;
+                }
+                for chunk in titles.chunks(PAGE_BATCH_SIZE) {
+                    let mut sql: SQLtuple = (format!("SELECT page_title,cl_to FROM page,categorylinks WHERE cl_from=page_id AND page_namespace={} AND page_title IN (", namespace_id + 1),vec![]);
+                    Platform::append_sql(&mut sql, Platform::prep_quote(chunk));
+                    sql.0 += ") AND (cl_to LIKE '%-Class\\_%' OR cl_to LIKE '%-class\\_%' OR cl_to LIKE '%-importance\\_%')";
+                    let rows = conn.query_as::<(Vec<u8>,Vec<u8>)>(sql).await?;
+                    for (page_title, category) in rows {
+                        let page_title = String::from_utf8_lossy(&page_title);
+                        let category = String::from_utf8_lossy(&category);
+                        if let Some(project) = &project {
+                            if !category.contains(project.replace(' ', "_").as_str()) {
+                                continue;
+                            }
+                        }
+                        let assessment = assessments
+                            .entry(Title::new(&page_title, namespace_id))
+                            .or_insert_with(PageAssessment::default);
+                        if let Some(caps) = RE_CLASS.captures(&category) {
+                            assessment.add(&caps[1], "");
+                        }
+                        if let Some(caps) = RE_IMPORTANCE.captures(&category) {
+                            assessment.add("", &caps[1]);
+                        }
+                    }
+                }
+            }
+        }
+        conn.close().await?;
+
+        let is_filter = !classes.is_empty() || !importances.is_empty();
+        let entries = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .filter_map(|entry| {
+                let assessment = assessments.get(entry.title());
+                if is_filter && !assessment.map_or(false, |a| a.matches(&classes, &importances)) {
+                    return None;
+                }
+                let mut entry = entry.to_owned();
+                entry.set_assessment(assessment.cloned());
+                Some(entry)
+            })
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// Keeps only pages whose creator (the user of the first revision) was inexperienced at creation time:
+    /// registered less than `creator_registered_days` days before, with fewer than `creator_max_edits` edits,
+    /// and/or in the user group `creator_group`. Pages created by IPs never match.
+    async fn process_creator_experience(&self, result: &PageList) -> Result<(), String> {
+        let registered_days = self.get_param("creator_registered_days").and_then(|s| s.trim().parse::<u32>().ok());
+        let max_edits = self.get_param("creator_max_edits").and_then(|s| s.trim().parse::<u32>().ok());
+        let group = self.get_param("creator_group").map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        let conditions = match Self::sql_creator_experience(registered_days, max_edits, &group) {
+            Some(conditions) => conditions,
+            None => return Ok(()),
+        };
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_creator_experience: no wiki set in result".to_string()),
+        };
+
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        let mut matching: HashSet<Title> = HashSet::new();
+        for batch in result.to_sql_batches(CREATOR_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace FROM page,revision r,actor_revision,user WHERE r.rev_page=page_id AND r.rev_parent_id=0 AND r.rev_actor=actor_id AND actor_user=user_id AND ".to_string(), vec![]);
+            Platform::append_sql(&mut sql, batch);
+            Platform::append_sql(&mut sql, conditions.clone());
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID)>(sql).await?;
+            for (page_title, namespace_id) in rows {
+                matching.insert(Title::new(&String::from_utf8_lossy(&page_title), namespace_id));
+            }
+        }
+        conn.close().await?;
+        result.retain_entries(&|entry| matching.contains(entry.title()))
+    }
+
+    /// Classifies pages by which of the `infobox_templates` they transclude; `infobox_count` ("none", "one", "multiple")
+    /// keeps only pages with that many of them
+    async fn process_infoboxes(&self, result: &PageList) -> Result<(), String> {
+        let templates: Vec<String> = self
+            .get_param_as_vec("infobox_templates", "\n")
+            .iter()
+            .map(|t| Self::template_name(t))
+            .filter(|t| !t.is_empty())
+            .collect();
+        if templates.is_empty() || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_infoboxes: no wiki set in result".to_string()),
+        };
+
+        let mut infoboxes: HashMap<Title, Vec<String>> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace,lt_title FROM page,templatelinks,linktarget WHERE tl_from=page_id AND tl_target_id=lt_id AND lt_namespace=10 AND lt_title IN (".to_string(), vec![]);
+            Platform::append_sql(&mut sql, Platform::prep_quote(&templates));
+            sql.0 += ") AND ";
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>)>(sql).await?;
+            for (page_title, namespace_id, template) in rows {
+                infoboxes
+                    .entry(Title::new(&String::from_utf8_lossy(&page_title), namespace_id))
+                    .or_insert_with(Vec::new)
+                    .push(String::from_utf8_lossy(&template).replace('_', " "));
+            }
+        }
+        conn.close().await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        entries.iter_mut().for_each(|entry| {
+            let mut found = infoboxes.get(entry.title()).cloned().unwrap_or_default();
+            found.sort();
+            found.dedup();
+            entry.set_infoboxes(Some(found));
+        });
+        let count = self.get_param_blank("infobox_count");
+        entries.retain(|entry| {
+            let found = entry.get_infoboxes().map(|found| found.len()).unwrap_or(0);
+            match count.as_str() {
+                "none" => found == 0,
+                "one" => found == 1,
+                "multiple" => found > 1,
+                _ => true,
+            }
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// With `duplicate_titles`, flags result pages whose titles collide with other result pages across namespaces, or
+    /// differ from them only by case or diacritics (eg "Café" and "Category:Cafe")
+    async fn process_duplicate_titles(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_param("duplicate_titles") || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_duplicate_titles: no wiki set in result".to_string()),
+        };
+        let api = self.state.get_api_for_wiki(wiki).await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let mut groups: HashMap<String, Vec<Title>> = HashMap::new();
+        entries.iter().for_each(|entry| {
+            groups
+                .entry(title_normalization::collision_key(entry.title().pretty()))
+                .or_insert_with(Vec::new)
+                .push(entry.title().to_owned());
+        });
+        entries.iter_mut().for_each(|entry| {
+            let key = title_normalization::collision_key(entry.title().pretty());
+            let mut duplicates: Vec<String> = groups
+                .get(&key)
+                .map(|group| group.iter().filter(|t| *t != entry.title()).cloned().collect::<Vec<Title>>())
+                .unwrap_or_default()
+                .iter()
+                .map(|t| t.full_pretty(&api).unwrap_or_else(|| t.pretty().to_string()))
+                .collect();
+            duplicates.sort();
+            entry.set_duplicate_titles(Some(duplicates));
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// With `add_category_membership`, lists for each result page which of the input categories it is in, directly or
+    /// via which subcategories of their trees
+    async fn process_category_membership(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_param("add_category_membership") || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_category_membership: no wiki set in result".to_string()),
+        };
+        // The trees the database source expanded for the query; none if it did not run on the result wiki
+        let trees: Vec<(String, HashSet<String>)> = match self.category_trees.write().map_err(|e| format!("{:?}", e))?.take() {
+            Some((trees_wiki, trees)) if trees_wiki == wiki => trees
+                .into_iter()
+                .map(|(root, categories)| (root, categories.into_iter().collect()))
+                .collect(),
+            _ => vec![],
+        };
+        if trees.is_empty() {
+            return Ok(());
+        }
+
+        // Pages have few categories, so all of them are loaded rather than checking against the (large) trees
+        let mut page_categories: HashMap<Title, HashSet<String>> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for mut batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            batch.0 = "SELECT page_title,page_namespace,cl_to FROM page,categorylinks WHERE cl_from=page_id AND ".to_string() + &batch.0;
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>)>(batch).await?;
+            for (page_title, namespace_id, category) in rows {
+                page_categories
+                    .entry(Title::new(&String::from_utf8_lossy(&page_title), namespace_id))
+                    .or_insert_with(HashSet::new)
+                    .insert(String::from_utf8_lossy(&category).to_string());
+            }
+        }
+        conn.close().await?;
+
+        let no_categories = HashSet::new();
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        entries.iter_mut().for_each(|entry| {
+            let categories = page_categories.get(entry.title()).unwrap_or(&no_categories);
+            entry.set_category_membership(Some(Self::category_membership(&trees, categories)));
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// The input categories (`trees`) a page with `categories` is in: "Root" if directly, "Root (via Sub1, Sub2)" otherwise
+    fn category_membership(trees: &[(String, HashSet<String>)], categories: &HashSet<String>) -> Vec<String> {
+        trees
+            .iter()
+            .filter_map(|(root, tree)| {
+                if categories.contains(root) {
+                    return Some(Title::underscores_to_spaces(root));
+                }
+                let mut via: Vec<String> = categories
+                    .iter()
+                    .filter(|category| tree.contains(*category))
+                    .map(|category| Title::underscores_to_spaces(category))
+                    .collect();
+                if via.is_empty() {
+                    return None;
+                }
+                via.sort();
+                Some(format!("{} (via {})", Title::underscores_to_spaces(root), via.join(", ")))
+            })
+            .collect()
+    }
+
+    /// Short descriptions are read from page_props ("wikibase-shortdesc", as set by {{Short description}} on enwiki);
+    /// with `shortdesc_wikidata`, pages without one fall back to the Wikidata description in the wiki language.
+    /// Filters on presence (`shortdesc` = with/without) and length in characters.
+    async fn process_short_descriptions(&self, result: &PageList) -> Result<(), String> {
+        let presence = self.get_param_blank("shortdesc");
+        let min_length = self.usize_option_from_param("shortdesc_min_length");
+        let max_length = self.usize_option_from_param("shortdesc_max_length");
+        if !self.has_param("add_shortdesc")
+            && presence != "with"
+            && presence != "without"
+            && min_length.is_none()
+            && max_length.is_none()
+        {
+            return Ok(());
+        }
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_short_descriptions: no wiki set in result".to_string()),
+        };
+
+        let mut descriptions: HashMap<Title, String> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace,pp_value FROM page,page_props WHERE pp_page=page_id AND pp_propname='wikibase-shortdesc' AND ".to_string(), vec![]);
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>)>(sql).await?;
+            for (page_title, namespace_id, pp_value) in rows {
+                descriptions.insert(
+                    Title::new(&String::from_utf8_lossy(&page_title), namespace_id),
+                    String::from_utf8_lossy(&pp_value).to_string(),
+                );
+            }
+        }
+        conn.close().await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        if self.has_param("shortdesc_wikidata") {
+            let api = self.state.get_api_for_wiki(wiki).await?;
+            let language = api.get_site_info_string("general", "lang")?.to_string();
+            let items: Vec<String> = entries
+                .iter()
+                .filter(|entry| !descriptions.contains_key(entry.title()))
+                .filter_map(|entry| entry.get_wikidata_item())
+                .collect();
+            let wikidata_descriptions = self.get_wikidata_descriptions(&items, &language).await?;
+            entries.iter().for_each(|entry| {
+                if descriptions.contains_key(entry.title()) {
+                    return;
+                }
+                if let Some(description) = entry.get_wikidata_item().and_then(|q| wikidata_descriptions.get(&q)) {
+                    descriptions.insert(entry.title().to_owned(), description.to_owned());
+                }
+            });
+        }
+        entries
+            .iter_mut()
+            .for_each(|entry| entry.set_short_description(descriptions.get(entry.title()).cloned()));
+        entries.retain(|entry| {
+            Self::short_description_matches(&entry.get_short_description(), &presence, min_length, max_length)
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Item => description, in the given language
+    async fn get_wikidata_descriptions(&self, items: &[String], language: &str) -> Result<HashMap<String, String>, String> {
+        let mut ret = HashMap::new();
+        if items.is_empty() {
+            return Ok(ret);
+        }
+        let mut conn = self.state.get_wiki_db(&"wikidatawiki".to_string()).await?;
+        for chunk in items.chunks(PAGE_BATCH_SIZE) {
+            // Numeric IDs only, so they are "database safe"
+            let item_ids: Vec<String> = chunk
+                .iter()
+                .filter_map(|q| q[1..].parse::<u64>().ok())
+                .map(|id| id.to_string())
+                .collect();
+            if item_ids.is_empty() {
+                continue;
+            }
+            let sql = format!("SELECT concat('Q',wbit_item_id) AS item,wbx_text FROM wbt_item_terms
+INNER JOIN wbt_term_in_lang ON wbit_term_in_lang_id = wbtl_id
+INNER JOIN wbt_type ON wbtl_type_id = wby_id AND wby_name='description'
+INNER JOIN wbt_text_in_lang ON wbtl_text_in_lang_id = wbxl_id
+INNER JOIN wbt_text ON wbxl_text_id = wbx_id AND wbxl_language=?
+WHERE wbit_item_id IN ({})",item_ids.join(","));
+            let rows = conn.query_as::<(Vec<u8>,Vec<u8>)>((sql, vec![MyValue::Bytes(language.to_owned().into())])).await?;
+            for (item, text) in rows {
+                ret.insert(String::from_utf8_lossy(&item).to_string(), String::from_utf8_lossy(&text).to_string());
+            }
+        }
+        conn.close().await?;
+        Ok(ret)
+    }
+
+    fn short_description_matches(description: &Option<String>, presence: &str, min_length: Option<usize>, max_length: Option<usize>) -> bool {
+        match description {
+            Some(description) => {
+                let length = description.chars().count();
+                presence != "without"
+                    && min_length.map_or(true, |min| length >= min)
+                    && max_length.map_or(true, |max| length <= max)
+            }
+            None => presence != "with" && min_length.is_none() && max_length.is_none(),
+        }
+    }
+
+    /// "Template:Infobox person" => "Infobox_person"
+    fn template_name(s: &str) -> String {
+        let s = s.trim().replace(' ', "_");
+        let s = match s.find(':') {
+            Some(pos) if s[..pos].eq_ignore_ascii_case("template") => s[pos + 1..].to_string(),
+            _ => s,
+        };
+        let mut chars = s.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+            None => String::new(),
+        }
+    }
+
+    /// Conditions on the creation revision `r` and its `user`; None if there are none.
+    /// The earlier edits are not counted, to keep the index scan for prolific users short: there are fewer than
+    /// `max_edits` if there is no edit at that offset.
+    fn sql_creator_experience(registered_days: Option<u32>, max_edits: Option<u32>, group: &Option<String>) -> Option<SQLtuple> {
+        let mut sql = Platform::sql_tuple();
+        if let Some(days) = registered_days {
+            sql.0 += &format!(" AND user_registration>=DATE_FORMAT(DATE_SUB(STR_TO_DATE(r.rev_timestamp,'%Y%m%d%H%i%s'),INTERVAL {} DAY),'%Y%m%d%H%i%s')", days);
+        }
+        match max_edits {
+            Some(0) => sql.0 += " AND 1=0",
+            Some(edits) => sql.0 += &format!(" AND (SELECT ru.rev_id FROM revision_userindex ru WHERE ru.rev_actor=r.rev_actor AND ru.rev_timestamp<r.rev_timestamp ORDER BY ru.rev_timestamp LIMIT 1 OFFSET {}) IS NULL", edits - 1),
+            None => {}
+        }
+        if let Some(group) = group {
+            sql.0 += " AND EXISTS (SELECT * FROM user_groups WHERE ug_user=user_id AND ug_group=?)";
+            sql.1.push(MyValue::Bytes(group.to_owned().into()));
+        }
+        if sql.0.is_empty() {
+            None
+        } else {
+            Some(sql)
+        }
+    }
+
+    /// Keeps only articles with a predicted topic in `ores_topic` (eg "STEM.Biology" or "STEM.*"),
+    /// with at least `ores_topic_threshold` probability
+    async fn process_ores_topics(&self, result: &PageList) -> Result<(), String> {
+        let wanted: Vec<String> = self
+            .get_param_as_vec("ores_topic", "\n")
+            .iter()
+            .flat_map(|s| s.split(','))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if wanted.is_empty() || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        if result.len()? > MAX_ORES_TOPIC_PAGES {
+            return Err(format!(
+                "Too many pages ({}) for the topic filter, maximum is {}",
+                result.len()?,
+                MAX_ORES_TOPIC_PAGES
+            ));
+        }
+        let threshold = self
+            .get_param("ores_topic_threshold")
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(DEFAULT_TOPIC_THRESHOLD);
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_ores_topics: no wiki set in result".to_string()),
+        };
+
+        // Predictions are per revision
+        let mut latest: Vec<(Title, u64)> = vec![];
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for mut sql in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            sql.0 = "SELECT page_title,page_namespace,page_latest FROM page WHERE ".to_string() + &sql.0;
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,u64)>(sql).await?;
+            for (page_title, namespace_id, page_latest) in rows {
+                latest.push((Title::new(&String::from_utf8_lossy(&page_title), namespace_id), page_latest));
+            }
+        }
+        conn.close().await?;
+
+        let rev_ids: Vec<u64> = latest.iter().map(|(_, rev_id)| *rev_id).collect();
+        let topics = ArticleTopics::new(self.state.http_client(), self.state.endpoints().liftwing(), &wiki).get_topics(&rev_ids).await;
+        if topics.iter().any(|t| t.is_none()) {
+            self.warn("<span tt='warn_ores_topic'></span>".to_string())?;
+        }
+        let keep: HashSet<Title> = latest
+            .into_iter()
+            .zip(topics)
+            .filter_map(|((title, _), topics)| match topics {
+                Some(topics) if ArticleTopics::matches(&topics, &wanted, threshold) => Some(title),
+                _ => None,
+            })
+            .collect();
+        result.retain_entries(&|entry: &PageListEntry| keep.contains(entry.title()))
+    }
+
+    /// Adds pageviews for the last `pageviews_days` days, and applies the `min_views`/`max_views` filters
+    async fn process_pageviews(&self, result: &PageList) -> Result<(), String> {
+        let min_views = self.get_param("min_views").and_then(|s| s.parse::<u64>().ok());
+        let max_views = self.get_param("max_views").and_then(|s| s.parse::<u64>().ok());
+        if !self.has_param("add_pageviews")
+            && self.get_param_blank("sortby") != "views"
+            && min_views.is_none()
+            && max_views.is_none()
+        {
+            return Ok(());
+        }
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        if result.len()? > MAX_PAGEVIEWS_PAGES {
+            if min_views.is_some() || max_views.is_some() {
+                return Err(format!(
+                    "Too many pages ({}) for the pageview filters, maximum is {}",
+                    result.len()?,
+                    MAX_PAGEVIEWS_PAGES
+                ));
+            }
+            self.warn(format!("<span tt='warn_pageviews_limit'></span> {}", MAX_PAGEVIEWS_PAGES))?;
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_pageviews: no wiki set in result".to_string()),
+        };
+        let days = self
+            .get_param("pageviews_days")
+            .and_then(|s| s.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_PAGEVIEWS_DAYS);
+        let pageviews = Pageviews::new(self.state.http_client(), &self.state.get_server_url_for_wiki(&wiki)?, days);
+        let api = self.state.get_api_for_wiki(wiki).await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let titles: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.title().full_with_underscores(&api).unwrap_or_default())
+            .collect();
+        let views = pageviews.get_views(&titles).await;
+        entries
+            .iter_mut()
+            .zip(views)
+            .for_each(|(entry, views)| entry.page_views = views);
+        if entries.iter().any(|entry| entry.page_views.is_none()) {
+            self.warn("<span tt='warn_pageviews'></span>".to_string())?;
+        }
+        entries.retain(|entry| match entry.page_views {
+            Some(views) => {
+                min_views.map_or(true, |min| views >= min) && max_views.map_or(true, |max| views <= max)
+            }
+            None => min_views.is_none() && max_views.is_none(),
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Adds the days since the latest edit, and keeps only pages not edited for `not_edited_for` (eg "2y", "18m", "90d")
+    async fn process_last_edit_age(&self, result: &PageList) -> Result<(), String> {
+        let not_edited_for = match self.get_param("not_edited_for") {
+            Some(s) if !s.trim().is_empty() => match Self::parse_age_days(&s) {
+                Some(days) => Some(days),
+                None => return Err(format!("Bad not_edited_for value '{}'", s)),
+            },
+            _ => None,
+        };
+        if !self.has_param("add_last_edit_age")
+            && self.get_param_blank("sortby") != "last_edit_age"
+            && not_edited_for.is_none()
+        {
+            return Ok(());
+        }
+        if result.is_empty()? {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_last_edit_age: no wiki set in result".to_string()),
+        };
+
+        let now = chrono::Utc::now().naive_utc();
+        let mut last_edit: HashMap<Title, (String, u64)> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for batch in result.to_sql_batches(LAST_EDIT_AGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace,rev_timestamp FROM page,revision WHERE rev_id=page_latest AND ".to_string(), vec![]);
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>)>(sql).await?;
+            for (page_title, namespace_id, timestamp) in rows {
+                let timestamp = String::from_utf8_lossy(&timestamp).to_string();
+                let edited = match chrono::NaiveDateTime::parse_from_str(&timestamp, "%Y%m%d%H%M%S") {
+                    Ok(edited) => edited,
+                    Err(_) => continue,
+                };
+                let days = now.signed_duration_since(edited).num_days().max(0) as u64;
+                last_edit.insert(Title::new(&String::from_utf8_lossy(&page_title), namespace_id), (timestamp, days));
+            }
+        }
+        conn.close().await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        entries.iter_mut().for_each(|entry| {
+            if let Some((timestamp, days)) = last_edit.get(entry.title()) {
+                if entry.get_page_timestamp().is_none() {
+                    entry.set_page_timestamp(Some(timestamp.to_owned()));
+                }
+                entry.last_edit_age_days = Some(*days);
+            }
+        });
+        if let Some(min_days) = not_edited_for {
+            entries.retain(|entry| entry.last_edit_age_days.map_or(false, |days| days >= min_days));
+        }
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Adds the number of watchers, and applies the `min_watchers`/`max_watchers` filters.
+    /// Counts the API hides are 0, so `max_watchers` keeps (nearly) unwatched pages.
+    async fn process_watchers(&self, result: &PageList) -> Result<(), String> {
+        let min_watchers = self.get_param("min_watchers").and_then(|s| s.trim().parse::<u64>().ok());
+        let max_watchers = self.get_param("max_watchers").and_then(|s| s.trim().parse::<u64>().ok());
+        if !self.has_param("add_watchers")
+            && self.get_param_blank("sortby") != "watchers"
+            && min_watchers.is_none()
+            && max_watchers.is_none()
+        {
+            return Ok(());
+        }
+        if result.is_empty()? {
+            return Ok(());
+        }
+        if result.len()? > MAX_WATCHERS_PAGES {
+            if min_watchers.is_some() || max_watchers.is_some() {
+                return Err(format!(
+                    "Too many pages ({}) for the watcher filters, maximum is {}",
+                    result.len()?,
+                    MAX_WATCHERS_PAGES
+                ));
+            }
+            self.warn(format!("<span tt='warn_watchers_limit'></span> {}", MAX_WATCHERS_PAGES))?;
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_watchers: no wiki set in result".to_string()),
+        };
+        let watchers = Watchers::new(self.state.http_client(), &self.state.get_server_url_for_wiki(&wiki)?);
+        let api = self.state.get_api_for_wiki(wiki).await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let titles: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| entry.title().full_with_underscores(&api))
+            .collect();
+        let counts = watchers.get_watchers(&titles).await?;
+        entries.iter_mut().for_each(|entry| {
+            if let Some(title) = entry.title().full_with_underscores(&api) {
+                entry.watchers = counts.get(&title).cloned();
+            }
+        });
+        entries.retain(|entry| match entry.watchers {
+            Some(count) => {
+                min_watchers.map_or(true, |min| count >= min) && max_watchers.map_or(true, |max| count <= max)
+            }
+            None => min_watchers.is_none() && max_watchers.is_none(),
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Counts <ref> tags and "citation needed" templates (`citation_needed_templates`, one per line, to override the
+    /// enwiki defaults) in the wikitext, and applies the `min_references`/`max_references` and
+    /// `min_citation_needed`/`max_citation_needed` filters
+    async fn process_references(&self, result: &PageList) -> Result<(), String> {
+        let min_references = self.get_param("min_references").and_then(|s| s.trim().parse::<u64>().ok());
+        let max_references = self.get_param("max_references").and_then(|s| s.trim().parse::<u64>().ok());
+        let min_citation_needed = self.get_param("min_citation_needed").and_then(|s| s.trim().parse::<u64>().ok());
+        let max_citation_needed = self.get_param("max_citation_needed").and_then(|s| s.trim().parse::<u64>().ok());
+        let has_filter = min_references.is_some()
+            || max_references.is_some()
+            || min_citation_needed.is_some()
+            || max_citation_needed.is_some();
+        if !self.has_param("add_references") && !has_filter {
+            return Ok(());
+        }
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        if result.len()? > MAX_REFERENCES_PAGES {
+            if has_filter {
+                return Err(format!(
+                    "Too many pages ({}) for the reference filters, maximum is {}",
+                    result.len()?,
+                    MAX_REFERENCES_PAGES
+                ));
+            }
+            self.warn(format!("<span tt='warn_references_limit'></span> {}", MAX_REFERENCES_PAGES))?;
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_references: no wiki set in result".to_string()),
+        };
+        let mut templates = self.get_param_as_vec("citation_needed_templates", "\n");
+        if templates.is_empty() {
+            templates = DEFAULT_CITATION_NEEDED_TEMPLATES.iter().map(|s| s.to_string()).collect();
+        }
+        let references = References::new(self.state.http_client(), &self.state.get_server_url_for_wiki(&wiki)?, &templates)?;
+        let api = self.state.get_api_for_wiki(wiki).await?;
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let titles: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| entry.title().full_with_underscores(&api))
+            .collect();
+        let counts = references.get_counts(&titles).await?;
+        entries.iter_mut().for_each(|entry| {
+            if let Some(count) = entry.title().full_with_underscores(&api).and_then(|title| counts.get(&title)) {
+                entry.references = Some(count.references);
+                entry.citation_needed = Some(count.citation_needed);
+            }
+        });
+        let in_range = |value: Option<u64>, min: Option<u64>, max: Option<u64>| match value {
+            Some(value) => min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max),
+            None => min.is_none() && max.is_none(),
+        };
+        entries.retain(|entry| {
+            in_range(entry.references, min_references, max_references)
+                && in_range(entry.citation_needed, min_citation_needed, max_citation_needed)
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Thumbnail URLs of page images are needed for `add_image_url`, and the gallery output
+    pub fn has_image_urls(&self) -> bool {
+        self.has_param("add_image_url") || self.get_param_blank("format") == "gallery"
+    }
+
+    /// Resolves page images to thumbnail URLs, `thumbnail_width` pixels wide
+    async fn process_image_urls(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_image_urls() || result.is_empty()? {
+            return Ok(());
+        }
+        if result.len()? > MAX_IMAGE_URL_PAGES {
+            self.warn(format!("<span tt='warn_image_url_limit'></span> {}", MAX_IMAGE_URL_PAGES))?;
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_image_urls: no wiki set in result".to_string()),
+        };
+        let width = self
+            .get_param("thumbnail_width")
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_THUMBNAIL_WIDTH);
+        let thumbnails = Thumbnails::new(self.state.http_client(), &self.state.get_server_url_for_wiki(&wiki)?, width);
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let mut files: Vec<String> = entries.iter().filter_map(|entry| entry.get_page_image()).collect();
+        files.sort();
+        files.dedup();
+        let urls = thumbnails.get_urls(&files).await?;
+        entries.iter_mut().for_each(|entry| {
+            if let Some(file) = entry.get_page_image() {
+                entry.set_image_url(urls.get(&file).cloned());
+            }
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// Adds the values of the `harvest_properties` (eg "P31,P569") of the Wikidata item of each page, for output as extra columns
+    async fn process_property_harvest(&self, result: &PageList) -> Result<(), String> {
+        let harvest = match self.property_harvest() {
+            Some(harvest) => harvest,
+            None => return Ok(()),
+        };
+        if result.is_empty()? {
+            return Ok(());
+        }
+        if result.len()? > MAX_PROPERTY_HARVEST_PAGES {
+            self.warn(format!("<span tt='warn_property_harvest_limit'></span> {}", MAX_PROPERTY_HARVEST_PAGES))?;
+            return Ok(());
+        }
+        let is_wikidata = result.is_wikidata();
+        let item_for_entry = |entry: &PageListEntry| -> Option<String> {
+            match is_wikidata {
+                true if entry.title().namespace_id() == 0 => Some(entry.title().with_underscores()),
+                true => None,
+                false => entry.get_wikidata_item(),
+            }
+        };
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let mut items: Vec<String> = entries.iter().filter_map(|entry| item_for_entry(entry)).collect();
+        items.sort();
+        items.dedup();
+        let api_url = self.state.get_api_url_for_wiki("wikidatawiki")?;
+        let values = harvest.run(&self.state.http_client(), &api_url, &items).await?;
+        entries.iter_mut().for_each(|entry| {
+            if let Some(item) = item_for_entry(entry) {
+                entry.set_property_values(values.get(&item).cloned());
+            }
+        });
+        result.set_entries(entries.into_iter().collect())
+    }
+
+    /// A duration in days; a number with an optional unit of d(ays), w(eeks), m(onths), or y(ears). None if it is
+    /// not valid, or too large.
+    fn parse_age_days(s: &str) -> Option<u64> {
+        let s = s.trim().to_lowercase();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let number = s[..split].parse::<u64>().ok()?;
+        let factor = match s[split..].trim() {
+            "" | "d" => 1,
+            "w" => 7,
+            "m" => 30,
+            "y" => 365,
+            _ => return None,
+        };
+        number.checked_mul(factor)
+    }
+
+    async fn process_redlinks_batch(&self,conn:&mut dyn WikiDbConnection,sql:SQLtuple,redlink_counter: &mut HashMap<Title, LinkCount>) -> Result<(), String> {
+        let rows = conn.query_as::<(Vec<u8>,i64,usize)>(sql).await?;
+
+        for (page_title,namespace_id,_count) in rows {
+            let page_title = String::from_utf8_lossy(&page_title).to_string() ;
+            let title = Title::new(&page_title, namespace_id);
+            *redlink_counter.entry(title).or_insert_with(||0) += 1 ;
+        }
+        Ok(())
+    }
+
+    async fn process_namespace_conversion(&self, result: &PageList) -> Result<(), String> {
+        let namespace_conversion = self.get_param_default("namespace_conversion", "keep");
+        let add = match namespace_conversion.as_str() {
+            "topic" => 0,
+            "talk" => 1,
+            _ => return Ok(())
+        } ;
+        // Need tmp to avoid permanent double-lock on entries
+        let tmp = result
+        .entries()
+        .read()
+        .map_err(|e| format!("{:?}", e))?
+        .par_iter()
+        .map(|entry| {
+            let mut nsid = entry.title().namespace_id() ;
+            nsid = nsid - (nsid&1) + add; // Change "talk" bit
+            let t = entry.title().pretty();
+            let new_title = Title::new(t, nsid);
+            PageListEntry::new(new_title)
+        })
+        .collect();
+        *(result.entries().write().map_err(|e| format!("{:?}", e))?) = tmp ;
+        Ok(())
+    }
+
+    /// For wikis with Extension:Translate (Meta, Commons, mediawiki.org etc.): `translation_mode=collapse` replaces
+    /// translation subpages ("Foo/de") with their root page, `translation_language` keeps only the translations into
+    /// that language. A translation page is a page whose page_lang is set, with the language code as last subpage.
+    async fn process_translations(&self, result: &PageList) -> Result<(), String> {
+        let collapse = self.get_param_blank("translation_mode") == "collapse";
+        let language = self.get_param_blank("translation_language").trim().to_lowercase();
+        if (!collapse && language.is_empty()) || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_translations: no wiki set in result".to_string()),
+        };
+
+        // Translation page => (root page, language)
+        let mut translations: HashMap<Title, (Title, String)> = HashMap::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace,page_lang FROM page WHERE page_lang IS NOT NULL AND ".to_string(), vec![]);
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>)>(sql).await?;
+            for (page_title, namespace_id, page_lang) in rows {
+                let page_title = String::from_utf8_lossy(&page_title);
+                let page_lang = String::from_utf8_lossy(&page_lang).to_lowercase();
+                if let Some(root) = Self::translation_root(&page_title, &page_lang) {
+                    translations.insert(
+                        Title::new(&page_title, namespace_id),
+                        (Title::new(&root, namespace_id), page_lang),
+                    );
+                }
+            }
+        }
+        conn.close().await?;
+
+        let entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let entries: HashSet<PageListEntry> = entries
+            .into_iter()
+            .filter(|entry| {
+                language.is_empty()
+                    || translations.get(entry.title()).map_or(false, |(_, lang)| *lang == language)
+            })
+            .map(|entry| match (collapse, translations.get(entry.title())) {
+                (true, Some((root, _))) => PageListEntry::new(root.to_owned()),
+                _ => entry,
+            })
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// The pages of `pagelist` that exist, with their target (and section) if they are redirects
+    async fn load_redirect_targets(
+        conn: &mut dyn WikiDbConnection,
+        pagelist: &PageList,
+    ) -> Result<HashMap<Title, Option<(Title, String)>>, String> {
+        let mut ret: HashMap<Title, Option<(Title, String)>> = HashMap::new();
+        for batch in pagelist.to_sql_batches(PAGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace,rd_title,rd_namespace,rd_fragment FROM page LEFT JOIN redirect ON rd_from=page_id AND (rd_interwiki IS NULL OR rd_interwiki='') WHERE ".to_string(), vec![]);
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Option<Vec<u8>>,Option<NamespaceID>,Option<Vec<u8>>)>(sql).await?;
+            for (page_title, namespace_id, rd_title, rd_namespace, rd_fragment) in rows {
+                let target = match (rd_title, rd_namespace) {
+                    (Some(rd_title), Some(rd_namespace)) => {
+                        let fragment = rd_fragment.map(|f| String::from_utf8_lossy(&f).into_owned()).unwrap_or_default();
+                        Some((Title::new(&String::from_utf8_lossy(&rd_title), rd_namespace), fragment))
+                    }
+                    _ => None,
+                };
+                ret.insert(Title::new(&String::from_utf8_lossy(&page_title), namespace_id), target);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// With `redirect_problems`, keeps only the redirects that point to another redirect (with the page at the end of
+    /// the chain as fix), to a page that does not exist, or in a circle. `format=redirect_fixes` lists them for bots.
+    async fn process_redirect_problems(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_param("redirect_problems") || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_redirect_problems: no wiki set in result".to_string()),
+        };
+
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        let mut pages = Self::load_redirect_targets(conn.as_mut(), result).await?;
+        let redirects: Vec<(Title, (Title, String))> = pages
+            .iter()
+            .filter_map(|(title, target)| Some((title.to_owned(), target.to_owned()?)))
+            .collect();
+
+        // Follow the targets, one step per round; a page in `checked` but not in `pages` does not exist
+        let mut checked: HashSet<Title> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .map(|entry| entry.title().to_owned())
+            .collect();
+        let mut to_check: HashSet<Title> = redirects.iter().map(|(_, (target, _))| target.to_owned()).collect();
+        for _ in 0..=MAX_REDIRECT_HOPS {
+            to_check.retain(|title| !checked.contains(title));
+            if to_check.is_empty() {
+                break;
+            }
+            let pagelist = PageList::new_from_wiki(&wiki);
+            for title in to_check.drain() {
+                pagelist.add_entry(PageListEntry::new(title.to_owned()))?;
+                checked.insert(title);
+            }
+            let found = Self::load_redirect_targets(conn.as_mut(), &pagelist).await?;
+            to_check = found.values().filter_map(|target| Some(target.as_ref()?.0.to_owned())).collect();
+            pages.extend(found);
+        }
+        conn.close().await?;
+
+        let problems: HashMap<Title, RedirectProblem> = redirects
+            .iter()
+            .filter_map(|(redirect, target)| {
+                let problem = RedirectProblem::new_for_redirect(redirect, target, &pages)?;
+                Some((redirect.to_owned(), problem))
+            })
+            .collect();
+        let entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let entries: HashSet<PageListEntry> = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let problem = problems.get(entry.title())?.to_owned();
+                entry.set_redirect_problem(Some(problem));
+                Some(entry)
+            })
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// For the redirects in the result, from the redirect table: `add_redirect_target` shows where they point to,
+    /// `resolve_redirects` replaces them with their targets (each target only once). Interwiki redirects are kept.
+    async fn process_redirect_targets(&self, result: &PageList) -> Result<(), String> {
+        let add_target = self.has_param("add_redirect_target");
+        let resolve = self.has_param("resolve_redirects");
+        if (!add_target && !resolve) || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_redirect_targets: no wiki set in result".to_string()),
+        };
+        let api = self.state.get_api_for_wiki(wiki.clone()).await?;
+
+        // Redirect => (target, section)
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        let targets: HashMap<Title, (Title, String)> = Self::load_redirect_targets(conn.as_mut(), result)
+            .await?
+            .into_iter()
+            .filter_map(|(title, target)| Some((title, target?)))
+            .collect();
+        conn.close().await?;
+
+        let entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let entries: HashSet<PageListEntry> = entries
+            .into_iter()
+            .map(|mut entry| match targets.get(entry.title()) {
+                Some((target, _)) if resolve => PageListEntry::new(target.to_owned()),
+                Some((target, fragment)) => {
+                    let mut target = target.full_pretty(&api).unwrap_or_else(|| target.pretty().to_string());
+                    if !fragment.is_empty() {
+                        target = format!("{}#{}", target, fragment.replace('_', " "));
+                    }
+                    entry.set_redirect_target(Some(target));
+                    entry
+                }
+                None => entry,
+            })
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// "Foo/Bar/de" with page language "de" => "Foo/Bar"
+    fn translation_root(page_title: &str, page_lang: &str) -> Option<String> {
+        let root = page_title.strip_suffix(page_lang)?.strip_suffix('/')?;
+        match root.is_empty() {
+            true => None,
+            false => Some(root.to_string()),
+        }
+    }
+
+    async fn process_subpages(&self, result: &PageList) -> Result<(), String> {
+        let add_subpages = self.has_param("add_subpages");
+        let subpage_filter = self.get_param_default("subpage_filter", "either");
+        if !add_subpages && subpage_filter != "subpages" && subpage_filter != "no_subpages" {
+            return Ok(());
+        }
+
+        if add_subpages {
+            let title_ns: Vec<(String, NamespaceID)> = result
+                .entries()
+                .read()
+                .map_err(|e| format!("{:?}", e))?
+                .par_iter()
+                .map(|entry| {
+                    (
+                        entry.title().with_underscores(),
+                        entry.title().namespace_id(),
+                    )
+                })
+                .collect();
+
+            let wiki = match result.wiki()? {
+                Some(wiki) => wiki.to_owned(),
+                None => return Err("Platform::process_redlinks: no wiki set in result".to_string()),
+            };
+            let mut conn = self.state.get_wiki_db(&wiki).await?;
+
+            for (title, namespace_id) in title_ns {
+                let sql: SQLtuple = (
+                    "SELECT page_title,page_namespace FROM page WHERE page_namespace=? AND page_title LIKE ?"
+                        .to_string(),
+                    vec![MyValue::Int(namespace_id), MyValue::Bytes(safe_sql::like_prefix(&format!("{}/", &title)).into())],
+                );
+
+                let rows = conn.query_as::<(Vec<u8>,i64)>(sql).await?;
+
+                for (page_title,page_namespace) in rows {
+                    let page_title = String::from_utf8_lossy(&page_title);
+                    result.add_entry(PageListEntry::new(Title::new(&page_title,page_namespace))).unwrap_or(());
+                }
+            }
+            conn.close().await?;
+            // TODO if new pages were added, they should get some of the post_process_result treatment as well
+        }
+
+        if subpage_filter != "subpages" && subpage_filter != "no_subpages" {
+            return Ok(());
+        }
+
         let keep_subpages = subpage_filter == "subpages";
         result.retain_entries(&|entry: &PageListEntry| {
             let has_slash = entry.title().pretty().find('/').is_some();
@@ -666,14 +2355,17 @@ impl Platform {
         let is_kml = self.get_param_blank("format")=="kml" ;
         let is_wikidata = result.wiki()==Ok(Some("wikidatawiki".to_string())) ;
         let add_coordinates = self.has_param("add_coordinates")||is_kml;
-        let add_image = self.has_param("add_image") || is_kml ;
+        let add_image = self.has_param("add_image") || self.has_image_urls() || is_kml ;
         let add_defaultsort = self.has_param("add_defaultsort")||self.get_param_blank("sortby")=="defaultsort";
+        let add_sortkey = self.has_param("add_sortkey")||self.get_param_blank("sortby")=="sortkey";
+        let sortkey_category = title_normalization::normalize_ucfirst(&self.get_param_blank("sortkey_category"), true);
         let add_disambiguation = self.has_param("add_disambiguation");
         let add_incoming_links = self.get_param_blank("sortby") == "incoming_links";
         let add_sitelinks = self.get_param_blank("sortby") == "sitelinks" && !result.has_sitelink_counts()?;
         if !add_coordinates
             && !add_image
             && !add_defaultsort
+            && !add_sortkey
             && !add_disambiguation
             && !add_incoming_links
             && !add_sitelinks
@@ -686,9 +2378,18 @@ impl Platform {
                 .par_iter_mut()
                 .map(|mut sql_batch| {
                     let mut sql ="SELECT page_title,page_namespace".to_string();
+                    let mut params: Vec<MyValue> = vec![];
                     if add_image {sql += ",(SELECT pp_value FROM page_props WHERE pp_page=page_id AND pp_propname IN ('page_image','page_image_free') LIMIT 1) AS image" ;}
                     if add_coordinates {sql += ",(SELECT concat(gt_lat,',',gt_lon) FROM geo_tags WHERE gt_primary=1 AND gt_globe='earth' AND gt_page_id=page_id LIMIT 1) AS coord" ;}
                     if add_defaultsort {sql += ",(SELECT pp_value FROM page_props WHERE pp_page=page_id AND pp_propname='defaultsort' LIMIT 1) AS defaultsort" ;}
+                    if add_sortkey {
+                        if sortkey_category.is_empty() {
+                            sql += ",(SELECT cl_sortkey_prefix FROM categorylinks WHERE cl_from=page_id AND cl_sortkey_prefix!='' LIMIT 1) AS sortkey" ;
+                        } else {
+                            sql += ",(SELECT cl_sortkey_prefix FROM categorylinks WHERE cl_from=page_id AND cl_to=? LIMIT 1) AS sortkey" ;
+                            params.push(MyValue::Bytes(sortkey_category.as_bytes().to_vec()));
+                        }
+                    }
                     if add_disambiguation {sql += ",(SELECT pp_value FROM page_props WHERE pp_page=page_id AND pp_propname='disambiguation' LIMIT 1) AS disambiguation" ;}
                     if add_incoming_links {sql += ",(SELECT count(*) FROM pagelinks WHERE pl_namespace=page_namespace AND pl_title=page_title AND pl_from_namespace=0) AS incoming_links" ;}
                     if add_sitelinks {
@@ -700,12 +2401,14 @@ impl Platform {
                     }
                     sql += " FROM page WHERE " ;
                     sql_batch.0 = sql + &sql_batch.0 ;
+                    params.append(&mut sql_batch.1);
+                    sql_batch.1 = params ;
                     sql_batch.to_owned()
                 })
                 .collect::<Vec<SQLtuple>>();
 
-        let the_f = |row: my::Row, entry: &mut PageListEntry| {
-            let mut parts = row.unwrap(); // Unwrap into vector, should be safe
+        let the_f = |row: &DbRow, entry: &mut PageListEntry| {
+            let mut parts = row.to_owned();
             parts.remove(0); // page_title
             parts.remove(0); // page_namespace
             if add_image {
@@ -730,6 +2433,12 @@ impl Platform {
                     _ => None,
                 });
             }
+            if add_sortkey {
+                entry.set_sortkey(match parts.remove(0) {
+                    my::Value::Bytes(s) => String::from_utf8(s).ok().filter(|s| !s.is_empty()),
+                    _ => None,
+                });
+            }
             if add_disambiguation {
                 entry.disambiguation = match parts.remove(0) {
                     my::Value::NULL => TriState::No,
@@ -750,25 +2459,166 @@ impl Platform {
             }
         };
 
-        let col_title : usize = 0 ;
-        let col_ns : usize = 1 ;
-        result.run_batch_queries(&self.state(), batches).await?
+        let col_title : usize = 0 ;
+        let col_ns : usize = 1 ;
+        result.run_batch_queries(&self.state(), batches).await?
+            .iter()
+            .filter_map(|row| {
+                result.entry_from_row(row, col_title, col_ns)
+                    .map(|entry| (row, entry))
+            })
+            .filter_map(|(row, entry)| {
+                match result.entries().read() {
+                    Ok(entries) => entries.get(&entry).map(|e| (row, e.clone())),
+                    _ => None, // TODO error?
+                }
+            })
+            .for_each(|(row, mut entry)| {
+                the_f(row, &mut entry);
+                result.add_entry(entry).unwrap_or(());
+            });
+        Ok(())
+    }
+
+    /// File name => SHA1, from the image table of `wiki`; by file names, or with `by_sha1` by SHA1 values
+    async fn load_file_sha1s(&self, wiki: &str, values: &[String], by_sha1: bool) -> Result<Vec<(String, String)>, String> {
+        let mut ret = vec![];
+        if values.is_empty() {
+            return Ok(ret);
+        }
+        let column = if by_sha1 { "img_sha1" } else { "img_name" };
+        let mut conn = self.state.get_wiki_db(wiki).await?;
+        for chunk in values.chunks(PAGE_BATCH_SIZE) {
+            let mut sql: SQLtuple = (format!("SELECT img_name,img_sha1 FROM image_compat WHERE img_sha1!='' AND {} IN (", column), vec![]);
+            Platform::append_sql(&mut sql, safe_sql::string_list(chunk));
+            sql.0 += ")";
+            let rows = conn.query_as::<(Vec<u8>,Vec<u8>)>(sql).await?;
+            rows.iter().for_each(|(name, sha1)| {
+                ret.push((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(sha1).into_owned()))
+            });
+        }
+        conn.close().await?;
+        Ok(ret)
+    }
+
+    /// With `file_duplicates`, lists for each file in the result the other result files with the same content (SHA1),
+    /// and on wikis other than Commons, the Commons files with it. `file_duplicates_only` keeps only files that have any.
+    async fn process_file_duplicates(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_param("file_duplicates") || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_file_duplicates: no wiki set in result".to_string()),
+        };
+        let api = self.state.get_api_for_wiki(wiki.clone()).await?;
+        let only_duplicates = self.has_param("file_duplicates_only");
+
+        let files: Vec<String> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .filter(|entry| entry.title().namespace_id() == 6)
+            .map(|entry| entry.title().with_underscores())
+            .collect();
+        let sha1_by_file: HashMap<String, String> = self.load_file_sha1s(&wiki, &files, false).await?.into_iter().collect();
+        let mut by_sha1: HashMap<String, Vec<String>> = HashMap::new();
+        for (file, sha1) in &sha1_by_file {
+            let title = Title::new(file, 6).full_pretty(&api).unwrap_or_else(|| format!("File:{}", file));
+            by_sha1.entry(sha1.to_owned()).or_default().push(title);
+        }
+        if wiki != "commonswiki" {
+            let mut sha1s: Vec<String> = by_sha1.keys().cloned().collect();
+            sha1s.sort_unstable();
+            for (file, sha1) in self.load_file_sha1s("commonswiki", &sha1s, true).await? {
+                by_sha1.entry(sha1).or_default().push(format!("commons:File:{}", file.replace('_', " ")));
+            }
+        }
+
+        let entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
             .iter()
-            .filter_map(|row| {
-                result.entry_from_row(row, col_title, col_ns)
-                    .map(|entry| (row, entry))
-            })
-            .filter_map(|(row, entry)| {
-                match result.entries().read() {
-                    Ok(entries) => entries.get(&entry).map(|e| (row, e.clone())),
-                    _ => None, // TODO error?
+            .cloned()
+            .collect();
+        let entries: HashSet<PageListEntry> = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let own_title = entry.title().full_pretty(&api).unwrap_or_default();
+                let mut duplicates: Vec<String> = sha1_by_file
+                    .get(&entry.title().with_underscores())
+                    .filter(|_| entry.title().namespace_id() == 6)
+                    .and_then(|sha1| by_sha1.get(sha1))
+                    .map(|titles| titles.iter().filter(|t| **t != own_title).cloned().collect())
+                    .unwrap_or_default();
+                duplicates.sort();
+                if only_duplicates && duplicates.is_empty() {
+                    return None;
+                }
+                if !duplicates.is_empty() {
+                    entry.set_file_duplicates(Some(duplicates));
                 }
+                Some(entry)
             })
-            .for_each(|(row, mut entry)| {
-                the_f(row.clone(), &mut entry);
-                result.add_entry(entry).unwrap_or(());
-            });
-        Ok(())
+            .collect();
+        result.set_entries(entries)
+    }
+
+    /// Adds on how many wikis, and pages, each file is used (globalimagelinks on Commons), and applies the
+    /// `min_global_usage`/`max_global_usage` filters on the number of pages. Unused files count as 0; other
+    /// namespaces are removed by the filters.
+    async fn process_global_usage(&self, result: &PageList) -> Result<(), String> {
+        let min_usage = self.get_param("min_global_usage").and_then(|s| s.trim().parse::<u64>().ok());
+        let max_usage = self.get_param("max_global_usage").and_then(|s| s.trim().parse::<u64>().ok());
+        if !self.has_param("add_global_usage") && min_usage.is_none() && max_usage.is_none() {
+            return Ok(());
+        }
+        if result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+
+        let mut entries: Vec<PageListEntry> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .cloned()
+            .collect();
+        let files: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.title().namespace_id() == 6)
+            .map(|entry| entry.title().with_underscores())
+            .collect();
+        let mut usage: HashMap<String, (u64, u64)> = HashMap::new();
+        if !files.is_empty() {
+            let mut conn = self.state.get_wiki_db("commonswiki").await?;
+            for chunk in files.chunks(PAGE_BATCH_SIZE) {
+                let mut sql: SQLtuple = (
+                    "SELECT gil_to,COUNT(DISTINCT gil_wiki),COUNT(*) FROM globalimagelinks WHERE gil_to IN (".to_string(),
+                    vec![],
+                );
+                Platform::append_sql(&mut sql, safe_sql::string_list(chunk));
+                sql.0 += ") GROUP BY gil_to";
+                let rows = conn.query_as::<(Vec<u8>,u64,u64)>(sql).await?;
+                rows.into_iter().for_each(|(file, wikis, pages)| {
+                    usage.insert(String::from_utf8_lossy(&file).into_owned(), (wikis, pages));
+                });
+            }
+            conn.close().await?;
+        }
+
+        entries.iter_mut().filter(|entry| entry.title().namespace_id() == 6).for_each(|entry| {
+            let (wikis, pages) = usage.get(&entry.title().with_underscores()).cloned().unwrap_or((0, 0));
+            entry.global_usage_wikis = Some(wikis);
+            entry.global_usage_pages = Some(pages);
+        });
+        entries.retain(|entry| match entry.global_usage_pages {
+            Some(count) => min_usage.map_or(true, |min| count >= min) && max_usage.map_or(true, |max| count <= max),
+            None => min_usage.is_none() && max_usage.is_none(),
+        });
+        result.set_entries(entries.into_iter().collect())
     }
 
     async fn process_files(&self, result: &PageList) -> Result<(), String> {
@@ -793,7 +2643,7 @@ impl Platform {
                 })
                 .collect::<Vec<SQLtuple>>();
 
-            let the_f = |row: my::Row, entry: &mut PageListEntry| if let Some(gil_group) = PageList::string_from_row(&row, 2) {
+            let the_f = |row: &DbRow, entry: &mut PageListEntry| if let Some(gil_group) = PageList::string_from_row(row, 2) {
                 let fi = FileInfo::new_from_gil_group(&gil_group);
                 entry.set_file_info(Some(fi));
             } ;
@@ -812,7 +2662,7 @@ impl Platform {
                     }
                 })
                 .for_each(|(row, mut entry)| {
-                    the_f(row.clone(), &mut entry);
+                    the_f(row, &mut entry);
                     result.add_entry(entry).unwrap_or(());
                 });
         }
@@ -829,7 +2679,7 @@ impl Platform {
                 })
                 .collect::<Vec<SQLtuple>>();
 
-            let the_f = |row: my::Row, entry: &mut PageListEntry| {
+            let the_f = |row: &DbRow, entry: &mut PageListEntry| {
                     let (
                         _img_name,
                         _namespace_id,
@@ -842,7 +2692,7 @@ impl Platform {
                         img_user_text,
                         img_timestamp,
                         img_sha1,
-                    ) = my::from_row::<(
+                    ) = match from_db_row::<(
                         String,
                         usize,
                         usize,
@@ -854,7 +2704,10 @@ impl Platform {
                         String,
                         String,
                         String,
-                    )>(row);
+                    )>(row) {
+                        Ok(values) => values,
+                        Err(_) => return,
+                    };
                     let mut file_info = match entry.get_file_info() {
                         Some(fi) => fi,
                         None => FileInfo::new(),
@@ -885,7 +2738,7 @@ impl Platform {
                     }
                 })
                 .for_each(|(row, mut entry)| {
-                    the_f(row.clone(), &mut entry);
+                    the_f(row, &mut entry);
                     result.add_entry(entry).unwrap_or(());
                 });
         }
@@ -930,21 +2783,16 @@ impl Platform {
         });
 
         // Duplicated from Patelist::annotate_batch_results
-        let rows: TokioMutex<Vec<my::Row>> = TokioMutex::new(vec![]);
+        let rows: TokioMutex<Vec<DbRow>> = TokioMutex::new(vec![]);
 
         for sql in batches {
             // Run query
             let mut conn = self.state
-                .get_wiki_db_connection(&"wikidatawiki".to_string())
+                .get_wiki_db(&"wikidatawiki".to_string())
                 .await
                 .map_err(|e| format!("{:?}", e))?;
-            let mut result = conn
-                .exec_iter(sql.0.as_str(),mysql_async::Params::Positional(sql.1)).await
-                .map_err(|e|format!("{:?}",e))?
-                .collect_and_drop()
-                .await
-                .map_err(|e|format!("{:?}",e))?;
-            conn.disconnect().await.map_err(|e|format!("{:?}",e))?;
+            let mut result = conn.query(sql).await?;
+            conn.close().await?;
             rows.lock().await.append(&mut result);
         }
 
@@ -954,7 +2802,7 @@ impl Platform {
             .for_each(|row| {
                 let full_page_title = match row.get(0) {
                     Some(title) => match title {
-                        my::Value::Bytes(uv) => match String::from_utf8(uv) {
+                        my::Value::Bytes(uv) => match String::from_utf8(uv.to_owned()) {
                             Ok(s) => s,
                             Err(_) => return,
                         },
@@ -964,7 +2812,7 @@ impl Platform {
                 };
                 let ips_item_id = match row.get(1) {
                     Some(title) => match title {
-                        my::Value::Int(i) => i,
+                        my::Value::Int(i) => *i,
                         _ => return,
                     },
                     None => return,
@@ -1032,6 +2880,48 @@ impl Platform {
         Ok(())
     }
 
+    /// Unconnected pages mode (`unconnected`, which implies `wikidata_item=without`): also removes redirects, pages marked
+    /// with `__EXPECTED_UNCONNECTED_PAGE__`, and pages using one of the exclusion templates of the wiki (eg {{bots}}), or
+    /// the ones given in `unconnected_exclude_templates`. `format=quickstatements` then gives commands to create items.
+    async fn process_unconnected(&self, result: &PageList) -> Result<(), String> {
+        if !self.has_param("unconnected") || result.is_empty()? || result.is_wikidata() {
+            return Ok(());
+        }
+        let wiki = match result.wiki()? {
+            Some(wiki) => wiki,
+            None => return Err("Platform::process_unconnected: no wiki set in result".to_string()),
+        };
+        let templates = if self.has_param("unconnected_exclude_templates") {
+            self.get_param_as_vec("unconnected_exclude_templates", "\n")
+        } else {
+            self.state.get_unconnected_exclude_templates(&wiki)
+        };
+        let templates: Vec<String> = templates
+            .iter()
+            .map(|t| Self::template_name(t))
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let mut excluded: HashSet<Title> = HashSet::new();
+        let mut conn = self.state.get_wiki_db(&wiki).await?;
+        for batch in result.to_sql_batches(PAGE_BATCH_SIZE)? {
+            let mut sql: SQLtuple = ("SELECT page_title,page_namespace FROM page WHERE (page_is_redirect=1 OR EXISTS (SELECT * FROM page_props WHERE pp_page=page_id AND pp_propname='expectedUnconnectedPage')".to_string(), vec![]);
+            if !templates.is_empty() {
+                sql.0 += " OR EXISTS (SELECT * FROM templatelinks,linktarget WHERE tl_from=page_id AND tl_target_id=lt_id AND lt_namespace=10 AND lt_title IN (";
+                Platform::append_sql(&mut sql, Platform::prep_quote(&templates));
+                sql.0 += "))";
+            }
+            sql.0 += ") AND ";
+            Platform::append_sql(&mut sql, batch);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID)>(sql).await?;
+            for (page_title, namespace_id) in rows {
+                excluded.insert(Title::new(&String::from_utf8_lossy(&page_title), namespace_id));
+            }
+        }
+        conn.close().await?;
+        result.retain_entries(&|entry| !excluded.contains(entry.title()))
+    }
+
     /// Adds page properties that might be missing if none of the original sources was "categories"
     async fn process_missing_database_filters(&self, result: &PageList) -> Result<(), String> {
         let mut params = SourceDatabaseParameters::db_params(self).await;
@@ -1043,7 +2933,7 @@ impl Platform {
     }
 
     async fn process_labels_old(&self, result: &PageList) -> Result<(), String> {
-        let mut sql = self.get_label_sql();
+        let mut sql = self.get_label_sql(LabelEntityType::Item);
         if sql.1.is_empty() {
             return Ok(());
         }
@@ -1066,14 +2956,14 @@ impl Platform {
             .collect::<Vec<SQLtuple>>();
 
         result.clear_entries()?;
-        let the_f = |row: my::Row| {
-            let term_full_entity_id = my::from_row::<String>(row);
+        let the_f = |row: &DbRow| {
+            let term_full_entity_id = column::<String>(row, 0).ok()?;
             Platform::entry_from_entity(&term_full_entity_id)
         };
         result.run_batch_queries(&self.state(), batches)
             .await?
             .iter()
-            .filter_map(|row| the_f(row.to_owned()))
+            .filter_map(the_f)
             .for_each(|entry| result.add_entry(entry).unwrap_or(()));
         Ok(())
     }
@@ -1130,8 +3020,8 @@ impl Platform {
                 ret.1.append(&mut tmp.1);
             }
             if has_pattern {
-                ret.0 += " AND wbxl_text_id=wbx_id AND wbx_text LIKE ?";
-                ret.1.push(MyValue::Bytes(s.to_owned().into()));
+                ret.0 += " AND wbxl_text_id=wbx_id AND ";
+                Self::append_sql(ret, safe_sql::like("wbx_text", s));
             }
         }
     }
@@ -1142,9 +3032,9 @@ impl Platform {
                 Regex::new(r#"[^a-z,]"#).expect("Platform::get_label_sql Regex is invalid");
         }
         let mut ret: SQLtuple = (String::new(), vec![]);
-        let yes = self.get_param_as_vec("labels_yes", "\n");
-        let any = self.get_param_as_vec("labels_any", "\n");
-        let no = self.get_param_as_vec("labels_no", "\n");
+        let yes = self.get_label_sql_patterns("labels_yes", false);
+        let any = self.get_label_sql_patterns("labels_any", false);
+        let no = self.get_label_sql_patterns("labels_no", true);
         if yes.len() + any.len() + no.len() == 0 {
             return None;
         }
@@ -1221,24 +3111,152 @@ impl Platform {
             .collect();
 
         result.clear_entries()?;
-        let the_f = |row: my::Row| {
-            let term_full_entity_id = my::from_row::<String>(row);
+        let the_f = |row: &DbRow| {
+            let term_full_entity_id = column::<String>(row, 0).ok()?;
             Platform::entry_from_entity(&term_full_entity_id)
         } ;
         result.run_batch_queries(&self.state(), batches)
             .await?
             .iter()
-            .filter_map(|row| the_f(row.to_owned()))
+            .filter_map(the_f)
             .for_each(|entry| result.add_entry(entry).unwrap_or(()));
         Ok(())
     }
 
     async fn process_labels(&self, result: &PageList) -> Result<(), String> {
         if false {
-            self.process_labels_old(result).await
+            self.process_labels_old(result).await?;
         } else {
-            self.process_labels_new(result).await
+            self.process_labels_new(result).await?;
+        }
+        self.process_label_regexes(result).await
+    }
+
+    /// Raw lines of a labels field; not title-normalized, as spaces and underscores matter in patterns
+    fn get_label_lines(&self, key: &str) -> Vec<String> {
+        self.form_parameters()
+            .get_all(key)
+            .iter()
+            .flat_map(|value| value.split('\n'))
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    /// LIKE patterns for the label SQL. Regular expressions are checked after the query; here, they match their literal
+    /// prefix or anything, or are skipped (for "no")
+    fn get_label_sql_patterns(&self, key: &str, skip_regex: bool) -> Vec<String> {
+        let allow_wildcards = self.has_param("label_wildcards");
+        self.get_label_lines(key)
+            .iter()
+            .filter_map(|line| {
+                let pattern = LabelPattern::parse(line, allow_wildcards).ok()?;
+                match pattern.sql_like() {
+                    Some(like) => Some(like),
+                    None if skip_regex => None,
+                    None => Some(pattern.sql_like_prefix().unwrap_or_else(|| "%".to_string())),
+                }
+            })
+            .collect()
+    }
+
+    /// As a source, each regular expression line needs a literal prefix, or the query would read all terms
+    pub fn check_label_source_regexes(&self) -> Result<(), String> {
+        let allow_wildcards = self.has_param("label_wildcards");
+        for key in ["labels_yes", "labels_any"].iter() {
+            for line in self.get_label_lines(key) {
+                let pattern = LabelPattern::parse(&line, allow_wildcards)?;
+                if pattern.is_regex() && pattern.sql_like_prefix().is_none() {
+                    return Err(format!(
+                        "The label regular expression '{}' needs a literal prefix, like '^Foo', to be used as a source",
+                        line
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_label_type_ids(&self, part1: &str) -> Vec<u64> {
+        let mut ret = vec![];
+        if self.has_param(&("cb_labels_".to_owned() + part1 + "_l")) {
+            ret.push(1);
+        }
+        if self.has_param(&("cb_labels_".to_owned() + part1 + "_a")) {
+            ret.push(3);
+        }
+        if self.has_param(&("cb_labels_".to_owned() + part1 + "_d")) {
+            ret.push(2);
+        }
+        ret
+    }
+
+    /// Regular expression label lines can not be checked in SQL, so all label lines are checked against the item terms, if there is one
+    pub async fn process_label_regexes(&self, result: &PageList) -> Result<(), String> {
+        let mut lists = vec![];
+        for part1 in ["yes", "any", "no"].iter() {
+            let patterns = self
+                .get_label_lines(&format!("labels_{}", part1))
+                .iter()
+                .map(|line| LabelPattern::parse(line, self.has_param("label_wildcards")))
+                .collect::<Result<Vec<LabelPattern>, String>>()?;
+            let languages = self.get_param_as_vec(&format!("langs_labels_{}", part1), ",");
+            lists.push((patterns, languages, self.get_label_type_ids(part1)));
+        }
+        if !lists.iter().any(|(patterns, _, _)| patterns.iter().any(|p| p.is_regex())) {
+            return Ok(());
+        }
+        result.convert_to_wiki("wikidatawiki", &self).await?;
+        if result.is_empty()? {
+            return Ok(());
         }
+
+        let item_ids: Vec<String> = result
+            .group_by_namespace()?
+            .get(&0)
+            .map(|titles| titles.iter().map(|title| title[1..].to_string()).collect())
+            .unwrap_or_default();
+        let batches: Vec<SQLtuple> = item_ids
+            .chunks(PAGE_BATCH_SIZE)
+            .map(|chunk| {
+                let sql = "SELECT wbit_item_id,wbxl_language,wbtl_type_id,wbx_text FROM wbt_item_terms,wbt_term_in_lang,wbt_text_in_lang,wbt_text WHERE wbit_term_in_lang_id=wbtl_id AND wbtl_text_in_lang_id=wbxl_id AND wbxl_text_id=wbx_id AND wbit_item_id IN (".to_string()
+                    + &Platform::get_questionmarks(chunk.len())
+                    + ")";
+                (sql, chunk.iter().map(|id| MyValue::Bytes(id.to_owned().into())).collect())
+            })
+            .collect();
+
+        // item => (language, type, text)
+        let mut terms: HashMap<String, Vec<(String, u64, String)>> = HashMap::new();
+        for row in result.run_batch_queries(&self.state(), batches).await? {
+            let (item_id, language, type_id, text) = from_db_row::<(u64, Vec<u8>, u64, Vec<u8>)>(&row)?;
+            terms.entry(format!("Q{}", item_id)).or_insert_with(Vec::new).push((
+                String::from_utf8_lossy(&language).to_string(),
+                type_id,
+                String::from_utf8_lossy(&text).to_string(),
+            ));
+        }
+
+        let matches = |item_terms: &[(String, u64, String)], pattern: &LabelPattern, languages: &[String], types: &[u64]| {
+            item_terms.iter().any(|(language, type_id, text)| {
+                (languages.is_empty() || languages.contains(language))
+                    && (types.is_empty() || types.contains(type_id))
+                    && pattern.is_match(text)
+            })
+        };
+        let (yes, any, no) = (&lists[0], &lists[1], &lists[2]);
+        result.retain_entries(&|entry: &PageListEntry| {
+            // Properties etc. are not checked
+            if entry.title().namespace_id() != 0 {
+                return true;
+            }
+            let empty = vec![];
+            let item_terms = terms.get(&entry.title().with_underscores()).unwrap_or(&empty);
+            // As in the SQL, "any" only applies with languages given
+            yes.0.iter().all(|p| matches(item_terms, p, &yes.1, &yes.2))
+                && (any.1.is_empty() || any.0.is_empty() || any.0.iter().any(|p| matches(item_terms, p, &any.1, &any.2)))
+                && !no.0.iter().any(|p| matches(item_terms, p, &no.1, &no.2))
+        })
     }
 
     async fn process_sitelinks(&self, result: &PageList) -> Result<(), String> {
@@ -1251,12 +3269,14 @@ impl Platform {
         let sitelinks_no = self.get_param_as_vec("sitelinks_no", "\n");
         let sitelinks_min = self.get_param_blank("min_sitelink_count");
         let sitelinks_max = self.get_param_blank("max_sitelink_count");
+        let without_sitelinks = Self::sql_without_sitelinks(&self.get_param_blank("without_sitelinks"));
 
         if sitelinks_yes.is_empty()
             && sitelinks_any.is_empty()
             && sitelinks_no.is_empty()
             && sitelinks_min.is_empty()
             && sitelinks_max.is_empty()
+            && without_sitelinks.is_none()
         {
             return Ok(());
         }
@@ -1291,6 +3311,9 @@ impl Platform {
             sql.0 += " AND NOT EXISTS (SELECT * FROM wb_items_per_site WHERE ips_item_id=substr(page_title,2)*1 AND ips_site_id=? LIMIT 1)" ;
             sql.1.push(site.into());
         });
+        if let Some(without_sitelinks) = without_sitelinks {
+            sql.0 += &without_sitelinks;
+        }
         sql.0 += " AND ";
 
         let mut having: Vec<String> = vec![];
@@ -1319,21 +3342,36 @@ impl Platform {
 
         result.clear_entries()?;
         let state = self.state();
-        let the_f = |row: my::Row| {
-            let (page_title, _sitelinks_count) = my::from_row::<(String, usize)>(row);
+        let the_f = |row: &DbRow| {
+            let (page_title, _sitelinks_count) = from_db_row::<(String, usize)>(row).ok()?;
             Some(PageListEntry::new(Title::new(&page_title, 0)))
         } ;
 
         result.run_batch_queries(&state, batches)
             .await?
             .iter()
-            .filter_map(|row| the_f(row.to_owned()))
+            .filter_map(the_f)
             .for_each(|entry| result.add_entry(entry).unwrap_or(()));
 
         if let Some(wiki) = old_wiki { result.convert_to_wiki(&wiki, &self).await? }
         Ok(())
     }
 
+    /// For `without_sitelinks`: "all" keeps items without any sitelinks, "wikipedia" items without a Wikipedia sitelink
+    fn sql_without_sitelinks(mode: &str) -> Option<String> {
+        let condition = match mode {
+            "all" => "",
+            "wikipedia" => {
+                " AND ips_site_id LIKE '%wiki' AND ips_site_id NOT IN ('commonswiki','incubatorwiki','mediawikiwiki','metawiki','outreachwiki','sourceswiki','specieswiki','wikidatawiki','wikifunctionswiki','wikimaniawiki')"
+            }
+            _ => return None,
+        };
+        Some(format!(
+            " AND NOT EXISTS (SELECT * FROM wb_items_per_site WHERE ips_item_id=substr(page_title,2)*1{} LIMIT 1)",
+            condition
+        ))
+    }
+
     async fn filter_wikidata(&self, result: &PageList) -> Result<(), String> {
         if result.is_empty()? {
             return Ok(());
@@ -1421,20 +3459,123 @@ impl Platform {
 
         result.clear_entries()?;
         let state = self.state();
-        let the_f = |row: my::Row| {
-            let pp_value: String = my::from_row(row);
+        let the_f = |row: &DbRow| {
+            let pp_value = column::<String>(row, 0).ok()?;
             Some(PageListEntry::new(Title::new(&pp_value, 0)))
         } ;
         result.run_batch_queries(&state, batches)
             .await?
             .iter()
-            .filter_map(|row| the_f(row.to_owned()))
+            .filter_map(the_f)
             .for_each(|entry| result.add_entry(entry).unwrap_or(()));
 
         if let Some(wiki) = original_wiki { result.convert_to_wiki(&wiki, &self).await? }
         Ok(())
     }
 
+    /// Keeps items with statements like "P31=Q5", honoring rank and qualifier options, via generated SPARQL
+    async fn process_statement_filter(&self, result: &PageList) -> Result<(), String> {
+        if result.is_empty()? {
+            return Ok(());
+        }
+        let filter = match self.statement_filter()? {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        if result.len()? > MAX_STATEMENT_FILTER_PAGES {
+            return Err(format!(
+                "Too many pages ({}) for the statement filter, maximum is {}",
+                result.len()?,
+                MAX_STATEMENT_FILTER_PAGES
+            ));
+        }
+        // Filter in place, by the item of each page, so the entries keep their metadata
+        let is_wikidata = result.is_wikidata();
+        if !is_wikidata {
+            self.annotate_with_wikidata_item(result).await?;
+        }
+        let item_of = |entry: &PageListEntry| -> Option<String> {
+            match is_wikidata {
+                true if entry.title().namespace_id() == 0 => Some(entry.title().with_underscores()),
+                true => None,
+                false => entry.get_wikidata_item(),
+            }
+        };
+        let items: Vec<String> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .filter_map(item_of)
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+        let matching = filter.run(&self.state.http_client(), self.state.endpoints().wdqs(), &items).await?;
+        result.retain_entries(&|entry: &PageListEntry| item_of(entry).map_or(false, |q| matching.contains(&q)))?;
+        Ok(())
+    }
+
+    /// The properties to harvest from the parameters, read raw, as title normalization would turn "P31, P569" into "P31,_P569"
+    pub fn property_harvest(&self) -> Option<PropertyHarvest> {
+        PropertyHarvest::new(&[self.get_param_blank("harvest_properties")])
+    }
+
+    /// The statement filter from the parameters; lines are read raw, as title normalization would mangle "P31 = Q5"
+    fn statement_filter(&self) -> Result<Option<StatementFilter>, String> {
+        let lines = |param: &str, separator: char| -> Vec<String> {
+            self.get_param_blank(param)
+                .split(separator)
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        };
+        StatementFilter::new(
+            &lines("statement_filter", '\n'),
+            StatementRank::new_from_str(&self.get_param_default("statement_rank", "any")),
+            &lines("statement_qualifier_yes", ','),
+            &lines("statement_qualifier_no", ','),
+        )
+    }
+
+    /// Structured data filters for files on Commons; only files with matching structured data are kept
+    async fn process_sdc_filter(&self, result: &PageList) -> Result<(), String> {
+        let filter = match SdcFilter::new(
+            SdcPresence::new_from_str(&self.get_param_blank("sdc_depicts")),
+            &self.get_param_as_vec("sdc_depicts_items", "\n"),
+            SdcPresence::new_from_str(&self.get_param_blank("sdc_license")),
+        ) {
+            Some(filter) => filter,
+            None => return Ok(()),
+        };
+        if result.is_empty()? {
+            return Ok(());
+        }
+        if result.wiki()? != Some("commonswiki".to_string()) {
+            return Err("Structured data filters only work on Commons".to_string());
+        }
+        if result.len()? > MAX_SDC_FILTER_PAGES {
+            return Err(format!(
+                "Too many pages ({}) for the structured data filters, maximum is {}",
+                result.len()?,
+                MAX_SDC_FILTER_PAGES
+            ));
+        }
+        let page_ids: Vec<u32> = result
+            .entries()
+            .read()
+            .map_err(|e| format!("{:?}", e))?
+            .iter()
+            .filter(|entry| entry.title().namespace_id() == 6)
+            .filter_map(|entry| entry.page_id)
+            .collect();
+        let api_url = self.state.get_api_url_for_wiki("commonswiki")?;
+        let matching = filter.run(&self.state.http_client(), &api_url, &page_ids).await?;
+        result.retain_entries(&|entry: &PageListEntry| {
+            entry.title().namespace_id() == 6 && entry.page_id.map_or(false, |page_id| matching.contains(&page_id))
+        })
+    }
+
     pub fn entry_from_entity(entity: &str) -> Option<PageListEntry> {
         // TODO media-info?
         match entity.chars().next() {
@@ -1449,6 +3590,14 @@ impl Platform {
         self.get_param(key)?.parse::<usize>().ok()
     }
 
+    /// Does the main wiki have a replica database? If not, the API source is used instead of the database one
+    pub fn has_replica_db(&self) -> bool {
+        match self.get_main_wiki() {
+            Some(wiki) => self.state.has_replica_db(&wiki),
+            None => true,
+        }
+    }
+
     pub fn get_main_wiki(&self) -> Option<String> {
         let language = self.get_param_default("lang", "en"); // Fallback
         let language = self
@@ -1476,6 +3625,31 @@ impl Platform {
         }
     }
 
+    /// The wikilinks from pages in `entries` to other pages in `entries`, as (source, target)
+    pub async fn load_links_within(&self, wiki: &str, entries: &[PageListEntry]) -> Result<Vec<(Title, Title)>, String> {
+        let pagelist = PageList::new_from_wiki(wiki);
+        pagelist.set_entries(entries.iter().cloned().collect())?;
+        let titles: HashSet<Title> = entries.iter().map(|entry| entry.title().to_owned()).collect();
+        let batches = pagelist.to_sql_batches(PAGE_BATCH_SIZE)?;
+        let mut ret = vec![];
+        if batches.is_empty() {
+            return Ok(ret);
+        }
+        let mut conn = self.state.get_wiki_db(wiki).await?;
+        for mut sql in batches {
+            sql.0 = format!("SELECT page_title,page_namespace,pl_title,pl_namespace FROM page,pagelinks WHERE pl_from=page_id AND {}", &sql.0);
+            let rows = conn.query_as::<(Vec<u8>,NamespaceID,Vec<u8>,NamespaceID)>(sql).await?;
+            rows.iter().for_each(|(page_title, page_namespace, link_title, link_namespace)| {
+                let target = Title::new(&String::from_utf8_lossy(link_title), *link_namespace);
+                if titles.contains(&target) {
+                    ret.push((Title::new(&String::from_utf8_lossy(page_title), *page_namespace), target));
+                }
+            });
+        }
+        conn.close().await?;
+        Ok(ret)
+    }
+
     pub async fn get_response(&self) -> Result<MyResponse, String> {
         // Shortcut: WDFIST
         match &self.wdfist_result {
@@ -1486,6 +3660,9 @@ impl Platform {
             }
             None => {}
         }
+        if let Some(j) = &self.count_estimate {
+            return Ok(self.state.output_json(j, self.form_parameters.params.get("callback")));
+        }
 
         let result = match &self.result {
             Some(result) => result,
@@ -1506,28 +3683,91 @@ impl Platform {
             result.drain_into_sorted_vec(PageListSort::new_from_params(&sortby, sort_order))?;
         self.apply_results_limit(&mut pages);
 
-        match self.get_param_blank("format").as_str() {
+        let format = self.get_param_blank("format");
+        let mut response = match format.as_str() {
             "wiki" => RenderWiki::new().response(&self, &wiki, pages).await,
             "csv" => RenderTSV::new(",").response(&self, &wiki, pages).await,
             "tsv" => RenderTSV::new("\t").response(&self, &wiki, pages).await,
             "json" => RenderJSON::new().response(&self, &wiki, pages).await,
             "pagepile" => RenderPagePile::new().response(&self, &wiki, pages).await,
             "kml" => RenderKML::new().response(&self, &wiki, pages).await,
+            "gallery" => RenderGallery::new().response(&self, &wiki, pages).await,
             "plain" => RenderPlainText::new().response(&self, &wiki, pages).await,
+            "quickstatements" => RenderQuickStatements::new().response(&self, &wiki, pages).await,
+            "redirect_fixes" => RenderRedirectFixes::new().response(&self, &wiki, pages).await,
+            "graph" => RenderGraph::new().response(&self, &wiki, pages).await,
+            "category_graph" => RenderCategoryGraph::new().response(&self, &wiki, pages).await,
             _ => RenderHTML::new().response(&self, &wiki, pages).await,
+        }?;
+        if self.has_param("download") || self.has_param("filename") {
+            let date = Utc::now().format("%Y%m%d").to_string();
+            let format = match format.as_str() {
+                "graph" | "category_graph" => GraphFormat::new_from_param(&self.get_param_blank("graph_format")).extension().to_string(),
+                _ => format,
+            };
+            response.filename = Self::download_filename(self.get_param("filename"), &wiki, &format, &date);
         }
+        response.partial_result = self.partial_result;
+        Ok(response)
     }
 
-    pub fn get_param_as_vec(&self, param: &str, separator: &str) -> Vec<String> {
-        match self.get_param(param) {
-            Some(s) => s
-                .split(separator)
-                .map(|s| s.trim().trim_matches('\u{200E}').trim_matches('\u{200F}')) // See https://doc.rust-lang.org/reference/whitespace.html
-                .filter(|s| !s.is_empty())
-                .map(|s| Title::spaces_to_underscores(&s.to_string()))
+    /// `requested` (cleaned up), or eg "enwiki_20240131.csv"; None for formats that are not downloaded as files
+    fn download_filename(requested: Option<String>, wiki: &str, format: &str, date: &str) -> Option<String> {
+        let extension = match format {
+            "csv" | "tsv" | "kml" | "json" | "dot" | "graphml" => format,
+            "redirect_fixes" => "tsv",
+            "wiki" | "plain" | "quickstatements" => "txt",
+            _ => return None,
+        };
+        let name: String = match requested {
+            Some(name) => name
+                .trim()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+                .take(MAX_DOWNLOAD_FILENAME_LENGTH)
                 .collect(),
-            None => vec![],
+            None => format!("{}_{}", wiki, date),
+        };
+        let name = name.trim_start_matches('.');
+        if name.is_empty() {
+            return Self::download_filename(None, wiki, format, date);
+        }
+        if name.to_lowercase().ends_with(&format!(".{}", extension)) {
+            Some(name.to_string())
+        } else {
+            Some(format!("{}.{}", name, extension))
+        }
+    }
+
+    /// Returns the values of a parameter, merged across repeated keys and split by `separator`
+    pub fn get_param_as_vec(&self, param: &str, separator: &str) -> Vec<String> {
+        if !self.has_param(param) {
+            return vec![];
+        }
+        let mut ret: Vec<String> = vec![];
+        let mut seen: HashSet<String> = HashSet::new();
+        for value in self.form_parameters().get_all(param) {
+            for s in value.split(separator) {
+                let s = title_normalization::normalize(s);
+                if !s.is_empty() && seen.insert(s.to_owned()) {
+                    ret.push(s);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Title prefixes (without namespace), one per line, with underscores and first-letter uppercase if the wiki does that
+    pub async fn get_title_prefixes(&self, param: &str) -> Vec<String> {
+        let prefixes = self.get_param_as_vec(param, "\n");
+        if prefixes.is_empty() {
+            return prefixes;
         }
+        let is_case_insensitive = !self.get_namespace_case_sensitivity(0).await;
+        prefixes
+            .iter()
+            .map(|prefix| title_normalization::normalize_ucfirst(prefix, is_case_insensitive))
+            .collect()
     }
 
     pub fn is_param_blank(&self, param: &str) -> bool {
@@ -1554,13 +3794,7 @@ impl Platform {
 
     /// Returns a tuple with a string containing comma-separated question marks, and the (non-empty) Vec elements
     pub fn prep_quote(strings: &[String]) -> SQLtuple {
-        let escaped: Vec<MyValue> = strings
-            .par_iter()
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .map(|s| MyValue::Bytes(s.into()))
-            .collect();
-        (Platform::get_questionmarks(escaped.len()), escaped)
+        safe_sql::string_list(strings)
     }
 
     pub fn full_entity_id_to_number(strings: &[String]) -> SQLtuple {
@@ -1576,9 +3810,7 @@ impl Platform {
 
     // Generates a string with `len` comma-separated question marks
     pub fn get_questionmarks(len: usize) -> String {
-        let mut questionmarks: Vec<String> = Vec::new();
-        questionmarks.resize(len, "?".to_string());
-        questionmarks.join(",")
+        safe_sql::questionmarks(len)
     }
 
     pub fn sql_tuple() -> SQLtuple {
@@ -1603,15 +3835,20 @@ impl Platform {
         }
     }
 
-    pub fn get_label_sql(&self) -> SQLtuple {
+    /// The labels source query for items or properties; empty for lexemes and forms, which are not in the term store
+    pub fn get_label_sql(&self, entity_type: LabelEntityType) -> SQLtuple {
         lazy_static! {
             static ref RE1: Regex =
                 Regex::new(r#"[^a-z,]"#).expect("Platform::get_label_sql Regex is invalid");
         }
         let mut ret: SQLtuple = (String::new(), vec![]);
-        let yes = self.get_param_as_vec("labels_yes", "\n");
-        let any = self.get_param_as_vec("labels_any", "\n");
-        let no = self.get_param_as_vec("labels_no", "\n");
+        let (table, id_field, term_field, prefix) = match entity_type.term_store() {
+            Some(term_store) => term_store,
+            None => return ret,
+        };
+        let yes = self.get_label_sql_patterns("labels_yes", false);
+        let any = self.get_label_sql_patterns("labels_any", false);
+        let no = self.get_label_sql_patterns("labels_no", true);
         if yes.len() + any.len() + no.len() == 0 {
             return ret;
         }
@@ -1620,18 +3857,23 @@ impl Platform {
         let langs_any = self.get_param_as_vec("langs_labels_any", ",");
         let langs_no = self.get_param_as_vec("langs_labels_no", ",");
 
-        ret.0 = "SELECT DISTINCT concat('Q',wbit_item_id) AS term_full_entity_id 
-            FROM wbt_text,wbt_item_terms wbt_item_terms1,wbt_type,wbt_term_in_lang,wbt_text_in_lang 
-            WHERE wbit_term_in_lang_id = wbtl_id 
+        ret.0 = format!(
+            "SELECT DISTINCT concat('{prefix}',terms1.{id}) AS term_full_entity_id 
+            FROM wbt_text,{table} terms1,wbt_type,wbt_term_in_lang,wbt_text_in_lang 
+            WHERE terms1.{term} = wbtl_id 
             AND wbtl_type_id = wby_id 
             AND wbtl_text_in_lang_id = wbxl_id 
-            AND wbxl_text_id = wbx_id"
-            .to_string();
+            AND wbxl_text_id = wbx_id",
+            prefix = prefix,
+            id = id_field,
+            table = table,
+            term = term_field
+        );
 
         yes.iter().for_each(|s| {
             if s != "%" {
-                ret.0 += &(" AND wbx_text LIKE ?".to_owned());
-                ret.1.push(MyValue::Bytes(s.to_owned().into()));
+                ret.0 += " AND ";
+                Self::append_sql(&mut ret, safe_sql::like("wbx_text", s));
             }
             if !langs_yes.is_empty() {
                 let mut tmp = Self::prep_quote(&langs_yes);
@@ -1650,9 +3892,11 @@ impl Platform {
                 } else {
                     ret.0 += " OR "
                 }
-                if s != "%" {
-                    ret.0 += &(" ( wbx_text LIKE ?".to_owned());
-                    ret.1.push(MyValue::Bytes(s.to_owned().into()));
+                ret.0 += " ( ";
+                if s == "%" {
+                    ret.0 += "1=1";
+                } else {
+                    Self::append_sql(&mut ret, safe_sql::like("wbx_text", s));
                 }
                 if !langs_any.is_empty() {
                     let mut tmp = Self::prep_quote(&langs_any);
@@ -1666,26 +3910,31 @@ impl Platform {
         }
 
         no.iter().for_each(|s| {
-            ret.0 += " AND NOT EXISTS (
+            // The entity type is given by the terms table, not wbt_type (which is label/alias/description)
+            ret.0 += &format!(
+                " AND NOT EXISTS (
                 SELECT * FROM 
                 wbt_text wbt_text2,
-                wbt_item_terms wbt_item_terms2,
+                {table} terms2,
                 wbt_type wbt_type2,
                 wbt_term_in_lang wbt_term_in_lang2,
                 wbt_text_in_lang wbt_text_in_lang2
-                WHERE wbt_item_terms2.wbit_term_in_lang_id = wbt_term_in_lang2.wbtl_id 
+                WHERE terms2.{term} = wbt_term_in_lang2.wbtl_id 
                 AND wbt_term_in_lang2.wbtl_type_id = wbt_type2.wby_id 
                 AND wbt_term_in_lang2.wbtl_text_in_lang_id = wbt_text_in_lang2.wbxl_id 
                 AND wbt_text_in_lang2.wbxl_text_id = wbt_text2.wbx_id
-                AND wbt_item_terms1.wbit_item_id=wbt_item_terms2.wbit_item_id 
-                AND wbt_type2.wby_name='item'";
+                AND terms1.{id}=terms2.{id}",
+                table = table,
+                term = term_field,
+                id = id_field
+            );
             if s != "%" {
-                ret.0 += &(" AND wbt_text2.wbx_text LIKE ?".to_owned());
-                ret.1.push(MyValue::Bytes(s.to_owned().into()));
+                ret.0 += " AND ";
+                Self::append_sql(&mut ret, safe_sql::like("wbt_text2.wbx_text", s));
             }
             if !langs_no.is_empty() {
                 let mut tmp = Self::prep_quote(&langs_no);
-                ret.0 += &(" AND wbt_type2.wbxl_language IN (".to_owned() + &tmp.0 + ")");
+                ret.0 += &(" AND wbt_text_in_lang2.wbxl_language IN (".to_owned() + &tmp.0 + ")");
                 ret.1.append(&mut tmp.1);
                 self.get_label_sql_helper(&mut ret, "no", "wbt_type2.wby_name");
             }
@@ -1701,7 +3950,7 @@ impl Platform {
         }
         match s.trim().to_lowercase().as_str() {
             "" => return Combination::None,
-            "categories" | "sparql" | "manual" | "pagepile" | "wikidata" | "search" => {
+            "categories" | "sparql" | "manual" | "pagepile" | "wikidata" | "search" | "url" | "edit_tags" | "user_contributions" | "deleted_pages" | "log" => {
                 return Combination::Source(s.to_string())
             }
             _ => {}
@@ -1915,6 +4164,8 @@ impl Platform {
 mod tests {
     use super::*;
     use crate::app_state::AppState;
+    use crate::golden::{GoldenSnapshot, DEFAULT_GOLDEN_PATH, GOLDEN_QUERIES_FILE};
+    use proptest::prelude::*;
     use serde_json::Value;
     use std::env;
     use std::fs::File;
@@ -1988,6 +4239,61 @@ mod tests {
         check_results_for_psid_ext(psid, "", wiki, expected).await
     }
 
+    /// Replays the recorded PetScan v2 queries in `fixtures/golden`
+    #[tokio::test]
+    async fn test_golden_snapshots() {
+        let state = get_state().await;
+        let snapshots = GoldenSnapshot::load_directory(DEFAULT_GOLDEN_PATH).unwrap();
+        let mut differences: Vec<String> = GoldenSnapshot::load_queries(GOLDEN_QUERIES_FILE)
+            .unwrap()
+            .into_iter()
+            .filter(|(name, _, _)| !snapshots.iter().any(|snapshot| snapshot.name == *name))
+            .map(|(name, _, _)| format!("{}: no recorded snapshot, run record_golden_snapshots", name))
+            .collect();
+        assert!(!snapshots.is_empty(), "No golden snapshots in {}", DEFAULT_GOLDEN_PATH);
+        for snapshot in snapshots {
+            let form_parameters = FormParameters::outcome_from_query(&snapshot.query).unwrap();
+            let mut platform = Platform::new_from_parameters(&form_parameters, state.clone());
+            match platform.run().await {
+                Ok(_) => differences.append(&mut snapshot.compare(platform.result.as_ref().unwrap()).unwrap()),
+                Err(e) => differences.push(format!("{}: {}", snapshot.name, e)),
+            }
+        }
+        assert_eq!(differences, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_statement_filter_params() {
+        let state = get_state().await;
+        let form_parameters = FormParameters::new_from_query(
+            "statement_filter=P31+%3D+Q5%0D%0AP18&statement_rank=truthy&statement_qualifier_no=P582%2C+P580",
+        );
+        let platform = Platform::new_from_parameters(&form_parameters, state.clone());
+        let expected = StatementFilter::new(
+            &["P31=Q5".to_string(), "P18".to_string()],
+            StatementRank::Truthy,
+            &[],
+            &["P582".to_string(), "P580".to_string()],
+        );
+        assert_eq!(platform.statement_filter(), expected);
+        assert!(expected.unwrap().is_some());
+
+        let form_parameters = FormParameters::new_from_query("statement_filter=P31+%3D+Q5%0AP31%3D");
+        let platform = Platform::new_from_parameters(&form_parameters, state);
+        assert!(platform.statement_filter().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_property_harvest_params() {
+        let state = get_state().await;
+        let form_parameters = FormParameters::new_from_query("harvest_properties=P31%2C+P569%0D%0AP18");
+        let platform = Platform::new_from_parameters(&form_parameters, state);
+        assert_eq!(
+            platform.property_harvest().map(|harvest| harvest.properties().to_owned()),
+            Some(vec!["P31".to_string(), "P569".to_string(), "P18".to_string()])
+        );
+    }
+
     #[tokio::test]
     async fn test_parse_combination_string() {
         let res =
@@ -2002,6 +4308,95 @@ mod tests {
         assert_eq!(res, expected);
     }
 
+    fn arbitrary_combination() -> impl Strategy<Value = Combination> {
+        let source = prop::sample::select(vec!["categories", "sparql", "manual", "pagepile", "wikidata", "search"])
+            .prop_map(|s| Combination::Source(s.to_string()));
+        source.prop_recursive(4, 16, 2, |inner| {
+            (inner.clone(), inner, 0..3).prop_map(|(a, b, op)| {
+                let pair = (Box::new(a), Box::new(b));
+                match op {
+                    0 => Combination::Intersection(pair),
+                    1 => Combination::Union(pair),
+                    _ => Combination::Not(pair),
+                }
+            })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn parse_any_combination_string(s in ".*") {
+            let _ = Platform::parse_combination_string(&s);
+        }
+
+        #[test]
+        fn parse_combination_string_like(s in "[a-z()' ]{0,40}|((categories|sparql|AND|OR|NOT|[()]) ){0,16}") {
+            let _ = Platform::parse_combination_string(&s);
+        }
+
+        #[test]
+        fn combination_round_trip(comb in arbitrary_combination()) {
+            prop_assert_eq!(Platform::parse_combination_string(&comb.to_string()), comb);
+        }
+    }
+
+    #[test]
+    fn test_download_filename() {
+        let name = |requested: Option<&str>, format: &str| {
+            Platform::download_filename(requested.map(|s| s.to_string()), "enwiki", format, "20240131")
+        };
+        assert_eq!(name(None, "csv"), Some("enwiki_20240131.csv".to_string()));
+        assert_eq!(name(None, "wiki"), Some("enwiki_20240131.txt".to_string()));
+        assert_eq!(name(None, "html"), None);
+        assert_eq!(name(Some("My list"), "tsv"), Some("My_list.tsv".to_string()));
+        assert_eq!(name(Some("report.KML"), "kml"), Some("report.KML".to_string()));
+        assert_eq!(name(None, "graphml"), Some("enwiki_20240131.graphml".to_string()));
+        assert_eq!(name(Some("../\"\r\nx"), "csv"), Some("____x.csv".to_string()));
+        assert_eq!(name(Some("..."), "csv"), Some("enwiki_20240131.csv".to_string()));
+    }
+
+    #[test]
+    fn test_category_membership() {
+        let tree = |root: &str, categories: &[&str]| {
+            let mut tree: HashSet<String> = categories.iter().map(|c| c.to_string()).collect();
+            tree.insert(root.to_string());
+            (root.to_string(), tree)
+        };
+        let trees = vec![
+            tree("Physicists", &["German_physicists", "Nuclear_physicists"]),
+            tree("1879_births", &[]),
+        ];
+        let categories: HashSet<String> = ["German_physicists", "Nuclear_physicists", "1879_births", "Violinists"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            Platform::category_membership(&trees, &categories),
+            vec!["Physicists (via German physicists, Nuclear physicists)".to_string(), "1879 births".to_string()]
+        );
+        assert!(Platform::category_membership(&trees, &HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_negated_sources() {
+        let comb = Platform::parse_combination_string("categories NOT (sparql OR manual)");
+        assert_eq!(comb.negated_sources(), vec!["sparql".to_string(), "manual".to_string()]);
+        let comb = Platform::parse_combination_string("categories NOT (sparql NOT manual)");
+        assert_eq!(comb.negated_sources(), vec!["sparql".to_string()]);
+        let comb = Platform::parse_combination_string("categories AND sparql");
+        assert!(comb.negated_sources().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_is_intersection_only() {
+        let comb = Platform::parse_combination_string("categories AND (sparql AND manual)");
+        assert!(Platform::is_intersection_only(&comb));
+        let comb = Platform::parse_combination_string("categories AND (sparql OR manual)");
+        assert!(!Platform::is_intersection_only(&comb));
+        let comb = Platform::parse_combination_string("categories NOT sparql");
+        assert!(!Platform::is_intersection_only(&comb));
+    }
+
     #[tokio::test]
     async fn test_manual_list_enwiki_use_props() {
         check_results_for_psid(10087995, "enwiki", vec![Title::new("Magnus_Manske", 0)]).await;
@@ -2221,6 +4616,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sql_creator_experience() {
+        assert!(Platform::sql_creator_experience(None, None, &None).is_none());
+        let sql = Platform::sql_creator_experience(Some(30), Some(10), &Some("autoconfirmed".to_string())).unwrap();
+        assert!(sql.0.contains("INTERVAL 30 DAY"));
+        assert!(sql.0.contains("FROM revision_userindex ru WHERE"));
+        assert!(sql.0.contains("ORDER BY ru.rev_timestamp LIMIT 1 OFFSET 9) IS NULL"));
+        assert!(sql.0.ends_with("ug_group=?)"));
+        assert_eq!(sql.1.len(), 1);
+    }
+
+    #[test]
+    fn test_sql_without_sitelinks() {
+        assert!(Platform::sql_without_sitelinks("").is_none());
+        assert!(!Platform::sql_without_sitelinks("all").unwrap().contains("ips_site_id"));
+        let sql = Platform::sql_without_sitelinks("wikipedia").unwrap();
+        assert!(sql.starts_with(" AND NOT EXISTS"));
+        assert!(sql.contains("'commonswiki'"));
+    }
+
+    #[test]
+    fn test_short_description_matches() {
+        let desc = Some("American physicist".to_string());
+        assert!(Platform::short_description_matches(&desc, "with", None, None));
+        assert!(!Platform::short_description_matches(&desc, "without", None, None));
+        assert!(Platform::short_description_matches(&None, "without", None, None));
+        assert!(!Platform::short_description_matches(&None, "with", None, None));
+        assert!(Platform::short_description_matches(&desc, "", Some(18), Some(18)));
+        assert!(!Platform::short_description_matches(&desc, "", None, Some(10)));
+        assert!(!Platform::short_description_matches(&None, "", Some(1), None));
+    }
+
+    #[test]
+    fn test_translation_root() {
+        assert_eq!(Platform::translation_root("Foo/Bar/de", "de"), Some("Foo/Bar".to_string()));
+        assert_eq!(Platform::translation_root("Tech/News/zh-hans", "zh-hans"), Some("Tech/News".to_string()));
+        assert_eq!(Platform::translation_root("Foo/de", "fr"), None);
+        assert_eq!(Platform::translation_root("Mode", "de"), None);
+        assert_eq!(Platform::translation_root("/de", "de"), None);
+    }
+
+    #[test]
+    fn test_template_name() {
+        assert_eq!(Platform::template_name(" Template:Infobox person"), "Infobox_person");
+        assert_eq!(Platform::template_name("infobox settlement"), "Infobox_settlement");
+        assert_eq!(Platform::template_name(""), "");
+    }
+
+    #[test]
+    fn test_parse_age_days() {
+        assert_eq!(Platform::parse_age_days("90"), Some(90));
+        assert_eq!(Platform::parse_age_days(" 2Y"), Some(730));
+        assert_eq!(Platform::parse_age_days("18m"), Some(540));
+        assert_eq!(Platform::parse_age_days("3 w"), Some(21));
+        assert_eq!(Platform::parse_age_days("y"), None);
+        assert_eq!(Platform::parse_age_days("5 years"), None);
+        assert_eq!(Platform::parse_age_days("18446744073709551615y"), None);
+    }
+
     #[tokio::test]
     async fn test_sort_by_defaultsort() {
         check_results_for_psid(
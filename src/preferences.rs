@@ -0,0 +1,203 @@
+use crate::form_parameters::FormParameters;
+use hyper::header::{HeaderMap, ACCEPT_LANGUAGE, COOKIE};
+use serde_json::Value;
+
+pub static PREFERENCES_COOKIE: &str = "petscan_prefs";
+static PREFERENCES_MAX_AGE_SECONDS: u64 = 365 * 24 * 3600;
+
+/// Defaults for parameters a query does not set, from the preferences cookie (set via /preferences), and the
+/// browser's Accept-Language
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserPreferences {
+    pub language: Option<String>,
+    pub project: Option<String>,
+    pub format: Option<String>,
+    pub namespaces: Vec<usize>,
+    /// The preferred language from Accept-Language, eg "de" for "de-AT,de;q=0.9,en;q=0.5"
+    pub accept_language: Option<String>,
+}
+
+impl UserPreferences {
+    pub fn new_from_headers(headers: &HeaderMap) -> Self {
+        let cookie = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().strip_prefix(PREFERENCES_COOKIE)?.strip_prefix('='))
+            .next()
+            .unwrap_or_default();
+        let mut ret = Self::new_from_query(cookie);
+        ret.accept_language = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::language_from_accept_language);
+        ret
+    }
+
+    /// From "language=de&project=wikipedia&format=json&ns=0,14", as given to /preferences and stored in the cookie
+    pub fn new_from_query(query: &str) -> Self {
+        let fp = FormParameters::new_from_query(query);
+        let value = |key: &str| {
+            fp.params
+                .get(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .filter(|v| v.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'))
+        };
+        Self {
+            language: value("language").map(|l| l.to_lowercase()),
+            project: value("project").map(|p| p.to_lowercase()),
+            format: value("format"),
+            namespaces: fp
+                .params
+                .get("ns")
+                .map(|ns| ns.split(',').filter_map(|ns| ns.trim().parse::<usize>().ok()).collect())
+                .unwrap_or_default(),
+            accept_language: None,
+        }
+    }
+
+    /// The language with the highest quality, without region; "*" does not count
+    pub fn language_from_accept_language(header: &str) -> Option<String> {
+        let mut best: Option<(String, f32)> = None;
+        for part in header.split(',') {
+            let mut params = part.split(';').map(|p| p.trim());
+            let language = match params.next() {
+                Some(tag) => tag.split('-').next().unwrap_or_default().to_lowercase(),
+                None => continue,
+            };
+            if language.is_empty() || language == "*" || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+                continue;
+            }
+            let quality = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.as_ref().map(|(_, q)| quality > *q).unwrap_or(true) {
+                best = Some((language, quality));
+            }
+        }
+        best.map(|(language, _)| language)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.language.is_none() && self.project.is_none() && self.format.is_none() && self.namespaces.is_empty()
+    }
+
+    /// Sets the parameters the query does not have. Accept-Language is only used for the HTML form (`is_form`);
+    /// otherwise, the same query URL would give different results depending on the browser.
+    pub fn apply(&self, form_parameters: &mut FormParameters, is_form: bool) {
+        let mut base = FormParameters::new();
+        let accept_language = self.accept_language.as_ref().filter(|_| is_form);
+        if let Some(language) = self.language.as_ref().or(accept_language) {
+            base.set_param("language", language);
+        }
+        if let Some(project) = &self.project {
+            base.set_param("project", project);
+        }
+        if let Some(format) = &self.format {
+            base.set_param("format", format);
+        }
+        if let Some(language) = accept_language {
+            base.set_param("interface_language", language);
+        }
+        // Namespaces are only a default as a whole, not added to those of the query
+        if form_parameters.ns.is_empty() {
+            self.namespaces
+                .iter()
+                .for_each(|ns| base.set_param(&format!("ns[{}]", ns), "1"));
+        }
+        form_parameters.rebase(&base);
+    }
+
+    fn to_query(&self) -> String {
+        let mut pairs = vec![];
+        let mut add = |key: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                pairs.push(format!("{}={}", key, FormParameters::percent_encode(value)));
+            }
+        };
+        add("language", &self.language);
+        add("project", &self.project);
+        add("format", &self.format);
+        if !self.namespaces.is_empty() {
+            let ns: Vec<String> = self.namespaces.iter().map(|ns| ns.to_string()).collect();
+            pairs.push(format!("ns={}", FormParameters::percent_encode(&ns.join(","))));
+        }
+        pairs.join("&")
+    }
+
+    /// Value of a Set-Cookie header that stores these preferences; empty preferences delete the cookie
+    pub fn set_cookie_header(&self) -> String {
+        let max_age = if self.is_empty() { 0 } else { PREFERENCES_MAX_AGE_SECONDS };
+        format!(
+            "{}={}; Path=/; Max-Age={}; SameSite=Lax; HttpOnly",
+            PREFERENCES_COOKIE,
+            self.to_query(),
+            max_age
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "language":self.language,
+            "project":self.project,
+            "format":self.format,
+            "ns":self.namespaces,
+            "accept_language":self.accept_language
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_from_accept_language() {
+        let language = UserPreferences::language_from_accept_language;
+        assert_eq!(language("de-AT,de;q=0.9,en;q=0.5"), Some("de".to_string()));
+        assert_eq!(language("en;q=0.5, fr"), Some("fr".to_string()));
+        assert_eq!(language("*, nl;q=0.1"), Some("nl".to_string()));
+        assert_eq!(language("en;q=0"), None);
+        assert_eq!(language(""), None);
+    }
+
+    #[test]
+    fn cookie_round_trip() {
+        let prefs = UserPreferences::new_from_query("language=DE&project=wikisource&format=json&ns=0,14,x");
+        assert_eq!(prefs.namespaces, vec![0, 14]);
+        let mut headers = HeaderMap::new();
+        let cookie = prefs.set_cookie_header();
+        let cookie = cookie.split(';').next().unwrap();
+        headers.insert(COOKIE, format!("other=1; {}", cookie).parse().unwrap());
+        headers.insert(ACCEPT_LANGUAGE, "fr-CH, fr;q=0.9".parse().unwrap());
+        let from_headers = UserPreferences::new_from_headers(&headers);
+        assert_eq!(from_headers.language, Some("de".to_string()));
+        assert_eq!(from_headers.project, Some("wikisource".to_string()));
+        assert_eq!(from_headers.namespaces, vec![0, 14]);
+        assert_eq!(from_headers.accept_language, Some("fr".to_string()));
+        assert!(UserPreferences::default().set_cookie_header().contains("Max-Age=0"));
+    }
+
+    #[test]
+    fn apply() {
+        let prefs = UserPreferences {
+            project: Some("wikisource".to_string()),
+            namespaces: vec![14],
+            accept_language: Some("fr".to_string()),
+            ..Default::default()
+        };
+        let mut fp = FormParameters::new_from_query("categories=Foo&project=&ns[0]=1");
+        prefs.apply(&mut fp, false);
+        assert_eq!(fp.params.get("project"), Some(&"wikisource".to_string()));
+        assert_eq!(fp.params.get("language"), None);
+        assert_eq!(fp.ns.len(), 1);
+        let mut fp = FormParameters::new_from_query("language=de");
+        prefs.apply(&mut fp, true);
+        assert_eq!(fp.params.get("language"), Some(&"de".to_string()));
+        assert_eq!(fp.params.get("interface_language"), Some(&"fr".to_string()));
+        assert!(fp.ns.contains(&14));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::form_parameters::FormParameters;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+
+pub static DEFAULT_PRESETS_PATH: &str = "./presets.json";
+
+/// A named bundle of query parameters
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub label: String,
+    pub parameters: FormParameters,
+}
+
+/// Named parameter bundles for common queries, selected with `preset=<name>`. Loaded from a JSON file
+/// (config "presets_path"), eg `{"unconnected_pages":{"label":"...","params":{"wikidata_item":"without"}}}`.
+#[derive(Debug, Clone, Default)]
+pub struct Presets {
+    presets: HashMap<String, Preset>,
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// A missing or broken file means no presets
+    pub fn new_from_file(path: &str) -> Self {
+        let mut ret = Self::new();
+        match fs::read(path).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok()) {
+            Some(json) => ret.add_from_json(&json),
+            None => println!("Could not load presets from {}", path),
+        }
+        ret
+    }
+
+    /// Adds presets from a JSON object; parameter values that are not strings are ignored
+    pub fn add_from_json(&mut self, json: &Value) {
+        let o = match json.as_object() {
+            Some(o) => o,
+            None => return,
+        };
+        for (name, preset) in o {
+            let pairs: Vec<(String, String)> = match preset["params"].as_object() {
+                Some(params) => params
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.to_string(), v.as_str()?.to_string())))
+                    .collect(),
+                None => continue,
+            };
+            let label = preset["label"].as_str().unwrap_or(name).to_string();
+            self.presets.insert(
+                name.to_string(),
+                Preset {
+                    label,
+                    parameters: FormParameters::new_from_pairs(pairs),
+                },
+            );
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets.get(name.trim())
+    }
+
+    /// name => label, for the interface
+    pub fn as_json(&self) -> Value {
+        let o: serde_json::Map<String, Value> = self
+            .presets
+            .iter()
+            .map(|(name, preset)| (name.to_string(), json!(preset.label)))
+            .collect();
+        Value::Object(o)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        let mut presets = Presets::new();
+        presets.add_from_json(&json!({
+            "unconnected_pages":{"label":"Pages without item","params":{"wikidata_item":"without","ns[0]":"1"}},
+            "broken":{"label":"No params"},
+            "unlabeled":{"params":{"show_uncategorized":"yes","depth":3}}
+        }));
+        let preset = presets.get("unconnected_pages").unwrap();
+        assert_eq!(preset.label, "Pages without item");
+        assert_eq!(preset.parameters.params.get("wikidata_item"), Some(&"without".to_string()));
+        assert!(preset.parameters.ns.contains(&0));
+        assert!(presets.get("broken").is_none());
+        let preset = presets.get("unlabeled").unwrap();
+        assert_eq!(preset.label, "unlabeled");
+        assert!(!preset.parameters.params.contains_key("depth"));
+        assert_eq!(presets.as_json()["unconnected_pages"], json!("Pages without item"));
+    }
+
+    #[test]
+    fn preset_file() {
+        let presets = Presets::new_from_file(DEFAULT_PRESETS_PATH);
+        assert!(presets.get("unconnected_pages").is_some());
+        assert!(presets.get("uncategorized_articles").is_some());
+    }
+}
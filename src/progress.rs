@@ -0,0 +1,69 @@
+use std::sync::{Arc, RwLock};
+
+/// What a query run is currently doing, eg "expanding categories: 12,000 found". Shared between the platform, its
+/// sources, the admin list of running queries, and the progress events for the HTML form.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    message: Arc<RwLock<String>>,
+}
+
+impl Progress {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: Arc::new(RwLock::new(message.to_string())),
+        }
+    }
+
+    pub fn set(&self, message: &str) {
+        if let Ok(mut m) = self.message.write() {
+            *m = message.to_string();
+        }
+    }
+
+    pub fn get(&self) -> String {
+        self.message.read().map(|m| m.to_string()).unwrap_or_default()
+    }
+
+    /// A number with thousands separators, for progress messages
+    pub fn format_count(n: usize) -> String {
+        let digits = n.to_string();
+        let mut ret = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                ret.push(',');
+            }
+            ret.push(c);
+        }
+        ret
+    }
+}
+
+/// Two handles are equal if they report the same run
+impl PartialEq for Progress {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.message, &other.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_message() {
+        let progress = Progress::new("starting");
+        let handle = progress.clone();
+        handle.set("running SPARQL");
+        assert_eq!(progress.get(), "running SPARQL");
+        assert_eq!(progress, handle);
+        assert_ne!(progress, Progress::new("running SPARQL"));
+    }
+
+    #[test]
+    fn format_count() {
+        assert_eq!(Progress::format_count(0), "0");
+        assert_eq!(Progress::format_count(999), "999");
+        assert_eq!(Progress::format_count(12000), "12,000");
+        assert_eq!(Progress::format_count(1234567), "1,234,567");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+
+static PROPERTY_HARVEST_BATCH_SIZE: usize = 50;
+static PROPERTY_HARVEST_CONCURRENT_REQUESTS: usize = 3;
+
+/// Property values (eg for "P31,P569") of Wikidata items, fetched via wbgetentities in batches.
+/// Each is returned as a plain string: an item ID, a string, a time, an amount, or "lat,lon".
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyHarvest {
+    properties: Vec<String>,
+}
+
+impl PropertyHarvest {
+    /// Property IDs may be separated by commas or whitespace; returns None if there are no (valid) property IDs
+    pub fn new(properties: &[String]) -> Option<Self> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^[Pp]\d+$").expect("PropertyHarvest::new RE");
+        }
+        let mut ret: Vec<String> = vec![];
+        properties
+            .iter()
+            .flat_map(|s| s.split(|c: char| c == ',' || c.is_whitespace()))
+            .map(|s| s.trim().to_uppercase())
+            .filter(|s| RE.is_match(s))
+            .for_each(|p| {
+                if !ret.contains(&p) {
+                    ret.push(p)
+                }
+            });
+        if ret.is_empty() {
+            return None;
+        }
+        Some(Self { properties: ret })
+    }
+
+    pub fn properties(&self) -> &Vec<String> {
+        &self.properties
+    }
+
+    /// Item => property => values, from the Wikidata API at `api_url`
+    pub async fn run(
+        &self,
+        http: &HttpClient,
+        api_url: &str,
+        items: &[String],
+    ) -> Result<HashMap<String, HashMap<String, Vec<String>>>, String> {
+        let mut ret = HashMap::new();
+        let batches: Vec<&[String]> = items.chunks(PROPERTY_HARVEST_BATCH_SIZE).collect();
+        for group in batches.chunks(PROPERTY_HARVEST_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.run_batch(http, api_url, batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn run_batch(
+        &self,
+        http: &HttpClient,
+        api_url: &str,
+        items: &[String],
+    ) -> Result<HashMap<String, HashMap<String, Vec<String>>>, String> {
+        let params = [
+            ("action", "wbgetentities".to_string()),
+            ("ids", items.join("|")),
+            ("props", "claims".to_string()),
+            ("format", "json".to_string()),
+        ];
+        let json: Value = http
+            .send(http.post(api_url).form(&params))
+            .await
+            .map_err(|e| format!("Property harvest: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Property harvest: {:?}", e))?;
+        Ok(self.values_from_result(&json))
+    }
+
+    fn values_from_result(&self, json: &Value) -> HashMap<String, HashMap<String, Vec<String>>> {
+        let entities = match json["entities"].as_object() {
+            Some(entities) => entities,
+            None => return HashMap::new(),
+        };
+        entities
+            .iter()
+            .map(|(item, entity)| {
+                let values = self
+                    .properties
+                    .iter()
+                    .map(|p| {
+                        let values: Vec<String> = match entity["claims"][p].as_array() {
+                            Some(statements) => statements
+                                .iter()
+                                .filter(|statement| statement["rank"].as_str() != Some("deprecated"))
+                                .filter_map(|statement| Self::value_as_string(&statement["mainsnak"]["datavalue"]))
+                                .collect(),
+                            None => vec![],
+                        };
+                        (p.to_owned(), values)
+                    })
+                    .collect();
+                (item.to_owned(), values)
+            })
+            .collect()
+    }
+
+    fn value_as_string(datavalue: &Value) -> Option<String> {
+        let value = &datavalue["value"];
+        match datavalue["type"].as_str()? {
+            "string" => value.as_str().map(|s| s.to_string()),
+            "wikibase-entityid" => value["id"].as_str().map(|s| s.to_string()),
+            "time" => value["time"].as_str().map(|s| s.to_string()),
+            "quantity" => value["amount"].as_str().map(|s| s.trim_start_matches('+').to_string()),
+            "monolingualtext" => value["text"].as_str().map(|s| s.to_string()),
+            "globecoordinate" => Some(format!("{},{}", value["latitude"].as_f64()?, value["longitude"].as_f64()?)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new() {
+        let ph = PropertyHarvest::new(&["P31, p569".to_string(), "Q5 P31".to_string()]).unwrap();
+        assert_eq!(ph.properties(), &vec!["P31".to_string(), "P569".to_string()]);
+        assert!(PropertyHarvest::new(&["foo".to_string()]).is_none());
+    }
+
+    #[test]
+    fn values_from_result() {
+        let ph = PropertyHarvest::new(&["P31,P625,P1082,P18".to_string()]).unwrap();
+        let j = json!({"entities":{"Q64":{"claims":{
+            "P31":[
+                {"rank":"normal","mainsnak":{"datavalue":{"type":"wikibase-entityid","value":{"id":"Q515"}}}},
+                {"rank":"deprecated","mainsnak":{"datavalue":{"type":"wikibase-entityid","value":{"id":"Q1"}}}},
+                {"rank":"normal","mainsnak":{"snaktype":"novalue"}}
+            ],
+            "P625":[{"rank":"normal","mainsnak":{"datavalue":{"type":"globecoordinate","value":{"latitude":52.5,"longitude":13.4}}}}],
+            "P1082":[{"rank":"preferred","mainsnak":{"datavalue":{"type":"quantity","value":{"amount":"+3644826"}}}}]
+        }}}});
+        let values = ph.values_from_result(&j);
+        let q64 = &values["Q64"];
+        assert_eq!(q64["P31"], vec!["Q515".to_string()]);
+        assert_eq!(q64["P625"], vec!["52.5,13.4".to_string()]);
+        assert_eq!(q64["P1082"], vec!["3644826".to_string()]);
+        assert!(q64["P18"].is_empty());
+    }
+}
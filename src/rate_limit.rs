@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static MAX_BUCKETS_BEFORE_PURGE: usize = 10000;
+
+/// Token bucket rate limiting, per client key (eg IP address)
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_minute: f64,
+    burst: f64,
+    /// client key => (tokens left, last update)
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// `requests_per_minute` of 0 disables rate limiting; `burst` is the bucket size
+    pub fn new(requests_per_minute: u64, burst: u64) -> Self {
+        Self {
+            requests_per_minute: requests_per_minute as f64,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn new_from_config(config: &serde_json::Value) -> Self {
+        let requests_per_minute = config["rate_limit"]["requests_per_minute"].as_u64().unwrap_or(0);
+        let burst = config["rate_limit"]["burst"].as_u64().unwrap_or(requests_per_minute);
+        Self::new(requests_per_minute, burst)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_minute > 0.0
+    }
+
+    /// Takes a token for `key`; returns the time until the next token is available, if there is none left
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let per_second = self.requests_per_minute / 60.0;
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(_) => return Ok(()), // Do not block anyone because of a poisoned lock
+        };
+        if buckets.len() > MAX_BUCKETS_BEFORE_PURGE {
+            let burst = self.burst;
+            buckets.retain(|_, (tokens, last)| {
+                *tokens + now.saturating_duration_since(*last).as_secs_f64() * per_second < burst
+            });
+        }
+        let (tokens, last) = buckets.entry(key.to_string()).or_insert((self.burst, now));
+        *tokens = (*tokens + now.saturating_duration_since(*last).as_secs_f64() * per_second).min(self.burst);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_empties_and_refills() {
+        let rl = RateLimiter::new(60, 2);
+        let now = Instant::now();
+        assert!(rl.check_at("a", now).is_ok());
+        assert!(rl.check_at("a", now).is_ok());
+        let wait = rl.check_at("a", now).unwrap_err();
+        assert!(wait.as_secs_f64() > 0.9 && wait.as_secs_f64() <= 1.0);
+        assert!(rl.check_at("b", now).is_ok());
+        assert!(rl.check_at("a", now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn disabled() {
+        let rl = RateLimiter::new_from_config(&json!({}));
+        assert!(!rl.is_enabled());
+        assert!((0..100).all(|_| rl.check("a").is_ok()));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+static REFERENCES_BATCH_SIZE: usize = 50;
+static REFERENCES_CONCURRENT_REQUESTS: usize = 3;
+pub static DEFAULT_CITATION_NEEDED_TEMPLATES: &[&str] = &["Citation needed", "Cn", "Fact", "Citation missing"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReferenceCount {
+    pub references: u64,
+    pub citation_needed: u64,
+}
+
+/// Counts `<ref>` tags and "citation needed" template uses in the current wikitext of pages, fetched via the API.
+/// Commented-out wikitext is ignored; references generated by templates (eg {{sfn}}) are not counted.
+pub struct References {
+    http: Arc<HttpClient>,
+    api_url: String,
+    re_citation_needed: Regex,
+}
+
+impl References {
+    /// `server_url` is eg "https://en.wikipedia.org"; `templates` are the names of the "citation needed" templates
+    /// and their redirects, without namespace
+    pub fn new(http: Arc<HttpClient>, server_url: &str, templates: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            http,
+            api_url: format!("{}/w/api.php", server_url.trim_end_matches('/')),
+            re_citation_needed: Self::citation_needed_regex(templates)?,
+        })
+    }
+
+    /// Full titles (with underscores) => counts; pages that do not exist, or whose wikitext could not be loaded,
+    /// are left out, so their counts are unknown rather than 0
+    pub async fn get_counts(&self, titles: &[String]) -> Result<HashMap<String, ReferenceCount>, String> {
+        let mut ret = HashMap::new();
+        let batches: Vec<&[String]> = titles.chunks(REFERENCES_BATCH_SIZE).collect();
+        for group in batches.chunks(REFERENCES_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.get_counts_batch(batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// The API returns the wikitext of only as many pages as fit into one response, and a `continue` for the rest
+    async fn get_counts_batch(&self, titles: &[String]) -> Result<HashMap<String, ReferenceCount>, String> {
+        let mut ret = HashMap::new();
+        let mut continue_params: Vec<(String, String)> = vec![];
+        loop {
+            let mut params: Vec<(String, String)> = vec![
+                ("action".to_string(), "query".to_string()),
+                ("prop".to_string(), "revisions".to_string()),
+                ("rvprop".to_string(), "content".to_string()),
+                ("rvslots".to_string(), "main".to_string()),
+                ("titles".to_string(), titles.join("|")),
+                ("format".to_string(), "json".to_string()),
+                ("formatversion".to_string(), "2".to_string()),
+            ];
+            params.append(&mut continue_params);
+            let json: Value = self
+                .http
+                .send(self.http.post(&self.api_url).form(&params))
+                .await
+                .map_err(|e| format!("References: {}", e))?
+                .json()
+                .await
+                .map_err(|e| format!("References: {:?}", e))?;
+            ret.extend(self.counts_from_result(&json));
+            continue_params = Self::continue_params(&json);
+            if continue_params.is_empty() {
+                return Ok(ret);
+            }
+        }
+    }
+
+    fn continue_params(json: &Value) -> Vec<(String, String)> {
+        match json["continue"].as_object() {
+            Some(o) => o
+                .iter()
+                .filter_map(|(k, v)| match v {
+                    Value::String(s) => Some((k.to_owned(), s.to_owned())),
+                    Value::Number(n) => Some((k.to_owned(), n.to_string())),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn counts_from_result(&self, json: &Value) -> HashMap<String, ReferenceCount> {
+        match json["query"]["pages"].as_array() {
+            Some(pages) => pages
+                .iter()
+                .filter_map(|page| {
+                    let title = page["title"].as_str()?.replace(' ', "_");
+                    let wikitext = page["revisions"][0]["slots"]["main"]["content"].as_str()?;
+                    Some((title, self.count_in_wikitext(wikitext)))
+                })
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+
+    fn count_in_wikitext(&self, wikitext: &str) -> ReferenceCount {
+        lazy_static! {
+            static ref RE_COMMENT: Regex = Regex::new(r"(?s)<!--.*?(-->|$)").expect("References RE_COMMENT");
+            static ref RE_REF: Regex = Regex::new(r"(?i)<ref[\s>/]").expect("References RE_REF");
+        }
+        let wikitext = RE_COMMENT.replace_all(wikitext, "");
+        ReferenceCount {
+            references: RE_REF.find_iter(&wikitext).count() as u64,
+            citation_needed: self.re_citation_needed.find_iter(&wikitext).count() as u64,
+        }
+    }
+
+    /// Template names match case-insensitively on the first letter, and with spaces or underscores
+    fn citation_needed_regex(templates: &[String]) -> Result<Regex, String> {
+        let names: Vec<String> = templates
+            .iter()
+            .map(|t| t.trim().replace('_', " "))
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                let mut chars = t.chars();
+                let first = chars.next().map(|c| c.to_string()).unwrap_or_default();
+                format!(
+                    "(?i:{}){}",
+                    regex::escape(&first),
+                    regex::escape(chars.as_str()).replace(' ', "[ _]+")
+                )
+            })
+            .collect();
+        if names.is_empty() {
+            return Err("References: no citation needed templates".to_string());
+        }
+        Regex::new(&format!(r"\{{\{{\s*(?:{})\s*[|}}]", names.join("|"))).map_err(|e| format!("References: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn references() -> References {
+        let templates: Vec<String> = DEFAULT_CITATION_NEEDED_TEMPLATES.iter().map(|s| s.to_string()).collect();
+        References::new(Arc::new(HttpClient::new_from_config(&json!({})).unwrap()), "https://en.wikipedia.org", &templates).unwrap()
+    }
+
+    #[test]
+    fn count_in_wikitext() {
+        let wikitext = "Foo.<ref>A</ref> Bar.<ref name=\"b\">B</ref> Baz.<ref name=b /> <!-- <ref>C</ref> {{cn}} -->\
+            Claim.{{citation needed|date=May 2020}} Other.{{Citation_needed}} {{Cnx}} {{fact}}\n==References==\n<references />";
+        let count = references().count_in_wikitext(wikitext);
+        assert_eq!(count, ReferenceCount { references: 3, citation_needed: 3 });
+    }
+
+    #[test]
+    fn counts_from_result() {
+        let j = json!({"query":{"pages":[
+            {"ns":0,"title":"Foo bar","revisions":[{"slots":{"main":{"content":"A<ref>x</ref>{{Cn}}"}}}]},
+            {"ns":0,"title":"Nope","missing":true}
+        ]}});
+        let counts = references().counts_from_result(&j);
+        assert_eq!(counts.get("Foo_bar"), Some(&ReferenceCount { references: 1, citation_needed: 1 }));
+        assert_eq!(counts.len(), 1);
+    }
+
+    #[test]
+    fn continue_params() {
+        let j = json!({"continue":{"rvcontinue":"12345|678","continue":"||"},"query":{"pages":[
+            {"ns":0,"title":"Not loaded","revisions":[{}]}
+        ]}});
+        let mut params = References::continue_params(&j);
+        params.sort();
+        assert_eq!(
+            params,
+            vec![
+                ("continue".to_string(), "||".to_string()),
+                ("rvcontinue".to_string(), "12345|678".to_string())
+            ]
+        );
+        assert!(references().counts_from_result(&j).is_empty());
+        assert!(References::continue_params(&json!({"batchcomplete":true})).is_empty());
+    }
+}
@@ -1,10 +1,13 @@
 use async_trait::async_trait;
 use crate::app_state::AppState;
 use crate::form_parameters::FormParameters;
-use crate::pagelist::{LinkCount, PageListEntry};
+use crate::graph::{Graph, GraphFormat};
+use crate::page_url::page_url;
+use crate::pagelist::{LinkCount, PageLengthStatistics, PageListEntry};
 use crate::platform::*;
 use chrono::prelude::*;
-use htmlescape::encode_minimal;
+use htmlescape::{encode_attribute, encode_minimal};
+use regex::Regex;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -15,12 +18,15 @@ use wikibase::mediawiki::title::Title;
 static MAX_HTML_RESULTS: usize = 10000;
 static AUTOLIST_WIKIDATA: &str = "www.wikidata.org";
 static AUTOLIST_COMMONS: &str = "commons.wikimedia.org";
+static PAGEPILE_CHUNK_SIZE: usize = 20000;
+static PAGEPILE_CHUNK_ATTEMPTS: u32 = 3;
 
 //________________________________________________________________________________________________________________________
 
 #[derive(Debug, Clone)]
 pub struct RenderParams {
     wiki: String,
+    server: String,
     file_data: bool,
     file_usage: bool,
     thumbnails_in_wiki_output: bool,
@@ -29,11 +35,29 @@ pub struct RenderParams {
     is_wikidata: bool,
     add_coordinates: bool,
     add_image: bool,
+    add_image_url: bool,
     add_defaultsort: bool,
+    add_sortkey: bool,
     add_disambiguation: bool,
     add_incoming_links: bool,
     add_sitelinks: bool,
     do_output_redlinks: bool,
+    add_redlinks_in_page: bool,
+    add_outlink_count: bool,
+    add_pageviews: bool,
+    add_last_edit_age: bool,
+    add_watchers: bool,
+    add_global_usage: bool,
+    add_references: bool,
+    add_assessment: bool,
+    add_infoboxes: bool,
+    add_short_description: bool,
+    add_duplicate_titles: bool,
+    add_category_membership: bool,
+    add_redirect_target: bool,
+    add_redirect_problem: bool,
+    add_file_duplicates: bool,
+    harvest_properties: Vec<String>,
     use_autolist: bool,
     autolist_creator_mode: bool,
     autolist_wiki_server: String,
@@ -45,6 +69,7 @@ pub struct RenderParams {
     json_sparse: bool,
     json_pretty: bool,
     giu: bool,
+    full_titles: bool,
 }
 
 impl RenderParams {
@@ -52,19 +77,47 @@ impl RenderParams {
         let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
         let mut ret = Self {
             wiki: wiki.to_string(),
+            server: platform.state().get_server_url_for_wiki(wiki).unwrap_or_default(),
             file_data: platform.has_param("ext_image_data"),
             file_usage: platform.has_param("file_usage_data"),
             thumbnails_in_wiki_output: platform.has_param("thumbnails_in_wiki_output"),
             wdi: platform.get_param_default("wikidata_item", "no"),
             add_coordinates: platform.has_param("add_coordinates"),
             add_image: platform.has_param("add_image")||platform.get_param_blank("format")=="kml",
+            add_image_url: platform.has_param("add_image_url"),
             add_defaultsort: platform.has_param("add_defaultsort"),
+            add_sortkey: platform.has_param("add_sortkey"),
             add_disambiguation: platform.has_param("add_disambiguation"),
             add_incoming_links: platform.get_param_blank("sortby") == "incoming_links",
             add_sitelinks: platform.get_param_blank("sortby") == "sitelinks",
             show_wikidata_item: false,
             is_wikidata: wiki == "wikidatawiki",
             do_output_redlinks: platform.do_output_redlinks(),
+            add_redlinks_in_page: platform.has_param("pages_with_redlinks"),
+            add_outlink_count: platform.has_param("add_outlink_count"),
+            add_assessment: platform.has_param("add_assessment")
+                || !platform.is_param_blank("assessment_class")
+                || !platform.is_param_blank("assessment_importance"),
+            add_infoboxes: !platform.is_param_blank("infobox_templates"),
+            add_short_description: platform.has_param("add_shortdesc"),
+            add_duplicate_titles: platform.has_param("duplicate_titles"),
+            add_category_membership: platform.has_param("add_category_membership"),
+            add_redirect_target: platform.has_param("add_redirect_target"),
+            add_redirect_problem: platform.has_param("redirect_problems"),
+            add_file_duplicates: platform.has_param("file_duplicates"),
+            add_pageviews: platform.has_param("add_pageviews")
+                || platform.get_param_blank("sortby") == "views",
+            add_last_edit_age: platform.has_param("add_last_edit_age")
+                || !platform.is_param_blank("not_edited_for")
+                || platform.get_param_blank("sortby") == "last_edit_age",
+            add_watchers: platform.has_param("add_watchers")
+                || platform.get_param_blank("sortby") == "watchers",
+            add_global_usage: platform.has_param("add_global_usage"),
+            add_references: platform.has_param("add_references"),
+            harvest_properties: platform
+                .property_harvest()
+                .map(|harvest| harvest.properties().to_owned())
+                .unwrap_or_default(),
             use_autolist: false,          // Possibly set downstream
             autolist_creator_mode: false, // Possibly set downstream
             autolist_wiki_server: AUTOLIST_WIKIDATA.to_string(), // Possibly set downstream
@@ -77,10 +130,29 @@ impl RenderParams {
             json_sparse: platform.has_param("sparse"),
             json_pretty: platform.has_param("json-pretty"),
             giu: platform.has_param("giu"),
+            full_titles: platform.has_param("full_titles"),
         };
         ret.show_wikidata_item = ret.wdi == "any" || ret.wdi == "with";
         Ok(ret)
     }
+
+    /// The absolute URL of a page on the wiki of the result
+    pub fn page_url(&self, title: &Title) -> Option<String> {
+        if self.server.is_empty() {
+            return None;
+        }
+        Some(page_url(&self.server, &title.full_with_underscores(&self.api)?))
+    }
+
+    /// The title for display, with spaces; with the localized namespace prefix (eg "Kategorie:Berlin") for "full_titles"
+    pub fn display_title(&self, title: &Title) -> String {
+        if self.full_titles {
+            if let Some(full_title) = title.full_pretty(&self.api) {
+                return full_title;
+            }
+        }
+        title.pretty().to_string()
+    }
 }
 
 //________________________________________________________________________________________________________________________
@@ -108,7 +180,7 @@ pub trait Render {
         ]
     }
 
-    fn get_initial_columns(&self, params: &RenderParams) -> Vec<&str> {
+    fn get_initial_columns<'a>(&'a self, params: &'a RenderParams) -> Vec<&'a str> {
         let mut columns = vec![];
         if params.use_autolist {
             columns.push("checkbox");
@@ -117,6 +189,9 @@ pub trait Render {
         if params.add_image {
             columns.push("image");
         }
+        if params.add_image_url {
+            columns.push("image_url");
+        }
         columns.push("title");
         if params.do_output_redlinks {
             //columns.push("namespace");
@@ -126,6 +201,53 @@ pub trait Render {
             columns.push("namespace");
             columns.push("size");
             columns.push("timestamp");
+            if params.add_redlinks_in_page {
+                columns.push("redlinks_in_page");
+            }
+            if params.add_outlink_count {
+                columns.push("linknumber");
+            }
+            if params.add_assessment {
+                columns.push("assessment_class");
+            }
+            if params.add_infoboxes {
+                columns.push("infoboxes");
+            }
+            if params.add_duplicate_titles {
+                columns.push("duplicate_titles");
+            }
+            if params.add_category_membership {
+                columns.push("category_membership");
+            }
+            if params.add_redirect_target {
+                columns.push("redirect_target");
+            }
+            if params.add_redirect_problem {
+                columns.push("redirect_problem");
+            }
+            if params.add_file_duplicates {
+                columns.push("file_duplicates");
+            }
+            if params.add_short_description {
+                columns.push("short_description");
+            }
+            if params.add_pageviews {
+                columns.push("pageviews");
+            }
+            if params.add_last_edit_age {
+                columns.push("last_edit_age");
+            }
+            if params.add_watchers {
+                columns.push("watchers");
+            }
+            if params.add_global_usage {
+                columns.push("global_usage_wikis");
+                columns.push("global_usage_pages");
+            }
+            if params.add_references {
+                columns.push("references");
+                columns.push("citation_needed");
+            }
         }
         if params.show_wikidata_item {
             columns.push("wikidata_item");
@@ -136,6 +258,9 @@ pub trait Render {
         if params.add_defaultsort {
             columns.push("defaultsort");
         }
+        if params.add_sortkey {
+            columns.push("sortkey");
+        }
         if params.add_disambiguation {
             columns.push("disambiguation");
         }
@@ -151,6 +276,7 @@ pub trait Render {
         if params.file_usage {
             columns.push("fileusage");
         }
+        params.harvest_properties.iter().for_each(|p| columns.push(p));
         columns
     }
 
@@ -159,6 +285,9 @@ pub trait Render {
     fn render_user_name(&self, _user: &String, _params: &RenderParams) -> String;
     fn render_cell_image(&self, _image: &Option<String>, _params: &RenderParams) -> String;
     fn render_cell_namespace(&self, _entry: &PageListEntry, _params: &RenderParams) -> String;
+    fn render_cell_url(&self, url: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(url)
+    }
     fn render_cell_checkbox(
         &self,
         _entry: &PageListEntry,
@@ -217,6 +346,15 @@ pub trait Render {
         o.as_ref().map(|x| x.to_string()).unwrap_or_else(String::new)
     }
 
+    /// Free text that anyone can edit (descriptions, sort keys, statement values etc.); raw, except in HTML
+    fn escape_text(&self, s: &str) -> String {
+        s.to_string()
+    }
+
+    fn opt_text(&self, o: &Option<String>) -> String {
+        self.escape_text(&self.opt_string(o))
+    }
+
     fn row_from_entry(
         &self,
         entry: &PageListEntry,
@@ -234,8 +372,10 @@ pub trait Render {
                 "timestamp" => self.opt_string(&entry.get_page_timestamp()),
                 "wikidata_item" => self.render_cell_wikidata_item(entry, params),
                 "image" => self.render_cell_image(&entry.get_page_image(), params),
+                "image_url" => self.render_cell_url(&entry.get_image_url(), params),
                 "number" => params.row_number.to_string(),
                 "defaultsort" => self.opt_string(&entry.get_defaultsort()),
+                "sortkey" => self.opt_text(&entry.get_sortkey()),
                 "disambiguation" => self.opt_bool(&entry.disambiguation.as_option_bool()),
                 "incoming_links" => self.opt_linkcount(&entry.incoming_links),
                 "sitelinks" => self.opt_linkcount(&entry.sitelink_count),
@@ -286,8 +426,55 @@ pub trait Render {
                     Some(lc) => format!("{}", &lc),
                     None => String::new(),
                 },
+                "redlinks_in_page" => self.opt_linkcount(&entry.redlinks_in_page),
+                "pageviews" => entry.page_views.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "last_edit_age" => entry.last_edit_age_days.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "watchers" => entry.watchers.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "global_usage_wikis" => entry.global_usage_wikis.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "global_usage_pages" => entry.global_usage_pages.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "references" => entry.references.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "citation_needed" => entry.citation_needed.map(|x| x.to_string()).unwrap_or_else(String::new),
+                "assessment_class" => match &entry.get_assessment() {
+                    Some(assessment) => assessment.classes.join(", "),
+                    None => String::new(),
+                },
+                "infoboxes" => match &entry.get_infoboxes() {
+                    Some(infoboxes) => match infoboxes.len() {
+                        0 => "none".to_string(),
+                        1 => infoboxes[0].to_owned(),
+                        _ => format!("multiple: {}", infoboxes.join(", ")),
+                    },
+                    None => String::new(),
+                },
+                "duplicate_titles" => match &entry.get_duplicate_titles() {
+                    Some(duplicates) => duplicates.join(", "),
+                    None => String::new(),
+                },
+                "category_membership" => match &entry.get_category_membership() {
+                    Some(membership) => membership.join("; "),
+                    None => String::new(),
+                },
+                "redirect_target" => self.opt_text(&entry.get_redirect_target()),
+                "file_duplicates" => match &entry.get_file_duplicates() {
+                    Some(duplicates) => duplicates.join(", "),
+                    None => String::new(),
+                },
+                "redirect_problem" => match &entry.get_redirect_problem() {
+                    Some(problem) => problem.describe(&params.api),
+                    None => String::new(),
+                },
+                "short_description" => self.opt_text(&entry.get_short_description()),
                 "coordinates" => self.render_coordinates(entry, params),
                 "fileusage" => self.render_cell_fileusage(&entry, &params),
+                p if params.harvest_properties.iter().any(|x| x == p) => {
+                    entry
+                        .get_property_values(p)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|value| self.escape_text(value))
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                }
 
                 _ => "<".to_string() + k + ">",
             };
@@ -321,6 +508,9 @@ impl Render for RenderWiki {
 
         let utc: DateTime<Utc> = Utc::now();
         rows.push(format!("Last updated on {}.", utc.to_rfc2822()));
+        if platform.is_partial_result() {
+            rows.push("'''Partial result:''' the query ran out of time; not all pages are listed.".to_string());
+        }
 
         rows.push(format!(
             "[{} Regenerate this table] or [{} edit the query].\n",
@@ -376,6 +566,8 @@ impl Render for RenderWiki {
         Ok(MyResponse {
             s: rows.join("\n"),
             content_type: ContentType::Plain,
+            filename: None,
+            partial_result: false,
         })
     }
 
@@ -440,7 +632,7 @@ impl RenderWiki {
                 .title()
                 .full_pretty(&params.api)
                 .unwrap_or_else(|| entry.title().pretty().to_string());
-            if !params.do_output_redlinks {
+            if !params.do_output_redlinks && !params.full_titles {
                 ret += "|";
             }
             ret += "]]";
@@ -514,11 +706,17 @@ impl Render for RenderTSV {
                 "\t" => ContentType::TSV,
                 _ => ContentType::Plain, // Fallback
             },
+            filename: None,
+            partial_result: false,
         })
     }
 
-    fn render_cell_title(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
-        entry.title().with_underscores()
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        if params.full_titles {
+            params.display_title(entry.title())
+        } else {
+            entry.title().with_underscores()
+        }
     }
 
     fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
@@ -571,6 +769,10 @@ pub struct RenderHTML {}
 
 #[async_trait]
 impl Render for RenderHTML {
+    fn escape_text(&self, s: &str) -> String {
+        encode_minimal(s)
+    }
+
     async fn response(
         &self,
         platform: &Platform,
@@ -633,15 +835,31 @@ impl Render for RenderHTML {
             "<h2><a name='results'></a><span id='num_results' num='{}'></span></h2>",
             entries.len()
         ));
+        if !params.do_output_redlinks && !entries.is_empty() {
+            let stats = PageLengthStatistics::new_from_entries(&entries);
+            rows.push(format!(
+                "<div id='page_length_statistics' style='font-size:9pt'><span tt='stats_total_bytes'></span>: {} &middot; <span tt='stats_median_bytes'></span>: {}</div>",
+                stats.total_bytes,
+                self.opt_u32(&stats.median_bytes)
+            ));
+        }
 
+        if platform.is_partial_result() {
+            rows.push("<div id='partial_result' class='alert alert-danger' style='clear:both'><b><span tt='partial_result'></span></b></div>".to_string());
+        }
         for warning in platform.warnings()? {
             rows.push(format!(
                 "<div class='alert alert-warning' style='clear:both'>{}</div>",
                 warning
             ));
         }
+        rows.push(self.render_effective_parameters(platform));
 
         let header = self.get_initial_columns(&params);
+        if !entries.is_empty() {
+            rows.push("<div id='results_tools' class='form-inline' style='clear:both'><input type='text' class='form-control form-control-sm' id='results_filter' tt_placeholder='results_filter' /> <span id='results_selected_count' class='smaller'></span> <button class='btn btn-sm btn-light' id='results_copy_selected' tt='copy_selected_titles'></button></div>".to_string());
+            rows.push("<textarea id='results_selected_titles' class='form-control' rows='3' readonly style='display:none'></textarea>".to_string());
+        }
         rows.push("<div style='clear:both;overflow:auto'>".to_string());
         rows.push(self.get_table_header(&header, &params));
         rows.push("<tbody>".to_string());
@@ -657,7 +875,8 @@ impl Render for RenderHTML {
             if params.row_number < MAX_HTML_RESULTS {
                 params.row_number += 1;
                 let row = self.row_from_entry(&entry, &header, &params, &platform);
-                let row = self.render_html_row(&row, &header);
+                let full_title = entry.title().full_with_underscores(&params.api).unwrap_or_default();
+                let row = self.render_html_row(&row, &header, &full_title);
                 output += &row;
             }
         });
@@ -676,10 +895,23 @@ impl Render for RenderHTML {
                 seconds
             ));
         }
+        if let Some(token) = &platform.result_token {
+            let links: Vec<String> = ["csv", "tsv", "json", "wiki", "plain", "kml"]
+                .iter()
+                .map(|format| format!("<a href='/result/{}?format={}'>{}</a>", token, format, format))
+                .collect();
+            rows.push(format!(
+                "<div style='font-size:8pt' id='result_token' data-token='{}'><span tt='download_result_as'></span> {}</div>",
+                token,
+                links.join(" | ")
+            ));
+        }
         rows.push("<script src='autolist.js'></script>".to_string());
+        rows.push("<script src='results.js'></script>".to_string());
         output += &rows.join("\n");
         let interface_language = platform.get_param_default("interface_language", "en");
         let state = platform.state();
+        let output = state.localize_html(&interface_language, &output);
         let html = state.get_main_page(interface_language);
         let html = html.replace(
             "<!--querystring-->",
@@ -694,6 +926,8 @@ impl Render for RenderHTML {
         Ok(MyResponse {
             s: html,
             content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
         })
     }
 
@@ -735,7 +969,7 @@ impl Render for RenderHTML {
                     _ => return String::new(),
                 };
                 let file = self.escape_attribute(img);
-                let url = format!("{}/wiki/File:{}", &server_url, &file);
+                let url = page_url(&server_url, &format!("File:{}", img));
                 let src = format!(
                     "{}/wiki/Special:Redirect/file/{}?width={}",
                     &server_url, &file, &thumnail_size
@@ -758,6 +992,12 @@ impl Render for RenderHTML {
         }
     }
 
+    fn render_cell_url(&self, url: &Option<String>, _params: &RenderParams) -> String {
+        match url {
+            Some(url) => format!("<a target='_blank' href='{}'>{}</a>", encode_attribute(url), encode_minimal(url)),
+            None => String::new(),
+        }
+    }
     fn render_cell_fileusage(&self, entry: &PageListEntry, params: &RenderParams) -> String {
         match &entry.get_file_info() {
             Some(fi) => {
@@ -820,8 +1060,20 @@ impl Render for RenderHTML {
     ) -> String {
         let mut q = String::new();
         let checked: &str;
+        let mut duplicates = String::new();
         if params.autolist_creator_mode {
-            if platform.label_exists(&entry.title().pretty().to_string()) || entry.title().pretty().contains('(') {
+            let possible_duplicates = platform.possible_duplicates(&entry.title().pretty().to_string());
+            if !possible_duplicates.is_empty() {
+                let links: Vec<String> = possible_duplicates
+                    .iter()
+                    .map(|q| format!("<a href='https://www.wikidata.org/wiki/{}' target='_blank'>{}</a>", q, q))
+                    .collect();
+                duplicates = format!(
+                    " <span class='smaller possible_duplicate'><span tt='possible_duplicate'></span> {}</span>",
+                    links.join(", ")
+                );
+            }
+            if platform.label_exists(&entry.title().pretty().to_string()) || entry.title().pretty().contains('(') || !possible_duplicates.is_empty() {
                 checked = "";
             } else {
                 checked = "checked";
@@ -848,8 +1100,8 @@ impl Render for RenderHTML {
             checked = "checked";
         };
         format!(
-            "<input type='checkbox' class='qcb' q='{}' id='autolist_checkbox_{}' {} />",
-            &q, &q, &checked
+            "<input type='checkbox' class='qcb' q='{}' id='autolist_checkbox_{}' {} />{}",
+            &q, &q, &checked, &duplicates
         )
     }
 }
@@ -859,6 +1111,35 @@ impl RenderHTML {
         Box::new(Self {})
     }
 
+    /// A collapsed table of the parameters as they were used, after legacy mapping and PSID rebase
+    fn render_effective_parameters(&self, platform: &Platform) -> String {
+        let rows: Vec<String> = match platform.form_parameters().effective_parameters().as_object() {
+            Some(parameters) => parameters
+                .iter()
+                .map(|(k, v)| {
+                    let value = match v {
+                        Value::Array(values) => values
+                            .iter()
+                            .filter_map(|v| v.as_str())
+                            .collect::<Vec<&str>>()
+                            .join("\n"),
+                        v => v.as_str().unwrap_or_default().to_string(),
+                    };
+                    format!(
+                        "<tr><th>{}</th><td style='white-space:pre-wrap'>{}</td></tr>",
+                        encode_minimal(k),
+                        encode_minimal(&value)
+                    )
+                })
+                .collect(),
+            None => vec![],
+        };
+        format!(
+            "<details id='effective_parameters' style='clear:both'><summary tt='effective_parameters'></summary><table class='table table-sm'>{}</table></details>",
+            rows.join("")
+        )
+    }
+
     fn escape_attribute(&self, s: &str) -> String {
         FormParameters::percent_encode(s)
             .replace('<', "&lt;")
@@ -889,11 +1170,11 @@ impl RenderHTML {
             Some(ft) => ft,
             None => format!("{:?}", title),
         };
-        let url = server + "/wiki/" + &self.escape_attribute(&full_title);
+        let url = page_url(&server, &full_title);
         let label = match alt_label {
             Some(label) => label.to_string(),
             None => match is_page_link {
-                true => title.pretty().to_string(),
+                true => params.display_title(title),
                 false => full_title_pretty,
             },
         };
@@ -909,15 +1190,16 @@ impl RenderHTML {
         if is_page_link && wiki == "wikidatawiki" && title.namespace_id() == 0 {
             ret += &format!("&nbsp;<small><tt>[{}]</tt></small>", title.pretty());
             match &wikidata_description {
-                Some(desc) => ret += &format!("<div class='smaller'>{}</div>", &desc),
+                Some(desc) => ret += &format!("<div class='smaller'>{}</div>", encode_minimal(desc)),
                 None => {}
             }
         }
         ret
     }
 
-    fn render_html_row(&self, row: &[String], header: &[(String, String)]) -> String {
-        let mut ret = "<tr>".to_string();
+    /// The full title is used by results.js, to select and copy titles
+    fn render_html_row(&self, row: &[String], header: &[(String, String)], full_title: &str) -> String {
+        let mut ret = format!("<tr data-title='{}'>", encode_attribute(full_title));
         for (col_num, item) in row.iter().enumerate() {
             let header_key = match header.get(col_num) {
                 Some(x) => x.0.to_string(),
@@ -942,7 +1224,7 @@ impl RenderHTML {
         ret
     }
 
-    fn get_table_header(&self, columns: &[&str], _params: &RenderParams) -> String {
+    fn get_table_header(&self, columns: &[&str], params: &RenderParams) -> String {
         let mut ret = "<table class='table table-sm table-striped' id='main_table'>".to_string();
         ret += "<thead><tr>";
         let fdk = self.file_data_keys();
@@ -952,16 +1234,34 @@ impl RenderHTML {
                 "checkbox" => "<th></th>".to_string(),
                 "number" => "<th class='text-right text-monospace'>#</th>".to_string(),
                 "image" => "<th tt='h_image'></th>".to_string(),
+                "image_url" => "<th tt='h_image_url'></th>".to_string(),
                 "title" => "<th class='text-nowrap' tt='h_title'></th>".to_string(),
                 "page_id" => "<th class='text-nowrap' tt='h_id'></th>".to_string(),
                 "namespace" => "<th class='text-nowrap' tt='h_namespace'></th>".to_string(),
                 "linknumber" => "<th tt='link_number'></th>".to_string(),
                 "redlink_count" => "<th tt='link_number'></th>".to_string(),
+                "redlinks_in_page" => "<th tt='h_redlinks_in_page'></th>".to_string(),
+                "pageviews" => "<th class='text-nowrap' tt='h_pageviews'></th>".to_string(),
+                "last_edit_age" => "<th class='text-nowrap' tt='h_last_edit_age'></th>".to_string(),
+                "watchers" => "<th class='text-nowrap' tt='h_watchers'></th>".to_string(),
+                "global_usage_wikis" => "<th class='text-nowrap' tt='h_global_usage_wikis'></th>".to_string(),
+                "global_usage_pages" => "<th class='text-nowrap' tt='h_global_usage_pages'></th>".to_string(),
+                "references" => "<th class='text-nowrap' tt='h_references'></th>".to_string(),
+                "citation_needed" => "<th class='text-nowrap' tt='h_citation_needed'></th>".to_string(),
+                "assessment_class" => "<th tt='h_assessment_class'></th>".to_string(),
+                "infoboxes" => "<th tt='h_infoboxes'></th>".to_string(),
+                "duplicate_titles" => "<th tt='h_duplicate_titles'></th>".to_string(),
+                "category_membership" => "<th tt='h_category_membership'></th>".to_string(),
+                "redirect_target" => "<th tt='h_redirect_target'></th>".to_string(),
+                "redirect_problem" => "<th tt='h_redirect_problem'></th>".to_string(),
+                "file_duplicates" => "<th tt='h_file_duplicates'></th>".to_string(),
+                "short_description" => "<th tt='h_short_description'></th>".to_string(),
                 "size" => "<th class='text-nowrap' tt='h_len'></th>".to_string(),
                 "timestamp" => "<th class='text-nowrap' tt='h_touched'></th>".to_string(),
                 "wikidata_item" => "<th tt='h_wikidata'></th>".to_string(),
                 "coordinates" => "<th tt='h_coordinates'></th>".to_string(),
                 "defaultsort" => "<th tt='h_defaultsort'></th>".to_string(),
+                "sortkey" => "<th tt='h_sortkey'></th>".to_string(),
                 "disambiguation" => "<th tt='h_disambiguation'></th>".to_string(),
                 "incoming_links" => "<th tt='h_incoming_links'></th>".to_string(),
                 "sitelinks" => "<th tt='h_sitelinks'></th>".to_string(),
@@ -970,16 +1270,29 @@ impl RenderHTML {
                     // File data etc.
                     if fdk.contains(&other) {
                         format!("<th tt='h_{}'></th>", &other)
+                    } else if params.harvest_properties.iter().any(|p| p == other) {
+                        format!("<th class='text-nowrap'>{}</th>", &other)
                     } else {
                         format!("<th>UNKNOWN:'{}'</th>", &other)
                     }
                 }
             };
-            ret += &(&x.to_owned()).to_string();
+            ret += &x.replacen("<th", &Self::header_cell_attributes(&col), 1);
         }
         ret += "</tr></thead>";
         ret
     }
+
+    /// Column key and sort type for results.js; added to the opening <th> tag of a header cell
+    fn header_cell_attributes(col: &str) -> String {
+        let sort_type = match col {
+            "number" | "page_id" | "size" | "timestamp" | "linknumber" | "redlink_count" | "redlinks_in_page"
+            | "pageviews" | "last_edit_age" | "watchers" | "global_usage_wikis" | "global_usage_pages" | "references" | "citation_needed" | "incoming_links" | "sitelinks" | "img_size" | "img_width" | "img_height" => "number",
+            "checkbox" | "image" | "image_url" => "none",
+            _ => "text",
+        };
+        format!("<th data-col='{}' data-sort-type='{}'", col, sort_type)
+    }
 }
 
 //________________________________________________________________________________________________________________________
@@ -1068,6 +1381,8 @@ impl Render for RenderJSON {
         Ok(MyResponse {
             s: out,
             content_type,
+            filename: None,
+            partial_result: false,
         })
     }
 
@@ -1097,6 +1412,17 @@ impl RenderJSON {
         "https://petscan.wmflabs.org/?".to_string() + &platform.form_parameters().to_string()
     }
 
+    /// Deprecated parameters in the query, and what to use instead, eg {"lang":"language"}
+    fn deprecated_parameters(&self, platform: &Platform) -> Value {
+        let ret: serde_json::Map<String, Value> = platform
+            .form_parameters()
+            .deprecated_parameters()
+            .iter()
+            .map(|(old, new)| (old.to_string(), json!(new)))
+            .collect();
+        json!(ret)
+    }
+
     fn cat_scan(
         &self,
         platform: &Platform,
@@ -1115,12 +1441,13 @@ impl RenderJSON {
             entries.iter().map(|entry| {
                 let mut o = json!({
                     "n":"page",
-                    "title":entry.title().with_underscores(),
+                    "title":if params.full_titles { params.display_title(entry.title()) } else { entry.title().with_underscores() },
                     "id":entry.page_id.unwrap_or(0),
                     "namespace":entry.title().namespace_id(),
                     "len":entry.page_bytes.unwrap_or(0),
                     "touched":entry.get_page_timestamp().unwrap_or_else(String::new),
-                    "nstext":params.api.get_canonical_namespace_name(entry.title().namespace_id()).unwrap_or("")
+                    "nstext":params.api.get_canonical_namespace_name(entry.title().namespace_id()).unwrap_or(""),
+                    "url":params.page_url(entry.title())
                 });
                 if let Some(q) = entry.get_wikidata_item() {
                     o["q"] = json!(q);
@@ -1146,7 +1473,8 @@ impl RenderJSON {
             Some(duration) => (duration.as_millis() as f32) / (1000_f32),
             None => 0.0,
         };
-        json!({"n":"result","a":{"query":self.get_query_string(platform),"querytime_sec":seconds},"*":[{"n":"combination","a":{"type":platform.get_param_default("combination","subset"),"*":entry_data}}]})
+        let statistics = PageLengthStatistics::new_from_entries(&entries).as_json();
+        json!({"n":"result","a":{"query":self.get_query_string(platform),"querytime_sec":seconds,"partial":platform.is_partial_result(),"statistics":statistics,"deprecated":self.deprecated_parameters(platform),"effective_parameters":platform.form_parameters().effective_parameters()},"*":[{"n":"combination","a":{"type":platform.get_param_default("combination","subset"),"*":entry_data}}]})
     }
 
     fn quick_intersection(
@@ -1163,6 +1491,10 @@ impl RenderJSON {
             "max":entries.len()+1,
             "query":self.get_query_string(platform),
             "pagecount":entries.len(),
+            "partial":platform.is_partial_result(),
+            "statistics":PageLengthStatistics::new_from_entries(&entries).as_json(),
+            "deprecated":self.deprecated_parameters(platform),
+            "effective_parameters":platform.form_parameters().effective_parameters(),
             "pages":[]
         });
         if let Some(duration) = platform.query_time() {
@@ -1277,9 +1609,29 @@ impl RenderJSON {
                 "checkbox" | "number" | "page_id" | "title" | "namespace" | "size"
                 | "timestamp" => None,
                 "image" => entry.get_page_image().map(|s| json!(s)),
+                "image_url" => entry.get_image_url().map(|s| json!(s)),
                 "linknumber" => entry.link_count.as_ref().map(|s| json!(s)),
+                "redlinks_in_page" => entry.redlinks_in_page.as_ref().map(|s| json!(s)),
+                "pageviews" => entry.page_views.as_ref().map(|s| json!(s)),
+                "last_edit_age" => entry.last_edit_age_days.as_ref().map(|s| json!(s)),
+                "watchers" => entry.watchers.as_ref().map(|s| json!(s)),
+                "global_usage_wikis" => entry.global_usage_wikis.as_ref().map(|s| json!(s)),
+                "global_usage_pages" => entry.global_usage_pages.as_ref().map(|s| json!(s)),
+                "references" => entry.references.as_ref().map(|s| json!(s)),
+                "citation_needed" => entry.citation_needed.as_ref().map(|s| json!(s)),
+                "assessment_class" => entry
+                    .get_assessment()
+                    .map(|a| json!({"classes":a.classes,"importances":a.importances})),
+                "infoboxes" => entry.get_infoboxes().map(|i| json!(i)),
+                "duplicate_titles" => entry.get_duplicate_titles().map(|d| json!(d)),
+                "category_membership" => entry.get_category_membership().map(|m| json!(m)),
+                "redirect_target" => entry.get_redirect_target().map(|t| json!(t)),
+                "redirect_problem" => entry.get_redirect_problem().map(|p| p.as_json(&params.api)),
+                "file_duplicates" => entry.get_file_duplicates().map(|d| json!(d)),
+                "short_description" => entry.get_short_description().map(|s| json!(s)),
                 "wikidata" => entry.get_wikidata_item().map(|s| json!(s)),
                 "defaultsort" => entry.get_defaultsort().map(|s| json!(s)),
+                "sortkey" => entry.get_sortkey().map(|s| json!(s)),
                 "disambiguation" => Some(entry.disambiguation.as_json()),
                 "incoming_links" => entry.incoming_links.as_ref().map(|s| json!(s)),
                 "sitelinks" => entry.sitelink_count.as_ref().map(|s| json!(s)),
@@ -1288,7 +1640,10 @@ impl RenderJSON {
                     None => None,
                 },
                 "fileusage" => self.get_file_usage_as_string(entry),
-                other => self.get_file_info_value(entry, other),
+                other => match entry.get_property_values(other) {
+                    Some(values) => Some(json!(values)),
+                    None => self.get_file_info_value(entry, other),
+                },
             };
             if let Some(v) = value { o["metadata"][head] = v }
         });
@@ -1297,7 +1652,12 @@ impl RenderJSON {
 
 //________________________________________________________________________________________________________________________
 
-/// Renders PagePile
+/// Renders PagePile. Appending to a pile is not part of the PagePile API calls used elsewhere (create_pile_with_data,
+/// get_data), so chunked uploads need the append action configured as `pagepile_append_action`; otherwise the result
+/// is uploaded in one request. With chunks, the pile is created with the first one, and the others are appended.
+/// Creating and appending are not idempotent, so a chunk is only retried if the connection could not be made; if one
+/// fails, the error names the pile and offset, so the upload can be resumed with `pagepile_resume` (pile ID) and
+/// `pagepile_offset` (pages already uploaded) after checking the pile.
 pub struct RenderPagePile {}
 
 #[async_trait]
@@ -1308,50 +1668,80 @@ impl Render for RenderPagePile {
         wiki: &str,
         entries: Vec<PageListEntry>,
     ) -> Result<MyResponse, String> {
-        let api = platform.state().get_api_for_wiki(wiki.to_string()).await?;
-        let url = "https://pagepile.toolforge.org/api.php";
-        let data: String = entries
+        let state = platform.state();
+        let append_action = state.get_pagepile_append_action();
+        let lines: Vec<String> = entries
             .iter()
             .map(|e| format!("{}\t{}", e.title().pretty(), e.title().namespace_id()))
-            .collect::<Vec<String>>()
-            .join("\n");
-        let mut params: HashMap<String, String> =
-            vec![("action", "create_pile_with_data"), ("wiki", wiki)]
-                .iter()
-                .map(|x| (x.0.to_string(), x.1.to_string()))
-                .collect();
-        params.insert("data".to_string(), data);
-
-        let result = match api.query_raw(url, &params, "POST").await {
-            Ok(r) => r,
-            Err(e) => return Err(format!("PagePile generation failed: {:?}", e)),
+            .collect();
+        let mut pagepile_id = platform.get_param("pagepile_resume").and_then(|s| s.trim().parse::<u64>().ok());
+        if pagepile_id.is_some() && append_action.is_none() {
+            return Err("pagepile_resume needs a PagePile installation that can append to a pile".to_string());
+        }
+        let chunk_size = match append_action {
+            Some(_) => PAGEPILE_CHUNK_SIZE,
+            None => lines.len(),
         };
-        let json: serde_json::value::Value = match serde_json::from_str(&result) {
-            Ok(j) => j,
-            Err(e) => {
-                return Err(format!(
-                    "PagePile generation did not return valid JSON: {:?}",
-                    e
-                ))
-            }
+        let mut offset = match pagepile_id {
+            Some(_) => platform.usize_option_from_param("pagepile_offset").unwrap_or(0).min(lines.len()),
+            None => 0,
         };
-        let pagepile_id = match json["pile"]["id"].as_u64() {
-            Some(id) => id,
-            None => {
-                return Err(format!(
-                    "PagePile generation did not return a pagepile ID: {:?}",
-                    json.clone()
-                ))
+        while offset < lines.len() || pagepile_id.is_none() {
+            let chunk_end = (offset + chunk_size).min(lines.len());
+            platform.set_stage(&format!("uploading to PagePile: {}/{}", offset, lines.len()));
+            let mut params: HashMap<String, String> = HashMap::new();
+            params.insert("wiki".to_string(), wiki.to_string());
+            params.insert("data".to_string(), lines[offset..chunk_end].join("\n"));
+            match (pagepile_id, &append_action) {
+                (Some(id), Some(action)) => {
+                    params.insert("action".to_string(), action.to_owned());
+                    params.insert("id".to_string(), id.to_string());
+                }
+                _ => {
+                    params.insert("action".to_string(), "create_pile_with_data".to_string());
+                }
             }
-        };
+            let json = match Self::post(&state, &params).await {
+                Ok(json) => json,
+                Err(e) => {
+                    return Err(match pagepile_id {
+                        Some(id) => format!(
+                            "PagePile upload failed after {} of {} pages; resume with pagepile_resume={}&pagepile_offset={}: {}",
+                            offset,
+                            lines.len(),
+                            id,
+                            offset,
+                            e
+                        ),
+                        None => format!("PagePile generation failed: {}", e),
+                    })
+                }
+            };
+            if pagepile_id.is_none() {
+                pagepile_id = match json["pile"]["id"].as_u64() {
+                    Some(id) => Some(id),
+                    None => {
+                        return Err(format!(
+                            "PagePile generation did not return a pagepile ID: {:?}",
+                            json.clone()
+                        ))
+                    }
+                };
+            }
+            offset = chunk_end;
+        }
+        let pagepile_id = pagepile_id.unwrap_or(0);
         let url = format!(
-            "https://tools.wmflabs.org/pagepile/api.php?action=get_data&id={}",
+            "{}?action=get_data&id={}",
+            platform.state().endpoints().pagepile(),
             pagepile_id
         );
         let html = format!("<html><head><meta http-equiv=\"refresh\" content=\"0; url={}\" /></head><BODY><H1>Redirect</H1>The document can be found <A HREF='{}'>here</A>.</BODY></html>",&url,&url) ;
         Ok(MyResponse {
             s: html,
             content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
         })
     }
 
@@ -1376,6 +1766,29 @@ impl RenderPagePile {
     pub fn new() -> Box<Self> {
         Box::new(Self {})
     }
+
+    /// Retries only if the connection failed, so the request was not sent; after that, PagePile might have stored the
+    /// data even if the response was lost, and sending it again would duplicate it
+    async fn post(state: &AppState, params: &HashMap<String, String>) -> Result<Value, String> {
+        let mut attempt = 0;
+        let response = loop {
+            if attempt > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(1000 * 2u64.pow(attempt))).await;
+            }
+            match state.http_client().post(state.endpoints().pagepile()).form(params).send().await {
+                Ok(response) => break response,
+                Err(e) if e.is_connect() && attempt + 1 < PAGEPILE_CHUNK_ATTEMPTS => attempt += 1,
+                Err(e) => return Err(format!("{:?}", e)),
+            }
+        };
+        let text = response
+            .error_for_status()
+            .map_err(|e| format!("{:?}", e))?
+            .text()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        serde_json::from_str::<Value>(&text).map_err(|e| format!("did not return valid JSON: {:?}", e))
+    }
 }
 
 
@@ -1400,6 +1813,9 @@ impl Render for RenderKML {
         let mut kml = String::new();
         kml += r#"<?xml version="1.0" encoding="UTF-8"?>
         <kml xmlns="http://www.opengis.net/kml/2.2"><Document>"# ;
+        if platform.is_partial_result() {
+            kml += "<!-- Partial result: the query ran out of time; not all pages are listed -->";
+        }
 
         for entry in entries {
             if let Some(coords) = &entry.get_coordinates() {
@@ -1410,7 +1826,7 @@ impl Render for RenderKML {
                         None => title.pretty().to_string()
                     }
                 } else {
-                    title.pretty().to_string()
+                    params.display_title(title)
                 } ;
                 kml += r#"<Placemark>"# ;
                 kml += format!("<name>{}</name>",self.escape_xml(&label)).as_str() ;
@@ -1427,7 +1843,7 @@ impl Render for RenderKML {
                     Some(ft) => ft,
                     None => format!("{:?}", title),
                 };
-                let url = format!("{}/wiki/{}",&server,&self.escape_attribute(&full_title));
+                let url = page_url(&server, &full_title);
                 kml += format!("<Data name=\"url\"><value>{}</value></Data>",self.escape_xml(&url)).as_str();
 
                 if let Some(img) = entry.get_page_image() {
@@ -1451,11 +1867,13 @@ impl Render for RenderKML {
         Ok(MyResponse {
             s: kml,
             content_type: ContentType::Plain,
+            filename: None,
+            partial_result: false,
         })
     }
 
-    fn render_cell_title(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
-        entry.title().pretty().to_string()
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
     }
 
     fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
@@ -1505,6 +1923,80 @@ impl RenderKML {
 }
 
 
+//________________________________________________________________________________________________________________________
+
+/// Renders an HTML gallery of the page images, as thumbnails; pages without image are left out
+pub struct RenderGallery {}
+
+#[async_trait]
+impl Render for RenderGallery {
+    async fn response(
+        &self,
+        platform: &Platform,
+        wiki: &str,
+        entries: Vec<PageListEntry>,
+    ) -> Result<MyResponse, String> {
+        let params = RenderParams::new(platform, wiki).await?;
+        let items: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| {
+                let url = entry.get_image_url()?;
+                let page = params.page_url(entry.title()).unwrap_or_default();
+                Some(format!(
+                    "<figure><a href='{}'><img src='{}' loading='lazy'/></a><figcaption>{}</figcaption></figure>",
+                    encode_attribute(&page),
+                    encode_attribute(&url),
+                    self.render_cell_title(entry, &params)
+                ))
+            })
+            .collect();
+        let html = format!(
+            "<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan gallery</title><style>body{{font-family:sans-serif}} figure{{display:inline-block;vertical-align:top;width:{}px;margin:4px;text-align:center}} img{{max-width:100%}}</style></head><body>{}</body></html>",
+            platform
+                .get_param("thumbnail_width")
+                .and_then(|s| s.trim().parse::<u64>().ok())
+                .unwrap_or(crate::thumbnails::DEFAULT_THUMBNAIL_WIDTH),
+            items.join("")
+        );
+        Ok(MyResponse {
+            s: html,
+            content_type: ContentType::HTML,
+            filename: None,
+            partial_result: false,
+        })
+    }
+
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        let label = match params.is_wikidata {
+            true => entry.get_wikidata_label().unwrap_or_else(|| entry.title().pretty().to_string()),
+            false => params.display_title(entry.title()),
+        };
+        encode_minimal(&label)
+    }
+
+    fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        self.opt_string(&entry.get_wikidata_item())
+    }
+
+    fn render_user_name(&self, user: &String, _params: &RenderParams) -> String {
+        encode_minimal(user)
+    }
+
+    fn render_cell_image(&self, image: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(image)
+    }
+
+    fn render_cell_namespace(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.title().namespace_id().to_string()
+    }
+}
+
+impl RenderGallery {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {})
+    }
+}
+
 //________________________________________________________________________________________________________________________
 
 /// Renders PlainText
@@ -1527,11 +2019,13 @@ impl Render for RenderPlainText {
         Ok(MyResponse {
             s: output,
             content_type: ContentType::Plain,
+            filename: None,
+            partial_result: false,
         })
     }
 
-    fn render_cell_title(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
-        entry.title().pretty().to_string()
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
     }
 
     fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
@@ -1562,3 +2056,283 @@ impl RenderPlainText {
         Box::new(Self {})
     }
 }
+
+//________________________________________________________________________________________________________________________
+
+/// The redirect problems found with `redirect_problems`, one per line for bots: redirect, problem ("double", "broken",
+/// "loop"), current target, and the new target for double redirects
+pub struct RenderRedirectFixes {}
+
+#[async_trait]
+impl Render for RenderRedirectFixes {
+    async fn response(
+        &self,
+        platform: &Platform,
+        wiki: &str,
+        entries: Vec<PageListEntry>,
+    ) -> Result<MyResponse, String> {
+        let params = RenderParams::new(platform, wiki).await?;
+        let output = entries
+            .iter()
+            .filter_map(|entry| {
+                let problem = entry.get_redirect_problem()?;
+                Some(format!(
+                    "{}\t{}\t{}\t{}",
+                    entry.title().full_pretty(&params.api)?,
+                    problem.kind.as_str(),
+                    problem.target_title(&params.api),
+                    problem.fix_title(&params.api).unwrap_or_default()
+                ))
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        Ok(MyResponse {
+            s: output,
+            content_type: ContentType::TSV,
+            filename: None,
+            partial_result: false,
+        })
+    }
+
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
+    }
+
+    fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.get_wikidata_item().unwrap_or_default()
+    }
+
+    fn render_user_name(&self, user: &String, _params: &RenderParams) -> String {
+        user.to_string()
+    }
+
+    fn render_cell_image(&self, image: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(image)
+    }
+
+    fn render_cell_namespace(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.title().namespace_id().to_string()
+    }
+}
+
+impl RenderRedirectFixes {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {})
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// The wikilinks between the result pages, as a graph in `graph_format` (DOT, GraphML, or JSON); pages without links
+/// within the result are included as single nodes
+pub struct RenderGraph {}
+
+#[async_trait]
+impl Render for RenderGraph {
+    async fn response(
+        &self,
+        platform: &Platform,
+        wiki: &str,
+        entries: Vec<PageListEntry>,
+    ) -> Result<MyResponse, String> {
+        let params = RenderParams::new(platform, wiki).await?;
+        let format = GraphFormat::new_from_param(&platform.get_param_blank("graph_format"));
+        let mut graph = Graph::new();
+        entries
+            .iter()
+            .filter_map(|entry| entry.title().full_pretty(&params.api))
+            .for_each(|title| {
+                graph.add_node(&title);
+            });
+        for (from, to) in platform.load_links_within(wiki, &entries).await? {
+            if let (Some(from), Some(to)) = (from.full_pretty(&params.api), to.full_pretty(&params.api)) {
+                graph.add_edge(&from, &to);
+            }
+        }
+        Ok(MyResponse {
+            s: graph.render(format),
+            content_type: format.content_type(),
+            filename: None,
+            partial_result: false,
+        })
+    }
+
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
+    }
+
+    fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.get_wikidata_item().unwrap_or_default()
+    }
+
+    fn render_user_name(&self, user: &String, _params: &RenderParams) -> String {
+        user.to_string()
+    }
+
+    fn render_cell_image(&self, image: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(image)
+    }
+
+    fn render_cell_namespace(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.title().namespace_id().to_string()
+    }
+}
+
+impl RenderGraph {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {})
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// The category tree the query traversed, in `graph_format`: each subcategory with the category it was first reached
+/// from, labelled with its depth below the root. Empty if the query has no categories.
+pub struct RenderCategoryGraph {}
+
+#[async_trait]
+impl Render for RenderCategoryGraph {
+    async fn response(
+        &self,
+        platform: &Platform,
+        _wiki: &str,
+        _entries: Vec<PageListEntry>,
+    ) -> Result<MyResponse, String> {
+        let format = GraphFormat::new_from_param(&platform.get_param_blank("graph_format"));
+        let graph = platform
+            .category_traversal()?
+            .map(|traversal| traversal.to_graph())
+            .unwrap_or_else(Graph::new);
+        Ok(MyResponse {
+            s: graph.render(format),
+            content_type: format.content_type(),
+            filename: None,
+            partial_result: false,
+        })
+    }
+
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
+    }
+
+    fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.get_wikidata_item().unwrap_or_default()
+    }
+
+    fn render_user_name(&self, user: &String, _params: &RenderParams) -> String {
+        user.to_string()
+    }
+
+    fn render_cell_image(&self, image: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(image)
+    }
+
+    fn render_cell_namespace(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.title().namespace_id().to_string()
+    }
+}
+
+impl RenderCategoryGraph {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {})
+    }
+}
+
+//________________________________________________________________________________________________________________________
+
+/// QuickStatements (v1) commands to create an item for each page without one, labelled with the title in the wiki
+/// language, for the unconnected pages mode
+pub struct RenderQuickStatements {}
+
+#[async_trait]
+impl Render for RenderQuickStatements {
+    async fn response(
+        &self,
+        platform: &Platform,
+        wiki: &str,
+        entries: Vec<PageListEntry>,
+    ) -> Result<MyResponse, String> {
+        let params = RenderParams::new(platform, wiki).await?;
+        let language = params.api.get_site_info_string("general", "lang")?.to_string();
+        let output = entries
+            .iter()
+            .filter(|entry| entry.get_wikidata_item().is_none())
+            .filter_map(|entry| {
+                let full_title = entry.title().full_pretty(&params.api)?;
+                // Quotes can not be escaped in QuickStatements strings
+                if full_title.contains('"') {
+                    return None;
+                }
+                let label = match entry.title().namespace_id() {
+                    0 => entry.title().pretty().to_string(),
+                    _ => full_title.to_owned(),
+                };
+                Some(Self::create_item_commands(&language, wiki, &label, &full_title))
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        Ok(MyResponse {
+            s: output,
+            content_type: ContentType::Plain,
+            filename: None,
+            partial_result: false,
+        })
+    }
+
+    fn render_cell_title(&self, entry: &PageListEntry, params: &RenderParams) -> String {
+        params.display_title(entry.title())
+    }
+
+    fn render_cell_wikidata_item(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.get_wikidata_item().unwrap_or_default()
+    }
+
+    fn render_user_name(&self, user: &String, _params: &RenderParams) -> String {
+        user.to_string()
+    }
+
+    fn render_cell_image(&self, image: &Option<String>, _params: &RenderParams) -> String {
+        self.opt_string(image)
+    }
+
+    fn render_cell_namespace(&self, entry: &PageListEntry, _params: &RenderParams) -> String {
+        entry.title().namespace_id().to_string()
+    }
+}
+
+impl RenderQuickStatements {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {})
+    }
+
+    /// The label is the title without a trailing disambiguation, eg "Mercury (planet)" => "Mercury"
+    fn create_item_commands(language: &str, wiki: &str, label: &str, full_title: &str) -> String {
+        lazy_static! {
+            static ref RE_DISAMBIGUATION: Regex = Regex::new(r"^(.+?) +\([^()]+\)$").expect("RE_DISAMBIGUATION");
+        }
+        let label = RE_DISAMBIGUATION.replace(label, "$1");
+        format!(
+            "CREATE\nLAST\tL{}\t\"{}\"\nLAST\tS{}\t\"{}\"",
+            language,
+            label,
+            wiki,
+            full_title
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_text() {
+        let description = Some("<script>alert(1)</script> & more".to_string());
+        assert_eq!(
+            RenderHTML::new().opt_text(&description),
+            "&lt;script&gt;alert(1)&lt;/script&gt; &amp; more"
+        );
+        assert_eq!(RenderTSV::new("\t").opt_text(&description), "<script>alert(1)</script> & more");
+        assert_eq!(RenderHTML::new().opt_text(&None), "");
+    }
+}
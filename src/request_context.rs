@@ -0,0 +1,130 @@
+use crate::oauth::OAuth;
+use crate::preferences::UserPreferences;
+use hyper::header::{HeaderMap, USER_AGENT};
+use std::net::SocketAddr;
+
+/// Longest user agent that is kept, eg for the audit log
+static MAX_USER_AGENT_LENGTH: usize = 255;
+
+/// Who sent a request: for logging, rate limiting, and the audit log
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestContext {
+    /// The connecting address, or the X-Forwarded-For hop appended by our own proxy, see `client_ip`
+    pub client_ip: String,
+    pub user_agent: Option<String>,
+    /// The (OAuth) user name, as set by an authenticating proxy in the configured `user_header`, or from the session
+    pub user: Option<String>,
+    /// The OAuth session cookie, see `OAuth`
+    pub session_id: Option<String>,
+    pub preferences: UserPreferences,
+}
+
+impl RequestContext {
+    /// `user_header` is only trusted if it is configured; otherwise, clients could set it themselves
+    pub fn new_from_headers(
+        headers: &HeaderMap,
+        remote_addr: &SocketAddr,
+        user_header: Option<&str>,
+        trusted_proxies: &[String],
+    ) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        Self {
+            client_ip: Self::client_ip(header("x-forwarded-for"), remote_addr, trusted_proxies),
+            user_agent: header(USER_AGENT.as_str()).map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            user: user_header.and_then(header),
+            session_id: OAuth::session_id_from_headers(headers),
+            preferences: UserPreferences::new_from_headers(headers),
+        }
+    }
+
+    /// Clients can send any X-Forwarded-For, and proxies append to it, so only the rightmost hops can be trusted.
+    /// With `trusted_proxies` configured, X-Forwarded-For is only used for connections from those, and the rightmost
+    /// hop that is not one of them is the client. Without, there is assumed to be a single proxy in front (as on
+    /// Toolforge), and its hop (the rightmost one) is the client.
+    fn client_ip(forwarded_for: Option<String>, remote_addr: &SocketAddr, trusted_proxies: &[String]) -> String {
+        let remote_ip = remote_addr.ip().to_string();
+        let forwarded_for = match forwarded_for {
+            Some(forwarded_for) => forwarded_for,
+            None => return remote_ip,
+        };
+        let hops: Vec<String> = forwarded_for
+            .split(',')
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty())
+            .collect();
+        if trusted_proxies.is_empty() {
+            return hops.last().cloned().unwrap_or(remote_ip);
+        }
+        if !trusted_proxies.contains(&remote_ip) {
+            return remote_ip;
+        }
+        hops.iter()
+            .rev()
+            .find(|ip| !trusted_proxies.contains(ip))
+            .or_else(|| hops.first())
+            .cloned()
+            .unwrap_or(remote_ip)
+    }
+
+    /// Identified users are limited by name, everyone else by address
+    pub fn rate_limit_key(&self) -> String {
+        match &self.user {
+            Some(user) => format!("user:{}", user),
+            None => self.client_ip.to_owned(),
+        }
+    }
+
+    /// For log lines and the list of running queries
+    pub fn description(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{} ({})", user, self.client_ip),
+            None => self.client_ip.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_from_headers() {
+        let remote_addr: SocketAddr = "10.0.0.1:4321".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        let context = RequestContext::new_from_headers(&headers, &remote_addr, Some("x-forwarded-user"), &[]);
+        assert_eq!(context.client_ip, "10.0.0.1");
+        assert_eq!(context.user_agent, None);
+        assert_eq!(context.rate_limit_key(), "10.0.0.1");
+
+        // The client sent "203.0.113.9"; the proxy appended the address it saw
+        headers.insert("x-forwarded-for", "203.0.113.9, 192.0.2.7".parse().unwrap());
+        headers.insert("user-agent", "MyBot/1.0".parse().unwrap());
+        headers.insert("x-forwarded-user", "Magnus Manske".parse().unwrap());
+        let context = RequestContext::new_from_headers(&headers, &remote_addr, None, &[]);
+        assert_eq!(context.client_ip, "192.0.2.7");
+        assert_eq!(context.user_agent, Some("MyBot/1.0".to_string()));
+        assert_eq!(context.user, None);
+        let context = RequestContext::new_from_headers(&headers, &remote_addr, Some("x-forwarded-user"), &[]);
+        assert_eq!(context.rate_limit_key(), "user:Magnus Manske");
+        assert_eq!(context.description(), "Magnus Manske (192.0.2.7)");
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let remote_addr: SocketAddr = "10.0.0.1:4321".parse().unwrap();
+        let forwarded_for = || Some("203.0.113.9, 192.0.2.7, 10.0.0.2".to_string());
+        let proxies = vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()];
+        assert_eq!(RequestContext::client_ip(forwarded_for(), &remote_addr, &proxies), "192.0.2.7");
+        // Not from a trusted proxy: X-Forwarded-For is ignored
+        let direct: SocketAddr = "198.51.100.1:4321".parse().unwrap();
+        assert_eq!(RequestContext::client_ip(forwarded_for(), &direct, &proxies), "198.51.100.1");
+        assert_eq!(RequestContext::client_ip(None, &remote_addr, &proxies), "10.0.0.1");
+        assert_eq!(RequestContext::client_ip(Some(" , ".to_string()), &remote_addr, &[]), "10.0.0.1");
+    }
+}
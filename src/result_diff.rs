@@ -0,0 +1,155 @@
+use crate::app_state::AppState;
+use crate::form_parameters::FormParameters;
+use crate::page_url::page_url;
+use crate::platform::Platform;
+use crate::request_context::RequestContext;
+use htmlescape::{encode_attribute, encode_minimal};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Runs the query stored under a PSID, like any other query, for `context`; returns the wiki, and the full titles of
+/// the result
+pub async fn titles_for_psid(
+    psid: u64,
+    context: &RequestContext,
+    state: Arc<AppState>,
+) -> Result<(String, Vec<String>), String> {
+    let query = state.get_query_from_psid(&psid.to_string()).await?;
+    let form_parameters = FormParameters::outcome_from_query(&query)?;
+    let (platform, platform_result) = Platform::run_logged(&form_parameters, context, None, state.clone()).await;
+    platform_result.map_err(AppState::user_visible_db_error)?;
+    let result = match platform.result() {
+        Some(result) => result,
+        None => return Err(format!("PSID {} has no result", psid)),
+    };
+    let wiki = match result.wiki()? {
+        Some(wiki) => wiki,
+        None => return Ok((String::new(), vec![])),
+    };
+    let api = state.get_api_for_wiki(wiki.clone()).await?;
+    let titles = result
+        .entries()
+        .read()
+        .map_err(|e| format!("{:?}", e))?
+        .iter()
+        .filter_map(|entry| entry.title().full_with_underscores(&api))
+        .collect();
+    Ok((wiki, titles))
+}
+
+/// Pages added and removed between the results of two queries ("a" is the old, "b" the new one)
+#[derive(Debug, Clone, Default)]
+pub struct ResultDiff {
+    pub psid_a: u64,
+    pub psid_b: u64,
+    pub wiki: String,
+    pub server: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl ResultDiff {
+    /// `titles_a` and `titles_b` are full titles, with underscores
+    pub fn new(psid_a: u64, titles_a: &[String], psid_b: u64, titles_b: &[String]) -> Self {
+        let set_a: HashSet<&String> = titles_a.iter().collect();
+        let set_b: HashSet<&String> = titles_b.iter().collect();
+        let mut added: Vec<String> = set_b.difference(&set_a).map(|t| t.to_string()).collect();
+        let mut removed: Vec<String> = set_a.difference(&set_b).map(|t| t.to_string()).collect();
+        added.sort();
+        removed.sort();
+        Self {
+            psid_a,
+            psid_b,
+            added,
+            removed,
+            unchanged: set_a.intersection(&set_b).count(),
+            ..Default::default()
+        }
+    }
+
+    pub fn as_json(&self) -> Value {
+        json!({
+            "a": self.psid_a,
+            "b": self.psid_b,
+            "wiki": self.wiki,
+            "added": self.added,
+            "removed": self.removed,
+            "unchanged": self.unchanged,
+        })
+    }
+
+    pub fn as_html(&self) -> String {
+        let rows = self.added.len().max(self.removed.len());
+        let rows: Vec<String> = (0..rows)
+            .map(|row| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    self.title_link(self.added.get(row)),
+                    self.title_link(self.removed.get(row))
+                )
+            })
+            .collect();
+        format!("<!DOCTYPE html><html><head><meta charset='utf-8'><title>PetScan result diff</title></head><body><h1>Changes from PSID <a href='/?psid={}'>{}</a> to <a href='/?psid={}'>{}</a></h1><p>{} added, {} removed, {} unchanged on {}</p><table border='1'><tr><th>Added</th><th>Removed</th></tr>{}</table></body></html>",
+            self.psid_a, self.psid_a, self.psid_b, self.psid_b,
+            self.added.len(), self.removed.len(), self.unchanged, encode_minimal(&self.wiki),
+            rows.join(""))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Two lists of wiki links, for posting to a report page
+    pub fn as_wikitext(&self) -> String {
+        self.as_lists(&|title| format!("* [[:{}]]", title.replace('_', " ")))
+    }
+
+    /// Plain text, for email
+    pub fn as_text(&self) -> String {
+        self.as_lists(&|title| format!("  {}", title))
+    }
+
+    fn as_lists(&self, line: &dyn Fn(&String) -> String) -> String {
+        let list = |titles: &Vec<String>| -> String { titles.iter().map(|title| line(title) + "\n").collect() };
+        format!(
+            "Added ({}):\n{}\nRemoved ({}):\n{}",
+            self.added.len(),
+            list(&self.added),
+            self.removed.len(),
+            list(&self.removed)
+        )
+    }
+
+    fn title_link(&self, title: Option<&String>) -> String {
+        match title {
+            Some(title) => format!(
+                "<a href='{}'>{}</a>",
+                encode_attribute(&page_url(&self.server, title)),
+                encode_minimal(&title.replace('_', " "))
+            ),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_and_removed() {
+        let a: Vec<String> = vec!["A".into(), "B".into(), "C".into()];
+        let b: Vec<String> = vec!["D".into(), "C".into(), "A".into(), "E".into()];
+        let diff = ResultDiff::new(1, &a, 2, &b);
+        assert_eq!(diff.added, vec!["D".to_string(), "E".to_string()]);
+        assert_eq!(diff.removed, vec!["B".to_string()]);
+        assert_eq!(diff.unchanged, 2);
+        assert_eq!(diff.as_json()["added"], json!(["D", "E"]));
+        assert!(diff.as_html().contains("<td></td>"));
+        assert!(diff.as_wikitext().contains("Removed (1):\n* [[:B]]\n"));
+        assert!(!diff.is_empty());
+        assert!(ResultDiff::new(1, &a, 2, &a).is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+//! Helpers for building SQL. User input only ever goes into the bound values of an `SQLtuple`, never into the SQL
+//! text; LIKE patterns built from user input are escaped, unless wildcards were asked for.
+
+use crate::datasource::SQLtuple;
+use mysql_async::Value as MyValue;
+use rayon::prelude::*;
+
+/// Escapes the LIKE wildcards (and the escape character), for a literal match
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// LIKE pattern for texts starting with `prefix` (taken literally)
+pub fn like_prefix(prefix: &str) -> String {
+    escape_like(prefix) + "%"
+}
+
+/// LIKE pattern for user input; `%` and `_` are only wildcards with `allow_wildcards`
+pub fn like_pattern(input: &str, allow_wildcards: bool) -> String {
+    match allow_wildcards {
+        true => input.to_string(),
+        false => escape_like(input),
+    }
+}
+
+/// Comma-separated question marks, for an IN list
+pub fn questionmarks(len: usize) -> String {
+    vec!["?"; len].join(",")
+}
+
+/// Placeholders for an IN list, and the (non-empty, trimmed) strings as values
+pub fn string_list(strings: &[String]) -> SQLtuple {
+    let values: Vec<MyValue> = strings
+        .par_iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| MyValue::Bytes(s.into()))
+        .collect();
+    (questionmarks(values.len()), values)
+}
+
+/// `column LIKE ?`, with the pattern as a value
+pub fn like(column: &str, pattern: &str) -> SQLtuple {
+    (format!("{} LIKE ?", column), vec![MyValue::Bytes(pattern.into())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static INJECTIONS: &[&str] = &["x' OR '1'='1", "\"; DROP TABLE page; --", "\\' OR 1=1 #", "50%_off"];
+
+    #[test]
+    fn escape_like() {
+        assert_eq!(super::escape_like("50%_off"), "50\\%\\_off");
+        assert_eq!(super::escape_like("C:\\foo"), "C:\\\\foo");
+        assert_eq!(like_prefix("Foo_"), "Foo\\_%");
+        assert_eq!(like_pattern("F_o%", true), "F_o%");
+        assert_eq!(like_pattern("F_o%", false), "F\\_o\\%");
+    }
+
+    #[test]
+    fn values_stay_out_of_sql() {
+        let strings: Vec<String> = INJECTIONS.iter().map(|s| s.to_string()).collect();
+        let sql = string_list(&strings);
+        assert_eq!(sql.0, "?,?,?,?");
+        assert_eq!(sql.1.len(), INJECTIONS.len());
+        for injection in INJECTIONS {
+            let sql = like("wbx_text", &like_pattern(injection, false));
+            assert_eq!(sql.0, "wbx_text LIKE ?");
+            assert!(!sql.0.contains(injection));
+        }
+    }
+
+    #[test]
+    fn string_list_skips_blank() {
+        let sql = string_list(&["a".to_string(), " ".to_string(), " b ".to_string()]);
+        assert_eq!(sql, ("?,?".to_string(), vec![MyValue::Bytes(b"a".to_vec()), MyValue::Bytes(b"b".to_vec())]));
+        assert_eq!(string_list(&[]).0, "");
+    }
+}
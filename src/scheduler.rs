@@ -0,0 +1,217 @@
+use crate::app_state::AppState;
+use crate::request_context::RequestContext;
+use crate::result_diff::{titles_for_psid, ResultDiff};
+use crate::wiki_edit::WikiEditor;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+static SCHEDULER_CHECK_SECONDS: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleInterval {
+    Daily,
+    Weekly,
+}
+
+impl ScheduleInterval {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            _ => None,
+        }
+    }
+
+    pub fn from_days(days: u64) -> Self {
+        if days >= 7 {
+            Self::Weekly
+        } else {
+            Self::Daily
+        }
+    }
+
+    pub fn days(&self) -> u64 {
+        match self {
+            Self::Daily => 1,
+            Self::Weekly => 7,
+        }
+    }
+}
+
+/// A PSID that is run regularly; changes to the result are posted to a wiki page, and/or emailed
+#[derive(Debug, Clone)]
+pub struct ScheduledQuery {
+    pub id: u64,
+    pub psid: u64,
+    pub interval: ScheduleInterval,
+    /// Full titles from the last run; None if it has never run
+    pub snapshot: Option<Vec<String>>,
+    pub report_wiki: String,
+    pub report_page: String,
+    pub email: String,
+    /// Who registered it
+    pub user: String,
+}
+
+impl ScheduledQuery {
+    pub fn has_notification(&self) -> bool {
+        (!self.report_wiki.is_empty() && !self.report_page.is_empty()) || !self.email.is_empty()
+    }
+
+    /// The bot posts reports on behalf of the user, so only into their own userspace ("User:Name" or "User:Name/...")
+    pub fn is_own_user_page(page: &str, user: &str) -> bool {
+        let normalize = |s: &str| {
+            let s = s.trim().replace('_', " ");
+            let mut chars = s.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        };
+        let page = normalize(page);
+        let name = match page.strip_prefix("User:") {
+            Some(rest) => normalize(rest),
+            None => return false,
+        };
+        let user = normalize(user);
+        !user.is_empty() && (name == user || name.starts_with(&format!("{}/", user)))
+    }
+}
+
+/// Runs due scheduled queries in the background
+pub struct Scheduler {
+    state: Arc<AppState>,
+}
+
+impl Scheduler {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+
+    pub async fn run_forever(&self) {
+        loop {
+            if let Err(e) = self.run_due().await {
+                println!("Scheduler: {}", e);
+            }
+            tokio::time::sleep(time::Duration::from_secs(SCHEDULER_CHECK_SECONDS)).await;
+        }
+    }
+
+    async fn run_due(&self) -> Result<(), String> {
+        // Due queries are run after maintenance instead
+        if self.state.get_maintenance_message().is_some() {
+            return Ok(());
+        }
+        for sq in self.state.get_due_scheduled_queries().await? {
+            if let Err(e) = self.run_scheduled_query(&sq).await {
+                println!("Scheduler: scheduled query {} (PSID {}) failed: {}", sq.id, sq.psid, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_scheduled_query(&self, sq: &ScheduledQuery) -> Result<(), String> {
+        // Logged as a query of the user who registered it
+        let context = RequestContext {
+            client_ip: "scheduler".to_string(),
+            user: Some(sq.user.to_owned()),
+            ..Default::default()
+        };
+        let (wiki, titles) = titles_for_psid(sq.psid, &context, self.state.clone()).await?;
+
+        // The first run only records the result to compare to. Otherwise, the new result is only stored once the
+        // changes are reported, so a failed notification is tried again on the next check.
+        if let Some(snapshot) = &sq.snapshot {
+            let mut diff = ResultDiff::new(sq.psid, snapshot, sq.psid, &titles);
+            if !diff.is_empty() {
+                diff.wiki = wiki;
+                self.notify(sq, &diff).await?;
+            }
+        }
+        self.state.set_scheduled_query_snapshot(sq.id, &titles).await
+    }
+
+    async fn notify(&self, sq: &ScheduledQuery, diff: &ResultDiff) -> Result<(), String> {
+        let heading = format!(
+            "PetScan PSID {}: {} added, {} removed",
+            sq.psid,
+            diff.added.len(),
+            diff.removed.len()
+        );
+        if !sq.report_wiki.is_empty() && !sq.report_page.is_empty() {
+            let editor = WikiEditor::new(self.state.clone()).ok_or_else(|| "No wiki bot account configured".to_string())?;
+            editor
+                .append_section(&sq.report_wiki, &sq.report_page, &heading, &diff.as_wikitext())
+                .await?;
+        }
+        if !sq.email.is_empty() {
+            self.send_email(&sq.email, &heading, &diff.as_text()).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs sendmail without blocking a runtime thread while it delivers
+    async fn send_email(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let sendmail = self.state.get_sendmail_path().ok_or_else(|| "No sendmail configured".to_string())?;
+        let mut child = Command::new(sendmail)
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{:?}", e))?;
+        let message = Self::email_message(to, subject, body)?;
+        let mut stdin = child.stdin.take().ok_or_else(|| "No sendmail stdin".to_string())?;
+        stdin
+            .write_all(message.as_bytes())
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        drop(stdin); // EOF, so sendmail sends
+        child.wait().await.map_err(|e| format!("{:?}", e))?;
+        Ok(())
+    }
+
+    /// Header injection is prevented by refusing line breaks in the address
+    fn email_message(to: &str, subject: &str, body: &str) -> Result<String, String> {
+        if to.contains('\n') || to.contains('\r') || !to.contains('@') {
+            return Err(format!("Invalid email address '{}'", to));
+        }
+        Ok(format!(
+            "To: {}\nSubject: {}\nContent-Type: text/plain; charset=utf-8\n\n{}",
+            to, subject, body
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interval() {
+        assert_eq!(ScheduleInterval::parse(" Weekly"), Some(ScheduleInterval::Weekly));
+        assert_eq!(ScheduleInterval::parse("hourly"), None);
+        assert_eq!(ScheduleInterval::from_days(ScheduleInterval::Daily.days()), ScheduleInterval::Daily);
+        assert_eq!(ScheduleInterval::from_days(ScheduleInterval::Weekly.days()), ScheduleInterval::Weekly);
+    }
+
+    #[test]
+    fn is_own_user_page() {
+        assert!(ScheduledQuery::is_own_user_page("User:Foo bar/Report", "Foo_bar"));
+        assert!(ScheduledQuery::is_own_user_page("user:foo_bar", "Foo bar"));
+        assert!(!ScheduledQuery::is_own_user_page("User:Foo barz/Report", "Foo bar"));
+        assert!(!ScheduledQuery::is_own_user_page("User talk:Foo bar", "Foo bar"));
+        assert!(!ScheduledQuery::is_own_user_page("Wikipedia:WikiProject X/Report", "Foo bar"));
+        assert!(!ScheduledQuery::is_own_user_page("User:Foo", ""));
+    }
+
+    #[test]
+    fn email_message() {
+        assert!(Scheduler::email_message("a@example.org", "S", "B")
+            .unwrap()
+            .starts_with("To: a@example.org\nSubject: S\n"));
+        assert!(Scheduler::email_message("a@example.org\nBcc: b@example.org", "S", "B").is_err());
+        assert!(Scheduler::email_message("nobody", "S", "B").is_err());
+    }
+}
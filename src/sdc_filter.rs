@@ -0,0 +1,162 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
+
+static SDC_FILTER_BATCH_SIZE: usize = 50;
+static SDC_FILTER_CONCURRENT_REQUESTS: usize = 3;
+static PROP_DEPICTS: &str = "P180";
+static PROP_LICENSE: &str = "P275";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SdcPresence {
+    Any,
+    Yes,
+    No,
+}
+
+impl SdcPresence {
+    pub fn new_from_str(s: &str) -> Self {
+        match s {
+            "yes" => SdcPresence::Yes,
+            "no" => SdcPresence::No,
+            _ => SdcPresence::Any,
+        }
+    }
+
+    fn matches(&self, present: bool) -> bool {
+        match self {
+            SdcPresence::Any => true,
+            SdcPresence::Yes => present,
+            SdcPresence::No => !present,
+        }
+    }
+}
+
+/// Filters on the structured data (MediaInfo entity "M<page_id>") of Commons files: depicts (P180) present or absent,
+/// depicts one of the given items, and license (P275) present or absent. Entities are fetched via wbgetentities in batches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdcFilter {
+    depicts: SdcPresence,
+    depicts_items: Vec<String>,
+    license: SdcPresence,
+}
+
+impl SdcFilter {
+    /// Returns None if there is nothing to filter on
+    pub fn new(depicts: SdcPresence, depicts_items: &[String], license: SdcPresence) -> Option<Self> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^Q\d+$").expect("SdcFilter::new RE");
+        }
+        let depicts_items: Vec<String> = depicts_items
+            .iter()
+            .flat_map(|s| s.split(','))
+            .map(|s| s.trim().to_uppercase())
+            .filter(|s| RE.is_match(s))
+            .collect();
+        if depicts == SdcPresence::Any && depicts_items.is_empty() && license == SdcPresence::Any {
+            return None;
+        }
+        Some(Self {
+            depicts,
+            depicts_items,
+            license,
+        })
+    }
+
+    /// Returns the page IDs of the files that match the filter, querying the Commons API at `api_url`
+    pub async fn run(&self, http: &HttpClient, api_url: &str, page_ids: &[u32]) -> Result<HashSet<u32>, String> {
+        let mut ret = HashSet::new();
+        let batches: Vec<&[u32]> = page_ids.chunks(SDC_FILTER_BATCH_SIZE).collect();
+        for group in batches.chunks(SDC_FILTER_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.run_batch(http, api_url, batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn run_batch(&self, http: &HttpClient, api_url: &str, page_ids: &[u32]) -> Result<Vec<u32>, String> {
+        let ids: Vec<String> = page_ids.iter().map(|page_id| format!("M{}", page_id)).collect();
+        let params = [
+            ("action", "wbgetentities".to_string()),
+            ("ids", ids.join("|")),
+            ("props", "claims".to_string()),
+            ("format", "json".to_string()),
+        ];
+        let json: Value = http
+            .send(http.post(api_url).form(&params))
+            .await
+            .map_err(|e| format!("Structured data filter: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Structured data filter: {:?}", e))?;
+        Ok(self.page_ids_from_result(&json))
+    }
+
+    /// Files without structured data have a "missing" entity; they match only "absent" conditions
+    fn page_ids_from_result(&self, json: &Value) -> Vec<u32> {
+        match json["entities"].as_object() {
+            Some(entities) => entities
+                .iter()
+                .filter(|(_, entity)| self.matches(entity))
+                .filter_map(|(id, _)| id.trim_start_matches('M').parse::<u32>().ok())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn matches(&self, entity: &Value) -> bool {
+        // MediaInfo entities have "statements", not "claims"
+        let statements = match entity["statements"].is_object() {
+            true => &entity["statements"],
+            false => &entity["claims"],
+        };
+        let has = |property: &str| statements[property].as_array().map_or(false, |a| !a.is_empty());
+        let depicts: Vec<&str> = statements[PROP_DEPICTS]
+            .as_array()
+            .map(|statements| {
+                statements
+                    .iter()
+                    .filter_map(|statement| statement["mainsnak"]["datavalue"]["value"]["id"].as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.depicts.matches(has(PROP_DEPICTS))
+            && self.license.matches(has(PROP_LICENSE))
+            && (self.depicts_items.is_empty() || self.depicts_items.iter().any(|q| depicts.contains(&q.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new() {
+        assert!(SdcFilter::new(SdcPresence::Any, &[], SdcPresence::Any).is_none());
+        let sf = SdcFilter::new(SdcPresence::Any, &["q146, Q5".to_string(), "foo".to_string()], SdcPresence::Any).unwrap();
+        assert_eq!(sf.depicts_items, vec!["Q146".to_string(), "Q5".to_string()]);
+    }
+
+    #[test]
+    fn page_ids_from_result() {
+        let j = json!({"entities":{
+            "M1":{"id":"M1","statements":{"P180":[{"mainsnak":{"datavalue":{"value":{"id":"Q146"}}}}],"P275":[]}},
+            "M2":{"id":"M2","statements":{"P275":[{"mainsnak":{"datavalue":{"value":{"id":"Q18199165"}}}}]}},
+            "M3":{"id":"M3","missing":""}
+        }});
+        let result = |sf: SdcFilter| -> Vec<u32> {
+            let mut ret = sf.page_ids_from_result(&j);
+            ret.sort();
+            ret
+        };
+        assert_eq!(result(SdcFilter::new(SdcPresence::Yes, &[], SdcPresence::Any).unwrap()), vec![1]);
+        assert_eq!(result(SdcFilter::new(SdcPresence::No, &[], SdcPresence::Any).unwrap()), vec![2, 3]);
+        assert_eq!(result(SdcFilter::new(SdcPresence::Any, &["Q146".to_string()], SdcPresence::Any).unwrap()), vec![1]);
+        assert_eq!(result(SdcFilter::new(SdcPresence::Any, &[], SdcPresence::Yes).unwrap()), vec![2]);
+        assert_eq!(result(SdcFilter::new(SdcPresence::No, &[], SdcPresence::No).unwrap()), vec![3]);
+    }
+}
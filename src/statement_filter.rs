@@ -0,0 +1,184 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
+
+static STATEMENT_FILTER_BATCH_SIZE: usize = 500;
+static STATEMENT_FILTER_CONCURRENT_REQUESTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatementRank {
+    Any,
+    /// Best rank, as in `wdt:` triples
+    Truthy,
+    Preferred,
+}
+
+impl StatementRank {
+    pub fn new_from_str(s: &str) -> Self {
+        match s {
+            "truthy" => StatementRank::Truthy,
+            "preferred" => StatementRank::Preferred,
+            _ => StatementRank::Any,
+        }
+    }
+}
+
+/// Statement filters (eg "P31=Q5", or "P18" for any value) on Wikidata items, with optional rank and qualifier conditions.
+/// All statement lines must match; the SPARQL is generated, and run on the items in batches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementFilter {
+    statements: Vec<(String, Option<String>)>,
+    rank: StatementRank,
+    qualifiers_yes: Vec<String>,
+    qualifiers_no: Vec<String>,
+}
+
+impl StatementFilter {
+    /// Returns None if there are no statement lines, and an error for a malformed line or qualifier
+    pub fn new(
+        statements: &[String],
+        rank: StatementRank,
+        qualifiers_yes: &[String],
+        qualifiers_no: &[String],
+    ) -> Result<Option<Self>, String> {
+        let statements: Vec<(String, Option<String>)> = statements
+            .iter()
+            .map(|line| Self::parse_statement(line).ok_or_else(|| format!("Bad statement filter line '{}'", line)))
+            .collect::<Result<_, String>>()?;
+        if statements.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            statements,
+            rank,
+            qualifiers_yes: Self::parse_qualifiers(qualifiers_yes)?,
+            qualifiers_no: Self::parse_qualifiers(qualifiers_no)?,
+        }))
+    }
+
+    fn parse_qualifiers(qualifiers: &[String]) -> Result<Vec<String>, String> {
+        qualifiers
+            .iter()
+            .map(|p| Self::parse_id(p, 'P').ok_or_else(|| format!("Bad statement filter qualifier '{}'", p)))
+            .collect()
+    }
+
+    /// "P31=Q5" => (P31, Some(Q5)); "P18" => (P18, None)
+    fn parse_statement(line: &str) -> Option<(String, Option<String>)> {
+        let mut parts = line.splitn(2, '=');
+        let property = Self::parse_id(parts.next()?, 'P')?;
+        match parts.next() {
+            Some(value) => Some((property, Some(Self::parse_id(value, 'Q')?))),
+            None => Some((property, None)),
+        }
+    }
+
+    fn parse_id(s: &str, prefix: char) -> Option<String> {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(r"^[PQpq]\d+$").expect("StatementFilter::parse_id RE");
+        }
+        let s = s.trim();
+        if !RE.is_match(s) {
+            return None;
+        }
+        let s = s.to_uppercase();
+        match s.starts_with(prefix) {
+            true => Some(s),
+            false => None,
+        }
+    }
+
+    pub fn to_sparql(&self, items: &[String]) -> String {
+        let mut ret = format!("SELECT DISTINCT ?item {{ VALUES ?item {{ wd:{} }}", items.join(" wd:"));
+        for (num, (property, value)) in self.statements.iter().enumerate() {
+            let st = format!("?st{}", num);
+            ret += &format!(" ?item p:{} {} .", property, st);
+            if let Some(value) = value {
+                ret += &format!(" {} ps:{} wd:{} .", st, property, value);
+            }
+            match self.rank {
+                StatementRank::Any => {}
+                StatementRank::Truthy => ret += &format!(" {} a wikibase:BestRank .", st),
+                StatementRank::Preferred => ret += &format!(" {} wikibase:rank wikibase:PreferredRank .", st),
+            }
+            for qualifier in &self.qualifiers_yes {
+                ret += &format!(" FILTER EXISTS {{ {} pq:{} [] }}", st, qualifier);
+            }
+            for qualifier in &self.qualifiers_no {
+                ret += &format!(" FILTER NOT EXISTS {{ {} pq:{} [] }}", st, qualifier);
+            }
+        }
+        ret + " }"
+    }
+
+    /// Returns the items (eg "Q42") that match the filter, querying the SPARQL endpoint at `wdqs_url`
+    pub async fn run(&self, http: &HttpClient, wdqs_url: &str, items: &[String]) -> Result<HashSet<String>, String> {
+        let mut ret = HashSet::new();
+        let batches: Vec<&[String]> = items.chunks(STATEMENT_FILTER_BATCH_SIZE).collect();
+        for group in batches.chunks(STATEMENT_FILTER_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.run_batch(http, wdqs_url, batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn run_batch(&self, http: &HttpClient, wdqs_url: &str, items: &[String]) -> Result<Vec<String>, String> {
+        let params = [("query", self.to_sparql(items)), ("format", "json".to_string())];
+        let json: Value = http
+            .send(http.post(wdqs_url).form(&params))
+            .await
+            .map_err(|e| format!("Statement filter SPARQL: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Statement filter SPARQL: {:?}", e))?;
+        Ok(Self::items_from_result(&json))
+    }
+
+    fn items_from_result(json: &Value) -> Vec<String> {
+        match json["results"]["bindings"].as_array() {
+            Some(bindings) => bindings
+                .iter()
+                .filter_map(|b| b["item"]["value"].as_str())
+                .filter_map(|url| url.rsplit('/').next())
+                .map(|q| q.to_string())
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_statements() {
+        let lines = vec!["P31 = Q5".to_string(), " p18 ".to_string()];
+        let sf = StatementFilter::new(&lines, StatementRank::Any, &[], &[]).unwrap().unwrap();
+        assert_eq!(sf.statements, vec![("P31".to_string(), Some("Q5".to_string())), ("P18".to_string(), None)]);
+        assert_eq!(StatementFilter::new(&[], StatementRank::Any, &[], &[]), Ok(None));
+        for bad in &["foo", "P31=", "Q5", "P31_=_Q5"] {
+            assert!(StatementFilter::new(&[bad.to_string()], StatementRank::Any, &[], &[]).is_err());
+        }
+        assert!(StatementFilter::new(&lines, StatementRank::Any, &["P5_8".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn to_sparql() {
+        let sf = StatementFilter::new(&["P39=Q30185".to_string()], StatementRank::Truthy, &[], &["P582".to_string()]).unwrap().unwrap();
+        assert_eq!(
+            sf.to_sparql(&["Q1".to_string(), "Q2".to_string()]),
+            "SELECT DISTINCT ?item { VALUES ?item { wd:Q1 wd:Q2 } ?item p:P39 ?st0 . ?st0 ps:P39 wd:Q30185 . ?st0 a wikibase:BestRank . FILTER NOT EXISTS { ?st0 pq:P582 [] } }"
+        );
+    }
+
+    #[test]
+    fn items_from_result() {
+        let j = json!({"head":{"vars":["item"]},"results":{"bindings":[{"item":{"type":"uri","value":"http://www.wikidata.org/entity/Q42"}}]}});
+        assert_eq!(StatementFilter::items_from_result(&j), vec!["Q42"]);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+static THUMBNAILS_BATCH_SIZE: usize = 50;
+static THUMBNAILS_CONCURRENT_REQUESTS: usize = 5;
+pub static DEFAULT_THUMBNAIL_WIDTH: u64 = 320;
+pub static MAX_THUMBNAIL_WIDTH: u64 = 1280;
+
+/// Resolves files to thumbnail URLs via the API of a wiki (`prop=imageinfo`); files from a shared repository
+/// (eg Commons) are resolved as well
+pub struct Thumbnails {
+    http: Arc<HttpClient>,
+    api_url: String,
+    width: u64,
+}
+
+impl Thumbnails {
+    /// `server_url` is eg "https://en.wikipedia.org"
+    pub fn new(http: Arc<HttpClient>, server_url: &str, width: u64) -> Self {
+        Self {
+            http,
+            api_url: format!("{}/w/api.php", server_url.trim_end_matches('/')),
+            width: width.max(1).min(MAX_THUMBNAIL_WIDTH),
+        }
+    }
+
+    /// File names (without namespace, with underscores) => thumbnail URL; files that do not exist are left out
+    pub async fn get_urls(&self, files: &[String]) -> Result<HashMap<String, String>, String> {
+        let mut ret = HashMap::new();
+        let batches: Vec<&[String]> = files.chunks(THUMBNAILS_BATCH_SIZE).collect();
+        for group in batches.chunks(THUMBNAILS_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.get_urls_batch(batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn get_urls_batch(&self, files: &[String]) -> Result<HashMap<String, String>, String> {
+        let titles: Vec<String> = files.iter().map(|file| format!("File:{}", file)).collect();
+        let params = [
+            ("action", "query".to_string()),
+            ("prop", "imageinfo".to_string()),
+            ("iiprop", "url".to_string()),
+            ("iiurlwidth", self.width.to_string()),
+            ("titles", titles.join("|")),
+            ("format", "json".to_string()),
+            ("formatversion", "2".to_string()),
+        ];
+        let json: Value = self
+            .http
+            .send(self.http.post(&self.api_url).form(&params))
+            .await
+            .map_err(|e| format!("Thumbnails: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Thumbnails: {:?}", e))?;
+        Ok(Self::urls_from_result(&json))
+    }
+
+    /// Returned titles have the localized namespace prefix, which is removed
+    fn urls_from_result(json: &Value) -> HashMap<String, String> {
+        match json["query"]["pages"].as_array() {
+            Some(pages) => pages
+                .iter()
+                .filter_map(|page| {
+                    let title = page["title"].as_str()?;
+                    let file = title.splitn(2, ':').nth(1)?.replace(' ', "_");
+                    let imageinfo = &page["imageinfo"][0];
+                    let url = imageinfo["thumburl"].as_str().or_else(|| imageinfo["url"].as_str())?;
+                    Some((file, url.to_string()))
+                })
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_from_result() {
+        let j = json!({"query":{"pages":[
+            {"ns":6,"title":"Datei:Berlin Mitte.jpg","imageinfo":[{"thumburl":"https://upload.wikimedia.org/thumb/320px-Berlin_Mitte.jpg","url":"https://upload.wikimedia.org/Berlin_Mitte.jpg"}]},
+            {"ns":6,"title":"Datei:A:B.svg","imageinfo":[{"url":"https://upload.wikimedia.org/A_B.svg"}]},
+            {"ns":6,"title":"Datei:Nope.jpg","missing":true}
+        ]}});
+        let urls = Thumbnails::urls_from_result(&j);
+        assert_eq!(urls.get("Berlin_Mitte.jpg").unwrap(), "https://upload.wikimedia.org/thumb/320px-Berlin_Mitte.jpg");
+        assert_eq!(urls.get("A:B.svg").unwrap(), "https://upload.wikimedia.org/A_B.svg");
+        assert_eq!(urls.len(), 2);
+    }
+}
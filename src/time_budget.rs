@@ -0,0 +1,80 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Share of the budget the data sources get, together
+pub static SOURCES_BUDGET_SHARE: f64 = 0.6;
+/// Share of the budget a single optional post-processing stage gets, at most
+pub static STAGE_BUDGET_SHARE: f64 = 0.25;
+/// Share of the budget kept for combining, metadata, and rendering; optional stages can not use it
+static RESERVE_BUDGET_SHARE: f64 = 0.1;
+
+/// An overall time limit for a request, handed out to the data sources and post-processing stages in slices.
+/// A stage that exceeds its slice is cancelled; the caller reports it, and continues without it.
+#[derive(Debug, Clone)]
+pub struct TimeBudget {
+    started: Instant,
+    total: Option<Duration>,
+}
+
+impl TimeBudget {
+    /// `None` for no limit
+    pub fn new(total: Option<Duration>) -> Self {
+        Self {
+            started: Instant::now(),
+            total,
+        }
+    }
+
+    /// Time left for optional stages, without the reserve
+    pub fn remaining(&self) -> Option<Duration> {
+        let total = self.total?;
+        let usable = total.mul_f64(1.0 - RESERVE_BUDGET_SHARE);
+        Some(usable.checked_sub(self.started.elapsed()).unwrap_or_default())
+    }
+
+    /// `share` of the total budget, or what is left if that is less
+    pub fn slice(&self, share: f64) -> Option<Duration> {
+        let slice = self.total?.mul_f64(share);
+        self.remaining().map(|remaining| remaining.min(slice))
+    }
+
+    /// Runs `future` within `share` of the budget; an error names the stage if it was cancelled
+    pub async fn run<T, F>(&self, stage: &str, share: f64, future: F) -> Result<T, String>
+    where
+        F: Future<Output = T>,
+    {
+        let slice = match self.slice(share) {
+            Some(slice) => slice,
+            None => return Ok(future.await),
+        };
+        if slice == Duration::from_secs(0) {
+            return Err(format!("{} (skipped, time budget used up)", stage));
+        }
+        tokio::time::timeout(slice, future)
+            .await
+            .map_err(|_| format!("{} (cancelled after {}s)", stage, slice.as_secs()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice() {
+        assert_eq!(TimeBudget::new(None).slice(0.5), None);
+        let budget = TimeBudget::new(Some(Duration::from_secs(100)));
+        let slice = budget.slice(STAGE_BUDGET_SHARE).unwrap();
+        assert!(slice <= Duration::from_secs(25) && slice > Duration::from_secs(24));
+        assert!(budget.slice(1.0).unwrap() <= Duration::from_secs(90));
+    }
+
+    #[tokio::test]
+    async fn run() {
+        let budget = TimeBudget::new(Some(Duration::from_millis(100)));
+        assert_eq!(budget.run("quick", 0.5, async { 1 }).await, Ok(1));
+        let slow = budget.run("slow", 0.5, tokio::time::sleep(Duration::from_secs(10))).await;
+        assert_eq!(slow, Err("slow (cancelled after 0s)".to_string()));
+        assert_eq!(TimeBudget::new(None).run("any", 0.1, async { 2 }).await, Ok(2));
+    }
+}
@@ -0,0 +1,89 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use wikibase::mediawiki::title::Title;
+
+/// Direction marks and other characters that are invisible, and never part of a page title
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+/// Normalizes a user-entered title the way MediaWiki would: NFC, no invisible characters,
+/// any whitespace as underscores, no leading/trailing/repeated underscores
+pub fn normalize(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut last_was_underscore = true; // Drops leading underscores
+    for c in s.nfc().filter(|c| !is_invisible(*c)) {
+        if c == '_' || c.is_whitespace() {
+            if !last_was_underscore {
+                ret.push('_');
+            }
+            last_was_underscore = true;
+        } else {
+            ret.push(c);
+            last_was_underscore = false;
+        }
+    }
+    if ret.ends_with('_') {
+        ret.pop();
+    }
+    ret
+}
+
+/// Like `normalize`, plus an uppercase first letter for case-insensitive namespaces
+pub fn normalize_ucfirst(s: &str, is_case_insensitive: bool) -> String {
+    let ret = normalize(s);
+    match is_case_insensitive {
+        true => Title::first_letter_uppercase(&ret),
+        false => ret,
+    }
+}
+
+/// Key under which titles that differ only by case, diacritics, or spaces/underscores collide, eg "Café" and "cafe"
+pub fn collision_key(s: &str) -> String {
+    normalize(s)
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| if c == '_' { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_spaces_and_underscores() {
+        assert_eq!(normalize("  Foo  bar_ _baz "), "Foo_bar_baz");
+        assert_eq!(normalize("Foo\u{00A0}bar"), "Foo_bar");
+        assert_eq!(normalize("___"), "");
+    }
+
+    #[test]
+    fn normalize_invisible_characters() {
+        assert_eq!(normalize("\u{200E}Foo bar\u{200F}"), "Foo_bar");
+        assert_eq!(normalize("\u{FEFF}Foo\u{200B}bar"), "Foobar");
+    }
+
+    #[test]
+    fn normalize_nfc() {
+        // "e" + combining acute accent
+        assert_eq!(normalize("Caf\u{0065}\u{0301}"), "Caf\u{00E9}");
+    }
+
+    #[test]
+    fn normalize_first_letter() {
+        assert_eq!(normalize_ucfirst("foo bar", true), "Foo_bar");
+        assert_eq!(normalize_ucfirst("foo bar", false), "foo_bar");
+    }
+
+    #[test]
+    fn collision_key_folds_case_and_diacritics() {
+        assert_eq!(collision_key("Café_Müller"), "cafe muller");
+        assert_eq!(collision_key("cafe muller"), collision_key("CAFÉ MÜLLER"));
+        assert_ne!(collision_key("Cafe"), collision_key("Cafes"));
+    }
+}
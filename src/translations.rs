@@ -0,0 +1,163 @@
+use htmlescape::encode_minimal;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+
+pub static DEFAULT_TRANSLATIONS_PATH: &str = "./i18n";
+static FALLBACK_LANGUAGE: &str = "en";
+
+/// Interface messages, loaded from translatewiki-style JSON files (one `<language>.json` per language).
+/// Missing messages fall back to English, per key.
+#[derive(Debug, Clone, Default)]
+pub struct Translations {
+    messages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Translations {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    /// Loads all `*.json` files in `path`. A missing directory, or a broken file, only means fewer translations.
+    pub fn new_from_directory(path: &str) -> Self {
+        let mut ret = Self::new();
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return ret,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let file_path = entry.path();
+            if file_path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let language = match file_path.file_stem().and_then(|s| s.to_str()) {
+                Some(language) => language.to_string(),
+                None => continue,
+            };
+            let json: Value = match fs::read(&file_path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            {
+                Some(json) => json,
+                None => {
+                    println!("Could not parse translation file {:?}", file_path);
+                    continue;
+                }
+            };
+            ret.add_language(&language, &json);
+        }
+        ret
+    }
+
+    /// Adds messages for a language from a JSON object; "@metadata" and non-string values are ignored
+    pub fn add_language(&mut self, language: &str, json: &Value) {
+        let messages = self
+            .messages
+            .entry(Self::sanitize_language(language))
+            .or_insert_with(HashMap::new);
+        if let Some(o) = json.as_object() {
+            o.iter()
+                .filter(|(k, _v)| !k.starts_with('@'))
+                .filter_map(|(k, v)| v.as_str().map(|v| (k, v)))
+                .for_each(|(k, v)| {
+                    messages.insert(k.to_string(), v.to_string());
+                });
+        }
+    }
+
+    /// Returns the message for `key` in `language`, or in English
+    pub fn get(&self, language: &str, key: &str) -> Option<&String> {
+        self.messages
+            .get(&Self::sanitize_language(language))
+            .and_then(|messages| messages.get(key))
+            .or_else(|| {
+                self.messages
+                    .get(FALLBACK_LANGUAGE)
+                    .and_then(|messages| messages.get(key))
+            })
+    }
+
+    /// Fills empty elements with a `tt` attribute with their message, eg `<span tt='h_title'></span>`.
+    /// The `tt` attribute is kept, so the JavaScript interface can still switch languages.
+    pub fn localize_html(&self, language: &str, html: &str) -> String {
+        lazy_static! {
+            static ref RE: Regex = Regex::new(
+                r#"<([a-zA-Z0-9]+)([^<>]*\stt=['"]([a-zA-Z0-9_\-]+)['"][^<>]*)></([a-zA-Z0-9]+)>"#
+            )
+            .expect("Translations::localize_html:RE");
+        }
+        if self.messages.is_empty() {
+            return html.to_string();
+        }
+        RE.replace_all(html, |caps: &Captures| {
+            if caps[1] != caps[4] {
+                return caps[0].to_string();
+            }
+            match self.get(language, &caps[3]) {
+                Some(message) => format!(
+                    "<{}{}>{}</{}>",
+                    &caps[1],
+                    &caps[2],
+                    encode_minimal(message),
+                    &caps[4]
+                ),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+    }
+
+    /// Language codes are used as keys and in HTML attributes, so only keep safe characters
+    pub fn sanitize_language(language: &str) -> String {
+        language
+            .trim()
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translations() -> Translations {
+        let mut ret = Translations::new();
+        ret.add_language(
+            "en",
+            &json!({"@metadata":{"authors":["X"]},"h_title":"Title","h_len":"Size"}),
+        );
+        ret.add_language("de", &json!({"h_title":"Titel"}));
+        ret
+    }
+
+    #[test]
+    fn fallback_per_key() {
+        let t = translations();
+        assert_eq!(t.get("de", "h_title"), Some(&"Titel".to_string()));
+        assert_eq!(t.get("de", "h_len"), Some(&"Size".to_string()));
+        assert_eq!(t.get("fr", "h_title"), Some(&"Title".to_string()));
+        assert_eq!(t.get("de", "@metadata"), None);
+        assert_eq!(t.get("de", "no_such_key"), None);
+    }
+
+    #[test]
+    fn localize_html() {
+        let t = translations();
+        assert_eq!(
+            t.localize_html("de", "<th class='x' tt='h_title'></th><span tt=\"h_len\"></span>"),
+            "<th class='x' tt='h_title'>Titel</th><span tt=\"h_len\">Size</span>"
+        );
+        // Unknown keys, non-empty elements, and mismatched tags stay as they are
+        let html = "<span tt='unknown'></span><span tt='h_title'>Foo</span><b tt='h_len'></i>";
+        assert_eq!(t.localize_html("de", html), html);
+    }
+
+    #[test]
+    fn sanitize_language() {
+        assert_eq!(Translations::sanitize_language(" DE "), "de");
+        assert_eq!(Translations::sanitize_language("zh-hans'><"), "zh-hans");
+    }
+}
@@ -0,0 +1,248 @@
+use crate::form_parameters::FormParameters;
+
+/// Largest accepted POST body, including uploaded files
+pub static MAX_POST_BYTES: usize = 50 * 1024 * 1024;
+
+/// One part of a multipart/form-data body
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartPart {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content: Vec<u8>,
+}
+
+/// Returns the boundary from a `multipart/form-data; boundary=...` content type
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    if parts.next()?.trim().to_lowercase() != "multipart/form-data" {
+        return None;
+    }
+    parts
+        .filter_map(|p| p.trim().strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"').to_string())
+        .find(|b| !b.is_empty())
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    (from..=haystack.len() - needle.len()).find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+fn header_parameter(header: &str, key: &str) -> Option<String> {
+    header.split(';').map(|p| p.trim()).find_map(|p| {
+        let value = p.strip_prefix(key)?.strip_prefix('=')?;
+        Some(value.trim_matches('"').to_string())
+    })
+}
+
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<MultipartPart>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut ret = vec![];
+    let mut pos = find(body, &delimiter, 0).ok_or_else(|| "Multipart body without boundary".to_string())?;
+    loop {
+        pos += delimiter.len();
+        if body[pos..].starts_with(b"--") {
+            break; // Final boundary
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("Multipart boundary without line break".to_string());
+        }
+        // Headers and content are checked to be within the body, and in order; malformed input is an error
+        let headers_start = pos + 2;
+        let headers_end = find(body, b"\r\n\r\n", pos)
+            .ok_or_else(|| "Multipart part without headers".to_string())?;
+        let content_start = headers_end + 4;
+        let next = find(body, &[b"\r\n".as_ref(), &delimiter].concat(), content_start)
+            .ok_or_else(|| "Multipart part without end".to_string())?;
+        if content_start > next || next > body.len() {
+            return Err("Malformed multipart part".to_string());
+        }
+        let headers = match headers_end >= headers_start {
+            true => String::from_utf8_lossy(&body[headers_start..headers_end]),
+            false => String::new().into(),
+        };
+        let disposition = headers
+            .split("\r\n")
+            .find(|h| h.to_lowercase().starts_with("content-disposition:"))
+            .unwrap_or("");
+        if let Some(name) = header_parameter(disposition, "name") {
+            ret.push(MultipartPart {
+                name,
+                filename: header_parameter(disposition, "filename"),
+                content: body[content_start..next].to_vec(),
+            });
+        }
+        pos = next + 2;
+    }
+    Ok(ret)
+}
+
+/// Decodes uploaded text: UTF-8 or UTF-16 with BOM, UTF-8, or else Latin-1
+pub fn decode_text(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).to_string();
+    }
+    let utf16 = |rest: &[u8], le: bool| -> String {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|c| if le { u16::from_le_bytes([c[0], c[1]]) } else { u16::from_be_bytes([c[0], c[1]]) })
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return utf16(rest, true);
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return utf16(rest, false);
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|b| *b as char).collect(),
+    }
+}
+
+/// Splits a CSV line into fields, with "quoted" fields and "" as escaped quote
+fn csv_fields(line: &str, separator: char) -> Vec<String> {
+    let mut ret = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => ret.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    ret.push(field);
+    ret
+}
+
+/// Titles from an uploaded file. For .csv/.tsv, `column` is a 1-based column number or a header name (default: first column).
+pub fn titles_from_file(filename: &str, content: &[u8], column: &str) -> Result<Vec<String>, String> {
+    let text = decode_text(content);
+    let lines = text.lines().map(|l| l.trim_end_matches('\r'));
+    let filename = filename.to_lowercase();
+    let separator = if filename.ends_with(".csv") {
+        ','
+    } else if filename.ends_with(".tsv") {
+        '\t'
+    } else {
+        return Ok(lines.map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect());
+    };
+    let rows: Vec<Vec<String>> = lines.map(|l| csv_fields(l, separator)).collect();
+    let column = column.trim();
+    let (index, skip) = if column.is_empty() {
+        (0, 0)
+    } else if let Ok(num) = column.parse::<usize>() {
+        (num.max(1) - 1, 0)
+    } else {
+        let header = rows.get(0).ok_or_else(|| "Uploaded file is empty".to_string())?;
+        let index = header
+            .iter()
+            .position(|h| h.trim() == column)
+            .ok_or_else(|| format!("Column '{}' not found in uploaded file", column))?;
+        (index, 1)
+    };
+    Ok(rows
+        .iter()
+        .skip(skip)
+        .filter_map(|row| row.get(index))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+/// Turns an uploaded multipart form into a query string; an uploaded title file is added to `manual_list`
+pub fn multipart_to_query(body: &[u8], boundary: &str) -> Result<String, String> {
+    let parts = parse_multipart(body, boundary)?;
+    let column = parts
+        .iter()
+        .find(|p| p.name == "manual_list_file_column")
+        .map(|p| decode_text(&p.content))
+        .unwrap_or_default();
+    let mut pairs: Vec<(String, String)> = vec![];
+    let mut uploaded: Vec<String> = vec![];
+    for part in &parts {
+        match (&part.filename, part.name.as_str()) {
+            (Some(filename), "manual_list_file") => {
+                if !part.content.is_empty() {
+                    uploaded.append(&mut titles_from_file(filename, &part.content, &column)?);
+                }
+            }
+            (Some(_), _) => {}
+            (None, name) => pairs.push((name.to_string(), decode_text(&part.content))),
+        }
+    }
+    if !uploaded.is_empty() {
+        match pairs.iter_mut().find(|(k, _)| k == "manual_list") {
+            Some((_, v)) => {
+                if !v.trim().is_empty() {
+                    v.push('\n');
+                }
+                *v += &uploaded.join("\n");
+            }
+            None => pairs.push(("manual_list".to_string(), uploaded.join("\n"))),
+        }
+    }
+    Ok(pairs
+        .iter()
+        .map(|(k, v)| FormParameters::percent_encode(k) + "=" + &FormParameters::percent_encode(v))
+        .collect::<Vec<String>>()
+        .join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body() -> Vec<u8> {
+        "--XyZ\r\nContent-Disposition: form-data; name=\"manual_list\"\r\n\r\nFoo\r\n--XyZ\r\nContent-Disposition: form-data; name=\"manual_list_file\"; filename=\"list.csv\"\r\nContent-Type: text/csv\r\n\r\nid,title\r\n1,\"Bar, baz\"\r\n2,Qux\r\n--XyZ\r\nContent-Disposition: form-data; name=\"manual_list_file_column\"\r\n\r\ntitle\r\n--XyZ--\r\n"
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn boundary() {
+        assert_eq!(multipart_boundary("multipart/form-data; boundary=XyZ"), Some("XyZ".to_string()));
+        assert_eq!(multipart_boundary("application/x-www-form-urlencoded"), None);
+    }
+
+    #[test]
+    fn parse_and_convert() {
+        let parts = parse_multipart(&body(), "XyZ").unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].filename, Some("list.csv".to_string()));
+        let query = multipart_to_query(&body(), "XyZ").unwrap();
+        let fp = FormParameters::new_from_query(&query);
+        assert_eq!(fp.params.get("manual_list"), Some(&"Foo\nBar, baz\nQux".to_string()));
+    }
+
+    #[test]
+    fn malformed() {
+        let parse = |s: &str| parse_multipart(s.as_bytes(), "b");
+        assert!(parse("--b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\n--b--").is_err());
+        assert!(parse("--b").is_err());
+        assert!(parse("--b\r\n").is_err());
+        assert!(parse("--b\r\nContent-Disposition: form-data; name=\"x\"").is_err());
+        assert!(parse("--bXX").is_err());
+        assert!(parse("no boundary").is_err());
+        let parts = parse("--b\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\n\r\n--b--").unwrap();
+        assert_eq!(parts[0].content, Vec::<u8>::new());
+        assert!(parse("--b\r\n\r\nFoo\r\n--b--").unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_text(&[0xEF, 0xBB, 0xBF, b'A']), "A");
+        assert_eq!(decode_text(&[0xFF, 0xFE, b'A', 0, b'B', 0]), "AB");
+        assert_eq!(decode_text(&[b'K', 0xF6, b'l', b'n']), "Köln");
+        assert_eq!(titles_from_file("x.tsv", b"a\tb\nc\td", "2").unwrap(), vec!["b", "d"]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+static WATCHERS_BATCH_SIZE: usize = 50;
+static WATCHERS_CONCURRENT_REQUESTS: usize = 5;
+
+/// Fetches the number of watchers of pages on a single wiki, via the API (`prop=info&inprop=watchers`).
+/// The API hides counts below a threshold (30 on Wikimedia wikis); these are returned as 0.
+pub struct Watchers {
+    http: Arc<HttpClient>,
+    api_url: String,
+}
+
+impl Watchers {
+    /// `server_url` is eg "https://en.wikipedia.org"
+    pub fn new(http: Arc<HttpClient>, server_url: &str) -> Self {
+        Self {
+            http,
+            api_url: format!("{}/w/api.php", server_url.trim_end_matches('/')),
+        }
+    }
+
+    /// Full titles (with underscores) => watchers; pages that do not exist are left out
+    pub async fn get_watchers(&self, titles: &[String]) -> Result<HashMap<String, u64>, String> {
+        let mut ret = HashMap::new();
+        let batches: Vec<&[String]> = titles.chunks(WATCHERS_BATCH_SIZE).collect();
+        for group in batches.chunks(WATCHERS_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = group.iter().map(|batch| self.get_watchers_batch(batch)).collect();
+            for result in join_all(futures).await {
+                ret.extend(result?);
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn get_watchers_batch(&self, titles: &[String]) -> Result<HashMap<String, u64>, String> {
+        let params = [
+            ("action", "query".to_string()),
+            ("prop", "info".to_string()),
+            ("inprop", "watchers".to_string()),
+            ("titles", titles.join("|")),
+            ("format", "json".to_string()),
+            ("formatversion", "2".to_string()),
+        ];
+        let json: Value = self
+            .http
+            .send(self.http.post(&self.api_url).form(&params))
+            .await
+            .map_err(|e| format!("Watchers: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Watchers: {:?}", e))?;
+        Ok(Self::watchers_from_result(&json))
+    }
+
+    fn watchers_from_result(json: &Value) -> HashMap<String, u64> {
+        match json["query"]["pages"].as_array() {
+            Some(pages) => pages
+                .iter()
+                .filter(|page| page["missing"].as_bool() != Some(true) && page["invalid"].as_bool() != Some(true))
+                .filter_map(|page| {
+                    let title = page["title"].as_str()?.replace(' ', "_");
+                    Some((title, page["watchers"].as_u64().unwrap_or(0)))
+                })
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchers_from_result() {
+        let j = json!({"query":{"pages":[
+            {"pageid":1,"ns":0,"title":"Albert Einstein","watchers":1234},
+            {"pageid":2,"ns":0,"title":"Obscure page"},
+            {"ns":0,"title":"Nope","missing":true}
+        ]}});
+        let watchers = Watchers::watchers_from_result(&j);
+        assert_eq!(watchers.get("Albert_Einstein"), Some(&1234));
+        assert_eq!(watchers.get("Obscure_page"), Some(&0));
+        assert!(!watchers.contains_key("Nope"));
+    }
+}
@@ -3,7 +3,7 @@ use crate::datasource::SQLtuple;
 use crate::form_parameters::FormParameters;
 use crate::pagelist::PageList;
 use crate::platform::*;
-use mysql_async as my;
+use crate::wiki_db::from_db_row;
 use mysql_async::from_row;
 use mysql_async::prelude::Queryable;
 use mysql_async::Value as MyValue;
@@ -12,7 +12,6 @@ use regex::Regex;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use wikibase::mediawiki::api::Api;
 
 pub static MIN_IGNORE_DB_FILE_COUNT: usize = 3;
 pub static MAX_FILE_COUNT_IN_RESULT_SET: usize = 5;
@@ -118,7 +117,7 @@ impl WDfist {
         // Collect pages and items, per wiki
         let mut wiki2title_q: HashMap<String, Vec<(String, String)>> = HashMap::new();
         rows.iter()
-            .map(|row| my::from_row::<(u64, String, String)>(row.to_owned()))
+            .filter_map(|row| from_db_row::<(u64, String, String)>(row).ok())
             .for_each(|(item_id, wiki, page)| {
                 if wiki == "wikidatawiki" {
                     return;
@@ -163,7 +162,7 @@ impl WDfist {
         let rows = pagelist.run_batch_queries(&self.state, batches).await?;
         let ret: Vec<(String, String)> = rows
             .par_iter()
-            .map(|row| my::from_row::<(String, String)>(row.to_owned()))
+            .filter_map(|row| from_db_row::<(String, String)>(row).ok())
             .filter(|(page, image)| page_file.contains(&(page.to_owned(), image.to_owned())))
             .collect();
 
@@ -195,7 +194,7 @@ impl WDfist {
             // Collect pages and items, per wiki
             let page_file: Vec<(String, String)> = rows
                     .par_iter()
-                    .map(|row| my::from_row::<(String, String)>(row.to_owned()))
+                    .filter_map(|row| from_db_row::<(String, String)>(row).ok())
                     .collect();
             let mut page_file = self.filter_page_images(&wiki, page_file).await.map_err(|e|format!("{:?}",e))?
                 .par_iter()
@@ -237,12 +236,11 @@ impl WDfist {
         // Process results
         let page_coords: Vec<(String, f64, f64)> = rows
             .par_iter()
-            .map(|row| my::from_row::<(String, f64, f64)>(row.to_owned()))
+            .filter_map(|row| from_db_row::<(String, f64, f64)>(row).ok())
             .collect();
 
         // Get nearby files
-        let api = Api::new("https://commons.wikimedia.org/w/api.php").await
-            .map_err(|e| format!("{:?}", e))?;
+        let api = self.state.get_api_for_wiki("commonswiki".to_string()).await?;
         //let add_item_file: Mutex<Vec<(String, String)>> = Mutex::new(vec![]);
 
         let params : Vec<_> = page_coords
@@ -339,11 +337,11 @@ impl WDfist {
         // Process results
         let item2label: Vec<(String, String)> = rows
             .par_iter()
-            .map(|row| my::from_row::<(String, String)>(row.to_owned()))
+            .filter_map(|row| from_db_row::<(String, String)>(row).ok())
             .collect();
 
         // Get search results
-        let api = Api::new("https://commons.wikimedia.org/w/api.php").await.map_err(|e| format!("{:?}", e))?;
+        let api = self.state.get_api_for_wiki("commonswiki".to_string()).await?;
 
         let params : Vec<_> = item2label
             .iter()
@@ -430,7 +428,7 @@ impl WDfist {
     async fn seed_ignore_files_from_wiki_page(&mut self) -> Result<(), String> {
         let url_with_ignore_list =
             "http://www.wikidata.org/w/index.php?title=User:Magnus_Manske/FIST_icons&action=raw";
-        let api = match Api::new("https://www.wikidata.org/w/api.php").await {
+        let api = match self.state.get_api_for_wiki("wikidatawiki".to_string()).await {
             Ok(api) => api,
             Err(_e) => return Err("Can\'t open Wikidata API".to_string()),
         };
@@ -498,7 +496,7 @@ impl WDfist {
 
         self.items = rows
             .par_iter()
-            .map(|row| my::from_row::<String>(row.to_owned()))
+            .filter_map(|row| from_db_row::<String>(row).ok())
             .collect();
         Ok(())
     }
@@ -601,7 +599,7 @@ impl WDfist {
             let rows = pagelist.run_batch_queries(&self.state, batches).await?;
             files_to_remove = rows
                 .par_iter()
-                .map(|row| my::from_row::<String>(row.to_owned()))
+                .filter_map(|row| from_db_row::<String>(row).ok())
                 .collect();
         }
 
@@ -0,0 +1,319 @@
+use crate::datasource::SQLtuple;
+use async_trait::async_trait;
+use mysql_async as my;
+use mysql_async::prelude::{FromValue, Queryable};
+use mysql_async::Value as MyValue;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Tries per query, on transient errors (see `retry_for_error`)
+static DB_QUERY_ATTEMPTS: u32 = 3;
+static DB_QUERY_RETRY_DELAY_MS: u64 = 500;
+
+/// The values of a result row, in column order
+pub type DbRow = Vec<MyValue>;
+
+/// A connection to the database of a wiki; the replicas in production, or a `MockDatabase` in tests
+#[async_trait]
+pub trait WikiDbConnection: Send {
+    async fn query(&mut self, sql: SQLtuple) -> Result<Vec<DbRow>, String>;
+    async fn close(self: Box<Self>) -> Result<(), String>;
+}
+
+impl dyn WikiDbConnection + '_ {
+    /// Runs a query, and converts each row, like `map_and_drop(from_row::<T>)` on a `my::Conn`
+    pub async fn query_as<T: FromDbRow>(&mut self, sql: SQLtuple) -> Result<Vec<T>, String> {
+        self.query(sql).await?.iter().map(T::from_db_row).collect()
+    }
+}
+
+/// Column `i` of a row, converted
+pub fn column<T: FromValue>(row: &DbRow, i: usize) -> Result<T, String> {
+    let value = row.get(i).cloned().ok_or_else(|| format!("No column {} in row", i))?;
+    my::from_value_opt::<T>(value).map_err(|e| format!("Column {}: {:?}", i, e))
+}
+
+/// A whole row, converted; as `my::from_row_opt`, for a single column type, or a tuple of them
+pub trait FromDbRow: Sized {
+    fn from_db_row(row: &DbRow) -> Result<Self, String>;
+}
+
+pub fn from_db_row<T: FromDbRow>(row: &DbRow) -> Result<T, String> {
+    T::from_db_row(row)
+}
+
+macro_rules! from_db_row_for_column {
+    ($($t:ty),+) => {
+        $(impl FromDbRow for $t {
+            fn from_db_row(row: &DbRow) -> Result<Self, String> {
+                column(row, 0)
+            }
+        })+
+    };
+}
+
+from_db_row_for_column!(Vec<u8>, String, u64, usize);
+
+macro_rules! from_db_row_for_tuple {
+    ($($t:ident $i:tt),+) => {
+        impl<$($t: FromValue),+> FromDbRow for ($($t,)+) {
+            fn from_db_row(row: &DbRow) -> Result<Self, String> {
+                Ok(($(column::<$t>(row, $i)?,)+))
+            }
+        }
+    };
+}
+
+from_db_row_for_tuple!(A 0);
+from_db_row_for_tuple!(A 0, B 1);
+from_db_row_for_tuple!(A 0, B 1, C 2);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+from_db_row_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Retry {
+    No,
+    SameConnection,
+    NewConnection,
+}
+
+/// Whether a failed query is worth running again: after a lock wait timeout (1205) or a deadlock (1213) on the same
+/// connection, after a lost connection (an I/O error; 2006 and 2013 are client errors, not sent by the server) on a new
+/// one. The query timeout (max_statement_time) is not retried.
+fn retry_for_error(e: &my::Error) -> Retry {
+    match e {
+        my::Error::Server(e) if e.code == 1205 || e.code == 1213 => Retry::SameConnection,
+        my::Error::Io(_) | my::Error::Driver(my::DriverError::ConnectionClosed) => Retry::NewConnection,
+        _ => Retry::No,
+    }
+}
+
+/// A replica connection; queries that fail with a transient error are run again, on a new connection if the old one
+/// was lost. The replicas are read-only, so running a query twice is safe.
+pub struct ReplicaConnection {
+    conn: my::Conn,
+}
+
+impl ReplicaConnection {
+    pub fn new(conn: my::Conn) -> Self {
+        Self { conn }
+    }
+
+    pub async fn query_rows(&mut self, sql: &SQLtuple) -> Result<Vec<my::Row>, String> {
+        let mut attempt = 1;
+        loop {
+            let e = match self.query_once(sql).await {
+                Ok(rows) => return Ok(rows),
+                Err(e) => e,
+            };
+            let retry = retry_for_error(&e);
+            if retry == Retry::No || attempt >= DB_QUERY_ATTEMPTS {
+                return Err(format!("{:?}", e));
+            }
+            tokio::time::sleep(Duration::from_millis(DB_QUERY_RETRY_DELAY_MS * attempt as u64)).await;
+            if retry == Retry::NewConnection {
+                // The options include the session settings, see AppState::get_session_init_statements
+                let opts = self.conn.opts().clone();
+                self.conn = my::Conn::new(opts)
+                    .await
+                    .map_err(|e2| format!("{:?}; could not reconnect: {:?}", e, e2))?;
+            }
+            attempt += 1;
+        }
+    }
+
+    pub async fn disconnect(self) -> Result<(), String> {
+        self.conn.disconnect().await.map_err(|e| format!("{:?}", e))
+    }
+
+    async fn query_once(&mut self, sql: &SQLtuple) -> Result<Vec<my::Row>, my::Error> {
+        self.conn
+            .exec_iter(sql.0.as_str(), mysql_async::Params::Positional(sql.1.clone()))
+            .await?
+            .collect_and_drop()
+            .await
+    }
+}
+
+#[async_trait]
+impl WikiDbConnection for ReplicaConnection {
+    async fn query(&mut self, sql: SQLtuple) -> Result<Vec<DbRow>, String> {
+        let rows = self.query_rows(&sql).await?;
+        Ok(rows.into_iter().map(|row| row.unwrap()).collect())
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), String> {
+        self.disconnect().await
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MockFixture {
+    /// The fixture answers queries that contain this
+    sql_contains: String,
+    rows: Vec<DbRow>,
+}
+
+/// An in-memory stand-in for the replicas. Queries are recorded, and answered with the rows of the first fixture
+/// whose SQL fragment they contain (no rows if none matches).
+#[derive(Debug, Default)]
+pub struct MockDatabase {
+    fixtures: Vec<MockFixture>,
+    queries: Mutex<Vec<(String, SQLtuple)>>,
+}
+
+impl MockDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fixtures as `[{"sql":"FROM page p","rows":[[1,"Foo",0]]}]`; numbers are integers, strings are bytes
+    pub fn new_from_json(json: &Value) -> Result<Self, String> {
+        let mut ret = Self::new();
+        for fixture in json.as_array().ok_or_else(|| "Mock database fixtures: not an array".to_string())? {
+            let sql_contains = fixture["sql"]
+                .as_str()
+                .ok_or_else(|| "Mock database fixture without 'sql'".to_string())?;
+            let rows = fixture["rows"]
+                .as_array()
+                .map(|rows| rows.iter().map(Self::row_from_json).collect())
+                .unwrap_or_default();
+            ret.add_fixture(sql_contains, rows);
+        }
+        Ok(ret)
+    }
+
+    pub fn add_fixture(&mut self, sql_contains: &str, rows: Vec<DbRow>) {
+        self.fixtures.push(MockFixture {
+            sql_contains: sql_contains.to_string(),
+            rows,
+        });
+    }
+
+    /// All queries so far, as (wiki, SQL)
+    pub fn queries(&self) -> Vec<(String, SQLtuple)> {
+        self.queries.lock().map(|q| q.clone()).unwrap_or_default()
+    }
+
+    pub fn connection(self: &Arc<Self>, wiki: &str) -> MockConnection {
+        MockConnection {
+            db: self.clone(),
+            wiki: wiki.to_string(),
+        }
+    }
+
+    fn row_from_json(row: &Value) -> DbRow {
+        row.as_array()
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|value| match value {
+                        Value::Number(n) => match n.as_i64() {
+                            Some(i) => MyValue::Int(i),
+                            None => MyValue::Double(n.as_f64().unwrap_or(0.0)),
+                        },
+                        Value::String(s) => MyValue::Bytes(s.as_bytes().to_vec()),
+                        Value::Bool(b) => MyValue::Int(*b as i64),
+                        _ => MyValue::NULL,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn answer(&self, wiki: &str, sql: SQLtuple) -> Result<Vec<DbRow>, String> {
+        let rows = self
+            .fixtures
+            .iter()
+            .find(|fixture| sql.0.contains(&fixture.sql_contains))
+            .map(|fixture| fixture.rows.clone())
+            .unwrap_or_default();
+        self.queries
+            .lock()
+            .map_err(|e| format!("{:?}", e))?
+            .push((wiki.to_string(), sql));
+        Ok(rows)
+    }
+}
+
+pub struct MockConnection {
+    db: Arc<MockDatabase>,
+    wiki: String,
+}
+
+#[async_trait]
+impl WikiDbConnection for MockConnection {
+    async fn query(&mut self, sql: SQLtuple) -> Result<Vec<DbRow>, String> {
+        self.db.answer(&self.wiki, sql)
+    }
+
+    async fn close(self: Box<Self>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_for_error() {
+        let server_error = |code| {
+            my::Error::Server(my::ServerError {
+                code,
+                message: String::new(),
+                state: String::new(),
+            })
+        };
+        assert_eq!(super::retry_for_error(&server_error(1205)), Retry::SameConnection);
+        assert_eq!(super::retry_for_error(&server_error(1213)), Retry::SameConnection);
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset");
+        assert_eq!(super::retry_for_error(&my::Error::from(reset)), Retry::NewConnection);
+        assert_eq!(super::retry_for_error(&my::Error::Driver(my::DriverError::ConnectionClosed)), Retry::NewConnection);
+        assert_eq!(super::retry_for_error(&server_error(1969)), Retry::No);
+        assert_eq!(super::retry_for_error(&server_error(1054)), Retry::No);
+    }
+
+    #[tokio::test]
+    async fn mock_database() {
+        let db = MockDatabase::new_from_json(&json!([
+            {"sql":"FROM page p","rows":[[12,"Albert_Einstein",0],[13,"Foo",0]]},
+            {"sql":"FROM page","rows":[[1]]}
+        ]))
+        .unwrap();
+        let db = Arc::new(db);
+        let mut conn: Box<dyn WikiDbConnection> = Box::new(db.connection("enwiki"));
+        let rows = conn
+            .query(("SELECT page_id,page_title,page_namespace FROM page p WHERE 1=1".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(column::<u32>(&rows[0], 0), Ok(12));
+        assert_eq!(column::<Vec<u8>>(&rows[0], 1), Ok(b"Albert_Einstein".to_vec()));
+        assert!(column::<u32>(&rows[0], 3).is_err());
+        assert_eq!(
+            from_db_row::<(u32, String, i64)>(&rows[1]),
+            Ok((13, "Foo".to_string(), 0))
+        );
+        assert!(from_db_row::<(u32, String, i64, u32)>(&rows[1]).is_err());
+        let titles = conn
+            .query_as::<(u32, Vec<u8>)>(("SELECT page_id,page_title FROM page p".to_string(), vec![]))
+            .await
+            .unwrap();
+        assert_eq!(titles[0], (12, b"Albert_Einstein".to_vec()));
+        let rows = conn.query(("SELECT 1 FROM category".to_string(), vec![])).await.unwrap();
+        assert!(rows.is_empty());
+        conn.close().await.unwrap();
+        let queries = db.queries();
+        assert_eq!(queries.len(), 3);
+        assert_eq!(queries[0].0, "enwiki");
+    }
+}
@@ -0,0 +1,194 @@
+use crate::app_state::AppState;
+use crate::oauth::OAuthSession;
+use serde_json::Value;
+use std::sync::Arc;
+
+pub static MAX_REPORT_PAGE_BYTES: usize = 1_000_000;
+
+enum EditAccount {
+    /// The bot account from the config ("wiki_bot":{"user":"...","password":"..."}, a bot password)
+    Bot { user: String, password: String },
+    /// A logged-in user, via their OAuth access token
+    OAuth { access_token: String },
+}
+
+/// Edits wiki pages, as the bot account (for scheduled reports), or as a logged-in user
+pub struct WikiEditor {
+    state: Arc<AppState>,
+    account: EditAccount,
+}
+
+impl WikiEditor {
+    /// None if no bot account is configured
+    pub fn new(state: Arc<AppState>) -> Option<Self> {
+        let (user, password) = state.get_wiki_bot_account()?;
+        Some(Self {
+            state,
+            account: EditAccount::Bot { user, password },
+        })
+    }
+
+    /// Edits as the user of `session`
+    pub fn new_for_session(state: Arc<AppState>, session: &OAuthSession) -> Self {
+        Self {
+            state,
+            account: EditAccount::OAuth {
+                access_token: session.access_token.to_owned(),
+            },
+        }
+    }
+
+    /// Replaces the text of a page
+    pub async fn replace_page(&self, wiki: &str, title: &str, text: &str, summary: &str) -> Result<(), String> {
+        self.edit(wiki, &[("title", title), ("text", text), ("summary", summary)])
+            .await
+    }
+
+    /// Replaces a report page with a query result, as wikitext. Refuses results over MAX_REPORT_PAGE_BYTES and,
+    /// unless `force`, results that would make an existing page less than half its size (eg a broken query).
+    pub async fn write_report(
+        &self,
+        wiki: &str,
+        title: &str,
+        text: &str,
+        summary: &str,
+        force: bool,
+    ) -> Result<(), String> {
+        let current_length = if force { 0 } else { self.get_page_length(wiki, title).await? };
+        Self::check_report_size(text, current_length)?;
+        self.replace_page(wiki, title, text, summary).await
+    }
+
+    fn check_report_size(text: &str, current_length: usize) -> Result<(), String> {
+        if text.len() > MAX_REPORT_PAGE_BYTES {
+            return Err(format!(
+                "Result is {} bytes, more than the {} bytes allowed for a report page",
+                text.len(),
+                MAX_REPORT_PAGE_BYTES
+            ));
+        }
+        if text.len() * 2 < current_length {
+            return Err(format!(
+                "Result is {} bytes, less than half the current page size of {} bytes; use force to write anyway",
+                text.len(),
+                current_length
+            ));
+        }
+        Ok(())
+    }
+
+    /// Current page size in bytes; 0 if the page does not exist
+    async fn get_page_length(&self, wiki: &str, title: &str) -> Result<usize, String> {
+        let api = self.state.get_api_for_wiki(wiki.to_string()).await?;
+        let params = api.params_into(&[("action", "query"), ("prop", "info"), ("titles", title)]);
+        let result = api.get_query_api_json(&params).await.map_err(|e| format!("{:?}", e))?;
+        Ok(result["query"]["pages"]
+            .as_object()
+            .and_then(|pages| pages.values().next())
+            .and_then(|page| page["length"].as_u64())
+            .unwrap_or(0) as usize)
+    }
+
+    /// Adds a new section at the bottom of a page
+    pub async fn append_section(&self, wiki: &str, title: &str, heading: &str, text: &str) -> Result<(), String> {
+        self.edit(
+            wiki,
+            &[
+                ("title", title),
+                ("section", "new"),
+                ("sectiontitle", heading),
+                ("text", text),
+                ("summary", heading),
+            ],
+        )
+        .await
+    }
+
+    async fn edit(&self, wiki: &str, edit_params: &[(&str, &str)]) -> Result<(), String> {
+        let (user, password) = match &self.account {
+            EditAccount::Bot { user, password } => (user, password),
+            EditAccount::OAuth { access_token } => return self.edit_with_oauth(wiki, access_token, edit_params).await,
+        };
+        let mut api = self.state.get_uncached_api_for_wiki(wiki.to_string()).await?;
+        api.login(user.to_owned(), password.to_owned())
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        let token = api.get_edit_token().await.map_err(|e| format!("{:?}", e))?;
+        let mut params = api.params_into(&[("action", "edit"), ("bot", "1"), ("token", &token)]);
+        for (k, v) in edit_params {
+            params.insert(k.to_string(), v.to_string());
+        }
+        let result: Value = api
+            .post_query_api_json_mut(&params)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        Self::check_edit_result(&result)
+    }
+
+    /// The edit is sent once, without retries, so it is not made twice
+    async fn edit_with_oauth(&self, wiki: &str, access_token: &str, edit_params: &[(&str, &str)]) -> Result<(), String> {
+        let url = self.state.endpoints().api_url(&self.state.get_server_url_for_wiki(wiki)?);
+        let http = self.state.http_client();
+        let tokens: Value = http
+            .send(
+                http.get(&url)
+                    .query(&[("action", "query"), ("meta", "tokens"), ("type", "csrf"), ("format", "json")])
+                    .bearer_auth(access_token),
+            )
+            .await
+            .map_err(|e| format!("Edit token: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Edit token: {:?}", e))?;
+        let token = tokens["query"]["tokens"]["csrftoken"]
+            .as_str()
+            .filter(|token| *token != "+\\")
+            .ok_or_else(|| "Could not get an edit token; please log in again".to_string())?;
+        let mut params = vec![("action", "edit"), ("format", "json"), ("token", token)];
+        params.extend_from_slice(edit_params);
+        let result: Value = http
+            .post(&url)
+            .form(&params)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        Self::check_edit_result(&result)
+    }
+
+    fn check_edit_result(result: &Value) -> Result<(), String> {
+        if let Some(error) = result["error"]["info"].as_str() {
+            return Err(error.to_string());
+        }
+        match result["edit"]["result"].as_str() {
+            Some("Success") => Ok(()),
+            _ => Err(format!("Edit failed: {}", result)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_edit_result() {
+        assert!(WikiEditor::check_edit_result(&json!({"edit":{"result":"Success","pageid":1}})).is_ok());
+        assert_eq!(
+            WikiEditor::check_edit_result(&json!({"error":{"code":"protectedpage","info":"This page is protected"}})),
+            Err("This page is protected".to_string())
+        );
+        assert!(WikiEditor::check_edit_result(&json!({})).is_err());
+    }
+
+    #[test]
+    fn check_report_size() {
+        assert!(WikiEditor::check_report_size("abc", 0).is_ok());
+        assert!(WikiEditor::check_report_size("abc", 6).is_ok());
+        assert!(WikiEditor::check_report_size("abc", 7).is_err());
+        assert!(WikiEditor::check_report_size(&"x".repeat(MAX_REPORT_PAGE_BYTES + 1), 0).is_err());
+    }
+}
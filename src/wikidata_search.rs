@@ -0,0 +1,171 @@
+use crate::http_client::HttpClient;
+use futures::future::join_all;
+use serde_json::Value;
+use std::sync::Arc;
+
+static WIKIDATA_SEARCH_CONCURRENT_REQUESTS: usize = 10;
+static WIKIDATA_SEARCH_LIMIT: usize = 5;
+/// Results per entity search request (the API maximum), and overall
+static WIKIDATA_ENTITY_SEARCH_BATCH: usize = 50;
+pub static MAX_WIKIDATA_ENTITY_SEARCH_RESULTS: usize = 500;
+
+/// Looks for existing Wikidata items with a given label or alias, to avoid creating duplicates
+pub struct WikidataSearch {
+    http: Arc<HttpClient>,
+    /// The Wikidata API
+    api_url: String,
+    language: String,
+}
+
+impl WikidataSearch {
+    pub fn new(http: Arc<HttpClient>, api_url: &str, language: &str) -> Self {
+        Self {
+            http,
+            api_url: api_url.to_string(),
+            language: language.to_string(),
+        }
+    }
+
+    /// Returns the items with a label or alias matching each search text, in order; None if the search failed
+    pub async fn find_items(&self, texts: &[String]) -> Vec<Option<Vec<String>>> {
+        let mut ret = vec![];
+        for chunk in texts.chunks(WIKIDATA_SEARCH_CONCURRENT_REQUESTS) {
+            let futures: Vec<_> = chunk.iter().map(|text| self.find_items_for_text(text)).collect();
+            ret.append(&mut join_all(futures).await);
+        }
+        ret
+    }
+
+    async fn find_items_for_text(&self, text: &str) -> Option<Vec<String>> {
+        let limit = WIKIDATA_SEARCH_LIMIT.to_string();
+        let params = [
+            ("action", "wbsearchentities"),
+            ("search", text),
+            ("language", self.language.as_str()),
+            ("type", "item"),
+            ("limit", limit.as_str()),
+            ("format", "json"),
+        ];
+        let response = self.http.send(self.http.get(&self.api_url).query(&params)).await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        let json: Value = response.json().await.ok()?;
+        Some(Self::matching_items(&json, text))
+    }
+
+    /// Entities of `entity_type` (eg "lexeme" for lemmas, "form" for form representations) whose terms start with
+    /// `text`, as (ID, matched text), and whether there were more than MAX_WIKIDATA_ENTITY_SEARCH_RESULTS;
+    /// None if the search failed
+    pub async fn find_entities(&self, text: &str, entity_type: &str) -> Option<(Vec<(String, String)>, bool)> {
+        let mut ret = vec![];
+        let mut complete = false;
+        let limit = WIKIDATA_ENTITY_SEARCH_BATCH.to_string();
+        let mut offset = 0;
+        while ret.len() < MAX_WIKIDATA_ENTITY_SEARCH_RESULTS {
+            let continue_from = offset.to_string();
+            let params = [
+                ("action", "wbsearchentities"),
+                ("search", text),
+                ("language", self.language.as_str()),
+                ("type", entity_type),
+                ("limit", limit.as_str()),
+                ("continue", continue_from.as_str()),
+                ("format", "json"),
+            ];
+            let response = self.http.send(self.http.get(&self.api_url).query(&params)).await.ok()?;
+            if !response.status().is_success() {
+                return None;
+            }
+            let json: Value = response.json().await.ok()?;
+            ret.append(&mut Self::search_results(&json));
+            match json["search-continue"].as_u64() {
+                Some(next) if next as usize > offset => offset = next as usize,
+                _ => {
+                    complete = true;
+                    break;
+                }
+            }
+        }
+        let truncated = !complete || ret.len() > MAX_WIKIDATA_ENTITY_SEARCH_RESULTS;
+        ret.truncate(MAX_WIKIDATA_ENTITY_SEARCH_RESULTS);
+        Some((ret, truncated))
+    }
+
+    /// (ID, matched text) for each search result
+    fn search_results(json: &Value) -> Vec<(String, String)> {
+        match json["search"].as_array() {
+            Some(results) => results
+                .iter()
+                .filter_map(|result| {
+                    let id = result["id"].as_str()?.to_string();
+                    let text = result["match"]["text"].as_str().or_else(|| result["label"].as_str())?;
+                    Some((id, text.to_string()))
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Search results are prefix matches; only keep items where label or alias match the whole text (ignoring case)
+    fn matching_items(json: &Value, text: &str) -> Vec<String> {
+        let text = text.to_lowercase();
+        match json["search"].as_array() {
+            Some(results) => results
+                .iter()
+                .filter(|result| {
+                    matches!(result["match"]["type"].as_str(), Some("label") | Some("alias"))
+                        && result["match"]["text"].as_str().map(|t| t.to_lowercase()) == Some(text.to_owned())
+                })
+                .filter_map(|result| result["id"].as_str().map(|id| id.to_string()))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// The content language of a wiki, eg "enwiki" => "en"; None for wikis without one
+    pub fn language_for_wiki(wiki: &str) -> Option<String> {
+        match wiki {
+            "commonswiki" | "wikidatawiki" | "specieswiki" | "metawiki" => None,
+            _ => wiki.strip_suffix("wiki").map(|l| l.replace('_', "-")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_items() {
+        let j = json!({"search":[
+            {"id":"Q1","match":{"type":"label","language":"en","text":"Foo bar"}},
+            {"id":"Q2","match":{"type":"alias","language":"en","text":"foo BAR"}},
+            {"id":"Q3","match":{"type":"label","language":"en","text":"Foo bar baz"}},
+            {"id":"Q4","match":{"type":"description","language":"en","text":"Foo bar"}}
+        ]});
+        assert_eq!(WikidataSearch::matching_items(&j, "Foo Bar"), vec!["Q1", "Q2"]);
+        assert!(WikidataSearch::matching_items(&json!({}), "Foo").is_empty());
+    }
+
+    #[test]
+    fn search_results() {
+        let j = json!({"search":[
+            {"id":"L7","label":"Haus","match":{"type":"label","language":"de","text":"Haus"}},
+            {"id":"L8-F2","label":"Häuser"},
+            {"label":"no ID"}
+        ],"search-continue":50});
+        assert_eq!(
+            WikidataSearch::search_results(&j),
+            vec![("L7".to_string(), "Haus".to_string()), ("L8-F2".to_string(), "Häuser".to_string())]
+        );
+    }
+
+    #[test]
+    fn language_for_wiki() {
+        assert_eq!(WikidataSearch::language_for_wiki("enwiki"), Some("en".to_string()));
+        assert_eq!(WikidataSearch::language_for_wiki("zh_yuewiki"), Some("zh-yue".to_string()));
+        assert_eq!(WikidataSearch::language_for_wiki("commonswiki"), None);
+        assert_eq!(WikidataSearch::language_for_wiki("enwikisource"), None);
+    }
+}